@@ -0,0 +1,222 @@
+// Горячая копия и восстановление на момент — снимок сундуков плюс журнал после него, писатели не ждут!
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokio::fs::{create_dir_all, File};
+use tokio::io::AsyncReadExt;
+use crate::crypto::Keyring;
+use crate::{catalog, wal, Database, DbConfig, DbError};
+
+// До какого места повторять журнал при восстановлении!
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryTarget {
+    Latest,    // Всё, что есть в копии и архиве!
+    Lsn(u64),  // Включительно до записи с этим номером!
+    Time(i64), // Включительно до этой секунды (сек с эпохи UNIX)!
+}
+
+impl RecoveryTarget {
+    fn includes(&self, record: &wal::WalRecord) -> bool {
+        match self {
+            RecoveryTarget::Latest => true,
+            RecoveryTarget::Lsn(lsn) => record.lsn <= *lsn,
+            RecoveryTarget::Time(at) => record.at / 1000 <= *at,
+        }
+    }
+}
+
+// Папка без данных базы — туда можно класть копию!
+async fn ensure_empty(dir: &Path) -> Result<(), DbError> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    if entries.next_entry().await?.is_some() {
+        return Err(DbError::Generic(format!("папка '{}' не пуста — чужую добычу не затираем", dir.display())));
+    }
+    Ok(())
+}
+
+fn dir_str(dir: &Path) -> Result<&str, DbError> {
+    dir.to_str().ok_or_else(|| DbError::Generic(format!("путь '{}' не в UTF-8", dir.display())))
+}
+
+impl Database {
+    // Горячая копия — `db.backup_to("backups/monday").await?`! Сундуки из снимка и журнал после него;
+    // из папки копии база открывается как обычная. Ворота записей закрываются на миг — только чтобы взять LSN. Вернёт LSN снимка.
+    pub async fn backup_to(&self, dir: impl AsRef<Path>) -> Result<u64, DbError> {
+        let dir = dir.as_ref();
+        ensure_empty(dir).await?;
+        create_dir_all(dir).await?;
+        // LSN и сегменты журнала — под замком точек сохранения: иначе точка между ними выкинула бы записи после LSN,
+        // и журнал копии начался бы с дыры. Открытый файл не пропадёт, даже если следующая точка его переименует или выкинет.
+        let (pin, lsn, next_ids, files) = {
+            let _flush = self.flush_lock.lock().await;
+            let (pin, lsn, next_ids) = self.snapshot_lsn().await;
+            let _wal = self.wal_file.lock().await;
+            let mut files = Vec::new();
+            for path in wal::segments(&self.data_dir).await? {
+                files.push(File::open(&path).await?);
+            }
+            (pin, lsn, next_ids, files)
+        };
+
+        let compression = self.snapshot_compression().await?;
+        let mut generation = wal::Generation::new(dir_str(dir)?);
+        for table_name in self.tables.iter().map(|t| t.key().clone()).collect::<Vec<_>>() {
+            let bytes = self.table_bytes(&table_name, pin.ts(), &next_ids, compression)?;
+            generation.stage(&format!("{}.bin", table_name), &bytes).await?;
+        }
+        drop(pin); // Сундуки записаны — старые версии сборщику!
+        generation.stage("sequences.seq", &self.sequences_bytes().await?).await?;
+
+        // Журнал после снимка — из открытых раньше сегментов! Оборванный хвост — запись ещё пишется, её нет в копии.
+        let keys = self.keys();
+        let mut tail = Vec::new();
+        for mut file in files {
+            let mut buffer = Vec::new();
+            file.read_to_end(&mut buffer).await?;
+            for record in wal::decode(&buffer, &keys)?.0.into_iter().filter(|r| r.lsn > lsn) {
+                tail.extend(wal::encode(record.lsn, record.at, &record.operation, &keys)?);
+            }
+        }
+        generation.stage("wal.log", &tail).await?;
+        if let Ok(config) = tokio::fs::read(&self.config_file).await {
+            generation.stage("config.toml", &config).await?; // Карту — рядом, чтобы копия была самодостаточной!
+        }
+        if let Ok(catalog) = tokio::fs::read(Path::new(&self.data_dir).join(catalog::CATALOG_FILE)).await {
+            generation.stage(catalog::CATALOG_FILE, &catalog).await?; // И каталог — схема копии, как она была!
+        }
+        generation.commit(lsn).await?;
+        Ok(lsn)
+    }
+
+    // Восстановление на момент — раскладываем копию в пустую `data_dir` и дописываем журнал из копии и архива
+    // до `target`. Потом открываем базу как обычно — `Database::new` повторит журнал. Вернёт LSN последней записи.
+    pub async fn restore_backup(
+        backup_dir: impl AsRef<Path>,
+        data_dir: impl AsRef<Path>,
+        archive_dir: Option<&Path>,
+        target: RecoveryTarget,
+    ) -> Result<u64, DbError> {
+        let (backup_dir, data_dir) = (backup_dir.as_ref(), data_dir.as_ref());
+        wal::settle(dir_str(backup_dir)?).await?; // Копию могли снять посреди шторма — доводим поколение!
+        let checkpoint_path = backup_dir.join("checkpoint");
+        if !checkpoint_path.exists() {
+            return Err(DbError::FormatError(format!("в '{}' нет точки сохранения — это не копия", backup_dir.display())));
+        }
+        let checkpoint = wal::read_checkpoint(dir_str(backup_dir)?).await?;
+        ensure_empty(data_dir).await?;
+        // Ключи — с карты из копии: ими копию и шифровали!
+        let keys = match tokio::fs::read_to_string(backup_dir.join("config.toml")).await {
+            Ok(text) => Keyring::from_config(toml::from_str::<DbConfig>(&text)?.encryption.as_ref())?,
+            Err(_) => Keyring::default(),
+        };
+
+        // Записи после снимка — из копии и архива; одна и та же запись может быть в обоих, номер один!
+        let mut records = BTreeMap::new();
+        let mut sources = wal::segments(dir_str(backup_dir)?).await?;
+        if let Some(archive) = archive_dir {
+            sources.extend(wal::segments(dir_str(archive)?).await?);
+        }
+        for path in sources {
+            for record in wal::read_segment(&path, &keys).await?.0 {
+                if record.lsn > checkpoint {
+                    records.insert(record.lsn, record);
+                }
+            }
+        }
+        let mut tail = Vec::new();
+        let mut last = checkpoint;
+        for (lsn, record) in records {
+            if lsn != last + 1 {
+                println!("Журнал обрывается после LSN {}: дальше нет записи {}, останавливаемся!", last, last + 1);
+                break; // Дыра в журнале — дальше повторять нельзя!
+            }
+            if !target.includes(&record) {
+                break;
+            }
+            tail.extend(wal::encode(record.lsn, record.at, &record.operation, &keys)?);
+            last = lsn;
+        }
+
+        // Раскладываем копию — сундуки с дельтами, последовательности и каталог, журнал до цели и точку сохранения последней!
+        create_dir_all(data_dir).await?;
+        let mut generation = wal::Generation::new(dir_str(data_dir)?);
+        let mut entries = tokio::fs::read_dir(backup_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let snapshot = matches!(path.extension().and_then(|e| e.to_str()), Some("bin" | "delta"));
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string();
+            if snapshot || name == "sequences.seq" || name == catalog::CATALOG_FILE {
+                generation.stage(&name, &tokio::fs::read(&path).await?).await?;
+            }
+        }
+        generation.stage("wal.log", &tail).await?;
+        generation.commit(checkpoint).await?;
+        Ok(last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use crate::Database;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn backup_during_checkpoints_keeps_its_wal_tail() {
+        let dir = std::env::temp_dir().join(format!("yuaidb-backup-checkpoint-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let dir = dir.to_string_lossy().into_owned();
+        let db = Database::new(&format!("{}/data", dir), &format!("{}/config.toml", dir)).await.unwrap();
+        db.execute_ddl("CREATE TABLE ships (n numeric)").await.unwrap();
+        // Тяжёлый сундук — копия пишется долго, и точка сохранения успевает проскочить между LSN и журналом!
+        db.execute_ddl("CREATE TABLE cargo (item text)").await.unwrap();
+        let cargo = (0..50_000).map(|i| std::collections::HashMap::from([("item".to_string(), format!("бочка {}", i))]));
+        db.bulk_load("cargo", cargo).await.unwrap();
+
+        // Пишем номера по порядку и без конца ставим точки сохранения — копия посреди этого шторма!
+        let done = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (db, done) = (db.clone(), done.clone());
+            tokio::spawn(async move {
+                let mut n = 0;
+                while !done.load(Ordering::SeqCst) {
+                    let mut q = db.insert("ships");
+                    let n_text = n.to_string();
+                    q.values(vec![("n", n_text.as_str())]);
+                    q.execute(&db).await.unwrap();
+                    n += 1;
+                }
+            })
+        };
+        let flusher = {
+            let (db, done) = (db.clone(), done.clone());
+            tokio::spawn(async move {
+                while !done.load(Ordering::SeqCst) {
+                    db.flush_wal_to_bin().await.unwrap();
+                }
+            })
+        };
+        let mut backups = Vec::new();
+        for i in 0..3 {
+            tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+            let backup = format!("{}/backup-{}", dir, i);
+            db.backup_to(&backup).await.unwrap();
+            backups.push(backup);
+        }
+        done.store(true, Ordering::SeqCst);
+        writer.await.unwrap();
+        flusher.await.unwrap();
+
+        // В копии — все номера подряд с нуля: дыра в журнале выбила бы кусок из середины!
+        for backup in backups {
+            let copy = Database::new(&backup, &format!("{}/config.toml", backup)).await.unwrap();
+            let mut numbers: Vec<u64> = copy.select("ships").execute(&copy).await.unwrap().unwrap_or_default().iter()
+                .map(|r| r["n"].parse().unwrap())
+                .collect();
+            numbers.sort();
+            assert_eq!(numbers, (0..numbers.len() as u64).collect::<Vec<_>>(), "{}", backup);
+        }
+    }
+}
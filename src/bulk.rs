@@ -0,0 +1,214 @@
+// Массовая загрузка — миллион строк без квадратов: проверяем параллельно, метки дополняем построчно, в WAL пишем одну запись!
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::{system, Database, DbError, Expr, FieldConfig, Row, TableConfig, TableData, Value, WalOperation};
+use crate::{apply_generated_exprs, identity_always, reject_generated, typed_value};
+
+// Итог загрузки — сколько легло в трюм и какие строки отбраковали!
+#[derive(Debug, Default)]
+pub struct BulkLoadReport {
+    pub loaded: usize,                 // Сколько строк загружено!
+    pub errors: Vec<(usize, DbError)>, // Отбракованные — номер строки во входе (с единицы) и причина!
+}
+
+// Типизированная строка — данные и время "до шторма"!
+type TypedRow = (HashMap<String, Value>, Option<i64>);
+
+// Типизируем строку по карте сундука — так же, как обычная вставка, только выражения уже разобраны!
+fn type_row(
+    table_config: Option<&TableConfig>,
+    generated: &[(FieldConfig, Expr)],
+    values: &HashMap<String, String>,
+    now: i64,
+) -> Result<TypedRow, DbError> {
+    let mut data = HashMap::new();
+    for (key, value) in values {
+        if key == "ttl" { continue; } // TTL — для шторма, а не для данных!
+        let field_config = table_config.and_then(|t| t.field(key));
+        reject_generated(field_config, key)?; // Генерируемое — руками не трогать!
+        let field_type = field_config.map_or("text", |f| f.field_type.as_str());
+        data.insert(key.clone(), typed_value(field_type, key, value)?);
+    }
+    apply_generated_exprs(generated, &mut data)?; // Генерируемые — пересчитываем!
+    let expires_at = values.get("ttl").and_then(|ttl| ttl.parse::<i64>().ok()).map(|ttl| now.saturating_add(ttl));
+    Ok((data, expires_at))
+}
+
+// Вписываем выданные номера в типизированную строку — и пересчитываем генерируемые, вдруг они от номера!
+fn numbered_values(
+    numbered: &[FieldConfig],
+    generated: &[(FieldConfig, Expr)],
+    numbers: &HashMap<String, String>,
+    data: &mut HashMap<String, Value>,
+) -> Result<(), DbError> {
+    let mut assigned = false;
+    for field in numbered {
+        if data.contains_key(&field.name) {
+            continue; // Номер задан руками — уже на месте!
+        }
+        if let Some(number) = numbers.get(&field.name) {
+            data.insert(field.name.clone(), typed_value(&field.field_type, &field.name, number)?);
+            assigned = true;
+        }
+    }
+    if assigned {
+        apply_generated_exprs(generated, data)?;
+    }
+    Ok(())
+}
+
+// Застолбить уникальные значения строки — кроме тех, что уже застолблены раньше (`known`)! Занято — дубликат.
+fn claim(
+    unique_fields: &[String],
+    taken: &mut [HashSet<String>],
+    data: &HashMap<String, Value>,
+    known: &[Option<String>],
+) -> Result<(), DbError> {
+    let fresh: Vec<(usize, String)> = unique_fields.iter().enumerate()
+        .filter_map(|(i, field)| data.get(field).map(|v| (i, v.to_string())))
+        .filter(|(i, v)| known[*i].as_ref() != Some(v))
+        .collect();
+    if let Some((i, value)) = fresh.iter().find(|(i, v)| taken[*i].contains(v)) {
+        return Err(DbError::DuplicateValue(unique_fields[*i].clone(), value.clone())); // Кракен заметил дубликат!
+    }
+    for (i, value) in fresh {
+        taken[i].insert(value);
+    }
+    Ok(())
+}
+
+impl Database {
+    // Массовая загрузка — `db.bulk_load("pirates", rows).await?`! Битые строки идут в отчёт, остальные — в трюм.
+    pub async fn bulk_load<I>(&self, table: &str, rows: I) -> Result<BulkLoadReport, DbError>
+    where
+        I: IntoIterator<Item = HashMap<String, String>>,
+    {
+        if system::is_read_only(table) {
+            return Err(DbError::ReadOnlyTable(table.to_string())); // Историю миграций пачкой не подделать!
+        }
+        let table_data = self.tables.get(table).map(|t| t.clone())
+            .ok_or_else(|| DbError::TableNotFound(table.to_string()))?;
+        let _gate = self.write_gate.read().await; // От номеров до трюма — за воротами точки сохранения!
+        // Есть уникальные — сверка с трюмом и укладка под замком сундука, как у обычной вставки: ворота, замок, карта!
+        let unique_fields = self.get_unique_fields(table).await;
+        let lock = self.unique_lock(table);
+        let _unique = if unique_fields.is_empty() { None } else { Some(lock.lock().await) };
+        let table_config = self.config.read().await.tables.iter().find(|t| t.name == table).cloned().map(Arc::new);
+        let mut report = BulkLoadReport::default();
+
+        // Номер с `identity = "always"` задан руками? Такую строку отбраковываем сразу!
+        let always: Vec<String> = table_config.iter()
+            .flat_map(|t| t.fields.iter())
+            .filter(|f| f.identity.as_deref() == Some("always"))
+            .map(|f| f.name.clone())
+            .collect();
+        // Номера из последовательностей выдаём после проверки — отбракованная строка счётчик не тратит!
+        // Пока откладываем только номера, заданные руками: счётчик должен их перешагнуть.
+        let numbered: Vec<FieldConfig> = table_config.iter()
+            .flat_map(|t| t.fields.iter())
+            .filter(|f| f.autoincrement.unwrap_or(false) || f.sequence.is_some())
+            .cloned()
+            .collect();
+        let mut lines = Vec::new();
+        let mut values = Vec::new();
+        let mut given = Vec::new();
+        for (i, row) in rows.into_iter().enumerate() {
+            match always.iter().find(|f| row.contains_key(*f)) {
+                Some(field) => report.errors.push((i + 1, identity_always(field))),
+                None => {
+                    lines.push(i + 1);
+                    given.push(numbered.iter()
+                        .filter_map(|f| row.get(&f.name).map(|v| (f.name.clone(), v.clone())))
+                        .collect::<HashMap<String, String>>());
+                    values.push(row);
+                }
+            }
+        }
+
+        // Типизируем параллельно — каждому потоку свой кусок, порядок кусков сохраняем!
+        let generated = Arc::new(table_config.as_ref().map_or(Ok(Vec::new()), |t| t.generated_exprs())?);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk = values.len().div_ceil(workers).max(1);
+        let mut tasks = Vec::new();
+        let mut values = values.into_iter();
+        loop {
+            let part: Vec<HashMap<String, String>> = values.by_ref().take(chunk).collect();
+            if part.is_empty() {
+                break;
+            }
+            let (table_config, generated) = (table_config.clone(), generated.clone());
+            tasks.push(tokio::task::spawn_blocking(move || {
+                part.iter().map(|row| type_row(table_config.as_deref(), &generated, row, now)).collect::<Vec<_>>()
+            }));
+        }
+        let mut typed = Vec::with_capacity(lines.len());
+        for task in tasks {
+            typed.extend(task.await.map_err(|e| DbError::Generic(e.to_string()))?);
+        }
+
+        // Уникальность — один проход по трюму под замком, дальше сверяемся с множеством, а не сканируем заново!
+        let mut taken: Vec<HashSet<String>> = unique_fields.iter()
+            .map(|field| table_data.iter().filter_map(|r| r.data.get(field).map(|v| v.to_string())).collect())
+            .collect();
+        let unknown = vec![None; unique_fields.len()];
+        let mut checked = Vec::new();
+        for ((line, result), given) in lines.into_iter().zip(typed).zip(given) {
+            let checked_row = result.and_then(|(data, expires_at)| {
+                claim(&unique_fields, &mut taken, &data, &unknown).map(|()| (line, data, expires_at, given))
+            });
+            match checked_row {
+                Ok(row) => checked.push(row),
+                Err(e) => report.errors.push((line, e)), // Битая или дубль — в отчёт, грузим дальше!
+            }
+        }
+
+        // Автоинкременты — одной бронью на принятые строки!
+        let mut numbers: Vec<HashMap<String, String>> = checked.iter_mut().map(|(.., given)| std::mem::take(given)).collect();
+        if !numbered.is_empty() {
+            self.assign_autoincrement(table, &mut numbers).await?;
+        }
+        let mut accepted = Vec::with_capacity(checked.len());
+        for ((line, mut data, expires_at, _), numbers) in checked.into_iter().zip(numbers) {
+            let known: Vec<Option<String>> = unique_fields.iter().map(|f| data.get(f).map(|v| v.to_string())).collect();
+            let numbered_row = numbered_values(&numbered, &generated, &numbers, &mut data)
+                .and_then(|()| claim(&unique_fields, &mut taken, &data, &known)); // Выданный номер тоже может столкнуться!
+            match numbered_row {
+                Ok(()) => accepted.push((data, expires_at)),
+                Err(e) => report.errors.push((line, e)),
+            }
+        }
+        report.errors.sort_by_key(|(line, _)| *line);
+        if accepted.is_empty() {
+            return Ok(report); // Грузить нечего — только отчёт!
+        }
+
+        // Номера строк — одним куском, и одна запись в WAL на всю пачку!
+        let write = self.mvcc.begin_write(); // Читатели увидят пачку целиком, когда она ляжет!
+        let first_id = self.row_id_counter(table).fetch_add(accepted.len() as u64, Ordering::SeqCst);
+        let rows: Vec<Row> = accepted.into_iter().zip(first_id..)
+            .map(|((data, expires_at), id)| Row { id, data, expires_at, version: 1, commit_ts: write.ts() })
+            .collect();
+        let operation = WalOperation::BulkLoad { table: table.to_string(), rows };
+        self.log_to_wal(&operation).await?;
+        let WalOperation::BulkLoad { rows, .. } = operation else { unreachable!() };
+        report.loaded = rows.len();
+        self.insert_loaded_rows(table, &table_data, rows).await;
+        Ok(report)
+    }
+
+    // Кладём готовые строки в трюм и дописываем их в метки — перестройка целиком потеряла бы чужие вставки рядом!
+    pub(crate) async fn insert_loaded_rows(&self, table: &str, table_data: &TableData, rows: Vec<Row>) {
+        if let Some(max) = rows.iter().map(|r| r.id).max() {
+            self.row_id_counter(table).fetch_max(max + 1, Ordering::SeqCst); // Номера из пачки больше не выдаём!
+        }
+        for row in rows {
+            self.dirty.mark(table, row.id);
+            self.update_indexes(table, &row, false).await;
+            table_data.insert(row.id, row);
+        }
+        self.join_cache.retain(|key, _| !key.contains(table)); // Чистим кэш — старое долой!
+    }
+}
@@ -0,0 +1,99 @@
+// Каталог — схема сундуков в папке данных, с номером версии! `config.toml` ему только подсказывает, а не командует.
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::crypto::Keyring;
+use crate::{wal, Database, DbError, TableConfig};
+
+pub(crate) const CATALOG_FILE: &str = "catalog.toml";
+const CATALOG_FORMAT: u32 = 1; // Формат файла — растёт, когда меняется его устройство!
+
+// Файл каталога — формат, версия схемы и карты сундуков!
+#[derive(Serialize, Deserialize)]
+struct CatalogFile {
+    format: u32,        // Формат файла — новее сборки не читаем!
+    version: u64,       // Версия схемы — растёт с каждой правкой!
+    updated_at: i64,    // Когда правили (сек с эпохи UNIX)!
+    #[serde(default)]
+    tables: Vec<TableConfig>,
+}
+
+// Читаем каталог — `None`, если его ещё нет: папка новая или база старше каталога!
+// Третьим — лежит ли он под текущим ключом: нет — перешифруем, иначе старый ключ с карты не убрать.
+pub(crate) async fn read(dir: &str, keys: &Keyring) -> Result<Option<(u64, Vec<TableConfig>, bool)>, DbError> {
+    let bytes = match tokio::fs::read(Path::new(dir).join(CATALOG_FILE)).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let (plain, fresh) = keys.open_file(bytes, CATALOG_FILE)?;
+    let text = String::from_utf8(plain).map_err(|_| DbError::FormatError(format!("{}: не UTF-8", CATALOG_FILE)))?;
+    let file: CatalogFile = toml::from_str(&text).map_err(|e| DbError::FormatError(format!("{}: {}", CATALOG_FILE, e)))?;
+    if file.format > CATALOG_FORMAT {
+        return Err(DbError::FormatError(format!("{} формата {} новее этой сборки ({})", CATALOG_FILE, file.format, CATALOG_FORMAT)));
+    }
+    Ok(Some((file.version, file.tables, fresh)))
+}
+
+// Каталог в байты — под ключом, если он есть на карте!
+fn encode(version: u64, tables: &[TableConfig], keys: &Keyring) -> Result<Vec<u8>, DbError> {
+    let file = CatalogFile {
+        format: CATALOG_FORMAT,
+        version,
+        updated_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
+        tables: tables.to_vec(),
+    };
+    let text = toml::to_string(&file).map_err(|e| DbError::Generic(e.to_string()))?;
+    keys.seal_file(text.into_bytes())
+}
+
+// Каталог в памяти — то, что лежит на диске, и номер его версии!
+#[derive(Default)]
+pub(crate) struct Catalog {
+    version: u64,               // Версия на диске — растёт с каждой правкой схемы!
+    tables: Vec<TableConfig>,   // Карты сундуков, как они записаны!
+    deferred: bool,             // Повтор журнала — промежуточные карты на диск не пишем!
+}
+
+impl Catalog {
+    pub(crate) fn new(version: u64, tables: Vec<TableConfig>) -> Self {
+        Catalog { version, tables, deferred: false }
+    }
+}
+
+impl Database {
+    // Версия схемы — растёт с каждой правкой каталога!
+    pub async fn schema_version(&self) -> u64 {
+        self.catalog.lock().await.version
+    }
+
+    // Пишем каталог после правки схемы — новая версия, только если карты и правда изменились!
+    pub(crate) async fn save_catalog(&self) -> Result<(), DbError> {
+        let mut catalog = self.catalog.lock().await; // Замок каталога — две правки не лягут на диск задом наперёд!
+        let tables = self.config.read().await.tables.clone();
+        if catalog.deferred || tables == catalog.tables {
+            return Ok(());
+        }
+        self.write_catalog(&mut catalog, tables).await
+    }
+
+    // Перезаписываем каталог под новым ключом — карты те же, а шифр сменился!
+    pub(crate) async fn reseal_catalog(&self) -> Result<(), DbError> {
+        let mut catalog = self.catalog.lock().await;
+        let tables = catalog.tables.clone();
+        self.write_catalog(&mut catalog, tables).await
+    }
+
+    // Повтор журнала — каталог пишем один раз в конце, а не на каждой правке из журнала!
+    pub(crate) async fn defer_catalog(&self, deferred: bool) {
+        self.catalog.lock().await.deferred = deferred;
+    }
+
+    async fn write_catalog(&self, catalog: &mut Catalog, tables: Vec<TableConfig>) -> Result<(), DbError> {
+        let version = catalog.version + 1;
+        let bytes = encode(version, &tables, &self.keys())?;
+        wal::write_atomic(&Path::new(&self.data_dir).join(CATALOG_FILE), &bytes).await?;
+        *catalog = Catalog { version, tables, deferred: catalog.deferred };
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+// Колоночные форматы — Arrow IPC и Parquet: типы колонок берём с карты сундука, аналитики открывают файл без разбора строк!
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+use arrow_array::cast::AsArray;
+use arrow_array::{Array, ArrayRef, BooleanArray, Float64Array, RecordBatch, RecordBatchOptions, StringArray, TimestampSecondArray};
+use arrow_schema::{ArrowError, DataType, Field, Schema, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use crate::transfer::{Column, ParsedLines};
+use crate::{DbError, Value};
+
+fn format_error(e: impl std::fmt::Display) -> DbError {
+    DbError::FormatError(e.to_string())
+}
+
+// Колонка по типу с карты! Хоть одно значение не того типа — вся колонка уходит текстом, чтобы ничего не потерять.
+fn column_array(field_type: &str, values: &[Option<&Value>]) -> ArrayRef {
+    let all = |fits: fn(&Value) -> bool| values.iter().flatten().all(|v| fits(v));
+    match field_type {
+        "numeric" if all(|v| matches!(v, Value::Numeric(_))) => Arc::new(Float64Array::from_iter(
+            values.iter().map(|v| match v { Some(Value::Numeric(n)) => Some(*n), _ => None }),
+        )),
+        "timestamp" if all(|v| matches!(v, Value::Timestamp(_))) => Arc::new(TimestampSecondArray::from_iter(
+            values.iter().map(|v| match v { Some(Value::Timestamp(t)) => Some(*t), _ => None }),
+        )),
+        "boolean" if all(|v| matches!(v, Value::Boolean(_))) => Arc::new(BooleanArray::from_iter(
+            values.iter().map(|v| match v { Some(Value::Boolean(b)) => Some(*b), _ => None }),
+        )),
+        _ => Arc::new(StringArray::from_iter(values.iter().map(|v| v.map(|v| v.to_string())))),
+    }
+}
+
+// Кодируем строки одним пакетом — пропуски становятся null!
+pub(crate) fn encode(columns: &[Column], rows: &[HashMap<String, Value>], parquet: bool) -> Result<Vec<u8>, DbError> {
+    let mut fields = Vec::with_capacity(columns.len());
+    let mut arrays = Vec::with_capacity(columns.len());
+    for (name, field_type) in columns {
+        let values: Vec<Option<&Value>> = rows.iter().map(|r| r.get(name)).collect();
+        let array = column_array(field_type, &values);
+        fields.push(Field::new(name, array.data_type().clone(), true));
+        arrays.push(array);
+    }
+    let schema = Arc::new(Schema::new(fields));
+    let options = RecordBatchOptions::new().with_row_count(Some(rows.len())); // Пустой сундук без колонок — тоже сундук!
+    let batch = RecordBatch::try_new_with_options(schema.clone(), arrays, &options).map_err(format_error)?;
+
+    let mut out = Vec::new();
+    if parquet {
+        let mut writer = ArrowWriter::try_new(&mut out, schema, None).map_err(format_error)?;
+        writer.write(&batch).map_err(format_error)?;
+        writer.close().map_err(format_error)?;
+    } else {
+        let mut writer = arrow_ipc::writer::FileWriter::try_new(&mut out, &schema).map_err(format_error)?;
+        writer.write(&batch).map_err(format_error)?;
+        writer.finish().map_err(format_error)?;
+    }
+    Ok(out)
+}
+
+// Колонку — в строки для погрузки! Время любой точности — в секунды, числа и флаги — как есть.
+fn text_column(name: &str, array: &ArrayRef) -> Result<StringArray, DbError> {
+    let array = match array.data_type() {
+        DataType::Timestamp(_, tz) => {
+            let seconds = arrow_cast::cast(array, &DataType::Timestamp(TimeUnit::Second, tz.clone())).map_err(format_error)?;
+            arrow_cast::cast(&seconds, &DataType::Int64).map_err(format_error)?
+        }
+        t if t.is_numeric() || matches!(t, DataType::Boolean | DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View) => array.clone(),
+        other => return Err(DbError::FormatError(format!("колонка {}: тип {} не грузим", name, other))),
+    };
+    let text = arrow_cast::cast(&array, &DataType::Utf8).map_err(format_error)?;
+    Ok(text.as_string::<i32>().clone())
+}
+
+// Разбираем Arrow IPC или Parquet — номер строки считаем по записям с единицы, null значит, что поля нет!
+pub(crate) fn decode(buffer: Vec<u8>, parquet: bool) -> Result<ParsedLines, DbError> {
+    let batches: Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>>> = if parquet {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(buffer)).map_err(format_error)?
+            .build().map_err(format_error)?;
+        Box::new(reader)
+    } else {
+        Box::new(arrow_ipc::reader::FileReader::try_new(Cursor::new(buffer), None).map_err(format_error)?)
+    };
+
+    let mut rows = Vec::new();
+    for batch in batches {
+        let batch = batch.map_err(format_error)?;
+        let schema = batch.schema();
+        let columns = schema.fields().iter().zip(batch.columns())
+            .map(|(field, array)| text_column(field.name(), array).map(|text| (field.name(), text)))
+            .collect::<Result<Vec<_>, _>>()?;
+        for i in 0..batch.num_rows() {
+            let row = columns.iter()
+                .filter(|(_, text)| text.is_valid(i))
+                .map(|(name, text)| (name.to_string(), text.value(i).to_string()))
+                .collect();
+            rows.push((rows.len() + 1, Ok(row)));
+        }
+    }
+    Ok(rows)
+}
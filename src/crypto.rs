@@ -0,0 +1,240 @@
+// Шифрование на диске — журнал и сундуки под замком AES-GCM или ChaCha20-Poly1305, ключ с берега, а не с палубы!
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::ChaCha20Poly1305;
+use serde::{Deserialize, Serialize};
+use crate::DbError;
+
+const FILE_MAGIC: &[u8; 4] = b"YUAE"; // Зашифрованный файл без своего заголовка — последовательности!
+const NONCE_LEN: usize = 12; // У обоих шифров — 96 бит!
+const TAG_LEN: usize = 16; // И тег подлинности — 128 бит!
+
+// Раздел `[encryption]` карты — шифр, ключ и старые ключи на время смены!
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub(crate) struct EncryptionConfig {
+    cipher: Option<String>,  // "aes-256-gcm" (по умолчанию) или "chacha20-poly1305"!
+    key: Option<String>,     // "env:ИМЯ" или "file:путь" — без ключа пишем открыто, но читать старое умеем!
+    #[serde(default)]
+    old_keys: Vec<String>,   // Прежние ключи — читаем ими, пока точка сохранения не перешифрует всё новым!
+}
+
+// Шифр — метка в каждом зашифрованном куске, чтобы смена шифра не ломала старые файлы!
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cipher {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    fn from_config(name: Option<&str>) -> Result<Self, DbError> {
+        match name.map(str::to_lowercase).as_deref() {
+            None | Some("aes-256-gcm") | Some("aes") => Ok(Cipher::Aes256Gcm),
+            Some("chacha20-poly1305") | Some("chacha20") => Ok(Cipher::ChaCha20Poly1305),
+            Some(other) => Err(DbError::InvalidValue("encryption.cipher".to_string(), other.to_string())),
+        }
+    }
+
+    fn tag(self) -> u8 {
+        match self {
+            Cipher::Aes256Gcm => 1,
+            Cipher::ChaCha20Poly1305 => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Self, DbError> {
+        match tag {
+            1 => Ok(Cipher::Aes256Gcm),
+            2 => Ok(Cipher::ChaCha20Poly1305),
+            other => Err(DbError::FormatError(format!("неизвестный шифр {}", other))),
+        }
+    }
+}
+
+type Key = [u8; 32];
+
+// Связка ключей — текущий для записи, он же и старые для чтения! Пустая — ничего не шифруем.
+#[derive(Default)]
+pub(crate) struct Keyring {
+    cipher: Option<Cipher>,
+    current: Option<Key>,
+    old: Vec<Key>,
+}
+
+// Ключ из источника — `env:ИМЯ` или `file:путь` (просто путь — тоже файл)!
+fn load_key(source: &str) -> Result<Key, DbError> {
+    let bytes = match source.strip_prefix("env:") {
+        Some(name) => std::env::var(name)
+            .map_err(|_| DbError::InvalidValue("encryption.key".to_string(), format!("переменная {} не задана", name)))?
+            .into_bytes(),
+        None => std::fs::read(source.strip_prefix("file:").unwrap_or(source))?,
+    };
+    parse_key(&bytes).ok_or_else(|| DbError::InvalidValue("encryption.key".to_string(),
+        format!("ключ из '{}' — не 32 байта и не 64 hex-символа", source)))
+}
+
+// Ключ — 64 hex-символа (пробелы по краям не в счёт) или ровно 32 сырых байта!
+fn parse_key(bytes: &[u8]) -> Option<Key> {
+    let text = std::str::from_utf8(bytes).map(str::trim).unwrap_or_default();
+    if text.len() == 64 && text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        return Some(key);
+    }
+    bytes.try_into().ok()
+}
+
+impl Keyring {
+    // Связка с карты — ключи читаем сразу, кривой ключ ловим при старте, а не посреди шторма!
+    pub(crate) fn from_config(config: Option<&EncryptionConfig>) -> Result<Self, DbError> {
+        let Some(config) = config else { return Ok(Keyring::default()) };
+        Ok(Keyring {
+            cipher: Some(Cipher::from_config(config.cipher.as_deref())?),
+            current: config.key.as_deref().map(load_key).transpose()?,
+            old: config.old_keys.iter().map(|source| load_key(source)).collect::<Result<_, _>>()?,
+        })
+    }
+
+    // Пишем ли зашифрованным?
+    pub(crate) fn sealing(&self) -> bool {
+        self.current.is_some()
+    }
+
+    // На сколько байт шифротекст длиннее открытого — метка шифра, nonce и тег!
+    pub(crate) fn overhead(&self) -> usize {
+        1 + NONCE_LEN + TAG_LEN
+    }
+
+    // Шифруем — метка шифра, случайный nonce и шифротекст с тегом; `aad` проверяется, но не прячется!
+    pub(crate) fn seal(&self, aad: &[u8], plain: &[u8]) -> Result<Vec<u8>, DbError> {
+        let (Some(key), Some(cipher)) = (self.current.as_ref(), self.cipher) else {
+            return Err(DbError::Generic("ключ шифрования не задан".to_string()));
+        };
+        let payload = Payload { msg: plain, aad };
+        let (nonce, sealed) = match cipher {
+            Cipher::Aes256Gcm => {
+                let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+                (nonce.to_vec(), Aes256Gcm::new(key.into()).encrypt(&nonce, payload))
+            }
+            Cipher::ChaCha20Poly1305 => {
+                let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+                (nonce.to_vec(), ChaCha20Poly1305::new(key.into()).encrypt(&nonce, payload))
+            }
+        };
+        let sealed = sealed.map_err(|_| DbError::Generic("шифрование не удалось".to_string()))?;
+        let mut out = Vec::with_capacity(1 + NONCE_LEN + sealed.len());
+        out.push(cipher.tag());
+        out.extend_from_slice(&nonce);
+        out.extend(sealed);
+        Ok(out)
+    }
+
+    // Расшифровываем — текущим ключом, потом старыми! Вернёт байты и признак «зашифровано текущим ключом и шифром».
+    pub(crate) fn open(&self, aad: &[u8], sealed: &[u8], what: &str) -> Result<(Vec<u8>, bool), DbError> {
+        if sealed.len() < 1 + NONCE_LEN {
+            return Err(DbError::FormatError(format!("{}: шифротекст оборван", what)));
+        }
+        let cipher = Cipher::from_tag(sealed[0])?;
+        let (nonce, body) = sealed[1..].split_at(NONCE_LEN);
+        if self.current.is_none() && self.old.is_empty() {
+            return Err(DbError::WrongKey(format!("{} зашифрован, а ключа на карте нет", what)));
+        }
+        for (i, key) in self.current.iter().chain(&self.old).enumerate() {
+            let payload = Payload { msg: body, aad };
+            let plain = match cipher {
+                Cipher::Aes256Gcm => Aes256Gcm::new(key.into()).decrypt(nonce.into(), payload),
+                Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(key.into()).decrypt(nonce.into(), payload),
+            };
+            if let Ok(plain) = plain {
+                let fresh = i == 0 && self.current.is_some() && self.cipher == Some(cipher);
+                return Ok((plain, fresh));
+            }
+        }
+        Err(DbError::WrongKey(format!("{} не открывается ни одним ключом с карты", what)))
+    }
+
+
+    // Целый файл — с ключом шифруем под меткой, без ключа кладём как есть!
+    pub(crate) fn seal_file(&self, plain: Vec<u8>) -> Result<Vec<u8>, DbError> {
+        if !self.sealing() {
+            return Ok(plain);
+        }
+        let mut out = FILE_MAGIC.to_vec();
+        out.extend(self.seal(FILE_MAGIC, &plain)?);
+        Ok(out)
+    }
+
+    // Обратно — файл без метки лежит открытым! Признак — как у `open`: лежит ли он так, как записали бы сейчас.
+    pub(crate) fn open_file(&self, bytes: Vec<u8>, what: &str) -> Result<(Vec<u8>, bool), DbError> {
+        match bytes.strip_prefix(FILE_MAGIC) {
+            Some(sealed) => self.open(FILE_MAGIC, sealed, what),
+            None => Ok((bytes, !self.sealing())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ключ в файле — 64 hex-символа из одного повторённого байта!
+    fn key_file(name: &str, byte: u8) -> String {
+        let path = std::env::temp_dir().join(format!("yuaidb-key-{}-{}", name, std::process::id()));
+        std::fs::write(&path, format!("{:02x}", byte).repeat(32)).unwrap();
+        format!("file:{}", path.display())
+    }
+
+    fn keyring(cipher: &str, key: Option<&str>, old_keys: &[&str]) -> Keyring {
+        let config = EncryptionConfig {
+            cipher: Some(cipher.to_string()),
+            key: key.map(str::to_string),
+            old_keys: old_keys.iter().map(|k| k.to_string()).collect(),
+        };
+        Keyring::from_config(Some(&config)).unwrap()
+    }
+
+    #[test]
+    fn rotated_key_still_opens_old_data_but_asks_for_rewrite() {
+        let (old, new) = (key_file("rotate-old", 1), key_file("rotate-new", 2));
+        let before = keyring("aes-256-gcm", Some(&old), &[]);
+        let sealed = before.seal(b"header", b"rum").unwrap();
+        assert_eq!(before.open(b"header", &sealed, "ships").unwrap(), (b"rum".to_vec(), true));
+
+        let after = keyring("aes-256-gcm", Some(&new), &[&old]);
+        assert_eq!(after.open(b"header", &sealed, "ships").unwrap(), (b"rum".to_vec(), false)); // Старым ключом — перепишем!
+        let resealed = after.seal(b"header", b"rum").unwrap();
+        assert_eq!(after.open(b"header", &resealed, "ships").unwrap(), (b"rum".to_vec(), true));
+
+        // Старый ключ убрали — новые данные открываются, старые уже нет!
+        let cleaned = keyring("aes-256-gcm", Some(&new), &[]);
+        assert!(cleaned.open(b"header", &resealed, "ships").is_ok());
+        assert!(matches!(cleaned.open(b"header", &sealed, "ships"), Err(DbError::WrongKey(_))));
+    }
+
+    #[test]
+    fn cipher_change_and_tampered_header_are_detected() {
+        let key = key_file("cipher", 3);
+        let aes = keyring("aes-256-gcm", Some(&key), &[]);
+        let chacha = keyring("chacha20-poly1305", Some(&key), &[]);
+        let sealed = aes.seal(b"header", b"rum").unwrap();
+        assert_eq!(chacha.open(b"header", &sealed, "ships").unwrap(), (b"rum".to_vec(), false)); // Шифр сменился — перепишем!
+        assert!(matches!(aes.open(b"forged", &sealed, "ships"), Err(DbError::WrongKey(_))));
+        assert!(matches!(Keyring::default().open(b"header", &sealed, "ships"), Err(DbError::WrongKey(_))));
+    }
+
+    #[test]
+    fn files_are_sealed_only_with_a_current_key() {
+        let old = key_file("file-old", 4);
+        let sealing = keyring("aes-256-gcm", Some(&old), &[]);
+        let sealed = sealing.seal_file(b"seq".to_vec()).unwrap();
+        assert!(sealed.starts_with(FILE_MAGIC));
+
+        // Ключ сняли, прежний — в старых: пишем открыто, старое читаем!
+        let reading = keyring("aes-256-gcm", None, &[&old]);
+        assert_eq!(reading.open_file(sealed, "sequences.seq").unwrap(), (b"seq".to_vec(), false));
+        assert_eq!(reading.seal_file(b"seq".to_vec()).unwrap(), b"seq");
+        assert_eq!(reading.open_file(b"seq".to_vec(), "sequences.seq").unwrap(), (b"seq".to_vec(), true));
+        assert_eq!(sealing.open_file(b"seq".to_vec(), "sequences.seq").unwrap(), (b"seq".to_vec(), false)); // Открытый при ключе — зашифровать!
+    }
+}
@@ -0,0 +1,213 @@
+// DDL — сундуки и метки заводим, правим и выкидываем через API и SQL, а не только правкой карты!
+use std::collections::HashSet;
+use std::sync::Arc;
+use dashmap::DashMap;
+use crate::migrate::{check_type, invalid};
+use crate::{Collation, Database, DbConfig, DbError, Expr, IndexKind, Migration, MigrationReport, TableConfig, WalOperation};
+
+// Служебные сундуки — имена с этим началом заводит только сам корабль!
+const SYSTEM_PREFIX: &str = "yuaidb_";
+
+// Разобранная DDL-команда — что сделать со схемой!
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ddl {
+    CreateTable(TableConfig),                                          // CREATE TABLE — новый сундук!
+    DropTable(String),                                                 // DROP TABLE — сундук за борт вместе с добычей!
+    AlterTable(Migration),                                             // ALTER TABLE — правка поля, как миграция!
+    CreateIndex { table: String, column: String, kind: IndexKind },    // CREATE [UNIQUE|FULLTEXT] INDEX ON t (поле)!
+    DropIndex { table: String, column: String, kind: IndexKind },      // DROP [UNIQUE|FULLTEXT] INDEX ON t (поле)!
+}
+
+// Проверяем карту сундука — имя годится для файла, поля не повторяются, типы и выражения в порядке!
+pub(crate) fn validate_table(table: &TableConfig) -> Result<(), DbError> {
+    if table.name.is_empty() || !table.name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(invalid("table", format!("'{}' — имя сундука только из букв, цифр и '_'", table.name)));
+    }
+    if table.name.starts_with(SYSTEM_PREFIX) {
+        return Err(invalid("table", format!("имена на '{}' — для служебных сундуков", SYSTEM_PREFIX)));
+    }
+    let mut names = HashSet::new();
+    for field in &table.fields {
+        if field.name.is_empty() || !names.insert(field.name.as_str()) {
+            return Err(invalid(&field.name, "поле без имени или повторяется"));
+        }
+        check_type(&field.field_type)?;
+        Collation::parse(field.collation.as_deref())?;
+        if field.identity.as_deref().is_some_and(|i| i != "always" && i != "by_default") {
+            return Err(invalid(&field.name, "identity бывает только \"always\" или \"by_default\""));
+        }
+    }
+    for (field, expr) in table.generated_exprs()? { // Кривое выражение ловим сейчас, а не на первой вставке!
+        check_deterministic(&field.name, &expr)?;
+    }
+    Ok(())
+}
+
+// Генерируемое поле журнал пересчитывает при повторе — now() после перезапуска дал бы другое значение!
+pub(crate) fn check_deterministic(field: &str, expr: &Expr) -> Result<(), DbError> {
+    let mut clock = false;
+    expr.visit(&mut |e| clock |= matches!(e, Expr::Func(name, _) if name == "now"));
+    if clock {
+        return Err(invalid(field, "в генерируемом поле нельзя now() — повтор журнала посчитал бы иначе"));
+    }
+    Ok(())
+}
+
+// Разница карт сундука — какие миграции приводят текущую к желаемой! Переименование не угадать, его — только явно.
+pub(crate) fn diff(current: &TableConfig, desired: &TableConfig) -> Result<Vec<Migration>, DbError> {
+    let table = &desired.name;
+    let mut drops = Vec::new();
+    let mut changes = Vec::new();
+    let mut adds = Vec::new();
+    let mut generated_adds = Vec::new();
+    // Генерируемые выкидываем первыми — они могут опираться на выкидываемые поля!
+    for field in current.fields.iter().filter(|f| desired.field(&f.name).is_none()) {
+        let drop = Migration::DropColumn { table: table.clone(), column: field.name.clone() };
+        if field.generated.is_some() { drops.insert(0, drop) } else { drops.push(drop) }
+    }
+    for field in &desired.fields {
+        let Some(old) = current.field(&field.name) else {
+            let add = Migration::AddColumn { table: table.clone(), field: field.clone(), default: None };
+            if field.generated.is_some() { generated_adds.push(add) } else { adds.push(add) }
+            continue;
+        };
+        if (&old.generated, old.autoincrement, &old.sequence, &old.identity, &old.collation)
+            != (&field.generated, field.autoincrement, &field.sequence, &field.identity, &field.collation) {
+            return Err(invalid(&field.name, "выражение, автоинкремент и алфавит поля меняются только через drop + add"));
+        }
+        if old.field_type != field.field_type {
+            changes.push(Migration::ChangeType { table: table.clone(), column: field.name.clone(), field_type: field.field_type.clone(), using: None });
+        }
+        for (kind, was, now) in [
+            (IndexKind::Index, old.indexed, field.indexed),
+            (IndexKind::Unique, old.unique, field.unique),
+            (IndexKind::Fulltext, old.fulltext, field.fulltext),
+        ] {
+            let column = field.name.clone();
+            match (was == Some(true), now == Some(true)) {
+                (false, true) => changes.push(Migration::AddIndex { table: table.clone(), column, kind }),
+                (true, false) => changes.push(Migration::DropIndex { table: table.clone(), column, kind }),
+                _ => {}
+            }
+        }
+    }
+    Ok(drops.into_iter().chain(changes).chain(adds).chain(generated_adds).collect())
+}
+
+// Ставим карту сундука на её место в списке, а новую — в конец!
+pub(crate) fn put_table(config: &mut DbConfig, table: TableConfig) {
+    match config.tables.iter_mut().find(|t| t.name == table.name) {
+        Some(current) => *current = table,
+        None => config.tables.push(table),
+    }
+}
+
+impl Database {
+    // Новый сундук — `db.create_table(TableConfig { .. }).await?`! Каталог сохраняется сразу, ждать шпиона не нужно.
+    pub async fn create_table(&self, table: TableConfig) -> Result<(), DbError> {
+        validate_table(&table)?;
+        let _gate = self.write_gate.write().await; // DDL по одному — и не посреди чужой записи!
+        if self.config.read().await.tables.iter().any(|t| t.name == table.name) {
+            return Err(DbError::TableExists(table.name));
+        }
+        self.log_to_wal(&WalOperation::CreateTable { table: table.clone() }).await?; // Сначала журнал — потом карта!
+        self.apply_create_table(table).await
+    }
+
+    // Кладём сундук на карту и в трюм — и при повторе журнала тоже!
+    pub(crate) async fn apply_create_table(&self, table: TableConfig) -> Result<(), DbError> {
+        let name = table.name.clone();
+        put_table(&mut *self.config.write().await, table);
+        let data = self.tables.entry(name.clone())
+            .or_insert_with(|| Arc::new(DashMap::with_hasher(Default::default())))
+            .clone();
+        if !data.is_empty() {
+            println!("Сундук '{}' снова на карте — строки в трюме остаются при нём!", name);
+        }
+        self.dirty.mark_full(&name);
+        self.rebuild_indexes(&name).await;
+        self.save_catalog().await?;
+        println!("Сундук '{}' заведён!", name);
+        Ok(())
+    }
+
+    // Сундук за борт — с картой, строками, метками и файлами на диске (их уберёт точка сохранения)!
+    pub async fn drop_table(&self, name: &str) -> Result<(), DbError> {
+        let _gate = self.write_gate.write().await;
+        let table = self.config.read().await.tables.iter().find(|t| t.name == name).cloned()
+            .ok_or_else(|| DbError::TableNotFound(name.to_string()))?;
+        self.log_to_wal(&WalOperation::DropTable { table }).await?;
+        self.apply_drop_table(name).await
+    }
+
+    // Выкидываем сундук — и при повторе журнала тоже!
+    pub(crate) async fn apply_drop_table(&self, name: &str) -> Result<(), DbError> {
+        self.config.write().await.tables.retain(|t| t.name != name);
+        self.tables.remove(name);
+        self.indexes.remove(name);
+        self.fulltext_indexes.remove(name);
+        self.row_ids.remove(name);
+        self.unique_locks.remove(name);
+        self.metrics.drop_table(name); // Серии сундука — за борт вместе с ним!
+        self.join_cache.retain(|key, _| !key.contains(name)); // Чистим кэш — старое долой!
+        self.dirty.mark_full(name); // Точка сохранения увидит, что сундука нет, и выкинет его файлы!
+        self.save_catalog().await?;
+        println!("Сундук '{}' выброшен за борт!", name);
+        Ok(())
+    }
+
+    // Правим сундук по желаемой карте — поля добавляем, выкидываем и перетипизируем миграциями, метки ставим и снимаем!
+    pub async fn alter_table(&self, table: TableConfig) -> Result<Vec<MigrationReport>, DbError> {
+        validate_table(&table)?;
+        let current = self.config.read().await.tables.iter().find(|t| t.name == table.name).cloned()
+            .ok_or_else(|| DbError::TableNotFound(table.name.clone()))?;
+        let migrations = diff(&current, &table)?;
+        if migrations.is_empty() {
+            return Ok(Vec::new()); // Карта та же — править нечего!
+        }
+        self.migrate_all(&table.name, migrations).await
+    }
+
+    // Метка на поле — обычная, уникальная или полнотекстовая!
+    pub async fn create_index(&self, table: &str, column: &str, kind: IndexKind) -> Result<MigrationReport, DbError> {
+        self.migrate(Migration::AddIndex { table: table.to_string(), column: column.to_string(), kind }).await
+    }
+
+    // Снимаем метку с поля — данные остаются!
+    pub async fn drop_index(&self, table: &str, column: &str, kind: IndexKind) -> Result<MigrationReport, DbError> {
+        self.migrate(Migration::DropIndex { table: table.to_string(), column: column.to_string(), kind }).await
+    }
+
+    // SQL-команда схемы — `db.execute_ddl("ALTER TABLE pirates ADD rank numeric DEFAULT 1").await?`! Миграции вернут свой отчёт.
+    pub async fn execute_ddl(&self, sql: &str) -> Result<Option<MigrationReport>, DbError> {
+        match crate::expr::parse_ddl(sql)? {
+            Ddl::CreateTable(table) => self.create_table(table).await.map(|_| None),
+            Ddl::DropTable(name) => self.drop_table(&name).await.map(|_| None),
+            Ddl::AlterTable(migration) => self.migrate(migration).await.map(Some),
+            Ddl::CreateIndex { table, column, kind } => self.create_index(&table, &column, kind).await.map(Some),
+            Ddl::DropIndex { table, column, kind } => self.drop_index(&table, &column, kind).await.map(Some),
+        }
+    }
+
+    // Перед повтором журнала — карты сундуков, которые правит журнал, откатываем к виду до первой правки:
+    // записи хранят значения строками и типизируются по той карте, что была при записи!
+    pub(crate) async fn rewind_schemas(&self, operations: &[WalOperation]) {
+        let mut rewound = HashSet::new();
+        let mut config = self.config.write().await;
+        for op in operations {
+            let (name, before) = match op {
+                WalOperation::Migrate { before, .. } => (&before.name, Some(before)),
+                WalOperation::CreateTable { table } => (&table.name, None),
+                WalOperation::DropTable { table } => (&table.name, Some(table)),
+                _ => continue,
+            };
+            if !rewound.insert(name.clone()) {
+                continue; // Только первая правка сундука — дальше карту поведут сами записи!
+            }
+            match before {
+                Some(before) => put_table(&mut config, before.clone()),
+                None => config.tables.retain(|t| t.name != *name), // Сундук заведён в журнале — до того его не было!
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+// Грязные страницы — что менялось в сундуках с прошлой точки сохранения, чтобы не переписывать всё подряд!
+use std::collections::{BTreeSet, HashMap};
+use dashmap::DashMap;
+use crate::{Hasher, RowId};
+
+// Строк на странице — страница ложится в дельту целиком, с удалёнными в ней строками вместе!
+pub(crate) const PAGE_ROWS: u64 = 1024;
+
+// Страница строки по номеру!
+pub(crate) fn page_of(id: RowId) -> u64 {
+    id / PAGE_ROWS
+}
+
+// Что поменялось в одном сундуке!
+#[derive(Debug, Default)]
+pub(crate) struct TableDirty {
+    pub(crate) full: bool,              // Сундук целиком — новый формат или потерянные страницы!
+    pub(crate) pages: BTreeSet<u64>,    // Тронутые страницы!
+}
+
+impl TableDirty {
+    fn merge(&mut self, other: TableDirty) {
+        self.full |= other.full;
+        self.pages.extend(other.pages);
+    }
+}
+
+// Отметки по сундукам — ставим после правки трюма, снимаем под воротами точки сохранения!
+#[derive(Default)]
+pub(crate) struct DirtyPages {
+    tables: DashMap<String, TableDirty, Hasher>,
+}
+
+impl DirtyPages {
+    // Строку тронули — её страница грязная!
+    pub(crate) fn mark(&self, table: &str, id: RowId) {
+        self.tables.entry(table.to_string()).or_default().pages.insert(page_of(id));
+    }
+
+    // Сундук переписать целиком!
+    pub(crate) fn mark_full(&self, table: &str) {
+        self.tables.entry(table.to_string()).or_default().full = true;
+    }
+
+    // Забираем все отметки — точка сохранения теперь за них отвечает!
+    pub(crate) fn take(&self) -> HashMap<String, TableDirty> {
+        let names: Vec<String> = self.tables.iter().map(|t| t.key().clone()).collect();
+        names.into_iter().filter_map(|name| self.tables.remove(&name)).collect()
+    }
+
+    // Точка сохранения не удалась — возвращаем отметки, следующая попробует снова!
+    pub(crate) fn restore(&self, taken: HashMap<String, TableDirty>) {
+        for (name, dirty) in taken {
+            self.tables.entry(name).or_default().merge(dirty);
+        }
+    }
+}
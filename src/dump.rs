@@ -0,0 +1,187 @@
+// Логический дамп — карта, счётчики и добыча одним текстовым файлом из одного снимка, чтобы перевезти базу в другой порт!
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::sync::Mutex;
+use crate::sequence::{Sequence, SequenceState};
+use crate::{Database, DbError, Row, RowId, TableConfig, TableData, WalOperation};
+
+// Метка формата в первой строке — чужой файл не примем за дамп!
+const DUMP_FORMAT: &str = "yuaidb-dump";
+const DUMP_VERSION: u32 = 1;
+
+// Сундук в дампе — карта (если была) и следующий номер строки, чтобы номера не пошли по второму кругу!
+#[derive(Serialize, Deserialize)]
+struct DumpTable {
+    name: String,
+    next_id: RowId,
+    schema: Option<TableConfig>,
+}
+
+// Первая строка дампа — всё, кроме самих строк!
+#[derive(Serialize, Deserialize)]
+struct DumpHeader {
+    format: String,
+    version: u32,
+    created_at: i64, // Когда снят снимок (сек с эпохи UNIX)!
+    tables: Vec<DumpTable>,
+    sequences: Vec<Sequence>,
+}
+
+// Остальные строки — по строке сундука: имя сундука и строка с номером, версией и значениями с типами!
+#[derive(Serialize, Deserialize)]
+struct DumpRow {
+    table: String,
+    #[serde(flatten)]
+    row: Row,
+}
+
+fn json_error(e: serde_json::Error) -> DbError {
+    DbError::FormatError(e.to_string())
+}
+
+impl Database {
+    // Дамп — `db.dump("backup.jsonl")`! Все сундуки читаем из одного снимка, ворота закрываются лишь на миг. Вернёт число строк.
+    pub async fn dump(&self, path: impl AsRef<Path>) -> Result<usize, DbError> {
+        // Снимок, имена, карта, счётчики и последовательности — за закрытыми воротами, как у горячей копии:
+        // CREATE, DROP или ALTER посреди сборки дали бы заголовок, который не сходится со строками!
+        let (pin, data, config, next_ids, mut sequences) = {
+            let _gate = self.write_gate.write().await;
+            let next_ids = self.next_row_ids();
+            // Трюмы — тоже здесь: сундук, выброшенный после снимка, всё равно ляжет в дамп со своими строками!
+            let mut data: Vec<(String, TableData)> = next_ids.keys()
+                .filter_map(|name| self.tables.get(name).map(|t| (name.clone(), t.clone())))
+                .collect();
+            data.sort_by(|a, b| a.0.cmp(&b.0));
+            let config = self.config.read().await.clone();
+            let mut sequences = Vec::new();
+            for state in self.sequences.iter().map(|s| s.value().clone()).collect::<Vec<_>>() {
+                sequences.push(state.lock().await.durable().clone()); // Бронь, а не выданное — после восстановления не повторимся!
+            }
+            (self.mvcc.pin(), data, config, next_ids, sequences)
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let tables = data.iter().map(|(name, _)| DumpTable {
+            name: name.clone(),
+            next_id: next_ids[name],
+            schema: config.tables.iter().find(|t| &t.name == name).cloned(),
+        }).collect();
+        sequences.sort_by(|a, b| a.name.cmp(&b.name));
+        let header = DumpHeader { format: DUMP_FORMAT.to_string(), version: DUMP_VERSION, created_at: now, tables, sequences };
+
+        let mut out = BufWriter::new(File::create(path.as_ref()).await?);
+        out.write_all(&serde_json::to_vec(&header).map_err(json_error)?).await?;
+        out.write_all(b"\n").await?;
+        let mut count = 0;
+        for (name, table_data) in &data {
+            let mut rows: Vec<Row> = self.mvcc.visible(name, table_data, pin.ts()).into_iter()
+                .filter(|r| r.expires_at.is_none_or(|at| at > now)) // Просрочку не везём!
+                .collect();
+            rows.sort_by_key(|r| r.id);
+            for row in rows {
+                let line = DumpRow { table: name.clone(), row };
+                out.write_all(&serde_json::to_vec(&line).map_err(json_error)?).await?;
+                out.write_all(b"\n").await?;
+                count += 1;
+            }
+        }
+        out.flush().await?;
+        Ok(count)
+    }
+
+    // Восстановление — `db.restore("backup.jsonl")`! Сундуки, которых нет на карте, заводим по карте из дампа,
+    // последовательности ставим поверх, строки кладём с прежними номерами. Только в пустые сундуки — чужую добычу не затираем!
+    pub async fn restore(&self, path: impl AsRef<Path>) -> Result<usize, DbError> {
+        let mut lines = BufReader::new(File::open(path.as_ref()).await?).lines();
+        let header: DumpHeader = match lines.next_line().await? {
+            Some(line) => serde_json::from_str(&line).map_err(|e| DbError::FormatError(format!("строка 1: {}", e)))?,
+            None => return Err(DbError::FormatError("пустой файл".to_string())),
+        };
+        if header.format != DUMP_FORMAT || header.version > DUMP_VERSION {
+            return Err(DbError::FormatError(format!("не дамп yuaidb версии до {}", DUMP_VERSION)));
+        }
+        for table in &header.tables {
+            if self.tables.get(&table.name).is_some_and(|t| !t.is_empty()) {
+                return Err(DbError::Generic(format!("сундук '{}' не пуст — восстанавливаем только в пустые", table.name)));
+            }
+        }
+        // Сундук без карты в дампе заводить не по чему — он должен уже быть в каталоге!
+        {
+            let config = self.config.read().await;
+            if let Some(table) = header.tables.iter().find(|t| t.schema.is_none() && !config.tables.iter().any(|c| c.name == t.name)) {
+                return Err(DbError::TableNotFound(table.name.clone()));
+            }
+        }
+
+        // Строки — сперва читаем весь файл: кривая строка не должна оставить полувосстановленную базу!
+        let mut rows: BTreeMap<String, Vec<Row>> = BTreeMap::new();
+        let mut number = 1;
+        while let Some(line) = lines.next_line().await? {
+            number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let DumpRow { table, row } = serde_json::from_str(&line)
+                .map_err(|e| DbError::FormatError(format!("строка {}: {}", number, e)))?;
+            if !header.tables.iter().any(|t| t.name == table) {
+                return Err(DbError::FormatError(format!("строка {}: сундук '{}' не описан в заголовке", number, table)));
+            }
+            rows.entry(table).or_default().push(row);
+        }
+
+        // Дальше — за закрытыми воротами до последней строки: вставка рядом не смешается с восстановленными номерами!
+        let gate = self.write_gate.write().await;
+        for table in &header.tables {
+            if self.tables.get(&table.name).is_some_and(|t| !t.is_empty()) {
+                return Err(DbError::Generic(format!("сундук '{}' не пуст — восстанавливаем только в пустые", table.name))); // Пока читали файл, кто-то успел!
+            }
+        }
+        // Схема — новые сундуки заводим в каталоге через журнал, как CREATE TABLE; уже заведённые не трогаем!
+        {
+            let missing: Vec<TableConfig> = {
+                let config = self.config.read().await;
+                header.tables.iter()
+                    .filter_map(|t| t.schema.clone())
+                    .filter(|schema| !config.tables.iter().any(|t| t.name == schema.name))
+                    .collect()
+            };
+            for table in missing {
+                self.log_to_wal(&WalOperation::CreateTable { table: table.clone() }).await?;
+                self.apply_create_table(table).await?;
+            }
+        }
+        for table in &header.tables {
+            self.row_id_counter(&table.name).fetch_max(table.next_id, Ordering::SeqCst); // Трюм завёл каталог — тут только счётчик!
+        }
+
+        // Последовательности — дамп главнее, ставим поверх через WAL!
+        for sequence in header.sequences {
+            self.log_to_wal(&WalOperation::Sequence { sequence: sequence.clone() }).await?;
+            self.sequences.insert(sequence.name.clone(), Arc::new(Mutex::new(SequenceState::new(sequence))));
+        }
+
+        // Строки — пачкой на сундук, как массовая загрузка!
+        let mut count = 0;
+        for (table, rows) in rows {
+            let Some(table_data) = self.tables.get(&table).map(|t| t.clone()) else { continue };
+            let write = self.mvcc.begin_write();
+            let rows: Vec<Row> = rows.into_iter().map(|row| Row { commit_ts: write.ts(), ..row }).collect();
+            let operation = WalOperation::BulkLoad { table: table.clone(), rows };
+            self.log_to_wal(&operation).await?;
+            let WalOperation::BulkLoad { rows, .. } = operation else { unreachable!() };
+            count += rows.len();
+            self.insert_loaded_rows(&table, &table_data, rows).await;
+        }
+        for table in &header.tables {
+            self.rebuild_indexes(&table.name).await; // Метки по карте — и для сундуков без строк!
+        }
+        drop(gate); // Точке сохранения ниже ворота нужны самой!
+        self.flush_wal_to_bin().await?; // Сразу на диск — восстановленное не должно жить только в журнале!
+        Ok(count)
+    }
+}
@@ -0,0 +1,1170 @@
+// Выражения — штурманские расчёты прямо в запросе!
+// `speed * 100 AS pct`, `upper(name)`, `CASE WHEN ... END` — всё считаем на лету.
+use std::cmp::Ordering; // Сравниваем добычу — кто больше?
+use std::time::{SystemTime, UNIX_EPOCH}; // Часы капитана — для now()!
+use serde::{Serialize, Deserialize}; // Выражения плавают в WAL вместе с условиями!
+use crate::{DbError, Value, Query, Condition, SortKey}; // Наши сокровища, штормы и запросы!
+use crate::{Ddl, FieldConfig, IndexKind, Migration, TableConfig}; // И команды схемы — CREATE, DROP, ALTER!
+
+// Бинарные операторы — пушки на обоих бортах!
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BinOp {
+    Add, Sub, Mul, Div, Mod, // Арифметика — считаем дублоны!
+    Concat,                  // `||` — склеиваем строки!
+    Eq, NotEq, Lt, LtEq, Gt, GtEq, // Сравнения — кто кого?
+    And, Or,                 // Логика — союз или выбор!
+}
+
+// Унарные операторы — один выстрел!
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum UnaryOp {
+    Neg, // Минус — долги капитана!
+    Not, // Отрицание — всё наоборот!
+}
+
+// Имя поля для текста выражения — в обратных кавычках, чтобы пробел или точка не разломали разбор!
+pub(crate) fn quote_ident(name: &str) -> String {
+    format!("`{}`", name.replace('`', "``"))
+}
+
+// Дерево выражения — карта расчёта!
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Expr {
+    Literal(Value),                                   // Готовое сокровище!
+    Null,                                             // Пустота — ничего в трюме!
+    Column(Option<String>, String),                   // Поле с кличкой или без!
+    Unary(UnaryOp, Box<Expr>),                        // Один операнд!
+    Binary(BinOp, Box<Expr>, Box<Expr>),              // Два операнда!
+    Func(String, Vec<Expr>),                          // Функция — lower, upper, substr...
+    Case(Option<Box<Expr>>, Vec<(Expr, Expr)>, Option<Box<Expr>>), // CASE [x] WHEN ... THEN ... ELSE ... END
+    Cast(Box<Expr>, String),                          // Перековка типа!
+    IsNull(Box<Expr>, bool),                          // IS [NOT] NULL — есть ли что в сундуке?
+    InList(Box<Expr>, Vec<Expr>),                     // x IN (1, 2, 3) — по шпаргалке!
+    InSubquery(Box<Expr>, Box<Query>),                // x IN (SELECT ...) — шпаргалка из другого сундука!
+    Exists(Box<Query>),                               // EXISTS (SELECT ...) — есть ли хоть кто-то?
+    Subquery(Box<Query>),                             // (SELECT ...) — одно значение из подзапроса!
+}
+
+// Откуда выражение берёт поля — строка, набор джойнов или что-то ещё!
+pub trait Bindings {
+    fn resolve(&self, alias: Option<&str>, field: &str) -> Option<Value>;
+
+    // Подзапрос — строки результата, значения в порядке проекций! Не всякий контекст умеет нырять в базу.
+    fn subquery(&self, _query: &Query) -> Result<Vec<Vec<Option<Value>>>, DbError> {
+        Err(DbError::InvalidExpression("подзапрос".to_string(), "здесь подзапросы не поддерживаются".to_string()))
+    }
+}
+
+// Голые данные без строки — для генерируемых колонок до вставки!
+impl Bindings for std::collections::HashMap<String, Value> {
+    fn resolve(&self, _alias: Option<&str>, field: &str) -> Option<Value> {
+        self.get(field).cloned()
+    }
+}
+
+// Токены — осколки выражения!
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),       // Число!
+    Str(String),    // Строка в кавычках!
+    Ident(String),  // Имя — поле, функция или ключевое слово!
+    Quoted(String), // Имя поля в `обратных кавычках` — пробелы и точки не мешают!
+    Op(String),     // Оператор — пушка!
+    LParen,         // (
+    RParen,         // )
+    Comma,          // ,
+}
+
+// Байтовые границы токена в исходнике — начало и конец!
+type Span = (usize, usize);
+
+// Режем выражение на токены — как абордажной саблей!
+// Каждый токен помнит свои байты в исходнике — подзапросам нужен текст условий!
+fn tokenize(input: &str) -> Result<(Vec<Token>, Vec<Span>), DbError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut offsets: Vec<usize> = input.char_indices().map(|(b, _)| b).collect();
+    offsets.push(input.len()); // Конец строки — тоже позиция!
+    let mut tokens = Vec::new();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let token_start = i;
+        let count = tokens.len();
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit())) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') { i += 1; }
+            let text: String = chars[start..i].iter().collect();
+            let num = text.parse::<f64>().map_err(|_| DbError::InvalidExpression(input.to_string(), format!("кривое число '{}'", text)))?;
+            tokens.push(Token::Num(num));
+        } else if c == '\'' || c == '"' {
+            // Строка — '...' или "...", удвоенная кавычка внутри — сама кавычка!
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(DbError::InvalidExpression(input.to_string(), "незакрытая кавычка".to_string())),
+                    Some(&q) if q == c => {
+                        if chars.get(i + 1) == Some(&c) { s.push(c); i += 2; } else { i += 1; break; }
+                    }
+                    Some(&ch) => { s.push(ch); i += 1; }
+                }
+            }
+            tokens.push(Token::Str(s));
+        } else if c == '`' {
+            // Поле в обратных кавычках — удвоенная внутри — сама кавычка!
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(DbError::InvalidExpression(input.to_string(), "незакрытая обратная кавычка".to_string())),
+                    Some('`') if chars.get(i + 1) == Some(&'`') => { s.push('`'); i += 2; }
+                    Some('`') => { i += 1; break; }
+                    Some(&ch) => { s.push(ch); i += 1; }
+                }
+            }
+            tokens.push(Token::Quoted(s));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') { i += 1; }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c == '(' {
+            tokens.push(Token::LParen); i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen); i += 1;
+        } else if c == ',' {
+            tokens.push(Token::Comma); i += 1;
+        } else {
+            // Операторы — двухсимвольные первыми!
+            let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            if ["<=", ">=", "!=", "<>", "||", "::", "=="].contains(&two.as_str()) {
+                tokens.push(Token::Op(two)); i += 2;
+            } else if "+-*/%=<>".contains(c) {
+                tokens.push(Token::Op(c.to_string())); i += 1;
+            } else {
+                return Err(DbError::InvalidExpression(input.to_string(), format!("непонятный символ '{}'", c)));
+            }
+        }
+        if tokens.len() > count {
+            spans.push((offsets[token_start], offsets[i])); // Запоминаем, где лежит токен!
+        }
+    }
+    Ok((tokens, spans))
+}
+
+// Парсер — рекурсивный спуск в трюм выражения!
+struct Parser<'a> {
+    tokens: Vec<Token>,        // Осколки!
+    spans: Vec<Span>, // Где каждый осколок лежал в исходнике!
+    pos: usize,                // Где стоим!
+    source: &'a str,           // Исходник — для ошибок и текста подзапросов!
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> { self.tokens.get(self.pos) }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn error(&self, msg: &str) -> DbError {
+        DbError::InvalidExpression(self.source.to_string(), msg.to_string())
+    }
+
+    // Ключевое слово впереди? Регистр не важен!
+    fn peek_keyword(&self, kw: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(s)) if s.eq_ignore_ascii_case(kw))
+    }
+
+    fn eat_keyword(&mut self, kw: &str) -> bool {
+        if self.peek_keyword(kw) { self.pos += 1; true } else { false }
+    }
+
+    fn expect_keyword(&mut self, kw: &str) -> Result<(), DbError> {
+        if self.eat_keyword(kw) { Ok(()) } else { Err(self.error(&format!("ожидается {}", kw))) }
+    }
+
+    fn eat_op(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(o)) if o == op) { self.pos += 1; true } else { false }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, DbError> { self.parse_or() }
+
+    fn parse_or(&mut self) -> Result<Expr, DbError> {
+        let mut left = self.parse_and()?;
+        while self.eat_keyword("or") {
+            let right = self.parse_and()?;
+            left = Expr::Binary(BinOp::Or, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, DbError> {
+        let mut left = self.parse_not()?;
+        while self.eat_keyword("and") {
+            let right = self.parse_not()?;
+            left = Expr::Binary(BinOp::And, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, DbError> {
+        if self.eat_keyword("not") {
+            return Ok(Expr::Unary(UnaryOp::Not, Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, DbError> {
+        let left = self.parse_additive()?;
+        if self.eat_keyword("is") {
+            let negated = self.eat_keyword("not");
+            self.expect_keyword("null")?;
+            return Ok(Expr::IsNull(Box::new(left), negated));
+        }
+        // [NOT] IN (список) или [NOT] IN (SELECT ...)!
+        let negated_in = self.peek_keyword("not") && matches!(self.tokens.get(self.pos + 1), Some(Token::Ident(s)) if s.eq_ignore_ascii_case("in"));
+        if negated_in { self.pos += 1; }
+        if self.eat_keyword("in") {
+            if !matches!(self.next(), Some(Token::LParen)) {
+                return Err(self.error("ожидается '(' после IN"));
+            }
+            let in_expr = if self.eat_keyword("select") {
+                let query = self.parse_select()?;
+                Expr::InSubquery(Box::new(left), Box::new(query))
+            } else {
+                let mut items = Vec::new();
+                loop {
+                    items.push(self.parse_expr()?);
+                    if matches!(self.peek(), Some(Token::Comma)) { self.pos += 1; } else { break; }
+                }
+                Expr::InList(Box::new(left), items)
+            };
+            if !matches!(self.next(), Some(Token::RParen)) {
+                return Err(self.error("ожидается ')' после IN"));
+            }
+            return Ok(if negated_in { Expr::Unary(UnaryOp::Not, Box::new(in_expr)) } else { in_expr });
+        }
+        let op = match self.peek() {
+            Some(Token::Op(o)) => match o.as_str() {
+                "=" | "==" => Some(BinOp::Eq),
+                "!=" | "<>" => Some(BinOp::NotEq),
+                "<" => Some(BinOp::Lt),
+                "<=" => Some(BinOp::LtEq),
+                ">" => Some(BinOp::Gt),
+                ">=" => Some(BinOp::GtEq),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.pos += 1;
+            let right = self.parse_additive()?;
+            return Ok(Expr::Binary(op, Box::new(left), Box::new(right)));
+        }
+        Ok(left)
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, DbError> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = if self.eat_op("+") { BinOp::Add }
+                else if self.eat_op("-") { BinOp::Sub }
+                else if self.eat_op("||") { BinOp::Concat }
+                else { break };
+            let right = self.parse_multiplicative()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, DbError> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = if self.eat_op("*") { BinOp::Mul }
+                else if self.eat_op("/") { BinOp::Div }
+                else if self.eat_op("%") { BinOp::Mod }
+                else { break };
+            let right = self.parse_unary()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, DbError> {
+        if self.eat_op("-") {
+            return Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.parse_unary()?)));
+        }
+        if self.eat_op("+") {
+            return self.parse_unary();
+        }
+        let mut expr = self.parse_primary()?;
+        // Постфиксный каст — `x::numeric`!
+        while self.eat_op("::") {
+            match self.next() {
+                Some(Token::Ident(t)) => expr = Expr::Cast(Box::new(expr), normalize_type(&t, self)?),
+                _ => return Err(self.error("ожидается тип после '::'")),
+            }
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, DbError> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(Expr::Literal(Value::Numeric(n))),
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::Text(s))),
+            Some(Token::LParen) if self.peek_keyword("select") => {
+                self.pos += 1;
+                let query = self.parse_select()?; // Скалярный подзапрос — одно значение!
+                match self.next() {
+                    Some(Token::RParen) => Ok(Expr::Subquery(Box::new(query))),
+                    _ => Err(self.error("ожидается ')' после подзапроса")),
+                }
+            }
+            Some(Token::LParen) => {
+                let e = self.parse_expr()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(e),
+                    _ => Err(self.error("ожидается ')'")),
+                }
+            }
+            Some(Token::Ident(name)) => {
+                let lower = name.to_lowercase();
+                match lower.as_str() {
+                    "null" => return Ok(Expr::Null),
+                    "true" => return Ok(Expr::Literal(Value::Boolean(true))),
+                    "false" => return Ok(Expr::Literal(Value::Boolean(false))),
+                    "case" => return self.parse_case(),
+                    "cast" => return self.parse_cast(),
+                    "exists" => {
+                        if !matches!(self.next(), Some(Token::LParen)) || !self.eat_keyword("select") {
+                            return Err(self.error("ожидается '(SELECT' после EXISTS"));
+                        }
+                        let query = self.parse_select()?;
+                        if !matches!(self.next(), Some(Token::RParen)) {
+                            return Err(self.error("ожидается ')' после EXISTS"));
+                        }
+                        return Ok(Expr::Exists(Box::new(query)));
+                    }
+                    _ => {}
+                }
+                if matches!(self.peek(), Some(Token::LParen)) {
+                    self.pos += 1;
+                    let mut args = Vec::new();
+                    if !matches!(self.peek(), Some(Token::RParen)) {
+                        loop {
+                            args.push(self.parse_expr()?);
+                            match self.next() {
+                                Some(Token::Comma) => continue,
+                                Some(Token::RParen) => break,
+                                _ => return Err(self.error("ожидается ',' или ')' в аргументах")),
+                            }
+                        }
+                    } else {
+                        self.pos += 1;
+                    }
+                    return Ok(Expr::Func(lower, args));
+                }
+                Ok(match name.split_once('.') {
+                    Some((alias, field)) => Expr::Column(Some(alias.to_string()), field.to_string()),
+                    None => Expr::Column(None, name),
+                })
+            }
+            Some(Token::Quoted(name)) => Ok(Expr::Column(None, name)),
+            _ => Err(self.error("ожидается значение, поле или функция")),
+        }
+    }
+
+    // Куски до ключевого слова на верхнем уровне — скобки подзапросов не в счёт!
+    // Останавливаемся на закрывающей скобке родителя, не съедая её.
+    fn take_until(&mut self, stops: &[&str], stop_on_comma: bool) -> Result<String, DbError> {
+        let start = self.pos;
+        let mut depth = 0i32;
+        while let Some(t) = self.peek() {
+            match t {
+                Token::LParen => depth += 1,
+                Token::RParen if depth == 0 => break,
+                Token::RParen => depth -= 1,
+                Token::Comma if depth == 0 && stop_on_comma => break,
+                Token::Ident(s) if depth == 0 && stops.iter().any(|k| s.eq_ignore_ascii_case(k)) => break,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(self.error("ожидается выражение"));
+        }
+        Ok(self.source[self.spans[start].0..self.spans[self.pos - 1].1].trim().to_string())
+    }
+
+    fn expect_ident(&mut self, what: &str) -> Result<String, DbError> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(name),
+            _ => Err(self.error(&format!("ожидается {}", what))),
+        }
+    }
+
+    fn expect_count(&mut self, what: &str) -> Result<usize, DbError> {
+        match self.next() {
+            Some(Token::Num(n)) if n >= 0.0 && n.fract() == 0.0 => Ok(n as usize),
+            _ => Err(self.error(&format!("{} должен быть целым числом", what))),
+        }
+    }
+
+    // SELECT ... FROM ... — подзапрос внутри выражения! Слово SELECT уже съедено.
+    fn parse_select(&mut self) -> Result<Query, DbError> {
+        const CLAUSES: [&str; 5] = ["where", "order", "limit", "offset", "join"];
+        let mut query = Query::default();
+        if self.eat_keyword("distinct") {
+            if self.eat_keyword("on") {
+                if !matches!(self.next(), Some(Token::LParen)) {
+                    return Err(self.error("ожидается '(' после DISTINCT ON"));
+                }
+                loop {
+                    query.distinct_on.push(self.take_until(&[], true)?);
+                    match self.next() {
+                        Some(Token::Comma) => continue,
+                        Some(Token::RParen) => break,
+                        _ => return Err(self.error("ожидается ')' после DISTINCT ON")),
+                    }
+                }
+            } else {
+                query.distinct = true;
+            }
+        }
+        // Проекции — до FROM, через запятую!
+        query.fields.clear();
+        loop {
+            query.fields.push(self.take_until(&["from"], true)?);
+            if matches!(self.peek(), Some(Token::Comma)) { self.pos += 1; } else { break; }
+        }
+        self.expect_keyword("from")?;
+        query.table = self.expect_ident("имя сундука после FROM")?;
+        query.alias = query.table.clone();
+        // Кличка — с AS или без!
+        if self.eat_keyword("as") || matches!(self.peek(), Some(Token::Ident(s)) if !CLAUSES.iter().any(|k| s.eq_ignore_ascii_case(k))) {
+            query.alias = self.expect_ident("кличка сундука")?;
+        }
+        while self.eat_keyword("join") {
+            let table = self.expect_ident("имя сундука после JOIN")?;
+            self.eat_keyword("as");
+            let alias = if self.peek_keyword("on") { table.clone() } else { self.expect_ident("кличка для JOIN")? };
+            self.expect_keyword("on")?;
+            let left = self.expect_ident("левое поле JOIN")?;
+            if !self.eat_op("=") {
+                return Err(self.error("JOIN понимает только равенство полей"));
+            }
+            let right = self.expect_ident("правое поле JOIN")?;
+            query.joins.push((table, alias, left, right));
+        }
+        if self.eat_keyword("where") {
+            let condition = self.take_until(&["order", "limit", "offset"], false)?;
+            query.where_clauses.push(vec![Condition::Expr(condition)]);
+        }
+        if self.eat_keyword("order") {
+            self.expect_keyword("by")?;
+            loop {
+                let expr = self.take_until(&["asc", "desc", "nulls", "limit", "offset"], true)?;
+                let ascending = !self.eat_keyword("desc");
+                if ascending { self.eat_keyword("asc"); }
+                let nulls_first = if self.eat_keyword("nulls") {
+                    if self.eat_keyword("first") { Some(true) } else { self.expect_keyword("last")?; Some(false) }
+                } else {
+                    None
+                };
+                query.order_by.push(SortKey { expr, ascending, nulls_first });
+                if matches!(self.peek(), Some(Token::Comma)) { self.pos += 1; } else { break; }
+            }
+        }
+        if self.eat_keyword("limit") {
+            query.limit = Some(self.expect_count("LIMIT")?);
+        }
+        if self.eat_keyword("offset") {
+            query.offset = Some(self.expect_count("OFFSET")?);
+        }
+        Ok(query)
+    }
+
+    fn expect_token(&mut self, token: Token, what: &str) -> Result<(), DbError> {
+        if self.next() == Some(token) { Ok(()) } else { Err(self.error(&format!("ожидается {}", what))) }
+    }
+
+    // CREATE/DROP/ALTER — команда схемы целиком!
+    fn parse_ddl(&mut self) -> Result<Ddl, DbError> {
+        if self.eat_keyword("create") {
+            if self.eat_keyword("table") {
+                return self.parse_create_table();
+            }
+            let kind = self.index_kind();
+            self.expect_keyword("index")?;
+            if !self.peek_keyword("on") {
+                self.expect_ident("имя метки")?; // Имя — для привычки, метка живёт на поле!
+            }
+            let (table, column) = self.parse_index_target()?;
+            return Ok(Ddl::CreateIndex { table, column, kind });
+        }
+        if self.eat_keyword("drop") {
+            if self.eat_keyword("table") {
+                return Ok(Ddl::DropTable(self.expect_ident("имя сундука")?));
+            }
+            let kind = self.index_kind();
+            self.expect_keyword("index")?;
+            let (table, column) = self.parse_index_target()?;
+            return Ok(Ddl::DropIndex { table, column, kind });
+        }
+        self.expect_keyword("alter")?;
+        self.expect_keyword("table")?;
+        let table = self.expect_ident("имя сундука")?;
+        let migration = if self.eat_keyword("add") {
+            self.eat_keyword("column");
+            let (field, default) = self.parse_column()?;
+            Migration::AddColumn { table, field, default }
+        } else if self.eat_keyword("drop") {
+            self.eat_keyword("column");
+            Migration::DropColumn { column: self.expect_ident("имя поля")?, table }
+        } else if self.eat_keyword("rename") {
+            self.eat_keyword("column");
+            let from = self.expect_ident("имя поля")?;
+            self.expect_keyword("to")?;
+            Migration::RenameColumn { from, to: self.expect_ident("новое имя поля")?, table }
+        } else {
+            self.expect_keyword("alter")?;
+            self.eat_keyword("column");
+            let column = self.expect_ident("имя поля")?;
+            if self.eat_keyword("set") {
+                self.expect_keyword("data")?;
+            }
+            self.expect_keyword("type")?;
+            let field_type = self.parse_type()?;
+            let using = if self.eat_keyword("using") { Some(self.take_until(&[], false)?) } else { None };
+            Migration::ChangeType { table, column, field_type, using }
+        };
+        Ok(Ddl::AlterTable(migration))
+    }
+
+    // CREATE TABLE имя (поле тип ..., ...) — слово TABLE уже съедено!
+    fn parse_create_table(&mut self) -> Result<Ddl, DbError> {
+        let name = self.expect_ident("имя сундука")?;
+        self.expect_token(Token::LParen, "'(' после имени сундука")?;
+        let mut fields = Vec::new();
+        loop {
+            let (field, default) = self.parse_column()?;
+            if default.is_some() {
+                return Err(self.error("DEFAULT бывает только в ALTER TABLE ... ADD — для заполнения старых строк"));
+            }
+            fields.push(field);
+            match self.next() {
+                Some(Token::Comma) => continue,
+                Some(Token::RParen) => break,
+                _ => return Err(self.error("ожидается ',' или ')' после поля")),
+            }
+        }
+        Ok(Ddl::CreateTable(TableConfig { name, fields }))
+    }
+
+    // Поле — `имя тип [UNIQUE] [INDEX] [FULLTEXT] [AUTOINCREMENT] [GENERATED ...] [SEQUENCE s] [COLLATE c] [DEFAULT v]`!
+    fn parse_column(&mut self) -> Result<(FieldConfig, Option<String>), DbError> {
+        let name = self.expect_ident("имя поля")?;
+        let mut field = FieldConfig::new(&name, &self.parse_type()?);
+        let mut default = None;
+        loop {
+            if self.eat_keyword("unique") {
+                field.unique = Some(true);
+            } else if self.eat_keyword("index") || self.eat_keyword("indexed") {
+                field.indexed = Some(true);
+            } else if self.eat_keyword("fulltext") {
+                field.fulltext = Some(true);
+            } else if self.eat_keyword("autoincrement") {
+                field.autoincrement = Some(true);
+            } else if self.eat_keyword("sequence") {
+                field.sequence = Some(self.expect_ident("имя последовательности")?);
+            } else if self.eat_keyword("collate") {
+                field.collation = Some(match self.next() {
+                    Some(Token::Ident(c) | Token::Str(c)) => c,
+                    _ => return Err(self.error("ожидается алфавит после COLLATE")),
+                });
+            } else if self.eat_keyword("default") {
+                default = Some(self.parse_literal()?);
+            } else if self.eat_keyword("generated") {
+                // GENERATED ALWAYS|BY DEFAULT AS IDENTITY — счётчик; GENERATED ALWAYS AS (выражение) [STORED] — вычисляемое!
+                let identity = if self.eat_keyword("always") {
+                    "always"
+                } else {
+                    self.expect_keyword("by")?;
+                    self.expect_keyword("default")?;
+                    "by_default"
+                };
+                self.expect_keyword("as")?;
+                if self.eat_keyword("identity") {
+                    field.autoincrement = Some(true);
+                    field.identity = Some(identity.to_string());
+                } else if identity == "always" {
+                    self.expect_token(Token::LParen, "'(' перед выражением GENERATED")?;
+                    field.generated = Some(self.take_until(&[], false)?);
+                    self.expect_token(Token::RParen, "')' после выражения GENERATED")?;
+                    self.eat_keyword("stored");
+                } else {
+                    return Err(self.error("GENERATED BY DEFAULT бывает только AS IDENTITY"));
+                }
+            } else {
+                break;
+            }
+        }
+        Ok((field, default))
+    }
+
+    fn parse_type(&mut self) -> Result<String, DbError> {
+        match self.next() {
+            Some(Token::Ident(t)) => normalize_type(&t, self),
+            _ => Err(self.error("ожидается тип поля")),
+        }
+    }
+
+    // Значение по умолчанию — число, строка или true/false!
+    fn parse_literal(&mut self) -> Result<String, DbError> {
+        let negative = self.eat_op("-");
+        match self.next() {
+            Some(Token::Num(n)) => Ok(if negative { (-n).to_string() } else { n.to_string() }),
+            Some(Token::Str(s)) if !negative => Ok(s),
+            Some(Token::Ident(s)) if !negative && (s.eq_ignore_ascii_case("true") || s.eq_ignore_ascii_case("false")) => Ok(s.to_lowercase()),
+            _ => Err(self.error("ожидается число, строка или true/false после DEFAULT")),
+        }
+    }
+
+    // Вид метки — UNIQUE, FULLTEXT или обычная!
+    fn index_kind(&mut self) -> IndexKind {
+        if self.eat_keyword("unique") {
+            IndexKind::Unique
+        } else if self.eat_keyword("fulltext") {
+            IndexKind::Fulltext
+        } else {
+            IndexKind::Index
+        }
+    }
+
+    // ON сундук (поле)!
+    fn parse_index_target(&mut self) -> Result<(String, String), DbError> {
+        self.expect_keyword("on")?;
+        let table = self.expect_ident("имя сундука после ON")?;
+        self.expect_token(Token::LParen, "'(' перед полем")?;
+        let column = self.expect_ident("имя поля")?;
+        self.expect_token(Token::RParen, "')' после поля")?;
+        Ok((table, column))
+    }
+
+    // CASE [операнд] WHEN ... THEN ... [ELSE ...] END
+    fn parse_case(&mut self) -> Result<Expr, DbError> {
+        let operand = if self.peek_keyword("when") { None } else { Some(Box::new(self.parse_expr()?)) };
+        let mut whens = Vec::new();
+        while self.eat_keyword("when") {
+            let cond = self.parse_expr()?;
+            self.expect_keyword("then")?;
+            let result = self.parse_expr()?;
+            whens.push((cond, result));
+        }
+        if whens.is_empty() {
+            return Err(self.error("CASE без WHEN"));
+        }
+        let else_branch = if self.eat_keyword("else") { Some(Box::new(self.parse_expr()?)) } else { None };
+        self.expect_keyword("end")?;
+        Ok(Expr::Case(operand, whens, else_branch))
+    }
+
+    // CAST(x AS type)
+    fn parse_cast(&mut self) -> Result<Expr, DbError> {
+        if !matches!(self.next(), Some(Token::LParen)) {
+            return Err(self.error("ожидается '(' после CAST"));
+        }
+        let e = self.parse_expr()?;
+        self.expect_keyword("as")?;
+        let ty = match self.next() {
+            Some(Token::Ident(t)) => normalize_type(&t, self)?,
+            _ => return Err(self.error("ожидается тип в CAST")),
+        };
+        if !matches!(self.next(), Some(Token::RParen)) {
+            return Err(self.error("ожидается ')' после CAST"));
+        }
+        Ok(Expr::Cast(Box::new(e), ty))
+    }
+}
+
+// Приводим имя типа к нашим четырём — и парочке синонимов!
+fn normalize_type(name: &str, parser: &Parser) -> Result<String, DbError> {
+    match name.to_lowercase().as_str() {
+        "numeric" | "number" | "int" | "integer" | "float" | "real" | "double" => Ok("numeric".to_string()),
+        "text" | "string" | "varchar" => Ok("text".to_string()),
+        "timestamp" => Ok("timestamp".to_string()),
+        "boolean" | "bool" => Ok("boolean".to_string()),
+        other => Err(parser.error(&format!("неизвестный тип '{}'", other))),
+    }
+}
+
+// Разбираем выражение целиком — ни крошки не оставляем!
+pub fn parse(input: &str) -> Result<Expr, DbError> {
+    let (tokens, spans) = tokenize(input)?;
+    let mut parser = Parser { tokens, spans, pos: 0, source: input };
+    let expr = parser.parse_expr()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.error("лишние символы в конце выражения"));
+    }
+    Ok(expr)
+}
+
+// Разбираем SELECT целиком — тот же язык, что и в подзапросах!
+pub fn parse_select(input: &str) -> Result<Query, DbError> {
+    let (tokens, spans) = tokenize(input)?;
+    let mut parser = Parser { tokens, spans, pos: 0, source: input };
+    parser.expect_keyword("select")?;
+    let query = parser.parse_select()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.error("лишние символы в конце запроса"));
+    }
+    Ok(query)
+}
+
+// Разбираем команду схемы — CREATE/DROP/ALTER TABLE и CREATE/DROP INDEX! Точка с запятой в конце не мешает.
+pub fn parse_ddl(input: &str) -> Result<Ddl, DbError> {
+    let input = input.trim().trim_end_matches(';');
+    let (tokens, spans) = tokenize(input)?;
+    let mut parser = Parser { tokens, spans, pos: 0, source: input };
+    let ddl = parser.parse_ddl()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.error("лишние символы в конце команды"));
+    }
+    Ok(ddl)
+}
+
+// Проекция `выражение [AS кличка]` — возвращаем выражение и имя колонки в результате!
+pub fn parse_projection(input: &str) -> Result<(Expr, String), DbError> {
+    let trimmed = input.trim();
+    let (tokens, spans) = tokenize(trimmed)?;
+    // Ищем последний AS на верхнем уровне — за ним кличка колонки!
+    let mut depth = 0i32;
+    let mut as_pos = None;
+    for (i, t) in tokens.iter().enumerate() {
+        match t {
+            Token::LParen => depth += 1,
+            Token::RParen => depth -= 1,
+            Token::Ident(s) if depth == 0 && s.eq_ignore_ascii_case("as") => as_pos = Some(i),
+            _ => {}
+        }
+    }
+    match as_pos {
+        Some(pos) if pos + 2 == tokens.len() => {
+            let name = match &tokens[pos + 1] {
+                Token::Ident(n) | Token::Str(n) => n.clone(),
+                _ => return Err(DbError::InvalidExpression(trimmed.to_string(), "кличка после AS должна быть именем".to_string())),
+            };
+            let mut parser = Parser { tokens: tokens[..pos].to_vec(), spans: spans[..pos].to_vec(), pos: 0, source: trimmed };
+            let expr = parser.parse_expr()?;
+            if parser.pos < parser.tokens.len() {
+                return Err(parser.error("лишние символы перед AS"));
+            }
+            Ok((expr, name))
+        }
+        _ => Ok((parse(trimmed)?, trimmed.to_string())),
+    }
+}
+
+impl Expr {
+    // Простое поле? Тогда можно идти старой тропой — через индексы и без расчётов!
+    pub fn as_column(&self) -> Option<(Option<&str>, &str)> {
+        match self {
+            Expr::Column(alias, field) => Some((alias.as_deref(), field.as_str())),
+            _ => None,
+        }
+    }
+
+    // Обходим дерево — каждый узел показываем гостю! В подзапросы не ныряем — это чужой трюм.
+    pub fn visit(&self, f: &mut dyn FnMut(&Expr)) {
+        f(self);
+        match self {
+            Expr::Unary(_, e) | Expr::Cast(e, _) | Expr::IsNull(e, _) | Expr::InSubquery(e, _) => e.visit(f),
+            Expr::Binary(_, l, r) => { l.visit(f); r.visit(f); }
+            Expr::Func(_, args) => args.iter().for_each(|a| a.visit(f)),
+            Expr::InList(e, items) => { e.visit(f); items.iter().for_each(|a| a.visit(f)); }
+            Expr::Case(operand, whens, else_branch) => {
+                if let Some(o) = operand { o.visit(f); }
+                for (c, r) in whens { c.visit(f); r.visit(f); }
+                if let Some(e) = else_branch { e.visit(f); }
+            }
+            Expr::Literal(_) | Expr::Null | Expr::Column(..) | Expr::Exists(_) | Expr::Subquery(_) => {}
+        }
+    }
+
+    // Перестраиваем дерево снизу вверх — например, заменяем подзапросы готовыми ответами!
+    pub fn rewrite(self, f: &mut dyn FnMut(Expr) -> Result<Expr, DbError>) -> Result<Expr, DbError> {
+        let rebuilt = match self {
+            Expr::Unary(op, e) => Expr::Unary(op, Box::new(e.rewrite(f)?)),
+            Expr::Cast(e, ty) => Expr::Cast(Box::new(e.rewrite(f)?), ty),
+            Expr::IsNull(e, negated) => Expr::IsNull(Box::new(e.rewrite(f)?), negated),
+            Expr::InSubquery(e, q) => Expr::InSubquery(Box::new(e.rewrite(f)?), q),
+            Expr::Binary(op, l, r) => Expr::Binary(op, Box::new(l.rewrite(f)?), Box::new(r.rewrite(f)?)),
+            Expr::Func(name, args) => Expr::Func(name, args.into_iter().map(|a| a.rewrite(f)).collect::<Result<_, _>>()?),
+            Expr::InList(e, items) => Expr::InList(Box::new(e.rewrite(f)?), items.into_iter().map(|a| a.rewrite(f)).collect::<Result<_, _>>()?),
+            Expr::Case(operand, whens, else_branch) => Expr::Case(
+                match operand { Some(o) => Some(Box::new(o.rewrite(f)?)), None => None },
+                whens.into_iter().map(|(c, r)| Ok((c.rewrite(f)?, r.rewrite(f)?))).collect::<Result<_, DbError>>()?,
+                match else_branch { Some(e) => Some(Box::new(e.rewrite(f)?)), None => None },
+            ),
+            leaf => leaf,
+        };
+        f(rebuilt)
+    }
+
+    // Считаем выражение — None значит NULL!
+    pub fn eval(&self, b: &dyn Bindings) -> Result<Option<Value>, DbError> {
+        match self {
+            Expr::Literal(v) => Ok(Some(v.clone())),
+            Expr::Null => Ok(None),
+            Expr::Column(alias, field) => Ok(b.resolve(alias.as_deref(), field)),
+            Expr::Unary(op, e) => {
+                let v = e.eval(b)?;
+                Ok(match (op, v) {
+                    (_, None) => None,
+                    (UnaryOp::Neg, Some(Value::Numeric(n))) => Some(Value::Numeric(-n)),
+                    (UnaryOp::Neg, Some(Value::Timestamp(t))) => Some(Value::Timestamp(t.checked_neg().ok_or_else(|| time_overflow(BinOp::Sub))?)),
+                    (UnaryOp::Not, Some(v)) => Some(Value::Boolean(!truthy(&v))),
+                    (UnaryOp::Neg, Some(v)) => return Err(type_error("-", &v)),
+                })
+            }
+            Expr::Binary(BinOp::And, l, r) => {
+                // Трёхзначная логика — ложь побеждает NULL!
+                let lv = l.eval(b)?.map(|v| truthy(&v));
+                if lv == Some(false) { return Ok(Some(Value::Boolean(false))); }
+                let rv = r.eval(b)?.map(|v| truthy(&v));
+                Ok(match (lv, rv) {
+                    (_, Some(false)) => Some(Value::Boolean(false)),
+                    (Some(true), Some(true)) => Some(Value::Boolean(true)),
+                    _ => None,
+                })
+            }
+            Expr::Binary(BinOp::Or, l, r) => {
+                let lv = l.eval(b)?.map(|v| truthy(&v));
+                if lv == Some(true) { return Ok(Some(Value::Boolean(true))); }
+                let rv = r.eval(b)?.map(|v| truthy(&v));
+                Ok(match (lv, rv) {
+                    (_, Some(true)) => Some(Value::Boolean(true)),
+                    (Some(false), Some(false)) => Some(Value::Boolean(false)),
+                    _ => None,
+                })
+            }
+            Expr::Binary(op, l, r) => {
+                let (lv, rv) = match (l.eval(b)?, r.eval(b)?) {
+                    (Some(lv), Some(rv)) => (lv, rv),
+                    _ => return Ok(None), // NULL заражает всё вокруг!
+                };
+                binary(*op, lv, rv)
+            }
+            Expr::Func(name, args) => {
+                let values = args.iter().map(|a| a.eval(b)).collect::<Result<Vec<_>, _>>()?;
+                call(name, values)
+            }
+            Expr::Case(operand, whens, else_branch) => {
+                let base = match operand { Some(o) => Some(o.eval(b)?), None => None };
+                for (cond, result) in whens {
+                    let hit = match &base {
+                        Some(Some(bv)) => cond.eval(b)?.is_some_and(|cv| compare_values(bv, &cv) == Some(Ordering::Equal)),
+                        Some(None) => false,
+                        None => cond.eval(b)?.is_some_and(|cv| truthy(&cv)),
+                    };
+                    if hit { return result.eval(b); }
+                }
+                match else_branch { Some(e) => e.eval(b), None => Ok(None) }
+            }
+            Expr::Cast(e, ty) => match e.eval(b)? {
+                Some(v) => cast(v, ty).map(Some),
+                None => Ok(None),
+            },
+            Expr::IsNull(e, negated) => Ok(Some(Value::Boolean(e.eval(b)?.is_none() != *negated))),
+            Expr::InList(e, items) => {
+                let candidates = items.iter().map(|i| i.eval(b)).collect::<Result<Vec<_>, _>>()?;
+                Ok(membership(e.eval(b)?, candidates.iter()))
+            }
+            Expr::InSubquery(e, q) => {
+                let rows = b.subquery(q)?;
+                Ok(membership(e.eval(b)?, rows.iter().map(|r| r.first().cloned().flatten()).collect::<Vec<_>>().iter()))
+            }
+            Expr::Exists(q) => Ok(Some(Value::Boolean(!b.subquery(q)?.is_empty()))),
+            Expr::Subquery(q) => {
+                let rows = b.subquery(q)?;
+                if rows.len() > 1 {
+                    return Err(DbError::InvalidExpression(format!("(SELECT ... FROM {})", q.table), "скалярный подзапрос вернул больше одной строки".to_string()));
+                }
+                Ok(rows.into_iter().next().and_then(|r| r.into_iter().next().flatten()))
+            }
+        }
+    }
+
+    // Условие — истина или нет; NULL считаем за ложь, как в SQL!
+    pub fn matches(&self, b: &dyn Bindings) -> Result<bool, DbError> {
+        Ok(self.eval(b)?.is_some_and(|v| truthy(&v)))
+    }
+}
+
+// Есть ли значение в списке? Как в SQL: не нашли, но был NULL — ответ NULL!
+fn membership<'a>(needle: Option<Value>, candidates: impl Iterator<Item = &'a Option<Value>>) -> Option<Value> {
+    let needle = needle?;
+    let mut saw_null = false;
+    for candidate in candidates {
+        match candidate {
+            Some(c) if compare_values(&needle, c) == Some(Ordering::Equal) => return Some(Value::Boolean(true)),
+            Some(_) => {}
+            None => saw_null = true,
+        }
+    }
+    if saw_null { None } else { Some(Value::Boolean(false)) }
+}
+
+// Правда ли? Ноль, пустая строка и false — ложь!
+fn truthy(v: &Value) -> bool {
+    match v {
+        Value::Boolean(b) => *b,
+        Value::Numeric(n) => *n != 0.0,
+        Value::Timestamp(t) => *t != 0,
+        Value::Text(s) => !s.is_empty() && s != "false",
+    }
+}
+
+fn type_error(op: &str, v: &Value) -> DbError {
+    DbError::InvalidValue(op.to_string(), v.to_string())
+}
+
+// Сдвиг в секундах — только то, что влезает в i64: `as` молча срезал бы 1e19 до края!
+fn seconds(n: f64) -> Option<i64> {
+    (n.is_finite() && n >= i64::MIN as f64 && n < i64::MAX as f64).then_some(n as i64)
+}
+
+// Время вылетело за пределы i64 — сдвиг, разница или минус у крайней метки!
+fn time_overflow(op: BinOp) -> DbError {
+    DbError::InvalidValue(op_symbol(op).to_string(), "метка времени за пределами i64".to_string())
+}
+
+// Числовое лицо значения — если есть!
+fn as_number(v: &Value) -> Option<f64> {
+    match v {
+        Value::Numeric(n) => Some(*n),
+        Value::Timestamp(t) => Some(*t as f64),
+        Value::Text(s) => s.trim().parse::<f64>().ok(),
+        Value::Boolean(_) => None,
+    }
+}
+
+// Сравниваем два сокровища — числа как числа, время как время, остальное как текст!
+pub fn compare_values(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Numeric(x), Value::Numeric(y)) => x.partial_cmp(y),
+        (Value::Timestamp(x), Value::Timestamp(y)) => Some(x.cmp(y)),
+        (Value::Boolean(x), Value::Boolean(y)) => Some(x.cmp(y)),
+        (Value::Text(x), Value::Text(y)) => Some(x.cmp(y)),
+        (Value::Numeric(_) | Value::Timestamp(_), _) | (_, Value::Numeric(_) | Value::Timestamp(_)) => {
+            match (as_number(a), as_number(b)) {
+                (Some(x), Some(y)) => x.partial_cmp(&y),
+                _ => Some(a.to_string().cmp(&b.to_string())),
+            }
+        }
+        _ => Some(a.to_string().cmp(&b.to_string())),
+    }
+}
+
+// Двуручные операции — арифметика, склейка и сравнения!
+fn binary(op: BinOp, lv: Value, rv: Value) -> Result<Option<Value>, DbError> {
+    let cmp = |pred: fn(Ordering) -> bool| Ok(compare_values(&lv, &rv).map(|o| Value::Boolean(pred(o))));
+    match op {
+        BinOp::Eq => cmp(|o| o == Ordering::Equal),
+        BinOp::NotEq => cmp(|o| o != Ordering::Equal),
+        BinOp::Lt => cmp(|o| o == Ordering::Less),
+        BinOp::LtEq => cmp(|o| o != Ordering::Greater),
+        BinOp::Gt => cmp(|o| o == Ordering::Greater),
+        BinOp::GtEq => cmp(|o| o != Ordering::Less),
+        BinOp::Concat => Ok(Some(Value::Text(format!("{}{}", lv, rv)))),
+        BinOp::And | BinOp::Or => unreachable!("логика считается отдельно"),
+        _ => {
+            // Время ± число — сдвиг по секундам, время − время — разница в секундах!
+            match (&lv, &rv, op) {
+                // Сдвиг за край i64 — ошибка, а не паника: `now() + 1e19` пишут и всерьёз!
+                (Value::Timestamp(t), Value::Numeric(n), BinOp::Add) | (Value::Numeric(n), Value::Timestamp(t), BinOp::Add) => {
+                    return seconds(*n).and_then(|n| t.checked_add(n)).map(|t| Some(Value::Timestamp(t))).ok_or_else(|| time_overflow(op));
+                }
+                (Value::Timestamp(t), Value::Numeric(n), BinOp::Sub) => {
+                    return seconds(*n).and_then(|n| t.checked_sub(n)).map(|t| Some(Value::Timestamp(t))).ok_or_else(|| time_overflow(op));
+                }
+                (Value::Timestamp(a), Value::Timestamp(b), BinOp::Sub) => {
+                    return a.checked_sub(*b).map(|d| Some(Value::Numeric(d as f64))).ok_or_else(|| time_overflow(op));
+                }
+                _ => {}
+            }
+            let x = as_number(&lv).ok_or_else(|| type_error(op_symbol(op), &lv))?;
+            let y = as_number(&rv).ok_or_else(|| type_error(op_symbol(op), &rv))?;
+            Ok(match op {
+                BinOp::Add => Some(Value::Numeric(x + y)),
+                BinOp::Sub => Some(Value::Numeric(x - y)),
+                BinOp::Mul => Some(Value::Numeric(x * y)),
+                BinOp::Div if y == 0.0 => None, // Делить на ноль — только NULL в ответ!
+                BinOp::Div => Some(Value::Numeric(x / y)),
+                BinOp::Mod if y == 0.0 => None,
+                BinOp::Mod => Some(Value::Numeric(x % y)),
+                _ => None,
+            })
+        }
+    }
+}
+
+fn op_symbol(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+", BinOp::Sub => "-", BinOp::Mul => "*", BinOp::Div => "/", BinOp::Mod => "%",
+        _ => "?",
+    }
+}
+
+// Перековка типа — text в numeric и обратно!
+pub fn cast(v: Value, ty: &str) -> Result<Value, DbError> {
+    let fail = |v: &Value| DbError::InvalidValue(ty.to_string(), v.to_string());
+    Ok(match ty {
+        "numeric" => match &v {
+            Value::Boolean(b) => Value::Numeric(if *b { 1.0 } else { 0.0 }),
+            _ => Value::Numeric(as_number(&v).ok_or_else(|| fail(&v))?),
+        },
+        "timestamp" => match &v {
+            Value::Timestamp(_) => v,
+            _ => Value::Timestamp(as_number(&v).ok_or_else(|| fail(&v))? as i64),
+        },
+        "boolean" => match &v {
+            Value::Boolean(_) => v,
+            Value::Text(s) => Value::Boolean(s.trim().parse::<bool>().map_err(|_| fail(&v))?),
+            _ => Value::Boolean(truthy(&v)),
+        },
+        _ => Value::Text(v.to_string()),
+    })
+}
+
+// Дни с эпохи в гражданскую дату — алгоритм Говарда Хиннанта, без внешних крейтов!
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (yoe + era * 400 + if m <= 2 { 1 } else { 0 }, m, d)
+}
+
+// Метка времени в (год, месяц, день, час, минута, секунда) — по UTC!
+fn split_timestamp(ts: i64) -> (i64, u32, u32, i64, i64, i64) {
+    let days = ts.div_euclid(86_400);
+    let secs = ts.rem_euclid(86_400);
+    let (y, m, d) = civil_from_days(days);
+    (y, m, d, secs / 3600, (secs % 3600) / 60, secs % 60)
+}
+
+fn arg_number(name: &str, v: Option<&Value>) -> Result<Option<f64>, DbError> {
+    match v {
+        None => Ok(None),
+        Some(v) => as_number(v).map(Some).ok_or_else(|| type_error(name, v)),
+    }
+}
+
+fn arg_timestamp(name: &str, v: Option<&Value>) -> Result<Option<i64>, DbError> {
+    match v {
+        None => Ok(None),
+        Some(Value::Timestamp(t)) => Ok(Some(*t)),
+        Some(v) => as_number(v).map(|n| Some(n as i64)).ok_or_else(|| type_error(name, v)),
+    }
+}
+
+// Вызываем функцию — весь арсенал на палубе!
+fn call(name: &str, args: Vec<Option<Value>>) -> Result<Option<Value>, DbError> {
+    let arity = |n: usize| -> Result<(), DbError> {
+        if args.len() == n { Ok(()) } else {
+            Err(DbError::InvalidValue(name.to_string(), format!("ожидается аргументов: {}, получено: {}", n, args.len())))
+        }
+    };
+    let first = args.first().and_then(|a| a.as_ref());
+    Ok(match name {
+        "coalesce" => args.into_iter().flatten().next(),
+        "nullif" => {
+            arity(2)?;
+            match (&args[0], &args[1]) {
+                (Some(a), Some(b)) if compare_values(a, b) == Some(Ordering::Equal) => None,
+                _ => args[0].clone(),
+            }
+        }
+        "concat" => Some(Value::Text(args.iter().flatten().map(|v| v.to_string()).collect())),
+        "lower" => { arity(1)?; first.map(|v| Value::Text(v.to_string().to_lowercase())) }
+        "upper" => { arity(1)?; first.map(|v| Value::Text(v.to_string().to_uppercase())) }
+        "trim" => { arity(1)?; first.map(|v| Value::Text(v.to_string().trim().to_string())) }
+        "length" => { arity(1)?; first.map(|v| Value::Numeric(v.to_string().chars().count() as f64)) }
+        "substr" | "substring" => {
+            if args.len() != 2 && args.len() != 3 { arity(3)?; }
+            let (Some(s), Some(start)) = (first, arg_number(name, args[1].as_ref())?) else { return Ok(None) };
+            // Как в SQL — позиции с единицы, по символам, а не байтам!
+            let chars: Vec<char> = s.to_string().chars().collect();
+            let start = (start as i64).saturating_sub(1).max(0) as usize;
+            let len = match args.get(2) {
+                Some(a) => match arg_number(name, a.as_ref())? { Some(l) => l.max(0.0) as usize, None => return Ok(None) },
+                None => usize::MAX,
+            };
+            Some(Value::Text(chars.iter().skip(start).take(len).collect()))
+        }
+        "abs" => { arity(1)?; arg_number(name, first)?.map(|n| Value::Numeric(n.abs())) }
+        "round" => {
+            let n = arg_number(name, first)?;
+            let digits = match args.get(1) { Some(a) => arg_number(name, a.as_ref())?.unwrap_or(0.0), None => 0.0 };
+            n.map(|n| { let p = 10f64.powi(digits as i32); Value::Numeric((n * p).round() / p) })
+        }
+        "floor" => { arity(1)?; arg_number(name, first)?.map(|n| Value::Numeric(n.floor())) }
+        "ceil" => { arity(1)?; arg_number(name, first)?.map(|n| Value::Numeric(n.ceil())) }
+        "now" => {
+            arity(0)?;
+            Some(Value::Timestamp(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64))
+        }
+        "year" | "month" | "day" | "hour" | "minute" | "second" => {
+            arity(1)?;
+            arg_timestamp(name, first)?.map(|ts| {
+                let (y, mo, d, h, mi, s) = split_timestamp(ts);
+                Value::Numeric(match name {
+                    "year" => y as f64,
+                    "month" => mo as f64,
+                    "day" => d as f64,
+                    "hour" => h as f64,
+                    "minute" => mi as f64,
+                    _ => s as f64,
+                })
+            })
+        }
+        "date" => {
+            arity(1)?;
+            arg_timestamp(name, first)?.map(|ts| {
+                let (y, m, d, _, _, _) = split_timestamp(ts);
+                Value::Text(format!("{:04}-{:02}-{:02}", y, m, d))
+            })
+        }
+        "date_trunc" => {
+            // date_trunc('day', ts) — обрезаем время до нужной точности!
+            arity(2)?;
+            let unit = first.map(|v| v.to_string().to_lowercase());
+            let ts = arg_timestamp(name, args[1].as_ref())?;
+            match (unit.as_deref(), ts) {
+                (Some(unit), Some(ts)) => {
+                    let step = match unit {
+                        "minute" => 60,
+                        "hour" => 3600,
+                        "day" => 86_400,
+                        _ => return Err(DbError::InvalidValue(name.to_string(), unit.to_string())),
+                    };
+                    let start = ts.div_euclid(step).checked_mul(step)
+                        .ok_or_else(|| DbError::InvalidValue(name.to_string(), "метка времени за пределами i64".to_string()))?;
+                    Some(Value::Timestamp(start))
+                }
+                _ => None,
+            }
+        }
+        _ => return Err(DbError::InvalidValue(name.to_string(), "неизвестная функция".to_string())),
+    })
+}
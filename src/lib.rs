@@ -1,26 +1,73 @@
-use std::collections::HashMap; // Ключи и значения — пиратская карта добычи!
+use std::collections::{BTreeSet, HashMap}; // Ключи и значения — пиратская карта добычи!
 use std::sync::Arc; // Делимся сокровищами между потоками — надёжно!
+use std::sync::atomic::{AtomicU64, Ordering}; // Счётчик номеров — без замков и без гонок!
 use std::hash::BuildHasherDefault; // Хэш-функция — замок с хитрым ключом!
 use ahash::AHasher; // Быстрый хэшер — как молния в ночи!
 use dashmap::DashMap; // Турбо-карта — быстрая, многопоточная, без багов!
+use dashmap::mapref::entry::Entry; // Место в карте — занято или свободно?
 use serde::{Serialize, Deserialize}; // Магия превращения данных в байты и обратно!
-use toml; // Парсер TOML — читаем пиратские карты!
 use tokio::fs::{File, create_dir_all, OpenOptions}; // Асинхронная работа с сундуками на диске!
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter}; // Читаем и пишем байты — шустро!
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter}; // Читаем и пишем байты — шустро!
 use tokio::sync::{RwLock, Mutex}; // Замок для сокровищ — один пишет, другие ждут!
-use tokio::time::{sleep, Duration, interval}; // Таймеры — ждём момент для атаки!
+use tokio::time::{Duration, interval}; // Таймеры — ждём момент для атаки!
 use std::path::Path; // Путь к сокровищам — карта в руках!
-use bincode; // Сериализация — превращаем добычу в байты!
-use std::time::{SystemTime, UNIX_EPOCH}; // Часы капитана — метки времени для шторма!
+use std::time::{Instant, SystemTime, UNIX_EPOCH}; // Часы капитана — метки времени для шторма!
 use thiserror::Error; // Новый помощник для ошибок — штормы под контролем!
+use std::fmt; // Красиво печатаем добычу!
+
+mod expr; // Выражения — штурманские расчёты в запросах!
+mod sort; // Сортировка — порядок по нескольким ключам!
+mod mvcc; // Многоверсионность — снимки для читателей!
+mod sequence; // Последовательности — номера без повторов!
+mod bulk; // Массовая загрузка — миллион строк за один заход!
+mod transfer; // CSV и JSON Lines — добыча в чужие порты и обратно!
+mod dump; // Логический дамп и восстановление — вся база одним файлом!
+mod wal; // Журнал на диске — записи с LSN, сегменты и точки сохранения!
+mod backup; // Горячая копия и восстановление на момент времени!
+mod snapshot; // Формат сундуков на диске — версии, словарь полей, сжатие!
+mod dirty; // Грязные страницы — на диск только то, что менялось!
+mod crypto; // Шифрование журнала и сундуков на диске!
+mod migrate; // Миграции схемы — правим поля вместе с данными!
+mod ddl; // CREATE/DROP/ALTER — схема через API и SQL!
+mod catalog; // Каталог — схема с версией в папке данных, карта ему только подсказывает!
+mod reload; // Перечитка карты — по событиям файловой системы и через API!
+mod system; // Служебные сундуки — схема, метки и счётчики для обычного SELECT!
+mod metrics; // Метрики — счётчики, гистограммы и /metrics для Prometheus!
+#[cfg(feature = "columnar")]
+mod columnar; // Arrow IPC и Parquet — колонки для аналитиков!
+pub use expr::{Expr, BinOp, UnaryOp, Bindings, parse_select, parse_ddl};
+pub use sort::{SortKey, Collation};
+pub use sequence::SequenceOptions;
+pub use bulk::BulkLoadReport;
+pub use transfer::Format;
+pub use backup::RecoveryTarget;
+pub use migrate::{Migration, IndexKind, MigrationReport, MIGRATIONS_TABLE};
+pub use ddl::Ddl;
+pub use reload::ReloadReport;
+pub use system::SYSTEM_TABLES;
+pub use metrics::{MetricsReport, MetricsServer, QueryMetrics, TableMetrics, HistogramSnapshot};
+use mvcc::{Mvcc, ReadPin};
+use dirty::DirtyPages;
+use catalog::Catalog;
+use metrics::Metrics;
+use crypto::{EncryptionConfig, Keyring};
+use sequence::{Sequence, SequenceState};
 
 type Hasher = BuildHasherDefault<AHasher>; // Хэшер — наш верный помощник!
+pub type RowId = u64; // Номер строки — 64 бита, не переполнится и не повторится!
+type TableData = Arc<DashMap<RowId, Row, Hasher>>; // Трюм — строки по ID!
+type FieldIndex = Arc<DashMap<String, Vec<RowId>, Hasher>>; // Метка поля — значение в список ID!
+type TableIndexes = Arc<DashMap<String, Arc<DashMap<String, FieldIndex, Hasher>>, Hasher>>; // Все метки по сундукам и полям!
 
 // Ошибки — штормы и рифы, что топят корабль!
 #[derive(Debug, Error)]
 pub enum DbError {
     #[error("Йо-хо-хо, сундук с именем '{0}' на карте не значится!")]
     TableNotFound(String),
+    #[error("Сундук '{0}' уже есть на карте — второй такой не заведёшь!")]
+    TableExists(String),
+    #[error("Сундук '{0}' служебный — его только читают!")]
+    ReadOnlyTable(String),
     #[error("Кракен заметил дубликат! Поле '{0}' уже хранит '{1}'.")]
     DuplicateValue(String, String),
     #[error("Арр! '{1}' в поле '{0}' — это не добыча, а мусор с палубы!")]
@@ -31,6 +78,18 @@ pub enum DbError {
     SerializationError(#[from] bincode::Error),
     #[error("Карта сокровищ порвана! Ошибка в конфиге: {0}")]
     ConfigError(#[from] toml::de::Error),
+    #[error("Штурман запутался в расчётах! Выражение '{0}': {1}")]
+    InvalidExpression(String, String),
+    #[error("Абордаж отбит! В сундуке '{0}' строку уже поменяли: {1}")]
+    Conflict(String, String),
+    #[error("Счётчик '{0}' в судовом журнале не значится!")]
+    SequenceNotFound(String),
+    #[error("Счётчик '{0}' дошёл до края — номера кончились!")]
+    SequenceExhausted(String),
+    #[error("Кривой груз в файле: {0}")]
+    FormatError(String),
+    #[error("Чужой ключ! {0} — проверьте ключ шифрования на карте")]
+    WrongKey(String),
     #[error("Чёртова буря! Что-то пошло не так: {0}")]
     Generic(String),
 }
@@ -44,59 +103,143 @@ pub enum Value {
     Boolean(bool),  // Да/Нет — есть ли ром в трюме?
 }
 
-impl Value {
-    // Превращаем сокровище в строку — для карты или вывода!
-    fn to_string(&self) -> String {
+// Превращаем сокровище в строку — для карты или вывода!
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Value::Numeric(n) => n.to_string(),
-            Value::Text(s) => s.clone(),
-            Value::Timestamp(t) => t.to_string(),
-            Value::Boolean(b) => b.to_string(),
+            Value::Numeric(n) => write!(f, "{}", n),
+            Value::Text(s) => write!(f, "{}", s),
+            Value::Timestamp(t) => write!(f, "{}", t),
+            Value::Boolean(b) => write!(f, "{}", b),
         }
     }
 }
 
-// Конфиг базы — наш план сокровищ!
-#[derive(Debug, Serialize, Deserialize, Clone)]
+// Конфиг базы — наш план сокровищ! Пустой по умолчанию — если всё сломалось, начнём заново!
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct DbConfig {
-    tables: Vec<TableConfig>, // Таблицы — сундуки с добычей!
+    wal_archive: Option<String>, // Папка архива журнала — отработанные сегменты не стираем, а копим для восстановления на момент!
+    snapshot_compression: Option<String>, // Сжатие сундуков на диске — "none", "lz4" или "zstd"!
+    snapshot_compact_after: Option<usize>, // Сколько дельт копить до полной перезаписи сундука (по умолчанию 8)!
+    encryption: Option<EncryptionConfig>, // Шифр и ключи — журнал и сундуки на диске под замком!
+    #[serde(default)]
+    tables: Vec<TableConfig>, // Таблицы — сундуки с добычей! Пустая карта — тоже карта, сундуки заведёт `create_table`.
 }
 
-// Пустой конфиг — если всё сломалось, начнём заново!
-impl Default for DbConfig {
-    fn default() -> Self {
-        Self { tables: Vec::new() } // Пустой трюм — начинаем с нуля!
+// Описание сундука — что внутри?
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TableConfig {
+    pub name: String,              // Имя сундука — коротко и ясно!
+    pub fields: Vec<FieldConfig>, // Что прячем внутри?
+}
+
+// Поля — что за клад и как его искать!
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct FieldConfig {
+    pub name: String,           // Название клада!
+    pub field_type: String,     // Тип: "numeric", "text", "timestamp", "boolean" — что за добро?
+    pub indexed: Option<bool>,  // Индекс — шустрый поиск!
+    pub fulltext: Option<bool>, // Полнотекст — ищем по словам!
+    pub unique: Option<bool>,   // Уникальность — только один такой!
+    pub autoincrement: Option<bool>, // Авто-ID — для новых пиратов, из последовательности `{сундук}_{поле}_seq`!
+    pub sequence: Option<String>, // Своя последовательность для авто-ID — по имени, тоже включает автоинкремент!
+    pub identity: Option<String>, // "by_default" — можно задать руками, "always" — только счётчик!
+    pub generated: Option<String>, // Генерируемое поле — выражение, считаем сами при каждой записи!
+    pub collation: Option<String>, // Алфавит для сортировки: "binary", "nocase", "ru" — ё на своём месте!
+}
+
+impl FieldConfig {
+    // Простое поле — имя и тип, остальное по умолчанию!
+    pub fn new(name: &str, field_type: &str) -> Self {
+        FieldConfig { name: name.to_string(), field_type: field_type.to_string(), ..Default::default() }
     }
 }
 
-// Описание сундука — что внутри?
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct TableConfig {
-    name: String,              // Имя сундука — коротко и ясно!
-    fields: Vec<FieldConfig>, // Что прячем внутри?
+// Типизируем добычу по карте поля — золото, ром или карты?
+fn typed_value(field_type: &str, key: &str, value: &str) -> Result<Value, DbError> {
+    Ok(match field_type {
+        "numeric" => Value::Numeric(value.parse::<f64>()
+            .map_err(|_| DbError::InvalidValue(key.to_string(), value.to_string()))?),
+        "timestamp" => Value::Timestamp(value.parse::<i64>()
+            .map_err(|_| DbError::InvalidValue(key.to_string(), value.to_string()))?),
+        "boolean" => Value::Boolean(value.parse::<bool>()
+            .map_err(|_| DbError::InvalidValue(key.to_string(), value.to_string()))?),
+        _ => Value::Text(value.to_string()),
+    })
 }
 
-// Поля — что за клад и как его искать!
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct FieldConfig {
-    name: String,           // Название клада!
-    field_type: String,     // Тип: "numeric", "text", "timestamp", "boolean" — что за добро?
-    indexed: Option<bool>,  // Индекс — шустрый поиск!
-    fulltext: Option<bool>, // Полнотекст — ищем по словам!
-    unique: Option<bool>,   // Уникальность — только один такой!
-    autoincrement: Option<bool>, // Авто-ID — для новых пиратов!
+impl TableConfig {
+    // Ищем поле на карте сундука!
+    fn field(&self, name: &str) -> Option<&FieldConfig> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    // Пересчитываем генерируемые поля — по порядку из карты, одно может опираться на другое!
+    fn apply_generated(&self, data: &mut HashMap<String, Value>) -> Result<(), DbError> {
+        apply_generated_exprs(&self.generated_exprs()?, data)
+    }
+
+    // Разобранные выражения генерируемых полей — для пачки строк разбираем один раз!
+    fn generated_exprs(&self) -> Result<Vec<(FieldConfig, Expr)>, DbError> {
+        self.fields.iter()
+            .filter_map(|field| field.generated.as_ref().map(|source| Ok((field.clone(), expr::parse(source)?))))
+            .collect()
+    }
+}
+
+// Считаем генерируемые поля по готовым выражениям!
+fn apply_generated_exprs(exprs: &[(FieldConfig, Expr)], data: &mut HashMap<String, Value>) -> Result<(), DbError> {
+    for (field, expr) in exprs {
+        match expr.eval(&*data)? {
+            Some(v) => { data.insert(field.name.clone(), expr::cast(v, &field.field_type)?); }
+            None => { data.remove(&field.name); } // NULL — поле пустует!
+        }
+    }
+    Ok(())
+}
+
+// Номер поля с `identity = "always"` руками не задают — его выдаёт только последовательность!
+fn identity_always(key: &str) -> DbError {
+    DbError::InvalidValue(key.to_string(), "номер выдаёт только последовательность (identity = \"always\")".to_string())
+}
+
+// Генерируемое поле руками не трогаем — его считает сам корабль!
+fn reject_generated(field_config: Option<&FieldConfig>, key: &str) -> Result<(), DbError> {
+    if field_config.is_some_and(|f| f.generated.is_some()) {
+        return Err(DbError::InvalidValue(key.to_string(), "генерируемое поле нельзя задавать вручную".to_string()));
+    }
+    Ok(())
 }
 
 // Строка — кусочек добычи с ID, типами и теперь с датой "до свидания"!
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Row {
-    pub id: i32,                    // ID — номер пирата в команде!
+    pub id: RowId,                  // ID — номер пирата в команде, выдаётся один раз и навсегда!
     pub data: HashMap<String, Value>, // Данные — сундук с разным добром!
     pub expires_at: Option<i64>,    // Время "до шторма" — когда выбросить за борт (сек с эпохи UNIX)!
+    pub version: u64,               // Версия — растёт с каждой правкой, для compare-and-set!
+    #[serde(skip)]
+    pub(crate) commit_ts: u64,      // Метка коммита этой версии — на диск не идёт, после загрузки все видны!
+}
+
+// Метка "всё, что есть в трюме прямо сейчас" — для записей и восстановления!
+const LATEST: u64 = u64::MAX;
+
+// Служебная колонка с версией строки — в `*` не входит, берём по имени!
+pub const VERSION_FIELD: &str = "_version";
+
+impl Row {
+    // Достаём клад по имени — для выражений и вывода! `_version` — версия строки.
+    pub fn field(&self, name: &str) -> Option<Value> {
+        match self.data.get(name) {
+            None if name == VERSION_FIELD => Some(Value::Numeric(self.version as f64)),
+            value => value.cloned(),
+        }
+    }
 }
 
 // Условия — как выцепить нужный клад!
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Condition {
     Eq(String, String),         // Равно — точный удар!
     Lt(String, String),         // Меньше — мелочь в сторону!
@@ -104,6 +247,24 @@ pub enum Condition {
     Contains(String, String),   // Содержит — ищем тайники!
     In(String, Vec<String>),    // В списке — по шпаргалке!
     Between(String, String, String), // Между — диапазон на глаз!
+    Expr(String),               // Выражение — `speed * 100 > 50`, считаем для каждой строки!
+    InSubquery(String, Box<Query>), // В подзапросе — шпаргалка из другого сундука!
+    Exists(Box<Query>),         // Есть хоть одна строка в подзапросе?
+    NotExists(Box<Query>),      // Ни одной строки в подзапросе!
+}
+
+// ON CONFLICT — что делать, если уникальное поле уже занято!
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OnConflict {
+    pub target: Option<String>,   // Уникальное поле столкновения; None — любое уникальное!
+    pub action: ConflictAction,   // Обновить старую строку или молча пройти мимо!
+}
+
+// Действие при столкновении — как в Postgres!
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ConflictAction {
+    Nothing,                         // DO NOTHING — старая строка остаётся как была!
+    Update(HashMap<String, String>), // DO UPDATE SET — правим старую строку этими значениями!
 }
 
 // Write-Ahead Logging (WAL) — журнал операций для целостности данных!
@@ -122,24 +283,97 @@ enum WalOperation {
         table: String,                    // Имя сундука — откуда убираем!
         where_clauses: Vec<Vec<Condition>>, // Условия — что выкидываем!
     },
+    Upsert {
+        table: String,                    // Имя сундука — куда грузим!
+        values: Vec<HashMap<String, String>>, // Добыча — что кладём!
+        on_conflict: OnConflict,          // Что делать при столкновении!
+    },
+    UpdateExpr {
+        table: String,                    // Имя сундука — где правим!
+        values: HashMap<String, String>,  // Готовые значения!
+        set_exprs: Vec<(String, String)>, // Выражения — `score + 10`!
+        where_clauses: Vec<Vec<Condition>>, // Условия — что трогаем!
+    },
+    UpdateIfVersion {
+        table: String,                    // Имя сундука — где правим!
+        values: HashMap<String, String>,  // Готовые значения!
+        set_exprs: Vec<(String, String)>, // Выражения!
+        where_clauses: Vec<Vec<Condition>>, // Условия — что трогаем!
+        version: u64,                     // Какую версию ждём!
+    },
+    DeleteIfVersion {
+        table: String,                    // Имя сундука — откуда убираем!
+        where_clauses: Vec<Vec<Condition>>, // Условия — что выкидываем!
+        version: u64,                     // Какую версию ждём!
+    },
+    Sequence {
+        sequence: Sequence,               // Последовательность целиком — создание, бронь или setval!
+    },
+    DropSequence {
+        name: String,                     // Какую последовательность выкидываем!
+    },
+    BulkLoad {
+        table: String,                    // Имя сундука — куда грузим!
+        rows: Vec<Row>,                   // Готовые строки с номерами — повтор просто кладёт их на место!
+    },
+    Migrate {
+        id: RowId,                        // Номер в сундуке истории миграций!
+        applied_at: i64,                  // Когда применили (сек с эпохи UNIX)!
+        migration: migrate::Migration,    // Сама правка схемы — повтор пропускает уже сделанное!
+        before: TableConfig,              // Карта сундука до правки — записи перед миграцией повторяем по ней!
+    },
+    CreateTable {
+        table: TableConfig,               // Карта нового сундука целиком!
+    },
+    DropTable {
+        table: TableConfig,               // Карта выброшенного сундука — записи до него повторяем по ней!
+    },
 }
 
 // База — наш корабль с сокровищами!
 #[derive(Clone)]
 pub struct Database {
-    pub tables: Arc<DashMap<String, Arc<DashMap<i32, Row, Hasher>>, Hasher>>, // Таблицы — трюмы с добычей!
-    indexes: Arc<DashMap<String, Arc<DashMap<String, Arc<DashMap<String, Vec<i32>, Hasher>>, Hasher>>, Hasher>>, // Индексы — шустрые метки!
-    fulltext_indexes: Arc<DashMap<String, Arc<DashMap<String, Arc<DashMap<String, Vec<i32>, Hasher>>, Hasher>>, Hasher>>, // Полнотекст — словесный радар!
+    pub tables: Arc<DashMap<String, TableData, Hasher>>, // Таблицы — трюмы с добычей!
+    indexes: TableIndexes, // Индексы — шустрые метки!
+    fulltext_indexes: TableIndexes, // Полнотекст — словесный радар!
     data_dir: String,           // Папка — наш тайник на берегу!
     config_file: String,        // Карта — где всё спрятано!
+    #[allow(clippy::type_complexity)]
     join_cache: Arc<DashMap<String, Vec<(Row, Row)>, Hasher>>, // Кэш связок — быстрый доступ к флоту!
     config: Arc<RwLock<DbConfig>>, // Конфиг с замком — безопасность на уровне!
     wal_file: Arc<Mutex<BufWriter<File>>>, // WAL-файл — журнал для надёжности!
-    autoincrement_cache: Arc<DashMap<String, DashMap<String, i64, Hasher>, Hasher>>, // Кэш автоинкрементов — считаем метки для новичков!
+    sequences: Arc<DashMap<String, Arc<Mutex<SequenceState>>, Hasher>>, // Последовательности — и для авто-ID, и именные!
+    row_ids: Arc<DashMap<String, Arc<AtomicU64>, Hasher>>, // Следующий номер строки по сундукам — только растёт!
+    mvcc: Arc<Mvcc>, // Снимки и старые версии строк — читатели не видят полузаписанного!
+    wal_lsn: Arc<AtomicU64>, // Номер последней записи журнала — растёт и через перезапуски!
+    write_gate: Arc<RwLock<()>>, // Ворота записей — точка сохранения на миг закрывает их, чтобы снимок совпал с LSN!
+    flush_lock: Arc<Mutex<()>>, // Одна точка сохранения за раз — две не должны делить одни `.tmp`!
+    dirty: Arc<DirtyPages>, // Тронутые страницы с прошлой точки сохранения — нетронутые сундуки не переписываем!
+    keys: Arc<std::sync::RwLock<Arc<Keyring>>>, // Ключи шифрования — меняются вместе с картой!
+    catalog: Arc<Mutex<Catalog>>, // Каталог схемы в тайнике — его версия и то, что записано на диск!
+    reload_lock: Arc<Mutex<()>>, // Одна перечитка карты за раз — шпион и API не делят одну разницу!
+    metrics: Arc<Metrics>, // Счётчики и гистограммы — сколько, как быстро и чем искали!
+    unique_locks: Arc<DashMap<String, Arc<Mutex<()>>, Hasher>>, // Замки уникальности по сундукам — проверка и запись одним куском!
+}
+
+// Снимок базы — несколько SELECT подряд видят один и тот же трюм, что бы ни писали рядом!
+pub struct Snapshot {
+    db: Database,  // Корабль — чтобы было куда слать запросы!
+    pin: ReadPin,  // Метка снимка — пока держим, старые версии не выкинут!
+}
+
+impl Snapshot {
+    // Выполняем SELECT на снимке — повторяемое чтение! Запись через снимок не идёт.
+    pub async fn execute(&self, query: Query) -> Result<Option<Vec<HashMap<String, String>>>, DbError> {
+        if query.op != QueryOp::Select {
+            return Err(DbError::Generic("снимок только для чтения — пишите через базу".to_string()));
+        }
+        self.db.execute_select_at(query, self.pin.ts()).await
+    }
 }
 
 // Запрос — наш план захвата добычи!
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Query {
     pub table: String,                    // Куда лезем за сокровищами?
     pub fields: Vec<String>,             // Что берём из сундука?
@@ -148,14 +382,23 @@ pub struct Query {
     pub where_clauses: Vec<Vec<Condition>>, // Условия — отсекаем лишних! Внешний Vec — OR, внутренний — AND!
     pub values: Vec<HashMap<String, String>>, // Добыча для вставки!
     pub op: QueryOp,                     // Что делаем — грабим или смотрим?
-    pub order_by: Option<(String, bool)>, // Сортировка — порядок в трюме! ASC=true, DESC=false
+    pub order_by: Vec<SortKey>,          // Сортировка — порядок в трюме! Ключи по очереди, у каждого своё направление!
     pub group_by: Option<String>,         // Группировка — считаем добычу по кучам!
     pub limit: Option<usize>,            // Лимит — сколько сокровищ утащить с корабля?
     pub offset: Option<usize>,           // Смещение — с какого дублона начинаем грабёж?
+    pub distinct: bool,                  // DISTINCT — только уникальные строки результата!
+    pub distinct_on: Vec<String>,        // DISTINCT ON — первая строка на каждый ключ!
+    pub subquery_fields: Vec<(String, Query)>, // Скалярные подзапросы в проекции — кличка и запрос!
+    pub on_conflict: Option<OnConflict>, // UPSERT — вставка или правка при столкновении!
+    pub returning: Vec<String>,          // RETURNING — что показать из тронутых строк!
+    pub set_exprs: Vec<(String, String)>, // SET поле = выражение — считаем от текущего значения строки!
+    pub expected_version: Option<u64>,  // Compare-and-set — правим, только если версия не сменилась!
+    #[serde(skip)]
+    pub rejected: Option<(String, String)>, // Кривой аргумент построителя — поле и причина, откажем при запуске!
 }
 
 // Тип операции — команда для базы, коротко и чётко!
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub enum QueryOp {
     #[default]
     Select,  // Смотрим добычу!
@@ -175,10 +418,18 @@ impl Default for Query {
             where_clauses: Vec::new(),          // Без фильтров — всё в кучу!
             values: Vec::new(),                 // Пустой сундук — ждём добычу!
             op: QueryOp::Select,                // По умолчанию смотрим — любопытство!
-            order_by: None,                     // Хаос в трюме — без порядка!
+            order_by: Vec::new(),               // Хаос в трюме — без порядка!
             group_by: None,                     // Без кучек — всё вперемешку!
             limit: None,                        // Без лимита — тащим всё, что найдём!
             offset: None,                       // Без смещения — начинаем с первого клада!
+            distinct: false,                    // Все строки — даже близнецы!
+            distinct_on: Vec::new(),            // Без кучек по ключу!
+            subquery_fields: Vec::new(),        // Без подзапросов в проекции!
+            on_conflict: None,                  // Столкнулись — ошибка, как раньше!
+            returning: Vec::new(),              // Записали молча — ничего не возвращаем!
+            set_exprs: Vec::new(),              // Только готовые значения!
+            expected_version: None,             // Кто последний, тот и прав — как раньше!
+            rejected: None,                     // Аргументы в порядке!
         }
     }
 }
@@ -291,13 +542,54 @@ impl Query {
         // BETWEEN в игре — диапазон на мушке!
     }
 
+    // Где "выражение" — любые расчёты: `speed * 100 > 50`, `lower(name) = 'иван'`!
+    pub fn where_expr(&mut self, expr: &str) -> &mut Self {
+        if self.where_clauses.is_empty() { self.where_clauses.push(Vec::new()); } // Пусто? Новый фильтр!
+        self.where_clauses.last_mut().unwrap().push(Condition::Expr(expr.to_string()));
+        self // Штурман посчитает!
+    }
+
+    // Где "в подзапросе" — `ship_id IN (SELECT ship_id FROM ships ...)`!
+    pub fn where_in_subquery(&mut self, field: &str, subquery: Query) -> &mut Self {
+        if self.where_clauses.is_empty() { self.where_clauses.push(Vec::new()); } // Пусто? Новый фильтр!
+        self.where_clauses.last_mut().unwrap().push(Condition::InSubquery(field.to_string(), Box::new(subquery)));
+        self // Шпаргалка из другого сундука!
+    }
+
+    // Где "существует" — подзапрос вернул хоть одну строку! Внешние клички видны внутри через where_expr.
+    pub fn where_exists(&mut self, subquery: Query) -> &mut Self {
+        if self.where_clauses.is_empty() { self.where_clauses.push(Vec::new()); }
+        self.where_clauses.last_mut().unwrap().push(Condition::Exists(Box::new(subquery)));
+        self // Разведка доложила!
+    }
+
+    // Где "не существует" — подзапрос пуст!
+    pub fn where_not_exists(&mut self, subquery: Query) -> &mut Self {
+        if self.where_clauses.is_empty() { self.where_clauses.push(Vec::new()); }
+        self.where_clauses.last_mut().unwrap().push(Condition::NotExists(Box::new(subquery)));
+        self // Никого на горизонте!
+    }
+
+    // Скалярный подзапрос в проекции — одно значение на строку под своей кличкой!
+    pub fn field_subquery(&mut self, name: &str, subquery: Query) -> &mut Self {
+        self.subquery_fields.push((name.to_string(), subquery));
+        self // Ещё одна колонка от разведки!
+    }
+
     // Сортировка — порядок в трюме, ASC или DESC!
+    // Каждый вызов добавляет ключ — `order_by("speed", false).order_by("name", true)`!
     pub fn order_by(&mut self, field: &str, ascending: bool) -> &mut Self {
-        self.order_by = Some((field.to_string(), ascending)); // Поле и порядок: ASC=true, DESC=false — всё под контролем!
+        self.order_by.push(SortKey { expr: field.to_string(), ascending, nulls_first: None }); // Поле и порядок: ASC=true, DESC=false — всё под контролем!
         self // Цепочка — наш герой!
         // Сортировка готова — трюм в строю!
     }
 
+    // Сортировка с явным местом для пустот — NULLS FIRST или NULLS LAST!
+    pub fn order_by_nulls(&mut self, field: &str, ascending: bool, nulls_first: bool) -> &mut Self {
+        self.order_by.push(SortKey { expr: field.to_string(), ascending, nulls_first: Some(nulls_first) });
+        self // Пустоты на своём месте!
+    }
+
     // Группировка — делим добычу по кучкам!
     pub fn group_by(&mut self, field: &str) -> &mut Self {
         self.group_by = Some(field.to_string()); // Считаем добычу по полям — порядок в хаосе!
@@ -319,46 +611,329 @@ impl Query {
         // Смещение врубили — пропускаем лишнее!
     }
 
+    // DISTINCT — одинаковые строки результата схлопываем в одну!
+    pub fn distinct(&mut self) -> &mut Self {
+        self.distinct = true; // Близнецы — за борт!
+        self
+    }
+
+    // DISTINCT ON — первая строка (по сортировке) на каждое значение ключей!
+    pub fn distinct_on(&mut self, fields: Vec<&str>) -> &mut Self {
+        self.distinct_on = fields.into_iter().map(|s| s.to_string()).collect();
+        self // По одному из каждой кучки!
+    }
+
+    // ON CONFLICT (поле) — столкновение по этому уникальному полю не ошибка! По умолчанию DO NOTHING.
+    pub fn on_conflict(&mut self, field: &str) -> &mut Self {
+        self.on_conflict = Some(OnConflict { target: Some(field.to_string()), action: ConflictAction::Nothing });
+        self // Готовимся к столкновению!
+    }
+
+    // DO UPDATE SET — занято? Правим старую строку этими значениями!
+    pub fn do_update<V>(&mut self, values: V) -> &mut Self where V: IntoValues {
+        let values = values.into_values().into_iter().next().unwrap_or_default();
+        let target = self.on_conflict.take().and_then(|c| c.target);
+        self.on_conflict = Some(OnConflict { target, action: ConflictAction::Update(values) });
+        self // Старый сундук подкрасим!
+    }
+
+    // DO NOTHING — занято? Пропускаем строку, остальные грузим дальше!
+    pub fn do_nothing(&mut self) -> &mut Self {
+        let target = self.on_conflict.take().and_then(|c| c.target);
+        self.on_conflict = Some(OnConflict { target, action: ConflictAction::Nothing });
+        self // Мимо — и дальше!
+    }
+
+    // SET поле = выражение — считается от текущего значения прямо под замком строки!
+    pub fn set_expr(&mut self, field: &str, expr: &str) -> &mut Self {
+        self.set_exprs.push((field.to_string(), expr.to_string()));
+        self // Штурман посчитает на месте!
+    }
+
+    // Счётчик вверх — `score = score + by`, параллельные прибавки не теряются!
+    pub fn increment(&mut self, field: &str, by: f64) -> &mut Self {
+        self.set_arithmetic(field, "+", by)
+    }
+
+    // Счётчик вниз — `score = score - by`!
+    pub fn decrement(&mut self, field: &str, by: f64) -> &mut Self {
+        self.set_arithmetic(field, "-", by)
+    }
+
+    // Умножаем — `price = price * by`!
+    pub fn multiply(&mut self, field: &str, by: f64) -> &mut Self {
+        self.set_arithmetic(field, "*", by)
+    }
+
+    // `поле = поле <op> by` — NaN и бесконечность в текст выражения не пустим, разбор принял бы их за поле!
+    fn set_arithmetic(&mut self, field: &str, op: &str, by: f64) -> &mut Self {
+        if !by.is_finite() {
+            self.rejected.get_or_insert_with(|| (field.to_string(), format!("{} — не конечное число", by)));
+            return self;
+        }
+        let quoted = expr::quote_ident(field);
+        self.set_expr(field, &format!("{} {} ({})", quoted, op, by))
+    }
+
+    // Дописываем в конец строки — `log = log || text`!
+    pub fn append(&mut self, field: &str, text: &str) -> &mut Self {
+        self.set_expr(field, &format!("{} || '{}'", expr::quote_ident(field), text.replace('\'', "''")))
+    }
+
+    // Переключаем флаг — `active = NOT active`!
+    pub fn toggle(&mut self, field: &str) -> &mut Self {
+        self.set_expr(field, &format!("NOT {}", expr::quote_ident(field)))
+    }
+
+    // Ставим текущее время — `seen_at = now()`!
+    pub fn set_now(&mut self, field: &str) -> &mut Self {
+        self.set_expr(field, "now()")
+    }
+
+    // Compare-and-set — update/delete пройдут, только если у строки всё ещё версия `version`!
+    // Иначе `DbError::Conflict`: кто-то успел поменять строку, пока мы её читали.
+    pub fn where_version(&mut self, version: u64) -> &mut Self {
+        self.expected_version = Some(version);
+        self // Сверяем версию на абордаже!
+    }
+
+    // RETURNING — после вставки, правки или удаления вернуть эти поля тронутых строк!
+    // Поля как в SELECT: имена, выражения с AS, `*`. Для update — новые значения, для delete — выкинутые.
+    pub fn returning(&mut self, fields: Vec<&str>) -> &mut Self {
+        self.returning = fields.into_iter().map(|s| s.to_string()).collect();
+        self // Покажем, что натворили!
+    }
+
     // Выполняем запрос — время жать на кнопку с проверкой ошибок!
+    // Для записи без RETURNING — Ok(None), как и раньше; с RETURNING — тронутые строки.
     pub async fn execute(self, db: &Database) -> Result<Option<Vec<HashMap<String, String>>>, DbError> {
-        match self.op {
-            QueryOp::Select => db.execute_select(self).await, // Читаем добычу с умом!
+        if self.op == QueryOp::Select {
+            return db.execute_select(self).await; // Читаем добычу с умом!
+        }
+        self.execute_affected(db).await.map(|(_, rows)| rows)
+    }
+
+    // Выполняем запрос и считаем тронутые строки — сколько вставили, поправили или выкинули!
+    // Для SELECT — сколько нашли. Вторым идёт то же, что вернул бы `execute`.
+    pub async fn execute_affected(self, db: &Database) -> Result<(usize, Option<Vec<HashMap<String, String>>>), DbError> {
+        if self.op == QueryOp::Select {
+            let rows = db.execute_select(self).await?; // Читаем добычу с умом — счётчики SELECT ведёт он сам!
+            return Ok((rows.as_ref().map_or(0, |r| r.len()), rows));
+        }
+        let (op, table) = (self.op.clone(), self.table.clone());
+        let started = Instant::now();
+        let result = self.write(db).await;
+        let table = db.metrics_table(&*db.config.read().await, &table).to_string(); // Промах мимо карты — в `unknown`!
+        db.metrics.query(&op, &table, started.elapsed(), result.is_ok());
+        if let Ok((affected, _)) = &result {
+            db.metrics.returned(&table, *affected); // Тронутые строки — итог записи!
+        }
+        result
+    }
+
+    // Запись — журнал, трюм и RETURNING!
+    async fn write(mut self, db: &Database) -> Result<(usize, Option<Vec<HashMap<String, String>>>), DbError> {
+        let returning = self.returning.clone(); // Что показать — запоминаем до выполнения!
+        for field in returning.iter().filter(|f| *f != "*") {
+            expr::parse_projection(field)?; // Кривое RETURNING ловим до записи, а не после!
+        }
+        if self.op != QueryOp::Select && system::is_read_only(&self.table) {
+            return Err(DbError::ReadOnlyTable(self.table.clone())); // Служебный сундук или история миграций — руками не пишем!
+        }
+        let alias = self.alias.clone();
+        // Запись — от журнала до трюма за воротами: точка сохранения увидит её либо целиком, либо никак!
+        let _gate = match self.op {
+            QueryOp::Select => None,
+            _ => Some(db.write_gate.read().await),
+        };
+        let affected = match self.op {
+            QueryOp::Select => {
+                let rows = db.execute_select(self).await?; // Читаем добычу с умом!
+                return Ok((rows.as_ref().map_or(0, |r| r.len()), rows));
+            }
             QueryOp::Insert => {
-                // Записываем операцию в WAL — безопасность прежде всего!
-                let operation = WalOperation::Insert {
-                    table: self.table.clone(),
-                    values: self.values.clone(),
+                if !db.tables.contains_key(&self.table) {
+                    return Err(DbError::TableNotFound(self.table.clone())); // Сундука нет — и в журнал писать нечего!
+                }
+                db.assign_autoincrement(&self.table, &mut self.values).await?; // Номера — до журнала, повтор выдаст те же!
+                // Записываем операцию в WAL — безопасность прежде всего! UPSERT — одной записью!
+                let operation = match &self.on_conflict {
+                    Some(on_conflict) => WalOperation::Upsert {
+                        table: self.table.clone(),
+                        values: self.values.clone(),
+                        on_conflict: on_conflict.clone(),
+                    },
+                    None => WalOperation::Insert {
+                        table: self.table.clone(),
+                        values: self.values.clone(),
+                    },
                 };
                 db.log_to_wal(&operation).await?; // Лог в WAL — не потеряем!
-                db.execute_insert(self).await?; // Грузим с проверкой!
-                Ok(None) // Ничего не возвращаем — груз в трюме!
+                db.execute_insert(self).await? // Грузим с проверкой!
             }
             QueryOp::Update => {
-                if let Some(values) = self.values.first() {
-                    // Записываем операцию в WAL — фиксируем изменения!
-                    let operation = WalOperation::Update {
-                        table: self.table.clone(),
-                        values: values.clone(),
-                        where_clauses: self.where_clauses.clone(),
+                if let Some((field, reason)) = self.rejected.clone() {
+                    return Err(DbError::InvalidValue(field, reason)); // До журнала — кривую правку не пишем!
+                }
+                let values = self.values.first().cloned().unwrap_or_default();
+                if values.is_empty() && self.set_exprs.is_empty() {
+                    Vec::new() // Нечего менять — никого не тронули!
+                } else {
+                    // Выражения, версии и уникальные поля зависят от порядка правок — журнал и трюм под одним замком сундука,
+                    // иначе `score * 2` и `score + 1` лягут в журнал одним порядком, а в трюм другим!
+                    let unique_fields = db.get_unique_fields(&self.table).await;
+                    let ordered = !self.set_exprs.is_empty() || self.expected_version.is_some() || values.keys().any(|k| unique_fields.contains(k));
+                    let lock = db.unique_lock(&self.table);
+                    let _ordered = if ordered { Some(lock.lock().await) } else { None };
+                    // Записываем операцию в WAL — фиксируем изменения! Выражения — отдельной записью!
+                    let operation = if let Some(version) = self.expected_version {
+                        WalOperation::UpdateIfVersion {
+                            table: self.table.clone(),
+                            values,
+                            set_exprs: self.set_exprs.clone(),
+                            where_clauses: self.where_clauses.clone(),
+                            version,
+                        }
+                    } else if self.set_exprs.is_empty() {
+                        WalOperation::Update {
+                            table: self.table.clone(),
+                            values,
+                            where_clauses: self.where_clauses.clone(),
+                        }
+                    } else {
+                        WalOperation::UpdateExpr {
+                            table: self.table.clone(),
+                            values,
+                            set_exprs: self.set_exprs.clone(),
+                            where_clauses: self.where_clauses.clone(),
+                        }
                     };
-                    db.log_to_wal(&operation).await?; // WAL в курсе — всё под контролем!
-                    db.execute_update(self).await?; // Обновляем с гарантией!
+                    let at = db.log_to_wal(&operation).await?; // WAL в курсе — всё под контролем!
+                    db.execute_update(self, at / 1000).await? // Обновляем с гарантией — `now()` на время записи журнала!
                 }
-                Ok(None) // Обновили — и вперёд!
             }
             QueryOp::Delete => {
+                // С версией — журнал и трюм под замком сундука, как у правки: конфликт при повторе будет тем же!
+                let lock = db.unique_lock(&self.table);
+                let _ordered = if self.expected_version.is_some() { Some(lock.lock().await) } else { None };
                 // Записываем операцию в WAL — убираем с гарантией!
-                let operation = WalOperation::Delete {
-                    table: self.table.clone(),
-                    where_clauses: self.where_clauses.clone(),
+                let operation = match self.expected_version {
+                    Some(version) => WalOperation::DeleteIfVersion {
+                        table: self.table.clone(),
+                        where_clauses: self.where_clauses.clone(),
+                        version,
+                    },
+                    None => WalOperation::Delete {
+                        table: self.table.clone(),
+                        where_clauses: self.where_clauses.clone(),
+                    },
                 };
                 db.log_to_wal(&operation).await?; // WAL записал — чистим смело!
-                db.execute_delete(self).await?; // Удаляем с проверкой!
-                Ok(None) // Выкинули — чистота!
+                db.execute_delete(self).await? // Удаляем с проверкой!
             }
+        };
+        if returning.is_empty() {
+            return Ok((affected.len(), None)); // Ничего не просили — только счёт!
+        }
+        let rows = db.project_returning(&alias, &returning, &affected).await?;
+        Ok((affected.len(), if rows.is_empty() { None } else { Some(rows) }))
+    }
+}
+// Строка результата — имя колонки и значение (None — NULL), в порядке проекций!
+type ResultRow = Vec<(String, Option<Value>)>;
+
+// Область видимости выражения — одна строка или флот строк, а снаружи — внешний запрос!
+struct Scope<'a> {
+    db: &'a Database,                  // Корабль — для подзапросов!
+    config: &'a DbConfig,              // Карта — уже под замком у вызывающего!
+    snapshot: u64,                     // Метка снимка — подзапросы читают тот же трюм!
+    alias: &'a str,                    // Кличка основного сундука!
+    row: Option<&'a Row>,              // Одна строка — для WHERE!
+    row_set: &'a [(String, Row)],      // Флот строк — для проекций и сортировки после JOIN!
+    outer: Option<&'a dyn Bindings>,   // Внешний запрос — коррелированные подзапросы смотрят туда!
+}
+
+impl Bindings for Scope<'_> {
+    fn resolve(&self, alias: Option<&str>, field: &str) -> Option<Value> {
+        // Своя кличка или без клички — ищем у себя; чужая — может, она у внешнего запроса!
+        let own = match (self.row, alias) {
+            (Some(row), None) => return row.field(field),
+            (Some(row), Some(a)) if a == self.alias => return row.field(field),
+            (Some(_), Some(_)) => None,
+            (None, None) => self.row_set.iter().find(|(a, _)| a == self.alias).or(self.row_set.first()),
+            (None, Some(a)) => self.row_set.iter().find(|(ra, _)| ra == a),
+        };
+        match (own, alias) {
+            (Some((_, r)), _) => r.field(field),
+            (None, Some(a)) => self.outer.and_then(|o| o.resolve(Some(a), field)),
+            (None, None) => None,
+        }
+    }
+
+    fn subquery(&self, query: &Query) -> Result<Vec<Vec<Option<Value>>>, DbError> {
+        let rows = self.db.select_rows(self.config, self.snapshot, query, Some(self))?;
+        Ok(rows.into_iter().map(|r| r.into_iter().map(|(_, v)| v).collect()).collect())
+    }
+}
+
+// Клички, на которые подзапрос ссылается, но сам не объявляет — значит, он коррелированный!
+fn free_aliases(query: &Query) -> Result<std::collections::HashSet<String>, DbError> {
+    let mut used = std::collections::HashSet::new(); // Кого зовём!
+    let mut nested: Vec<Query> = Vec::new(); // Подзапросы поглубже!
+    let mut scan = |e: &Expr, nested: &mut Vec<Query>| {
+        e.visit(&mut |node| match node {
+            Expr::Column(Some(a), _) => { used.insert(a.clone()); }
+            Expr::InSubquery(_, q) | Expr::Exists(q) | Expr::Subquery(q) => nested.push((**q).clone()),
+            _ => {}
+        });
+    };
+    for field in query.fields.iter().filter(|f| *f != "*") {
+        scan(&expr::parse_projection(field)?.0, &mut nested);
+    }
+    for source in query.order_by.iter().map(|k| &k.expr).chain(query.distinct_on.iter()) {
+        scan(&expr::parse(source)?, &mut nested);
+    }
+    for condition in query.where_clauses.iter().flatten() {
+        match condition {
+            Condition::Expr(source) => scan(&expr::parse(source)?, &mut nested),
+            Condition::InSubquery(_, q) | Condition::Exists(q) | Condition::NotExists(q) => nested.push((**q).clone()),
+            _ => {}
         }
     }
+    nested.extend(query.subquery_fields.iter().map(|(_, q)| q.clone()));
+    for q in &nested {
+        used.extend(free_aliases(q)?);
+    }
+    used.remove(&query.alias); // Свои клички — не чужие!
+    for (_, join_alias, _, _) in &query.joins {
+        used.remove(join_alias);
+    }
+    Ok(used)
 }
+
+// Режем страницу — смещение и лимит, без выхода за борт!
+fn paginate<T>(items: Vec<T>, offset: Option<usize>, limit: Option<usize>) -> Vec<T> {
+    let offset = offset.unwrap_or(0); // С какого дублона начинаем — по умолчанию с первого!
+    match limit {
+        Some(lim) => items.into_iter().skip(offset).take(lim).collect(), // Лимит — не больше этого!
+        None => items.into_iter().skip(offset).collect(), // Без лимита — до последнего сокровища!
+    }
+}
+
+// Счётчик сундука со снимка! Сундук завели после снимка — счётчика там нет, а строк снимка и подавно: номер по живым.
+fn snapshot_next_id(next_ids: &HashMap<String, RowId>, table_name: &str, table: &TableData) -> RowId {
+    next_ids.get(table_name).copied().unwrap_or_else(|| table.iter().map(|r| r.id).max().map_or(1, |max| max + 1))
+}
+
+// Compare-and-set — версия одна, значит и строка одна! Иначе конфликт на третьей строке оставил бы первые две
+// уже поправленными, а клиенту сказал бы, что ничего не вышло.
+fn single_row_cas(expected_version: Option<u64>, matched: usize) -> Result<(), DbError> {
+    match expected_version {
+        Some(_) if matched > 1 => Err(DbError::InvalidValue("where_version".to_string(), format!("условие нашло {} строк, а версию сверяют у одной", matched))),
+        _ => Ok(()),
+    }
+}
+
 // "Пульт управления" — база в наших руках!
 impl Database {
     // Создаём базу — как собрать корабль с нуля!
@@ -368,20 +943,30 @@ pub async fn new(data_dir: &str, config_file: &str) -> Result<Self, DbError> {
         create_dir_all(data_dir).await?; // Копаем яму, если её нет!
     }
 
-    // Читаем карту — где спрятан план?
-    let config = match tokio::fs::read_to_string(config_file).await {
+    // Читаем карту — она задаёт настройки и ключи, а сундуки на ней — только пожелание к каталогу!
+    // Порванная карта — не плывём: с пустой картой не открыть зашифрованное, а кривые типы не должны тронуть добычу.
+    let (mut config, declared) = match tokio::fs::read_to_string(config_file).await {
         Ok(config_str) => {
-            match toml::from_str(&config_str) {
-                Ok(cfg) => cfg, // Успешно распарсили конфиг
-                Err(e) => {
-                    println!("Шторм на старте! Карта '{}' порвана: {}. Используем пустой трюм!", config_file, e);
-                    DbConfig::default() // Ошибка парсинга — берём пустой конфиг
-                }
-            }
+            let config: DbConfig = toml::from_str(&config_str)?;
+            (config.clone(), Some(config))
         }
-        Err(_) => {
-            println!("Карта '{}' затерялась в море, берём пустой трюм!", config_file);
-            DbConfig::default() // Нет файла — пустой конфиг
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            println!("Карта '{}' затерялась в море — схему берём из каталога!", config_file);
+            (DbConfig::default(), None)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let keys = Keyring::from_config(config.encryption.as_ref())?; // Ключ не читается — не плывём, иначе запишем открыто!
+
+    // Каталог в тайнике — главный по схеме! Нет его — заводим с карты, как было до каталога.
+    let (catalog, catalog_fresh) = match catalog::read(data_dir, &keys).await? {
+        Some((version, tables, fresh)) => {
+            config.tables = tables.clone();
+            (Catalog::new(version, tables), fresh)
+        }
+        None => {
+            println!("Каталога в '{}' ещё нет — заводим его с карты, сундуков: {}", data_dir, config.tables.len());
+            (Catalog::default(), true)
         }
     };
     let config = Arc::new(RwLock::new(config)); // Прячем под замок
@@ -406,18 +991,64 @@ pub async fn new(data_dir: &str, config_file: &str) -> Result<Self, DbError> {
         join_cache: Arc::new(DashMap::with_hasher(BuildHasherDefault::<AHasher>::default())),
         config,
         wal_file,
-        autoincrement_cache: Arc::new(DashMap::with_hasher(BuildHasherDefault::<AHasher>::default())),
+        sequences: Arc::new(DashMap::with_hasher(BuildHasherDefault::<AHasher>::default())),
+        row_ids: Arc::new(DashMap::with_hasher(BuildHasherDefault::<AHasher>::default())),
+        mvcc: Arc::new(Mvcc::default()),
+        wal_lsn: Arc::new(AtomicU64::new(0)),
+        write_gate: Arc::new(RwLock::new(())),
+        flush_lock: Arc::new(Mutex::new(())),
+        dirty: Arc::new(DirtyPages::default()),
+        keys: Arc::new(std::sync::RwLock::new(Arc::new(keys))),
+        catalog: Arc::new(Mutex::new(catalog)),
+        reload_lock: Arc::new(Mutex::new(())),
+        metrics: Arc::new(Metrics::default()),
+        unique_locks: Arc::new(DashMap::with_hasher(BuildHasherDefault::<AHasher>::default())),
     };
 
+    // Шторм посреди точки сохранения? Доводим поколение сундуков до конца, прежде чем грузить!
+    wal::settle(data_dir).await?;
+
     // Загружаем данные с диска — корабль оживает даже без конфига!
-    db.load_tables_from_disk().await.unwrap_or_else(|e| {
-        println!("Шторм при загрузке с диска: {}, плывём с пустыми трюмами!", e);
-    });
+    // Чужой ключ — не плывём вовсе: с пустыми трюмами точка сохранения выкинула бы журнал!
+    match db.load_tables_from_disk().await {
+        Err(e @ DbError::WrongKey(_)) => return Err(e),
+        Err(e) => println!("Шторм при загрузке с диска: {}, плывём с пустыми трюмами!", e),
+        Ok(()) => {}
+    }
+    match db.load_sequences().await {
+        Err(e @ DbError::WrongKey(_)) => return Err(e),
+        Err(e) => println!("Шторм при загрузке последовательностей: {}, автоинкременты начнут с максимумов!", e),
+        Ok(()) => {}
+    }
 
-    // Восстанавливаем из WAL — спасаем несохранённое
-    db.recover_from_wal().await.unwrap_or_else(|e| {
-        println!("Шторм при восстановлении WAL: {}, плывём с тем, что есть!", e);
-    });
+    // Восстанавливаем из WAL — спасаем несохранённое! Каталог пишем один раз, когда журнал повторён.
+    db.defer_catalog(true).await;
+    match db.recover_from_wal().await {
+        Err(e @ DbError::WrongKey(_)) => return Err(e),
+        Err(e) => println!("Шторм при восстановлении WAL: {}, плывём с тем, что есть!", e),
+        Ok(()) => {}
+    }
+    db.defer_catalog(false).await;
+    let version = db.schema_version().await;
+    db.save_catalog().await?;
+    // Каталог под старым ключом — перешифруем сразу, как при смене ключа на ходу: его точка сохранения не пишет!
+    if !catalog_fresh && db.schema_version().await == version {
+        db.reseal_catalog().await?;
+    }
+
+    // Пустые сундуки с карты — в трюм сразу, вставка не ждёт шпиона!
+    for table in db.config.read().await.tables.iter() {
+        db.tables.entry(table.name.clone())
+            .or_insert_with(|| Arc::new(DashMap::with_hasher(BuildHasherDefault::<AHasher>::default())));
+    }
+    // Сундуки на карте против каталога — новые заводим, безопасные правки применяем, остальное только показываем!
+    if let Some(declared) = declared {
+        match db.apply_config(&declared).await {
+            Ok(report) if report.is_empty() => {}
+            Ok(report) => println!("Карта сведена с каталогом:\n{}", report),
+            Err(e) => println!("Карта не сведена с каталогом: {} — плывём по каталогу!", e),
+        }
+    }
 
     // Запускаем шпиона за картой
     let db_clone = db.clone();
@@ -435,6 +1066,16 @@ pub async fn new(data_dir: &str, config_file: &str) -> Result<Self, DbError> {
         }
     });
 
+    // Фоновая задача для сборки старых версий — снимки отпустили, память вернули!
+    let db_gc = db.clone();
+    tokio::spawn(async move {
+        let mut interval = interval(Duration::from_secs(10));
+        loop {
+            interval.tick().await;
+            db_gc.mvcc.gc();
+        }
+    });
+
     // Фоновая задача для чистки просрочки
     let db_cleanup = db.clone();
     tokio::spawn(async move {
@@ -454,58 +1095,221 @@ pub async fn new(data_dir: &str, config_file: &str) -> Result<Self, DbError> {
     query_builder!(update, Update); // Меняем ром на золото!
     query_builder!(delete, Delete); // Выкидываем за борт!
 
-    // Записываем операцию в WAL — фиксируем намерения с проверкой!
-    async fn log_to_wal(&self, operation: &WalOperation) -> Result<(), DbError> {
-        let encoded = bincode::serialize(operation)?; // Кодируем операцию — в байты!
-        let len = encoded.len() as u64; // Длина операции в байтах
-        {
-            let mut wal = self.wal_file.lock().await;
-            wal.write_all(&len.to_le_bytes()).await?; // Пишем длину — шустро!
-            wal.write_all(&encoded).await?; // Пишем данные — надёжно!
-            wal.flush().await?; // Сбрасываем на диск — всё в порядке!
+    // Снимок — все SELECT через него видят базу на момент вызова!
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot { db: self.clone(), pin: self.mvcc.pin() }
+    }
+
+    // UPDATE с compare-and-set — `db.update_if_version("pirates", 3).where_eq("id", "1")`!
+    pub fn update_if_version(&self, table: &str, version: u64) -> Query {
+        let mut query = self.update(table);
+        query.where_version(version);
+        query // Правка пройдёт, только если строку никто не тронул!
+    }
+
+    // CREATE SEQUENCE — `db.create_sequence("tickets", SequenceOptions { start: Some(100), ..Default::default() })`!
+    pub async fn create_sequence(&self, name: &str, options: SequenceOptions) -> Result<(), DbError> {
+        let _gate = self.write_gate.read().await; // Журнал и счётчики — в ногу с точкой сохранения!
+        self.install_sequence(Sequence::new(name, &options)?).await
+    }
+
+    // DROP SEQUENCE — счётчик за борт!
+    pub async fn drop_sequence(&self, name: &str) -> Result<(), DbError> {
+        let _gate = self.write_gate.read().await;
+        self.sequence_state(name)?; // Нет такой — так и скажем!
+        self.log_to_wal(&WalOperation::DropSequence { name: name.to_string() }).await?;
+        self.sequences.remove(name);
+        Ok(())
+    }
+
+    // nextval — следующий номер; выданный не повторится даже после перезапуска!
+    pub async fn nextval(&self, name: &str) -> Result<i64, DbError> {
+        let _gate = self.write_gate.read().await;
+        let state = self.sequence_state(name)?;
+        let values = self.change_sequence(&state, |s| s.take(1)).await?;
+        Ok(values[0])
+    }
+
+    // setval — следующий nextval вернёт номер после `value`!
+    pub async fn setval(&self, name: &str, value: i64) -> Result<(), DbError> {
+        let _gate = self.write_gate.read().await;
+        let state = self.sequence_state(name)?;
+        self.change_sequence(&state, |s| Ok(((), Some(s.set(value)?)))).await
+    }
+
+    // Ищем последовательность по имени!
+    fn sequence_state(&self, name: &str) -> Result<Arc<Mutex<SequenceState>>, DbError> {
+        self.sequences.get(name).map(|s| s.clone()).ok_or_else(|| DbError::SequenceNotFound(name.to_string()))
+    }
+
+    // Заводим новую последовательность — в карту и в WAL, пока под её замком никто не берёт номера!
+    async fn install_sequence(&self, sequence: Sequence) -> Result<(), DbError> {
+        let state = Arc::new(Mutex::new(SequenceState::new(sequence.clone())));
+        let _guard = state.lock().await;
+        match self.sequences.entry(sequence.name.clone()) {
+            Entry::Occupied(_) => return Err(DbError::DuplicateValue("sequence".to_string(), sequence.name)),
+            Entry::Vacant(slot) => { slot.insert(state.clone()); }
+        }
+        let name = sequence.name.clone();
+        if let Err(e) = self.log_to_wal(&WalOperation::Sequence { sequence }).await {
+            self.sequences.remove(&name); // Журнал не принял — и последовательности нет!
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Меняем последовательность под её замком — сначала бронь в WAL, потом номера наружу!
+    async fn change_sequence<T>(
+        &self,
+        state: &Mutex<SequenceState>,
+        change: impl FnOnce(&mut SequenceState) -> Result<(T, Option<Sequence>), DbError>,
+    ) -> Result<T, DbError> {
+        let mut state = state.lock().await;
+        let mut next = state.clone(); // Правим копию — упадём, и счётчик останется как был!
+        let (result, record) = change(&mut next)?;
+        if let Some(sequence) = record {
+            self.log_to_wal(&WalOperation::Sequence { sequence }).await?; // Не записали — номера не выдаём!
+        }
+        *state = next;
+        Ok(result)
+    }
+
+    // Последовательность автоинкремента — своя по имени или `{сундук}_{поле}_seq`, её заводим при первой вставке!
+    async fn field_sequence(&self, table: &str, field: &FieldConfig) -> Result<Arc<Mutex<SequenceState>>, DbError> {
+        if let Some(name) = &field.sequence {
+            return self.sequence_state(name); // Своя — создают заранее через CREATE SEQUENCE!
+        }
+        let name = format!("{}_{}_seq", table, field.name);
+        if let Ok(state) = self.sequence_state(&name) {
+            return Ok(state);
+        }
+        // Данные старше последовательности — продолжаем после самого большого номера в сундуке!
+        let max = self.tables.get(table).and_then(|t| t.iter()
+            .filter_map(|r| match r.data.get(&field.name) { Some(Value::Numeric(n)) => Some(*n as i64), _ => None })
+            .max());
+        let options = SequenceOptions { start: Some(max.map_or(1, |m| m.max(0).saturating_add(1))), ..Default::default() };
+        match self.install_sequence(Sequence::new(&name, &options)?).await {
+            Ok(()) | Err(DbError::DuplicateValue(..)) => self.sequence_state(&name), // Соседняя вставка успела первой — берём её!
+            Err(e) => Err(e),
+        }
+    }
+
+    // Проставляем автоинкременты до WAL — в журнал попадут готовые номера, и повтор ничего не перевыдаст!
+    async fn assign_autoincrement(&self, table: &str, values: &mut [HashMap<String, String>]) -> Result<(), DbError> {
+        let fields: Vec<FieldConfig> = self.config.read().await.tables.iter()
+            .find(|t| t.name == table)
+            .map(|t| t.fields.iter().filter(|f| f.autoincrement.unwrap_or(false) || f.sequence.is_some()).cloned().collect())
+            .unwrap_or_default();
+        for field in &fields {
+            let always = field.identity.as_deref() == Some("always");
+            let given: Vec<&String> = values.iter().filter_map(|row| row.get(&field.name)).collect();
+            if always && !given.is_empty() {
+                return Err(identity_always(&field.name));
+            }
+            // by_default — свой номер можно, но счётчик его перешагнёт, чтобы не выдать второй раз!
+            let given: Vec<i64> = given.iter()
+                .filter_map(|v| v.parse::<f64>().ok())
+                .filter(|n| n.fract() == 0.0)
+                .map(|n| n as i64)
+                .collect();
+            let missing = values.iter().filter(|row| !row.contains_key(&field.name)).count() as u64;
+            if given.is_empty() && missing == 0 {
+                continue;
+            }
+            let state = self.field_sequence(table, field).await?;
+            let numbers = self.change_sequence(&state, |s| {
+                let mut record = None;
+                for n in &given {
+                    record = s.skip_past(*n)?.or(record);
+                }
+                let (numbers, reserve) = s.take(missing)?;
+                Ok((numbers, reserve.or(record))) // Последняя запись — полное состояние, её и пишем!
+            }).await?;
+            let mut numbers = numbers.into_iter();
+            for row in values.iter_mut().filter(|row| !row.contains_key(&field.name)) {
+                row.insert(field.name.clone(), numbers.next().unwrap_or_default().to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // Последовательности для диска — брони, а не выданные номера, чтобы после шторма не повториться!
+    // Лежат в `sequences.seq` — не .bin, это не сундук!
+    async fn sequences_bytes(&self) -> Result<Vec<u8>, DbError> {
+        let mut sequences = Vec::new();
+        for state in self.sequences.iter().map(|s| s.value().clone()).collect::<Vec<_>>() {
+            sequences.push(state.lock().await.durable().clone());
+        }
+        self.keys().seal_file(bincode::serialize(&sequences)?)
+    }
+
+    // Последовательности с диска — продолжаем с брони!
+    async fn load_sequences(&self) -> Result<(), DbError> {
+        let path = format!("{}/sequences.seq", self.data_dir);
+        if !Path::new(&path).exists() {
+            return Ok(()); // Ещё ни одной — автоинкременты заведут свои сами!
         }
-        println!("Йо-хо! Операция записана в WAL, длина: {}", len); // Для отладки
-        Ok(()) // WAL в курсе — полный вперёд!
+        let mut buffer = Vec::new();
+        File::open(&path).await?.read_to_end(&mut buffer).await?;
+        let (buffer, _) = self.keys().open_file(buffer, "sequences.seq")?; // Под старым ключом — перепишет точка сохранения!
+        let sequences: Vec<Sequence> = bincode::deserialize(&buffer)?;
+        for sequence in sequences {
+            self.sequences.insert(sequence.name.clone(), Arc::new(Mutex::new(SequenceState::new(sequence))));
+        }
+        Ok(())
+    }
+
+    // Записываем операцию в WAL — фиксируем намерения с проверкой! Возвращаем время записи (мс):
+    // по нему `now()` в правке считается и вживую, и при повторе.
+    async fn log_to_wal(&self, operation: &WalOperation) -> Result<i64, DbError> {
+        let mut wal = self.wal_file.lock().await;
+        let lsn = self.wal_lsn.load(Ordering::SeqCst) + 1; // Номер — под замком журнала, порядок в файле = порядок номеров!
+        let at = wal::now_millis();
+        let encoded = wal::encode(lsn, at, operation, &self.keys())?; // Кодируем операцию — в байты!
+        wal.write_all(&encoded).await?; // Пишем длину и данные — надёжно!
+        let buffered = !wal.buffer().is_empty(); // Крупная запись идёт мимо буфера — сбрасывать нечего!
+        wal.flush().await?; // Сбрасываем на диск — всё в порядке!
+        if buffered {
+            self.metrics.wal_flush();
+        }
+        self.wal_lsn.store(lsn, Ordering::SeqCst); // Номер занят, только когда запись легла!
+        self.metrics.wal_write(encoded.len()); // Счётчик вместо крика на каждую запись — печать съедала пропускную способность!
+        Ok(at) // WAL в курсе — полный вперёд!
     }
 
     // Восстанавливаем из WAL — спасаем добычу после шторма!
     async fn recover_from_wal(&self) -> Result<(), DbError> {
-        let wal_path = format!("{}/wal.log", self.data_dir);
-        if !Path::new(&wal_path).exists() {
-            println!("WAL не найден, начинаем с чистого листа!");
-            return Ok(());
+        // Точка сохранения — всё до неё уже в .bin, повторяем только то, что после!
+        let checkpoint = wal::read_checkpoint(&self.data_dir).await?;
+        self.wal_lsn.fetch_max(checkpoint, Ordering::SeqCst);
+        let mut records = Vec::new();
+        let mut torn = false;
+        let keys = self.keys();
+        for path in wal::segments(&self.data_dir).await? { // Отложенные сегменты по порядку, живой журнал последним!
+            let (segment, segment_torn) = wal::read_segment(&path, &keys).await?;
+            if segment_torn {
+                println!("WAL повреждён: хвост {} оборван, берём только целые записи.", path.display());
+                torn = true;
+            }
+            records.extend(segment);
         }
-        let file = File::open(&wal_path).await?; // Открываем журнал — что уцелело?
-        let mut reader = BufReader::new(file);
-        let mut buffer = Vec::new();
-        reader.read_to_end(&mut buffer).await?; // Читаем всё — до последнего байта!
-        if buffer.is_empty() {
+        if records.is_empty() && !torn {
             println!("WAL пуст, ничего не восстанавливаем!");
             return Ok(());
         }
-
-        let mut operations = Vec::new();
-        let mut offset = 0;
-        while offset < buffer.len() {
-            if offset + 8 > buffer.len() {
-                println!("WAL повреждён: недостаточно данных для длины. Очищаем и продолжаем.");
-                File::create(&wal_path).await?; // Чистим и плывём дальше!
-                return Ok(());
-            }
-            let len = u64::from_le_bytes(buffer[offset..offset + 8].try_into().unwrap()) as usize;
-            offset += 8;
-            if offset + len > buffer.len() {
-                println!("WAL повреждён: недостаточно данных для операции. Очищаем и продолжаем.");
-                File::create(&wal_path).await?; // Чистим и плывём дальше!
-                return Ok(());
-            }
-            let operation: WalOperation = bincode::deserialize(&buffer[offset..offset + len])?; // Расшифровываем операцию!
-            operations.push(operation);
-            offset += len;
+        if let Some(max) = records.iter().map(|r| r.lsn).max() {
+            self.wal_lsn.fetch_max(max, Ordering::SeqCst); // Новые записи — после старых!
         }
+        // Записи старого образца (без номера) повторяем всегда — точек сохранения тогда не было!
+        // Без времени записи `now()` считаем по текущим часам — у записей старого образца его нет!
+        let (operations, clocks): (Vec<WalOperation>, Vec<i64>) = records.into_iter()
+            .filter(|r| r.lsn == 0 || r.lsn > checkpoint)
+            .map(|r| (r.operation, if r.at > 0 { r.at / 1000 } else { SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64 }))
+            .unzip();
+        self.rewind_schemas(&operations).await;
 
         // Применяем операции только если их нет в таблицах
-        for op in operations {
+        for (op, now) in operations.into_iter().zip(clocks) {
             match op {
                 WalOperation::Insert { table, values } => {
                     let table_data = self.tables.entry(table.clone())
@@ -519,71 +1323,133 @@ pub async fn new(data_dir: &str, config_file: &str) -> Result<Self, DbError> {
                             .ok_or_else(|| DbError::TableNotFound(table.clone()))?; // Сундук на карте?
                         for (key, value) in &value_set {
                             if key == "ttl" { continue; } // TTL — для шторма, а не для данных!
-                            let field_config = table_config.fields.iter().find(|f| f.name == *key)
+                            let field_config = table_config.field(key)
                                 .ok_or_else(|| DbError::InvalidValue(key.clone(), "поле не найдено".to_string()))?;
-                            let typed_value = match field_config.field_type.as_str() {
-                                "numeric" => Value::Numeric(value.parse::<f64>()
-                                    .map_err(|_| DbError::InvalidValue(key.clone(), value.clone()))?),
-                                "timestamp" => Value::Timestamp(value.parse::<i64>()
-                                    .map_err(|_| DbError::InvalidValue(key.clone(), value.clone()))?),
-                                "boolean" => Value::Boolean(value.parse::<bool>()
-                                    .map_err(|_| DbError::InvalidValue(key.clone(), value.clone()))?),
-                                _ => Value::Text(value.clone()),
-                            };
-                            typed_data.insert(key.clone(), typed_value);
+                            typed_data.insert(key.clone(), typed_value(&field_config.field_type, key, value)?);
                         }
+                        table_config.apply_generated(&mut typed_data)?; // Генерируемые — пересчитываем!
                         let expires_at = value_set.get("ttl").and_then(|ttl| ttl.parse::<i64>().ok())
                             .map(|ttl| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64 + ttl);
                         let should_insert = unique_fields.iter().all(|field| {
                             !table_data.iter().any(|r| r.data.get(field) == typed_data.get(field))
                         });
                         if should_insert {
-                            let row_id = table_data.iter().map(|r| r.id).max().unwrap_or(0) + 1;
-                            let row = Row { id: row_id, data: typed_data, expires_at };
+                            let row_id = self.next_row_id(&table);
+                            let row = Row { id: row_id, data: typed_data, expires_at, version: 1, commit_ts: 0 };
                             table_data.insert(row_id, row.clone());
+                            self.dirty.mark(&table, row_id);
                             self.update_indexes(&table, &row, false).await;
                             println!("Восстановлена вставка в таблицу {}", table);
                         }
                     }
                 }
                 WalOperation::Update { table, values, where_clauses } => {
-                    if let Some(table_data) = self.tables.get(&table) {
+                    if let Some(table_data) = self.tables.get(&table).map(|t| t.clone()) {
                         let rows: Vec<Row> = table_data.iter().map(|r| r.clone()).collect();
-                        let to_update = self.filter_rows(&table, &rows, &where_clauses).await;
                         let config = self.config.read().await;
+                        let to_update = self.filter_rows(&config, LATEST, &table, &table, &rows, &where_clauses, None)?;
                         let table_config = config.tables.iter().find(|t| t.name == table)
                             .ok_or_else(|| DbError::TableNotFound(table.clone()))?;
                         for mut row in to_update {
                             let mut new_data = row.data.clone();
                             for (key, value) in &values {
-                                let field_config = table_config.fields.iter().find(|f| f.name == *key)
+                                let field_config = table_config.field(key)
                                     .ok_or_else(|| DbError::InvalidValue(key.clone(), "поле не найдено".to_string()))?;
-                                let typed_value = match field_config.field_type.as_str() {
-                                    "numeric" => Value::Numeric(value.parse::<f64>()
-                                        .map_err(|_| DbError::InvalidValue(key.clone(), value.clone()))?),
-                                    "timestamp" => Value::Timestamp(value.parse::<i64>()
-                                        .map_err(|_| DbError::InvalidValue(key.clone(), value.clone()))?),
-                                    "boolean" => Value::Boolean(value.parse::<bool>()
-                                        .map_err(|_| DbError::InvalidValue(key.clone(), value.clone()))?),
-                                    _ => Value::Text(value.clone()),
-                                };
-                                new_data.insert(key.clone(), typed_value);
+                                new_data.insert(key.clone(), typed_value(&field_config.field_type, key, value)?);
                             }
+                            table_config.apply_generated(&mut new_data)?; // Генерируемые — пересчитываем!
                             self.update_indexes(&table, &row, true).await;
                             row.data = new_data;
+                            row.version += 1; // Каждая правка — новая версия!
                             table_data.insert(row.id, row.clone());
+                            self.dirty.mark(&table, row.id);
                             self.update_indexes(&table, &row, false).await;
                             println!("Восстановлено обновление в таблице {}", table);
                         }
                     }
                 }
+                WalOperation::UpdateExpr { table, values, set_exprs, where_clauses } => {
+                    // Выражения считаем тем же путём, что и вживую — от текущих значений строк!
+                    let mut query = self.update(&table);
+                    query.values = vec![values];
+                    query.set_exprs = set_exprs;
+                    query.where_clauses = where_clauses;
+                    match self.execute_update(query, now).await {
+                        Ok(_) => println!("Восстановлено обновление с выражениями в таблице {}", table),
+                        Err(e) => println!("Пропущено обновление с выражениями в таблице {}: {}", table, e),
+                    }
+                }
+                WalOperation::UpdateIfVersion { table, values, set_exprs, where_clauses, version } => {
+                    // Версии в журнале идут тем же порядком — конфликт тогда будет конфликтом и сейчас!
+                    let mut query = self.update(&table);
+                    query.values = vec![values];
+                    query.set_exprs = set_exprs;
+                    query.where_clauses = where_clauses;
+                    query.expected_version = Some(version);
+                    match self.execute_update(query, now).await {
+                        Ok(_) => println!("Восстановлено обновление с версией в таблице {}", table),
+                        Err(e) => println!("Пропущено обновление с версией в таблице {}: {}", table, e),
+                    }
+                }
+                WalOperation::DeleteIfVersion { table, where_clauses, version } => {
+                    let mut query = self.delete(&table);
+                    query.where_clauses = where_clauses;
+                    query.expected_version = Some(version);
+                    match self.execute_delete(query).await {
+                        Ok(_) => println!("Восстановлено удаление с версией из таблицы {}", table),
+                        Err(e) => println!("Пропущено удаление с версией из таблицы {}: {}", table, e),
+                    }
+                }
+                WalOperation::Upsert { table, values, on_conflict } => {
+                    // UPSERT повторяется сам собой — занятые строки правятся тем же, новые грузятся!
+                    let mut query = self.insert(&table);
+                    query.values = values;
+                    query.on_conflict = Some(on_conflict);
+                    match self.execute_insert(query).await {
+                        Ok(_) => println!("Восстановлен UPSERT в таблицу {}", table),
+                        // В журнал пишем до выполнения — упавший тогда UPSERT упадёт и сейчас, пропускаем!
+                        Err(e) => println!("Пропущен UPSERT в таблицу {}: {}", table, e),
+                    }
+                }
+                WalOperation::Sequence { sequence } => {
+                    // Последняя запись о последовательности — её полное состояние, просто ставим поверх!
+                    println!("Восстановлена последовательность {}", sequence.name);
+                    self.sequences.insert(sequence.name.clone(), Arc::new(Mutex::new(SequenceState::new(sequence))));
+                }
+                WalOperation::DropSequence { name } => {
+                    self.sequences.remove(&name);
+                    println!("Восстановлено удаление последовательности {}", name);
+                }
+                WalOperation::BulkLoad { table, rows } => {
+                    let table_data = self.tables.entry(table.clone())
+                        .or_insert_with(|| Arc::new(DashMap::with_hasher(BuildHasherDefault::<AHasher>::default())))
+                        .clone();
+                    let count = rows.len();
+                    self.insert_loaded_rows(&table, &table_data, rows).await;
+                    println!("Восстановлена массовая загрузка в таблицу {}: {} строк", table, count);
+                }
+                WalOperation::CreateTable { table } => {
+                    self.apply_create_table(table).await?;
+                }
+                WalOperation::DropTable { table } => {
+                    self.apply_drop_table(&table.name).await?;
+                }
+                WalOperation::Migrate { id, applied_at, migration, .. } => {
+                    match self.replay_migration(id, applied_at, &migration).await {
+                        Ok(rows) => println!("Восстановлена миграция №{} сундука {}: {} строк", id, migration.table(), rows),
+                        Err(e) => println!("Шторм при повторе миграции №{}: {}, плывём дальше!", id, e),
+                    }
+                }
                 WalOperation::Delete { table, where_clauses } => {
-                    if let Some(table_data) = self.tables.get(&table) {
+                    if let Some(table_data) = self.tables.get(&table).map(|t| t.clone()) {
                         let rows: Vec<Row> = table_data.iter().map(|r| r.clone()).collect();
-                        let to_delete = self.filter_rows(&table, &rows, &where_clauses).await;
+                        let config = self.config.read().await;
+                        let to_delete = self.filter_rows(&config, LATEST, &table, &table, &rows, &where_clauses, None)?;
+                        drop(config);
                         for row in to_delete {
                             self.update_indexes(&table, &row, true).await;
                             table_data.remove(&row.id);
+                            self.dirty.mark(&table, row.id);
                             println!("Восстановлено удаление из таблицы {}", table);
                         }
                     }
@@ -597,16 +1463,116 @@ pub async fn new(data_dir: &str, config_file: &str) -> Result<Self, DbError> {
         Ok(()) // Корабль спасён — добыча на борту!
     }
 
-    // Сбрасываем WAL в основной файл — сохраняем порядок!
+    // Сбрасываем WAL в основной файл — точка сохранения! Снимок и LSN берём вместе, сундуки пишем из снимка,
+    // а отработанный журнал уходит в архив (если он задан на карте) или за борт.
+    // Пишем только тронутые сундуки, и те по возможности дельтой — нетронутые лежат как лежали.
     async fn flush_wal_to_bin(&self) -> Result<(), DbError> {
-        // Сохраняем все таблицы в .bin!
-        for table_name in self.tables.iter().map(|t| t.key().clone()).collect::<Vec<_>>() {
-            self.save_table(&table_name).await?; // Спасаем каждый трюм!
+        let _flush = self.flush_lock.lock().await; // Таймер и смена карты могут прийти разом — ждём своей очереди!
+        let (pin, lsn, next_ids, dirty) = {
+            let _gate = self.write_gate.write().await;
+            let dirty = self.dirty.take(); // До снимка — всё отмеченное в него уже попало!
+            (self.mvcc.pin(), self.wal_lsn.load(Ordering::SeqCst), self.next_row_ids(), dirty)
+        };
+        if dirty.is_empty() && lsn != 0 && lsn == wal::read_checkpoint(&self.data_dir).await? {
+            return Ok(()); // Ни правок, ни новых записей в журнале — диск и так в курсе!
+        }
+        let started = Instant::now();
+        match self.write_checkpoint(&pin, lsn, &next_ids, &dirty).await {
+            Ok(()) => {
+                self.metrics.checkpoint(started.elapsed());
+                Ok(()) // WAL сброшен — диск в курсе!
+            }
+            Err(e) => {
+                self.dirty.restore(dirty); // Не легло — отметки вернём, следующая точка попробует снова!
+                Err(e)
+            }
+        }
+    }
+
+    // Поколение точки сохранения — дельты и сжатые сундуки, брони последовательностей, потом журнал в архив!
+    async fn write_checkpoint(&self, pin: &ReadPin, lsn: u64, next_ids: &HashMap<String, RowId>, dirty: &HashMap<String, dirty::TableDirty>) -> Result<(), DbError> {
+        self.rotate_wal().await?; // Новые записи — в свежий журнал, старый ждёт, пока сундуки лягут!
+
+        let compression = self.snapshot_compression().await?;
+        let compact_after = self.config.read().await.snapshot_compact_after.unwrap_or(8);
+        let existing = snapshot::deltas(&self.data_dir).await?;
+        let mut generation = wal::Generation::new(&self.data_dir);
+        for (table_name, changes) in dirty {
+            let deltas = existing.get(table_name).map_or(&[][..], |d| d.as_slice());
+            let Some(table) = self.tables.get(table_name).map(|t| t.clone()) else {
+                // Сундук выброшен — его база и дельты уходят вместе с этим поколением!
+                generation.discard(&format!("{}.bin", table_name));
+                for (_, name) in deltas {
+                    generation.discard(name);
+                }
+                continue;
+            };
+            let has_base = Path::new(&self.data_dir).join(format!("{}.bin", table_name)).exists();
+            // Целиком — если базы нет, дельт накопилось много или тронута добрая половина сундука!
+            let full = changes.full || !has_base || deltas.len() >= compact_after
+                || changes.pages.len() as u64 * dirty::PAGE_ROWS * 2 >= table.len() as u64;
+            if full {
+                let bytes = self.table_bytes(table_name, pin.ts(), next_ids, compression)?; // Сжимаем сундук — база и дельты в один файл!
+                generation.stage(&format!("{}.bin", table_name), &bytes).await?;
+                for (_, name) in deltas {
+                    generation.discard(name); // Старые дельты — за борт, когда база встанет на место!
+                }
+            } else {
+                let number = deltas.last().map_or(1, |(n, _)| n + 1);
+                let bytes = self.delta_bytes(table_name, &table, pin.ts(), next_ids, &changes.pages, compression)?;
+                generation.stage(&snapshot::delta_name(table_name, number), &bytes).await?;
+            }
+        }
+        generation.stage("sequences.seq", &self.sequences_bytes().await?).await?; // И брони последовательностей!
+        generation.commit(lsn).await?;
+
+        // Отработанные сегменты — в архив или за борт, всё из них уже на диске!
+        let archive = self.config.read().await.wal_archive.clone();
+        for path in wal::segments(&self.data_dir).await? {
+            if wal::segment_lsn(&path).is_none_or(|last| last > lsn) {
+                continue; // Живой журнал и сегменты после точки не трогаем!
+            }
+            if let Some(archive) = &archive {
+                create_dir_all(archive).await?;
+                tokio::fs::copy(&path, Path::new(archive).join(path.file_name().unwrap())).await?; // Копия — архив может быть на другом диске!
+            }
+            tokio::fs::remove_file(&path).await?;
+        }
+        Ok(())
+    }
+
+    // Снимок, LSN и счётчики номеров, которые совпадают: ворота на миг закрыты, все начатые записи дописаны и видны!
+    async fn snapshot_lsn(&self) -> (ReadPin, u64, HashMap<String, RowId>) {
+        let _gate = self.write_gate.write().await;
+        (self.mvcc.pin(), self.wal_lsn.load(Ordering::SeqCst), self.next_row_ids())
+    }
+
+    // Счётчики номеров всех сундуков — берём за закрытыми воротами вместе со снимком! Прочитай их позже,
+    // счётчик убежал бы вперёд строк, и повтор журнала выдал бы тем же строкам другие номера.
+    fn next_row_ids(&self) -> HashMap<String, RowId> {
+        let names: Vec<String> = self.tables.iter().map(|t| t.key().clone()).collect(); // Имена — до счётчиков, карту сундуков не держим!
+        names.into_iter().map(|name| {
+            let next_id = self.peek_row_id(&name);
+            (name, next_id)
+        }).collect()
+    }
+
+    // Откладываем живой журнал в сегмент `wal.<LSN>.log` и начинаем новый! Пустой не трогаем.
+    async fn rotate_wal(&self) -> Result<(), DbError> {
+        let mut wal = self.wal_file.lock().await;
+        let buffered = !wal.buffer().is_empty();
+        wal.flush().await?;
+        if buffered {
+            self.metrics.wal_flush();
         }
-        // Очищаем WAL — всё синхронизировано!
         let wal_path = format!("{}/wal.log", self.data_dir);
-        File::create(&wal_path).await?; // Чистим журнал — новый лист!
-        Ok(()) // WAL сброшен — диск в курсе!
+        if tokio::fs::metadata(&wal_path).await.map_or(0, |m| m.len()) == 0 {
+            return Ok(());
+        }
+        let segment = Path::new(&self.data_dir).join(wal::segment_name(self.wal_lsn.load(Ordering::SeqCst)));
+        tokio::fs::rename(&wal_path, &segment).await?;
+        *wal = BufWriter::new(OpenOptions::new().create(true).append(true).open(&wal_path).await?);
+        Ok(())
     }
 
     // Ищем уникальное поле — кто тут особый?
@@ -620,105 +1586,34 @@ pub async fn new(data_dir: &str, config_file: &str) -> Result<Self, DbError> {
     // Чистим трюм от просрочки — выбрасываем за борт всё, что устарело!
     async fn cleanup_expired_rows(&self) {
         let current_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64; // Часы капитана — время шторма!
-        for table in self.tables.iter() {
-            let table_name = table.key().clone(); // Имя сундука — где копаем?
-            let table_data = table.value().clone(); // Добыча — что проверяем?
-            let expired_ids: Vec<i32> = table_data.iter()
+        let tables: Vec<(String, TableData)> = self.tables.iter().map(|t| (t.key().clone(), t.value().clone())).collect(); // Список сундуков — без замка на время чистки!
+        for (table_name, table_data) in tables {
+            let expired_ids: Vec<RowId> = table_data.iter()
                 .filter(|r| r.expires_at.is_some() && r.expires_at.unwrap() < current_time) // Нашли просрочку!
                 .map(|r| r.id) // Берём метки — кто на выброс?
                 .collect();
+            let mut evicted = 0;
             for id in expired_ids {
-                if let Some((_, row)) = table_data.remove(&id) { // Выкидываем за борт, берём только добычу!
+                // Выкидываем за борт, берём только добычу! Пропала до шторма — её уже удалили, считать нечего.
+                if let Some((_, row)) = table_data.remove(&id) {
+                    self.dirty.mark(&table_name, id); // Страница похудела — в следующую дельту!
                     self.update_indexes(&table_name, &row, true).await; // Чистим метки — следов не оставляем!
-                    println!("Йо-хо-хо! {} отправлен за борт из сундука {}!", row.id, table_name); // Кричим о чистке!
-                } else {
-                    println!("Арр! ID {} пропал из сундука {} до шторма!", id, table_name); // Шторм опоздал?
+                    evicted += 1;
                 }
             }
+            if evicted > 0 {
+                self.metrics.evicted(&table_name, evicted);
+                println!("Йо-хо-хо! {} строк отправлено за борт из сундука {}!", evicted, table_name); // Кричим о чистке — раз на сундук!
+            }
         }
         // Трюм чист — никакого старья на борту!
     }
 
-// Шпион следит за картой — глаз не спускает!
-async fn watch_config(&self) {
-    let mut last_content = String::new();
-    loop {
-        match tokio::fs::read_to_string(&self.config_file).await {
-            Ok(content) => {
-                if content != last_content {
-                    println!("Йо-хо-хо! Новая карта обнаружена в {}!", self.config_file);
-                    match toml::from_str::<DbConfig>(&content) {
-                        Ok(new_config) => {
-                            println!("Карта в порядке, перестраиваем корабль! Таблиц: {}", new_config.tables.len());
-                            let old_config = self.config.read().await.clone();
-
-                            // Сливаем WAL в .bin
-                            self.flush_wal_to_bin().await.unwrap_or_else(|e| {
-                                println!("Шторм при сбросе WAL: {}, продолжаем с риском!", e);
-                            });
-
-                            // Чистим кэши
-                            self.join_cache.clear();
-                            self.autoincrement_cache.clear();
-
-                            // Сохраняем текущие данные на диск перед очисткой
-                            for table_name in self.tables.iter().map(|t| t.key().clone()).collect::<Vec<_>>() {
-                                self.save_table(&table_name).await.unwrap_or_else(|e| {
-                                    println!("Шторм при сохранении '{}': {}", table_name, e);
-                                });
-                            }
-
-                            // Перестраиваем таблицы только для новых данных
-                            *self.config.write().await = new_config.clone();
-                            for table_config in &new_config.tables {
-                                let table = self.tables.entry(table_config.name.clone())
-                                    .or_insert_with(|| Arc::new(DashMap::with_hasher(BuildHasherDefault::<AHasher>::default())))
-                                    .clone();
-                                if table.is_empty() {
-                                    println!("Новый сундук '{}' добавлен в трюм!", table_config.name);
-                                }
-                                self.rebuild_indexes(&table_config.name).await;
-                            }
-
-                            // Логируем текущие таблицы
-                            let current_tables: Vec<String> = self.tables.iter().map(|t| t.key().clone()).collect();
-                            println!("Текущие сундуки в трюме: {}", current_tables.join(", "));
-
-                            // Проверяем старые vs новые таблицы
-                            let old_tables: Vec<String> = old_config.tables.iter().map(|t| t.name.clone()).collect();
-                            let new_tables: Vec<String> = new_config.tables.iter().map(|t| t.name.clone()).collect();
-                            for old_table in &old_tables {
-                                if !new_tables.contains(old_table) {
-                                    println!("Сундук '{}' пропал с карты, но данные остаются в трюме!", old_table);
-                                    // Не удаляем данные с диска — оставляем для ручного восстановления
-                                }
-                            }
-                            for new_table in &new_tables {
-                                if !old_tables.contains(new_table) {
-                                    println!("Сундук '{}' — новичок на борту!", new_table);
-                                }
-                            }
-
-                            // Восстанавливаем из WAL
-                            self.recover_from_wal().await.unwrap_or_else(|e| {
-                                println!("Шторм при восстановлении WAL: {}, плывём с тем, что есть!", e);
-                            });
-
-                            last_content = content;
-                            println!("Корабль перестроен, полный вперёд!");
-                        }
-                        Err(e) => println!("Карта порвана: {}, исправьте ошибку!", e),
-                    }
-                }
-            }
-            Err(e) => println!("Шторм! Карта {} недоступна: {}, плывём дальше!", self.config_file, e),
-        }
-        sleep(Duration::from_secs(5)).await; // Отдыхаем 5 сек
-    }
-}
     // Грузим добычу с диска — оживаем корабль!
     async fn load_tables_from_disk(&self) -> Result<(), DbError> {
         let mut entries = tokio::fs::read_dir(&self.data_dir).await?; // Читаем тайник — где наш склад?
+        let mut deltas = snapshot::deltas(&self.data_dir).await?; // Дельты — поверх баз, по порядку!
+        let keys = self.keys();
 
         // Проходим по сундукам — что тут у нас?
         while let Some(entry) = entries.next_entry().await? {
@@ -734,57 +1629,113 @@ async fn watch_config(&self) {
                     continue; // Пусто? Далее!
                 }
                 // Распаковываем добычу — сокровища в руках!
-                let rows: HashMap<i32, Row> = bincode::deserialize(&buffer)?;
+                let snapshot::TableFile { mut next_id, rows, migrated } = snapshot::decode(&buffer, &keys, &format!("{}.bin", table_name))?; // Старые версии и старые ключи мигрируем на лету!
+                if migrated {
+                    self.dirty.mark_full(&table_name); // Старый формат — перепишем целиком при первой же точке сохранения!
+                }
 
                 // Новый трюм — свежий контейнер!
                 let table = Arc::new(DashMap::with_hasher(BuildHasherDefault::<AHasher>::default()));
-                let config = self.config.read().await;
-                let table_config = config.tables.iter().find(|t| t.name == table_name);
-
-                // Собираем автоинкременты — кто считает сам?
-                let autoincrement_fields: Vec<String> = table_config
-                    .map(|t| t.fields.iter().filter(|f| f.autoincrement.unwrap_or(false)).map(|f| f.name.clone()).collect())
-                    .unwrap_or_default();
-
-                let autoincrement_map = DashMap::with_hasher(BuildHasherDefault::<AHasher>::default());
-                self.autoincrement_cache.insert(table_name.clone(), autoincrement_map.clone());
-
-                // Проходим по добыче — грузим добро и считаем метки!
+                // Проходим по добыче — грузим добро! Автоинкременты помнят свои последовательности.
                 for (id, row) in rows {
-                    table.insert(id, row.clone());
-                    for field in &autoincrement_fields {
-                        if let Some(Value::Numeric(val)) = row.data.get(field) {
-                            let mut current_max = autoincrement_map.entry(field.clone()).or_insert(0);
-                            *current_max = (*current_max).max(*val as i64); // Обновляем максимум — новый рекорд!
+                    table.insert(id, row);
+                }
+                // Дельты — каждая страница в дельте заменяет прежнюю целиком, с удалениями вместе!
+                for (_, name) in deltas.remove(&table_name).unwrap_or_default() {
+                    let delta = snapshot::decode_delta(&tokio::fs::read(Path::new(&self.data_dir).join(&name)).await?, &keys, &name)?;
+                    if delta.migrated {
+                        self.dirty.mark_full(&table_name); // Дельта старого образца — уберём полной перезаписью!
+                    }
+                    for page in &delta.pages {
+                        for id in page * delta.page_rows..(page + 1) * delta.page_rows {
+                            table.remove(&id);
                         }
                     }
+                    for row in delta.rows {
+                        table.insert(row.id, row);
+                    }
+                    next_id = next_id.max(delta.next_id);
                 }
+                let next_id = table.iter().map(|r| r.id).max().map_or(next_id, |max| next_id.max(max + 1)); // Номер не ниже живых строк!
+                self.row_id_counter(&table_name).fetch_max(next_id, Ordering::SeqCst); // Счётчик только растёт — даже при перезагрузке!
                 // Сохраняем трюм — место занято!
                 self.tables.insert(table_name.clone(), table);
                 // Перестраиваем метки — ускоряем поиск!
                 self.rebuild_indexes(&table_name).await;
             }
         }
+        for table_name in deltas.keys() {
+            println!("Арр! Дельты сундука '{}' без базового .bin — пропускаем!", table_name);
+        }
         Ok(()) // Добыча на борту — корабль жив!
     }
 
-    // Сохраняем трюм — прячем добычу на диск!
-    async fn save_table(&self, table_name: &str) -> Result<(), DbError> {
-        // Берём трюм — есть ли что спасать?
-        if let Some(table) = self.tables.get(table_name) {
-            let path = format!("{}/{}.bin", self.data_dir, table_name); // Путь для сундука — наш цифровой сейф!
-            let rows: HashMap<i32, Row> = table.iter().map(|r| (*r.key(), r.value().clone())).collect(); // Собираем добычу — всё в кучу!
-            let encoded = bincode::serialize(&rows)?; // Кодируем — превращаем в байты!
-            File::create(&path).await?
-                .write_all(&encoded).await?; // Пишем на диск — теперь не пропадёт!
-            // Диск в курсе — добыча в сейфе!
+    // Счётчик номеров сундука — новый начинаем после самого большого живого номера!
+    fn row_id_counter(&self, table_name: &str) -> Arc<AtomicU64> {
+        if let Some(counter) = self.row_ids.get(table_name) {
+            return counter.clone();
         }
-        Ok(()) // Трюм сохранён — полный вперёд!
+        let start = self.first_free_id(table_name);
+        self.row_ids.entry(table_name.to_string())
+            .or_insert_with(|| Arc::new(AtomicU64::new(start)))
+            .clone()
+    }
+
+    // Следующий номер строки — только подсмотреть: счётчика ещё нет, так и не заводим!
+    pub(crate) fn peek_row_id(&self, table_name: &str) -> RowId {
+        self.row_ids.get(table_name)
+            .map_or_else(|| self.first_free_id(table_name), |counter| counter.load(Ordering::SeqCst))
+    }
+
+    fn first_free_id(&self, table_name: &str) -> RowId {
+        self.tables.get(table_name)
+            .and_then(|t| t.iter().map(|r| r.id).max())
+            .map_or(1, |max| max + 1)
+    }
+
+    // Замок уникальности сундука — поиск дубля и запись под ним идут одним куском, две вставки одного ключа не разминутся!
+    pub(crate) fn unique_lock(&self, table_name: &str) -> Arc<Mutex<()>> {
+        if let Some(lock) = self.unique_locks.get(table_name) {
+            return lock.clone();
+        }
+        self.unique_locks.entry(table_name.to_string()).or_default().clone()
+    }
+
+    // Выдаём номер новой строке — один раз и навсегда, удалённые номера не возвращаются!
+    fn next_row_id(&self, table_name: &str) -> RowId {
+        self.row_id_counter(table_name).fetch_add(1, Ordering::SeqCst)
+    }
+
+    // Текущая связка ключей — клон Arc, замок не держим!
+    fn keys(&self) -> Arc<Keyring> {
+        self.keys.read().unwrap().clone()
+    }
+
+    // Сжатие сундуков с карты — кривое имя ловим до записи, а не после!
+    async fn snapshot_compression(&self) -> Result<snapshot::Compression, DbError> {
+        snapshot::Compression::from_config(self.config.read().await.snapshot_compression.as_deref())
+    }
+
+    // Дельта для диска — строки снимка на тронутых страницах, удалённые на них просто не попадут!
+    fn delta_bytes(&self, table_name: &str, table: &TableData, snapshot: u64, next_ids: &HashMap<String, RowId>, pages: &BTreeSet<u64>, compression: snapshot::Compression) -> Result<Vec<u8>, DbError> {
+        let next_id = snapshot_next_id(next_ids, table_name, table); // Счётчик — со снимка, не с живого трюма!
+        let ids = pages.iter().flat_map(|page| page * dirty::PAGE_ROWS..(page + 1) * dirty::PAGE_ROWS);
+        let rows = self.mvcc.visible_ids(table_name, table, snapshot, ids);
+        snapshot::encode_delta(next_id, dirty::PAGE_ROWS, pages.iter().copied().collect(), rows, compression, &self.keys())
+    }
+
+    // Трюм для диска — строки снимка `snapshot` и счётчик номеров с того же снимка, байты для `{сундук}.bin` в текущей версии формата!
+    fn table_bytes(&self, table_name: &str, snapshot: u64, next_ids: &HashMap<String, RowId>, compression: snapshot::Compression) -> Result<Vec<u8>, DbError> {
+        let (next_id, rows) = match self.tables.get(table_name).map(|t| t.clone()) {
+            Some(table) => (snapshot_next_id(next_ids, table_name, &table), self.mvcc.visible(table_name, &table, snapshot)), // Собираем добычу снимка!
+            None => (1, Vec::new()),
+        };
+        snapshot::encode(next_id, rows, compression, &self.keys()) // Кодируем — превращаем в байты!
     }
 
     // Перестраиваем метки — ускоряем корабль до турбо-режима!
     async fn rebuild_indexes(&self, table_name: &str) {
-        if let Some(table) = self.tables.get(table_name) {
+        if let Some(table) = self.tables.get(table_name).map(|t| t.clone()) { // Клон трюма — метку карты через .await не держим!
             let config = self.config.read().await;
             if let Some(table_config) = config.tables.iter().find(|t| t.name == table_name) {
                 for field in &table_config.fields {
@@ -855,18 +1806,61 @@ async fn watch_config(&self) {
         }
     }
 
+    // Готовим выражение — некоррелированные подзапросы считаем один раз и вклеиваем ответ!
+    fn prepare_expr(&self, config: &DbConfig, snapshot: u64, e: Expr) -> Result<Expr, DbError> {
+        e.rewrite(&mut |node| {
+            let query = match &node {
+                Expr::InSubquery(_, q) | Expr::Exists(q) | Expr::Subquery(q) => q,
+                _ => return Ok(node),
+            };
+            if !free_aliases(query)?.is_empty() {
+                return Ok(node); // Коррелированный — считаем для каждой строки!
+            }
+            let rows = self.select_rows(config, snapshot, query, None)?;
+            let literal = |v: Option<Value>| v.map_or(Expr::Null, Expr::Literal);
+            Ok(match node {
+                Expr::InSubquery(e, _) => Expr::InList(e, rows.into_iter().map(|r| literal(r.into_iter().next().and_then(|(_, v)| v))).collect()),
+                Expr::Exists(_) => Expr::Literal(Value::Boolean(!rows.is_empty())),
+                Expr::Subquery(q) if rows.len() > 1 => {
+                    return Err(DbError::InvalidExpression(format!("(SELECT ... FROM {})", q.table), "скалярный подзапрос вернул больше одной строки".to_string()));
+                }
+                _ => literal(rows.into_iter().next().and_then(|r| r.into_iter().next()).and_then(|(_, v)| v)),
+            })
+        })
+    }
+
+    // Фильтруем по выражению — каждая строка в своей области видимости!
+    fn filter_by_expr(&self, config: &DbConfig, snapshot: u64, alias: &str, rows: &[Row], e: Expr, outer: Option<&dyn Bindings>) -> Result<Vec<Row>, DbError> {
+        let e = self.prepare_expr(config, snapshot, e)?; // Разбираем один раз — считаем для каждой строки!
+        let mut matched = Vec::new();
+        for row in rows {
+            let scope = Scope { db: self, config, snapshot, alias, row: Some(row), row_set: &[], outer };
+            if e.matches(&scope)? {
+                matched.push(row.clone());
+            }
+        }
+        Ok(matched)
+    }
+
     // Фильтруем добычу — выцепляем нужное с умом и без лишних клонов!
-    async fn filter_rows(&self, table_name: &str, rows: &[Row], where_clauses: &[Vec<Condition>]) -> Vec<Row> {
+    #[allow(clippy::too_many_arguments)]
+    fn filter_rows(&self, config: &DbConfig, snapshot: u64, table_name: &str, alias: &str, rows: &[Row], where_clauses: &[Vec<Condition>], outer: Option<&dyn Bindings>) -> Result<Vec<Row>, DbError> {
         let mut filtered = rows.to_vec(); // Исходный набор строк
+        if where_clauses.is_empty() {
+            self.metrics.lookup(table_name, false); // Без условий — берём всё перебором!
+        }
         for and_group in where_clauses {
             let mut group_result = Vec::new();
             for condition in and_group {
+                if !matches!(condition, Condition::Eq(..) | Condition::Contains(..) | Condition::In(..)) {
+                    self.metrics.lookup(table_name, false); // Сравнения и выражения — только перебором!
+                }
                 let filtered_subset = match condition {
                     Condition::Eq(field, value) => {
-                        self.index_filter(table_name, field, value, &filtered, |v, val| v.to_string() == val)
+                        self.index_filter(table_name, field, value, &filtered, snapshot, |v, val| v.to_string() == val)
                     }
                     Condition::Contains(field, value) => {
-                        self.fulltext_filter(table_name, field, value, &filtered)
+                        self.fulltext_filter(table_name, field, value, &filtered, snapshot)
                     }
                     Condition::Lt(field, value) => {
                         filtered.iter().filter(|row| {
@@ -891,7 +1885,7 @@ async fn watch_config(&self) {
                     Condition::In(field, values) => {
                         let mut result = Vec::new();
                         for value in values {
-                            result.extend(self.index_filter(table_name, field, value, &filtered, |v, val| v.to_string() == val));
+                            result.extend(self.index_filter(table_name, field, value, &filtered, snapshot, |v, val| v.to_string() == val));
                         }
                         result
                     }
@@ -905,6 +1899,20 @@ async fn watch_config(&self) {
                             false
                         }).cloned().collect()
                     }
+                    Condition::Expr(source) => {
+                        self.filter_by_expr(config, snapshot, alias, &filtered, expr::parse(source)?, outer)?
+                    }
+                    Condition::InSubquery(field, subquery) => {
+                        let e = Expr::InSubquery(Box::new(Expr::Column(None, field.clone())), subquery.clone());
+                        self.filter_by_expr(config, snapshot, alias, &filtered, e, outer)?
+                    }
+                    Condition::Exists(subquery) => {
+                        self.filter_by_expr(config, snapshot, alias, &filtered, Expr::Exists(subquery.clone()), outer)?
+                    }
+                    Condition::NotExists(subquery) => {
+                        let e = Expr::Unary(UnaryOp::Not, Box::new(Expr::Exists(subquery.clone())));
+                        self.filter_by_expr(config, snapshot, alias, &filtered, e, outer)?
+                    }
                 };
                 if group_result.is_empty() {
                     group_result = filtered_subset;
@@ -914,64 +1922,142 @@ async fn watch_config(&self) {
             }
             filtered = group_result; // Обновляем результат для следующей группы OR
         }
-        filtered // Фильтр готов — добыча отсеяна!
+        Ok(filtered) // Фильтр готов — добыча отсеяна!
     }
 
-    // Фильтр по меткам — скорость наше оружие!
-    fn index_filter<F>(&self, table_name: &str, field: &str, value: &str, rows: &[Row], pred: F) -> Vec<Row>
+    // Фильтр по меткам — скорость наше оружие! Метка только подсказывает номера, строки берём из снимка `rows`.
+    fn index_filter<F>(&self, table_name: &str, field: &str, value: &str, rows: &[Row], snapshot: u64, pred: F) -> Vec<Row>
     where F: Fn(&Value, &str) -> bool {
+        let matches = |r: &Row| r.data.get(field).is_some_and(|v| pred(v, value));
         // Проверяем метки — есть ли шпаргалка?
-        if let Some(index_map) = self.indexes.get(table_name) {
-            if let Some(index) = index_map.get(field) {
-                // Используем метку — молниеносный поиск!
-                return index.get(value).map_or(Vec::new(), |ids| {
-                    ids.iter().filter_map(|id| self.tables.get(table_name).and_then(|t| t.get(id).map(|r| r.clone()))).collect()
-                });
+        let ids = self.indexes.get(table_name)
+            .and_then(|index_map| index_map.get(field).map(|index| index.get(value).map(|ids| ids.clone()).unwrap_or_default()));
+        if let Some(ids) = ids {
+            if let Some(found) = self.snapshot_pick(table_name, snapshot, rows, ids, matches) {
+                return found; // Метка сработала — молниеносный поиск!
             }
         }
-        // Нет метки? Фильтруем вручную — без паники!
-        rows.iter().filter(|r| r.data.get(field).map_or(false, |v| pred(v, value))).map(|r| r.clone()).collect()
+        // Нет метки или её обогнали правки после снимка? Фильтруем вручную — без паники!
+        self.metrics.lookup(table_name, false);
+        rows.iter().filter(|r| matches(r)).cloned().collect()
     }
 
     // Полнотекстовый фильтр — слова под микроскопом!
-    fn fulltext_filter(&self, table_name: &str, field: &str, value: &str, rows: &[Row]) -> Vec<Row> {
+    fn fulltext_filter(&self, table_name: &str, field: &str, value: &str, rows: &[Row], snapshot: u64) -> Vec<Row> {
         let value_lower = value.to_lowercase();
-        if let Some(ft_index_map) = self.fulltext_indexes.get(table_name) {
-            if let Some(ft_index) = ft_index_map.get(field) {
-                let mut ids = Vec::new();
-                for entry in ft_index.iter() {
-                    if entry.key().contains(&value_lower) {
-                        ids.extend(entry.value().clone());
-                    }
+        let matches = |r: &Row| r.data.get(field).is_some_and(|v| v.to_string().to_lowercase().contains(&value_lower));
+        let ids = self.fulltext_indexes.get(table_name).and_then(|ft_index_map| ft_index_map.get(field).map(|ft_index| {
+            let mut ids = Vec::new();
+            for entry in ft_index.iter() {
+                if entry.key().contains(&value_lower) {
+                    ids.extend(entry.value().clone());
                 }
-                ids.sort_unstable();
-                ids.dedup();
-                return ids.into_iter()
-                    .filter_map(|id| self.tables.get(table_name).and_then(|t| t.get(&id).map(|r| r.clone())))
-                    .collect();
+            }
+            ids
+        }));
+        if let Some(ids) = ids {
+            if let Some(found) = self.snapshot_pick(table_name, snapshot, rows, ids, matches) {
+                return found;
             }
         }
         // Запасной вариант: фильтрация вручную, если индекса нет
-        rows.iter()
-            .filter(|r| r.data.get(field).map_or(false, |v| v.to_string().to_lowercase().contains(&value_lower)))
-            .cloned()
-            .collect()
+        self.metrics.lookup(table_name, false);
+        rows.iter().filter(|r| matches(r)).cloned().collect()
+    }
+
+    // Номера из метки — в строки снимка! Метка живёт по свежему трюму: если строки сменили или выкинули после снимка,
+    // старое значение из неё уже вычеркнуто — тогда `None`, и фильтр идёт перебором. Историю смотрим после метки:
+    // старая версия попадает в историю раньше, чем правится метка, так что гонку не пропустим.
+    fn snapshot_pick(&self, table_name: &str, snapshot: u64, rows: &[Row], ids: Vec<RowId>, matches: impl Fn(&Row) -> bool) -> Option<Vec<Row>> {
+        if self.mvcc.changed_since(table_name, snapshot) {
+            return None;
+        }
+        self.metrics.lookup(table_name, true);
+        let by_id: HashMap<RowId, &Row> = rows.iter().map(|r| (r.id, r)).collect();
+        let mut ids = ids;
+        ids.sort_unstable();
+        ids.dedup();
+        // Вставленные после снимка в `rows` не попали, просроченные оттуда уже отсеяны — их и не вернём!
+        Some(ids.into_iter().filter_map(|id| by_id.get(&id).copied()).filter(|r| matches(r)).cloned().collect())
+    }
+
+    // RETURNING — проецируем тронутые строки, как SELECT проецирует найденные!
+    async fn project_returning(&self, alias: &str, fields: &[String], rows: &[Row]) -> Result<Vec<HashMap<String, String>>, DbError> {
+        let config = self.config.read().await; // Подзапросам в RETURNING тоже нужна карта!
+        let mut projections = Vec::with_capacity(fields.len());
+        for field in fields {
+            projections.push(if field == "*" {
+                None
+            } else {
+                let (e, name) = expr::parse_projection(field)?;
+                Some((self.prepare_expr(&config, LATEST, e)?, name))
+            });
+        }
+        let mut results = Vec::with_capacity(rows.len());
+        for row in rows {
+            let scope = Scope { db: self, config: &config, snapshot: LATEST, alias, row: Some(row), row_set: &[], outer: None };
+            let mut result = HashMap::new();
+            for projection in &projections {
+                match projection {
+                    None => result.extend(row.data.iter().map(|(k, v)| (k.clone(), v.to_string()))), // Всё из сундука!
+                    Some((e, name)) => {
+                        if let Some(value) = e.eval(&scope)? {
+                            result.insert(name.clone(), value.to_string());
+                        }
+                    }
+                }
+            }
+            results.push(result);
+        }
+        Ok(results)
     }
 
     // Выполняем SELECT — добываем сокровища с проверкой и без старья!
     async fn execute_select(&self, query: Query) -> Result<Option<Vec<HashMap<String, String>>>, DbError> {
-        let table = self.tables.get(&query.table)
-            .ok_or_else(|| DbError::TableNotFound(query.table.clone()))?; // Берём сундук — где добыча?
+        let pin = self.mvcc.pin(); // Свой снимок на время запроса — полузаписанного не увидим!
+        self.execute_select_at(query, pin.ts()).await
+    }
+
+    // SELECT на заданном снимке — для одиночного запроса и для `Snapshot`!
+    async fn execute_select_at(&self, query: Query, snapshot: u64) -> Result<Option<Vec<HashMap<String, String>>>, DbError> {
+        let started = Instant::now();
+        let config = self.config.read().await; // Читаем карту — где порядок?
+        let rows = self.select_rows(&config, snapshot, &query, None);
+        let table = self.metrics_table(&config, &query.table); // Промах мимо карты — в `unknown`!
+        self.metrics.query(&QueryOp::Select, table, started.elapsed(), rows.is_ok());
+        let rows = rows?;
+        self.metrics.returned(table, rows.len());
+        // NULL-ы в карту не кладём — как и раньше, пустого ключа просто нет!
+        let results: Vec<HashMap<String, String>> = rows.into_iter()
+            .map(|row| row.into_iter().filter_map(|(name, value)| value.map(|v| (name, v.to_string()))).collect())
+            .collect();
+        Ok(if results.is_empty() { None } else { Some(results) }) // Пусто? None! Есть добыча? Some!
+    }
+
+    // Строки сундука на снимке — служебные собираем на лету, а сундук отпускаем сразу: подзапросы могут заглянуть в него же!
+    fn snapshot_rows(&self, config: &DbConfig, table_name: &str, snapshot: u64) -> Result<Vec<Row>, DbError> {
+        if let Some(rows) = self.system_rows(config, table_name, snapshot) {
+            return Ok(rows);
+        }
+        let table = self.tables.get(table_name).map(|t| t.clone())
+            .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))?; // Берём сундук — где добыча?
+        Ok(self.mvcc.visible(table_name, &table, snapshot))
+    }
+
+    // Сердце SELECT — синхронно, чтобы подзапросы могли нырять сюда прямо из выражений!
+    fn select_rows(&self, config: &DbConfig, snapshot: u64, query: &Query, outer: Option<&dyn Bindings>) -> Result<Vec<ResultRow>, DbError> {
         let current_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64; // Часы капитана — что сейчас?
 
-        // Собираем добычу с кличками, только свежую — лениво выкидываем просрочку!
-        let rows: Vec<(String, Row)> = table.iter()
+        // Собираем добычу с кличками, только свежую и только со снимка — лениво выкидываем просрочку!
+        let visible = self.snapshot_rows(config, &query.table, snapshot)?;
+        self.metrics.scanned(&query.table, visible.len());
+        let rows: Vec<(String, Row)> = visible.into_iter()
             .filter(|r| r.expires_at.is_none() || r.expires_at.unwrap() > current_time) // Только живые сокровища!
-            .map(|r| (query.alias.clone(), r.clone()))
+            .map(|r| (query.alias.clone(), r))
             .collect();
 
         if rows.is_empty() {
-            return Ok(None); // Трюм пуст или всё устарело — нет добычи!
+            return Ok(Vec::new()); // Трюм пуст или всё устарело — нет добычи!
         }
 
         // Начинаем с простого — каждая добыча в своём наборе!
@@ -979,8 +2065,7 @@ async fn watch_config(&self) {
 
         // Джойним флот — связываем всё как профи, только с живыми!
         for (join_table, join_alias, on_left, on_right) in &query.joins {
-            let join_table_data = self.tables.get(join_table)
-                .ok_or_else(|| DbError::TableNotFound(join_table.clone()))?; // Берём союзный корабль!
+            let join_table_data = self.snapshot_rows(config, join_table, snapshot)?; // Союзник — с того же снимка!
             let left_field = on_left.split('.').nth(1).unwrap_or(on_left); // Левое поле — без лишних точек!
             let right_field = on_right.split('.').nth(1).unwrap_or(on_right); // Правое — тоже чистим!
             joined_rows = joined_rows.into_iter().filter_map(|mut row_set| {
@@ -997,13 +2082,14 @@ async fn watch_config(&self) {
 
         // Фильтруем добычу основной таблицы — отсекаем лишнее с умом!
         let filtered_rows = if !query.where_clauses.is_empty() {
-            self.filter_rows(&query.table, &joined_rows.iter().map(|r| r[0].1.clone()).collect::<Vec<Row>>(), &query.where_clauses).await
+            self.filter_rows(config, snapshot, &query.table, &query.alias, &joined_rows.iter().map(|r| r[0].1.clone()).collect::<Vec<Row>>(), &query.where_clauses, outer)?
         } else {
+            self.metrics.lookup(&query.table, false); // Без условий — весь сундук перебором!
             joined_rows.iter().map(|r| r[0].1.clone()).collect::<Vec<Row>>()
         };
 
         // Оставляем только нужные наборы — чистим флот по ID!
-        let filtered_ids: std::collections::HashSet<i32> = filtered_rows.into_iter().map(|r| r.id).collect();
+        let filtered_ids: std::collections::HashSet<RowId> = filtered_rows.into_iter().map(|r| r.id).collect();
         joined_rows.retain(|row_set| filtered_ids.contains(&row_set[0].1.id));
 
         // Лениво проверяем ещё раз — вдруг что-то устарело в процессе!
@@ -1012,172 +2098,241 @@ async fn watch_config(&self) {
         });
 
         if joined_rows.is_empty() {
-            return Ok(None); // Всё устарело или не подошло — пустой сундук!
+            return Ok(Vec::new()); // Всё устарело или не подошло — пустой сундук!
         }
 
-        let config = self.config.read().await; // Читаем карту — где порядок?
+        // Разбираем проекции — поля, выражения с кличками и скалярные подзапросы!
+        let mut projections: Vec<Option<(Expr, String)>> = Vec::with_capacity(query.fields.len() + query.subquery_fields.len());
+        for field in &query.fields {
+            projections.push(if field == "*" {
+                None
+            } else {
+                let (e, name) = expr::parse_projection(field)?;
+                Some((self.prepare_expr(config, snapshot, e)?, name))
+            });
+        }
+        for (name, subquery) in &query.subquery_fields {
+            projections.push(Some((self.prepare_expr(config, snapshot, Expr::Subquery(Box::new(subquery.clone())))?, name.clone())));
+        }
 
         // Применяем сортировку — раскладываем добычу по полочкам!
-        if let Some((field, ascending)) = &query.order_by {
-            let (alias, field_name) = field.split_once('.').unwrap_or(("", field)); // Разделяем кличку и клад!
-            let table_name = if alias.is_empty() || alias == query.alias {
-                &query.table // Основной сундук — наш корабль!
+        if !query.order_by.is_empty() {
+            let mut sort_exprs = Vec::with_capacity(query.order_by.len());
+            let mut specs = Vec::with_capacity(query.order_by.len());
+            for key in &query.order_by {
+                // Сортируем по кличке проекции или по любому выражению!
+                let sort_expr = match projections.iter().flatten().find(|(_, name)| *name == key.expr) {
+                    Some((e, _)) => e.clone(),
+                    None => self.prepare_expr(config, snapshot, expr::parse(&key.expr)?)?,
+                };
+                // Простое поле? Берём алфавит из карты его сундука!
+                let collation = match sort_expr.as_column() {
+                    Some((alias, field_name)) => {
+                        let table_name = match alias {
+                            Some(a) if a != query.alias => query.joins.iter().find(|(_, ja, _, _)| ja == a).map(|(t, _, _, _)| t.as_str()), // Ищем союзника во флоте!
+                            _ => Some(query.table.as_str()), // Основной сундук — наш корабль!
+                        };
+                        let field_config = table_name.and_then(|t| config.tables.iter().find(|tc| tc.name == t)).and_then(|tc| tc.field(field_name));
+                        Collation::parse(field_config.and_then(|f| f.collation.as_deref()))?
+                    }
+                    None => Collation::Binary,
+                };
+                specs.push(sort::SortSpec { ascending: key.ascending, nulls_first: key.nulls_first(), collation });
+                sort_exprs.push(sort_expr);
+            }
+            let mut keys = Vec::with_capacity(joined_rows.len());
+            for row_set in &joined_rows { // Считаем ключи один раз на строку — не мучаем штурмана!
+                let bindings = Scope { db: self, config, snapshot, alias: &query.alias, row: None, row_set, outer };
+                keys.push(sort_exprs.iter().map(|e| e.eval(&bindings)).collect::<Result<Vec<_>, _>>()?);
+            }
+            // С лимитом нужны только первые offset+limit — держим их в куче, остальное не сортируем!
+            let top = if query.distinct || !query.distinct_on.is_empty() {
+                None // Дубли ещё не выкинуты — верхушка может оказаться короче!
             } else {
-                query.joins.iter().find(|(_, a, _, _)| a == alias).map(|(t, _, _, _)| t).unwrap_or(&query.table) // Ищем союзника во флоте!
+                query.limit.map(|l| l.saturating_add(query.offset.unwrap_or(0)))
             };
-            let table_config = config.tables.iter().find(|t| t.name == *table_name)
-                .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))?; // Находим сундук на карте!
-            let field_type = table_config.fields.iter().find(|f| f.name == field_name)
-                .map(|f| f.field_type.as_str())
-                .unwrap_or("text"); // Тип клада — что сортируем?
-
-            joined_rows.sort_by(|a_set, b_set| { // Сортируем флот — порядок в трюме!
-                let a_row = a_set.iter().find(|(a, _)| a == alias || (alias.is_empty() && a == &query.alias)); // Ищем добычу по кличке!
-                let b_row = b_set.iter().find(|(a, _)| a == alias || (alias.is_empty() && a == &query.alias)); // Ищем вторую добычу!
-                let a_val = a_row.and_then(|(_, r)| r.data.get(field_name)); // Хватаем клад первой строки!
-                let b_val = b_row.and_then(|(_, r)| r.data.get(field_name)); // Хватаем клад второй строки!
-                let cmp = match (a_val, b_val, field_type) { // Сравниваем — по типу всё чётко!
-                    (Some(Value::Numeric(a)), Some(Value::Numeric(b)), "numeric") => a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal), // Числа — дублоны в порядке!
-                    (Some(Value::Timestamp(a)), Some(Value::Timestamp(b)), "timestamp") => a.cmp(b), // Время — метки на карте!
-                    (Some(Value::Boolean(a)), Some(Value::Boolean(b)), "boolean") => a.cmp(b), // Да/Нет — ром или вода?
-                    (Some(a), Some(b), _) => a.to_string().cmp(&b.to_string()), // Текст — имена по алфавиту!
-                    (Some(a), None, _) => a.to_string().cmp(&String::new()), // Есть у одного — он выше!
-                    (None, Some(b), _) => String::new().cmp(&b.to_string()), // Нет у первого — он ниже!
-                    (None, None, _) => std::cmp::Ordering::Equal, // Оба пусты — равны!
-                };
-                if *ascending { cmp } else { cmp.reverse() } // ASC или DESC — порядок наш!
-            });
+            let order = sort::order(&specs, keys, top);
+            let mut slots: Vec<Option<Vec<(String, Row)>>> = joined_rows.into_iter().map(Some).collect();
+            joined_rows = order.into_iter().filter_map(|pos| slots[pos].take()).collect();
         }
 
-        // Применяем смещение и лимит — грабим с умом!
-        let offset = query.offset.unwrap_or(0); // С какого дублона начинаем — по умолчанию с первого!
-        let limit = query.limit; // Сколько берём — или всё, если лимита нет!
-        let start = offset.min(joined_rows.len()); // Не выходим за борт — обрезаем смещение!
-        let end = match limit {
-            Some(lim) => (start + lim).min(joined_rows.len()), // Конец — лимит или край трюма!
-            None => joined_rows.len(), // Без лимита — до последнего сокровища!
-        };
-        joined_rows = joined_rows.into_iter().skip(start).take(end - start).collect(); // Пропускаем и берём нужное!
+        // DISTINCT ON — из каждой кучки с одинаковым ключом оставляем первую строку после сортировки!
+        if !query.distinct_on.is_empty() {
+            let mut on_exprs = Vec::with_capacity(query.distinct_on.len());
+            for field in &query.distinct_on {
+                on_exprs.push(match projections.iter().flatten().find(|(_, name)| name == field) {
+                    Some((e, _)) => e.clone(),
+                    None => self.prepare_expr(config, snapshot, expr::parse(field)?)?,
+                });
+            }
+            let mut seen_keys: std::collections::HashSet<String> = std::collections::HashSet::new(); // Виденные кучки!
+            let mut kept = Vec::with_capacity(joined_rows.len());
+            for row_set in joined_rows {
+                let bindings = Scope { db: self, config, snapshot, alias: &query.alias, row: None, row_set: &row_set, outer };
+                let key = on_exprs.iter().map(|e| e.eval(&bindings)).collect::<Result<Vec<_>, _>>()?;
+                if seen_keys.insert(format!("{:?}", key)) { // Первая в кучке — в улов!
+                    kept.push(row_set);
+                }
+            }
+            joined_rows = kept;
+        }
 
-        // Готовим список добычи — что показываем?
-        let field_order: Vec<String> = if query.fields == vec!["*".to_string()] {
-            config.tables.iter().find(|t| t.name == query.table).map_or(vec![], |t| t.fields.iter().map(|f| f.name.clone()).collect()) // Всё из основного сундука!
-        } else {
-            query.fields.clone() // Только выбранное — жадность под контролем!
-        };
+        // Без DISTINCT режем страницу сразу — не считаем проекции для лишних строк!
+        if !query.distinct {
+            joined_rows = paginate(joined_rows, query.offset, query.limit); // Пропускаем и берём нужное!
+        }
+
+        // Все поля основного сундука — для звёздочки!
+        let star_fields: Vec<String> = config.tables.iter().find(|t| t.name == query.table)
+            .map(|t| t.fields.iter().map(|f| f.name.clone()).collect())
+            .or_else(|| system::fields(&query.table).map(|f| f.iter().map(|name| name.to_string()).collect()))
+            .unwrap_or_default();
 
-        let mut results: Vec<HashMap<String, String>> = Vec::new(); // Карта добычи — чистый лист!
-        let mut seen: std::collections::HashSet<String> = std::collections::HashSet::new(); // Список виденного — дубли в бан!
+        let mut results: Vec<ResultRow> = Vec::new(); // Карта добычи — чистый лист!
 
         // Формируем добычу — красиво и по полочкам, только свежее!
         for row_set in joined_rows.iter() {
-            let mut result = HashMap::new(); // Новый сундук для строки!
-            if query.fields == vec!["*".to_string()] { // Всё? Гребём лопатой!
-                let fields = if field_order.is_empty() {
-                    let mut keys: Vec<&String> = row_set[0].1.data.keys().collect(); // Все ключи — полный улов!
-                    keys.sort(); // Сортируем — порядок в трюме!
-                    keys.into_iter().map(|k| k.to_string()).collect()
-                } else {
-                    field_order.clone() // Берём по списку — точность!
-                };
-                for field in &fields {
-                    if let Some(value) = row_set[0].1.data.get(field) { // Хватаем клад из основного сундука!
-                        result.insert(field.clone(), value.to_string()); // Кидаем в результат!
-                    }
-                }
-            } else { // Выборочно? Целимся точно!
-                for field in &field_order {
-                    let (alias, field_name) = field.split_once('.').unwrap_or(("", field)); // Разделяем кличку и клад!
-                    let row = row_set.iter().find(|(a, _)| a == alias || (alias.is_empty() && a == &query.alias)); // Ищем нужный корабль!
-                    if let Some((_, r)) = row {
-                        if let Some(value) = r.data.get(field_name) { // Хватаем клад!
-                            result.insert(field.clone(), value.to_string()); // Кидаем в сундук!
+            let mut result: ResultRow = Vec::new(); // Новый сундук для строки!
+            let bindings = Scope { db: self, config, snapshot, alias: &query.alias, row: None, row_set, outer };
+            for projection in &projections {
+                match projection {
+                    None => { // Всё? Гребём лопатой!
+                        let fields = if star_fields.is_empty() {
+                            let mut keys: Vec<&String> = row_set[0].1.data.keys().collect(); // Все ключи — полный улов!
+                            keys.sort(); // Сортируем — порядок в трюме!
+                            keys.into_iter().map(|k| k.to_string()).collect()
+                        } else {
+                            star_fields.clone() // Берём по списку — точность!
+                        };
+                        for field in fields {
+                            let value = row_set[0].1.field(&field); // Хватаем клад из основного сундука!
+                            result.push((field, value)); // Кидаем в результат!
                         }
                     }
+                    Some((e, name)) => { // Выборочно? Целимся точно — поле или расчёт!
+                        result.push((name.clone(), e.eval(&bindings)?)); // Кидаем в сундук!
+                    }
                 }
             }
-            let mut keys: Vec<&String> = result.keys().collect(); // Собираем ключи — проверяем добычу!
-            keys.sort(); // Сортируем — порядок в хаосе!
-            let row_key: String = keys.iter()
-                .map(|k| format!("{}:{}", k, result.get(*k).unwrap())) // Формируем метку строки!
-                .collect::<Vec<String>>()
-                .join("|"); // Склеиваем — уникальный след!
-            if !result.is_empty() && seen.insert(row_key) { // Не пусто и ново? В улов!
-                results.push(result);
-            }
+            results.push(result); // В улов — даже если такая строка уже была или все поля пустые: строка есть строка!
         }
 
-        Ok(if results.is_empty() { None } else { Some(results) }) // Пусто? None! Есть добыча? Some!
+        // DISTINCT — дубли в бан, но только по явной просьбе и до нарезки страницы!
+        if query.distinct {
+            let mut seen: std::collections::HashSet<String> = std::collections::HashSet::new(); // Список виденного!
+            results.retain(|result| seen.insert(format!("{:?}", result))); // Уникальный след строки — ново? Оставляем!
+            results = paginate(results, query.offset, query.limit); // Теперь и страница честная!
+        }
+
+        Ok(results) // Добыча по полочкам!
     }
 
-// Вставляем добычу — новый груз в трюм с проверкой и временем жизни!
-async fn execute_insert(&self, query: Query) -> Result<(), DbError> {
-    // Берём или создаём трюм — место для новенького!
-    let table_data = self.tables.entry(query.table.clone())
-        .or_insert_with(|| Arc::new(DashMap::with_hasher(BuildHasherDefault::<AHasher>::default())))
-        .clone();
-    
-    // Новый комментарий: Проверяем только self.tables, config — только для типов
-    if !self.tables.contains_key(&query.table) {
-        let available_tables: Vec<String> = self.tables.iter().map(|t| t.key().clone()).collect();
-        return Err(DbError::TableNotFound(format!(
-            "Йо-хо-хо, сундук с именем '{}' на корабле не значится! Доступны: {}",
-            query.table,
-            available_tables.join(", ")
-        )));
+// DO UPDATE — правим занятую строку целиком или никак: сначала всё проверяем, потом одна запись в трюм!
+#[allow(clippy::too_many_arguments)]
+async fn update_conflicting_row(&self, table: &str, table_data: &TableData, table_config: Option<&TableConfig>, unique_fields: &[String], id: RowId, values: &HashMap<String, String>, commit_ts: u64) -> Result<Option<Row>, DbError> {
+    let mut typed_values = Vec::with_capacity(values.len());
+    for (key, value) in values {
+        let field_config = table_config.and_then(|t| t.field(key));
+        reject_generated(field_config, key)?; // Генерируемое — руками не трогать!
+        let field_type = field_config.map_or("text", |f| f.field_type.as_str());
+        typed_values.push((key, typed_value(field_type, key, value)?));
+    }
+    // Строка с правкой — от живой строки, без генерируемых пересчётов тут не обойтись!
+    let rebuild = |live: &Row| -> Result<HashMap<String, Value>, DbError> {
+        let mut new_data = live.data.clone(); // Копируем сундук — работаем с запасом!
+        for (key, value) in &typed_values {
+            new_data.insert((*key).clone(), value.clone());
+        }
+        if let Some(t) = table_config { t.apply_generated(&mut new_data)?; } // Генерируемые — пересчитываем!
+        Ok(new_data)
+    };
+    // Правка не должна налететь на чужую уникальность! Уникальные поля меняют только под замком сундука —
+    // он у нас, так что проверка по копии строки верна и тогда, когда мы уже под замком шарда.
+    let Some(current) = table_data.get(&id).map(|r| r.clone()) else {
+        return Ok(None); // Строку уже выкинули — править нечего!
+    };
+    let checked = rebuild(&current)?;
+    for field in unique_fields {
+        if let Some(value) = checked.get(field) {
+            if table_data.iter().any(|r| r.id != id && r.data.get(field) == Some(value)) {
+                return Err(DbError::DuplicateValue(field.clone(), value.to_string())); // Кракен заметил дубликат!
+            }
+        }
     }
+    // Считаем и пишем под замком шарда — от живой строки, как `execute_update`: параллельный `increment` не потеряется!
+    let (old_row, row) = {
+        let Some(mut live) = table_data.get_mut(&id) else {
+            return Ok(None); // Пока проверяли, строку выкинули!
+        };
+        let old_row = live.clone();
+        let new_data = rebuild(&old_row)?;
+        self.mvcc.record(table, old_row.clone(), commit_ts); // Старую версию — в историю, снимкам!
+        live.data = new_data;
+        live.version += 1; // Новая версия — кто читал старую, тот опоздал!
+        live.commit_ts = commit_ts;
+        (old_row, live.clone())
+    }; // Замок снят — метки правим уже без него!
+    self.dirty.mark(table, id);
+    self.update_indexes(table, &old_row, true).await; // Убираем старые метки!
+    self.update_indexes(table, &row, false).await; // Новые метки — готово!
+    Ok(Some(row))
+}
 
-    let config = self.config.read().await; // Читаем карту — где настройки?
-    let table_config = config.tables.iter().find(|t| t.name == query.table);
+// Вставляем добычу — новый груз в трюм с проверкой и временем жизни!
+async fn execute_insert(&self, query: Query) -> Result<Vec<Row>, DbError> {
+    // Трюм должен уже быть — сундуки заводит `create_table` или карта, а не случайная вставка!
+    let table_data = self.tables.get(&query.table).map(|t| t.clone())
+        .ok_or_else(|| DbError::TableNotFound(query.table.clone()))?;
 
-    // Собираем автоинкременты и уникальные поля — кто считает и кто особый?
-    let autoincrement_fields: Vec<String> = table_config.map_or(vec![], |t| {
-        t.fields.iter()
-            .filter(|f| f.autoincrement.unwrap_or(false))
-            .map(|f| f.name.clone())
-            .collect()
-    });
+    // Уникальные поля — кто особый? Автоинкременты уже проставил `assign_autoincrement` до WAL.
     let unique_fields = self.get_unique_fields(&query.table).await;
-    let autoincrement_map = self.autoincrement_cache
-        .entry(query.table.clone())
-        .or_insert_with(|| DashMap::with_hasher(BuildHasherDefault::<AHasher>::default()))
-        .clone();
+    // Есть уникальные — поиск столкновения и вставка под замком сундука, ON CONFLICT применяется к строке атомарно!
+    // Замок — до карты: порядок у всех писателей один, ворота, замок, карта.
+    let lock = self.unique_lock(&query.table);
+    let _unique = if unique_fields.is_empty() { None } else { Some(lock.lock().await) };
+
+    let config = self.config.read().await; // Читаем карту — где настройки?
+    let table_config = config.tables.iter().find(|t| t.name == query.table);
 
-    // Считаем максимумы для автоинкрементов — кто тут главный?
-    for field in &autoincrement_fields {
-        let max_value = table_data.iter()
-            .filter_map(|r| r.data.get(field).and_then(|v| if let Value::Numeric(n) = v { Some(*n as i64) } else { None }))
-            .max()
-            .unwrap_or(0);
-        let mut current_max = autoincrement_map.entry(field.clone()).or_insert(0);
-        if *current_max < max_value {
-            *current_max = max_value; // Новый рекорд — метка на месте!
+    // ON CONFLICT — сталкиваться можно только по уникальному полю!
+    let conflict_fields: Vec<String> = match &query.on_conflict {
+        Some(OnConflict { target: Some(field), .. }) if !unique_fields.contains(field) => {
+            return Err(DbError::InvalidValue(field.clone(), "ON CONFLICT требует уникальное поле".to_string()));
         }
-    }
+        Some(OnConflict { target: Some(field), .. }) => vec![field.clone()],
+        Some(OnConflict { target: None, .. }) => unique_fields.clone(), // Без цели — любое уникальное!
+        None => Vec::new(),
+    };
 
     // Проверяем, есть ли ttl в конфиге — если да, игнорим его как поле данных!
-    let has_ttl_field = table_config.map_or(false, |t| t.fields.iter().any(|f| f.name == "ttl"));
+    let has_ttl_field = table_config.is_some_and(|t| t.fields.iter().any(|f| f.name == "ttl"));
+
+    let mut affected = Vec::new(); // Тронутые строки — для RETURNING и счёта!
+    let write = self.mvcc.begin_write(); // Метка коммита — одна на всю пачку!
 
     // Проходим по добыче — грузим всё в трюм!
     for query_values in query.values {
         let mut typed_data = HashMap::new(); // Новый сундук с типами — порядок в хаосе!
-        let row_id = table_data.iter().map(|r| r.id).max().unwrap_or(0) + 1; // Новый ID — место для новичка!
 
         // Типизируем добычу — золото, ром или карты?
         for (key, value) in &query_values {
             if key == "ttl" { continue; } // Пропускаем ttl — оно для шторма, а не для сундука!
-            let field_config = table_config.and_then(|t| t.fields.iter().find(|f| f.name == *key));
+            let field_config = table_config.and_then(|t| t.field(key));
+            reject_generated(field_config, key)?; // Генерируемое — руками не трогать!
             let field_type = field_config.map_or("text", |f| f.field_type.as_str()); // По умолчанию текст
-            let typed_value = match field_type {
-                "numeric" => Value::Numeric(value.parse::<f64>()
-                    .map_err(|_| DbError::InvalidValue(key.clone(), value.clone()))?),
-                "timestamp" => Value::Timestamp(value.parse::<i64>()
-                    .map_err(|_| DbError::InvalidValue(key.clone(), value.clone()))?),
-                "boolean" => Value::Boolean(value.parse::<bool>()
-                    .map_err(|_| DbError::InvalidValue(key.clone(), value.clone()))?),
-                _ => Value::Text(value.clone()),
-            };
-            typed_data.insert(key.clone(), typed_value); // Кидаем в сундук с типами!
+            typed_data.insert(key.clone(), typed_value(field_type, key, value)?); // Кидаем в сундук с типами!
+        }
+
+        // Столкнулись по цели ON CONFLICT? Правим старую строку или проходим мимо — без ошибки!
+        let existing = conflict_fields.iter().find_map(|field| {
+            let value = typed_data.get(field)?;
+            table_data.iter().find(|r| r.data.get(field) == Some(value)).map(|r| r.id)
+        });
+        if let (Some(id), Some(on_conflict)) = (existing, &query.on_conflict) {
+            if let ConflictAction::Update(values) = &on_conflict.action {
+                affected.extend(self.update_conflicting_row(&query.table, &table_data, table_config, &unique_fields, id, values, write.ts()).await?);
+            }
+            continue; // Вставку пропускаем — строка уже на борту!
         }
 
         // Проверяем уникальность — никаких дублей!
@@ -1190,14 +2345,8 @@ async fn execute_insert(&self, query: Query) -> Result<(), DbError> {
             }
         }
 
-        // Добавляем автоинкременты — считаем сами, если надо!
-        for field_name in &autoincrement_fields {
-            if !query_values.contains_key(field_name) {
-                let mut current_max = autoincrement_map.entry(field_name.clone()).or_insert(0);
-                *current_max += 1; // Новый номер в команде!
-                typed_data.insert(field_name.clone(), Value::Numeric(*current_max as f64)); // Кидаем в сундук!
-            }
-        }
+        // Генерируемые поля — автоинкременты уже в сундуке, они тоже в деле!
+        if let Some(t) = table_config { t.apply_generated(&mut typed_data)?; }
 
         // Устанавливаем время "до шторма" — если есть TTL в запросе!
         let expires_at = query_values.get("ttl").and_then(|ttl| {
@@ -1211,7 +2360,7 @@ async fn execute_insert(&self, query: Query) -> Result<(), DbError> {
             println!("Йо-хо-хо! Поле 'ttl' в карте сундука '{}' проигнорировано — оно только для шторма!", query.table);
         }
 
-        // Ещё раз проверяем уникальность — после автоинкрементов!
+        // Ещё раз проверяем уникальность — после генерируемых полей!
         for field in &unique_fields {
             if let Some(value) = typed_data.get(field) {
                 if table_data.iter().any(|r| r.data.get(field) == Some(value)) {
@@ -1221,90 +2370,171 @@ async fn execute_insert(&self, query: Query) -> Result<(), DbError> {
         }
 
         let row = Row { 
-            id: row_id, 
+            id: self.next_row_id(&query.table), // Новый ID — место для новичка, за O(1) и без гонок!
             data: typed_data, 
-            expires_at // Скоро шторм? Отмечаем, когда выбросить!
+            expires_at, // Скоро шторм? Отмечаем, когда выбросить!
+            version: 1, // Первая версия — свежий груз!
+            commit_ts: write.ts(), // Читатели увидят, когда вся вставка закончится!
         }; 
         table_data.insert(row.id, row.clone()); // Грузим в трюм!
+        self.dirty.mark(&query.table, row.id); // Страница грязная — ляжет в дельту!
         self.update_indexes(&query.table, &row, false).await; // Обновляем метки — всё под контролем!
+        affected.push(row);
     }
     // Не сохраняем сразу на диск — WAL уже зафиксировал изменения!
     self.join_cache.retain(|key, _| !key.contains(&query.table)); // Чистим кэш — старое долой!
-    Ok(()) // Груз в трюме — полный вперёд!
+    Ok(affected) // Груз в трюме — полный вперёд!
 }
    
    // Обновляем добычу — подкручиваем гайки с проверкой!
-    async fn execute_update(&self, query: Query) -> Result<(), DbError> {
+    // `now` — секунды, которыми считается `now()` в SET: время записи журнала, при повторе то же самое!
+    async fn execute_update(&self, query: Query, now: i64) -> Result<Vec<Row>, DbError> {
+        let mut affected = Vec::new(); // Поправленные строки — уже с новыми ценностями!
         // Берём сундук — есть ли что добавить?
-        if let Some(table) = self.tables.get(&query.table) {
+        if let Some(table) = self.tables.get(&query.table).map(|t| t.clone()) { // Клон трюма — метку карты через .await не держим, DROP TABLE не встанет!
             // Собираем добычу — полный список!
             let rows: Vec<Row> = table.iter().map(|r| r.clone()).collect();
+            self.metrics.scanned(&query.table, rows.len());
+            let unique_fields = self.get_unique_fields(&query.table).await; // Кто тут особый?
+            // Правим уникальное — проверка дубля и запись под замком сундука, как у вставки: его держит `write` с журнала!
+            let config = self.config.read().await; // Читаем карту — где настройки?
             // Фильтруем, если есть условия — только нужное!
             let to_update = if !query.where_clauses.is_empty() {
-                self.filter_rows(&query.table, &rows, &query.where_clauses).await
+                self.filter_rows(&config, LATEST, &query.table, &query.alias, &rows, &query.where_clauses, None)?
             } else {
+                self.metrics.lookup(&query.table, false); // Без условий — правим весь сундук!
                 rows
             };
+            single_row_cas(query.expected_version, to_update.len())?; // Версия на много строк — отказ до первой правки!
             // Есть что обновить? Вперёд!
-            if let Some(update_values) = query.values.first() {
-                let config = self.config.read().await; // Читаем карту — где настройки?
+            let empty = HashMap::new();
+            let update_values = query.values.first().unwrap_or(&empty);
+            if !update_values.is_empty() || !query.set_exprs.is_empty() {
                 let table_config = config.tables.iter().find(|t| t.name == query.table)
                     .ok_or_else(|| DbError::TableNotFound(query.table.clone()))?; // Находим сундук!
-                let unique_fields = self.get_unique_fields(&query.table).await; // Кто тут особый?
-
-                for mut row in to_update {
-                    let mut new_data = row.data.clone(); // Копируем сундук — работаем с запасом!
-                    // Типизируем новые ценности — порядок в трюме!
-                    for (key, value) in update_values {
-                        let field_config = table_config.fields.iter().find(|f| f.name == *key)
-                            .ok_or_else(|| DbError::InvalidValue(key.clone(), "поле не найдено".to_string()))?;
-                        let field_type = field_config.field_type.as_str();
-                        let typed_value = match field_type {
-                            "numeric" => Value::Numeric(value.parse::<f64>()
-                                .map_err(|_| DbError::InvalidValue(key.clone(), value.clone()))?),
-                            "timestamp" => Value::Timestamp(value.parse::<i64>()
-                                .map_err(|_| DbError::InvalidValue(key.clone(), value.clone()))?),
-                            "boolean" => Value::Boolean(value.parse::<bool>()
-                                .map_err(|_| DbError::InvalidValue(key.clone(), value.clone()))?),
-                            _ => Value::Text(value.clone()),
-                        };
-                        new_data.insert(key.clone(), typed_value.clone()); // Обновляем сундук!
 
-                        // Проверяем уникальность — никаких дублей!
-                        if unique_fields.contains(key) {
-                            if table.iter().any(|r| r.id != row.id && r.data.get(key) == Some(&typed_value)) {
-                                return Err(DbError::DuplicateValue(key.clone(), value.clone())); // Кракен заметил дубликат!
-                            }
+                // Типизируем новые ценности один раз — порядок в трюме!
+                let mut typed_values = Vec::with_capacity(update_values.len());
+                for (key, value) in update_values {
+                    let field_config = table_config.field(key)
+                        .ok_or_else(|| DbError::InvalidValue(key.clone(), "поле не найдено".to_string()))?;
+                    reject_generated(Some(field_config), key)?; // Генерируемое — руками не трогать!
+                    typed_values.push((key, value, typed_value(&field_config.field_type, key, value)?));
+                }
+
+                // Выражения SET — разбираем заранее: `score + 10`, `name || '!'`, `NOT active`, `now()`!
+                let mut set_exprs = Vec::with_capacity(query.set_exprs.len());
+                for (key, source) in &query.set_exprs {
+                    let field_config = table_config.field(key)
+                        .ok_or_else(|| DbError::InvalidValue(key.clone(), "поле не найдено".to_string()))?;
+                    reject_generated(Some(field_config), key)?;
+                    if unique_fields.contains(key) { // Уникальность под замком шарда не проверить — не рискуем!
+                        return Err(DbError::InvalidValue(key.clone(), "уникальное поле задаётся только значением, не выражением".to_string()));
+                    }
+                    let e = self.prepare_expr(&config, LATEST, expr::parse(source)?)?; // Некоррелированные подзапросы — заранее!
+                    // `now()` — одно время на всю правку, то же, что в журнале!
+                    let e = e.rewrite(&mut |node| Ok(match node {
+                        Expr::Func(name, args) if name == "now" && args.is_empty() => Expr::Literal(Value::Timestamp(now)),
+                        node => node,
+                    }))?;
+                    let mut correlated = false;
+                    e.visit(&mut |node| if matches!(node, Expr::InSubquery(..) | Expr::Exists(_) | Expr::Subquery(_)) { correlated = true; });
+                    if correlated { // Подзапрос под замком шарда может уткнуться в тот же шард!
+                        return Err(DbError::InvalidExpression(source.clone(), "коррелированный подзапрос в SET не поддерживается".to_string()));
+                    }
+                    set_exprs.push((key, field_config.field_type.as_str(), e));
+                }
+
+                let write = self.mvcc.begin_write(); // Метка коммита — вся правка станет видна разом!
+                for row in to_update {
+                    // Проверяем уникальность — никаких дублей!
+                    for (key, value, typed_value) in &typed_values {
+                        if unique_fields.contains(*key) && table.iter().any(|r| r.id != row.id && r.data.get(*key) == Some(typed_value)) {
+                            return Err(DbError::DuplicateValue((*key).clone(), (*value).clone())); // Кракен заметил дубликат!
                         }
                     }
-                    self.update_indexes(&query.table, &row, true).await; // Убираем старые метки!
-                    row.data = new_data; // Грузим обновлённый сундук!
-                    self.update_indexes(&query.table, &row, false).await; // Новые метки — готово!
-                    table.insert(row.id, row); // Обновляем трюм!
+                    // Считаем и пишем под замком шарда — от живой строки, а не от снимка:
+                    // два параллельных `score + 10` дадут +20, а не +10!
+                    let (old_row, new_row) = {
+                        let Some(mut live) = table.get_mut(&row.id) else {
+                            if query.expected_version.is_some() { // Ждали версию, а строку выкинули!
+                                return Err(DbError::Conflict(query.table.clone(), format!("строка {} удалена", row.id)));
+                            }
+                            continue; // Уже выкинули — мимо!
+                        };
+                        if let Some(expected) = query.expected_version.filter(|v| *v != live.version) {
+                            return Err(DbError::Conflict(query.table.clone(), format!("строка {}: ждали версию {}, а там {}", row.id, expected, live.version)));
+                        }
+                        let old_row = live.clone();
+                        let mut new_data = old_row.data.clone(); // Копируем сундук — работаем с запасом!
+                        for (key, _, typed_value) in &typed_values {
+                            new_data.insert((*key).clone(), typed_value.clone()); // Обновляем сундук!
+                        }
+                        let scope = Scope { db: self, config: &config, snapshot: LATEST, alias: &query.alias, row: Some(&old_row), row_set: &[], outer: None };
+                        for (key, field_type, e) in &set_exprs {
+                            match e.eval(&scope)? {
+                                Some(v) => { new_data.insert((*key).clone(), expr::cast(v, field_type)?); }
+                                None => { new_data.remove(*key); } // NULL — поле пустует!
+                            }
+                        }
+                        table_config.apply_generated(&mut new_data)?; // Генерируемые — пересчитываем!
+                        self.mvcc.record(&query.table, old_row.clone(), write.ts()); // Старую версию — в историю, снимкам!
+                        live.data = new_data; // Грузим обновлённый сундук!
+                        live.version += 1; // Новая версия — кто читал старую, тот опоздал!
+                        live.commit_ts = write.ts();
+                        (old_row, live.clone())
+                    }; // Замок снят — метки правим уже без него!
+                    self.dirty.mark(&query.table, new_row.id);
+                    self.update_indexes(&query.table, &old_row, true).await; // Убираем старые метки!
+                    self.update_indexes(&query.table, &new_row, false).await; // Новые метки — готово!
+                    affected.push(new_row);
                 }
                 // Не сохраняем сразу на диск — WAL уже зафиксировал изменения!
                 self.join_cache.retain(|key, _| !key.contains(&query.table)); // Чистим кэш — без хлама!
             }
         }
-        Ok(()) // Обновили — корабль в строю!
+        Ok(affected) // Обновили — корабль в строю!
     }
 
     // Удаляем добычу — чистим трюм от лишнего с проверкой!
-    async fn execute_delete(&self, query: Query) -> Result<(), DbError> {
+    async fn execute_delete(&self, query: Query) -> Result<Vec<Row>, DbError> {
         // Есть сундук? Убираем ненужное!
-        if let Some(table) = self.tables.get(&query.table) {
+        if let Some(table) = self.tables.get(&query.table).map(|t| t.clone()) {
             // Собираем добычу — полный список!
             let rows: Vec<Row> = table.iter().map(|r| r.clone()).collect();
+            self.metrics.scanned(&query.table, rows.len());
             // Фильтруем добычу — что под нож?
-            let to_delete = self.filter_rows(&query.table, &rows, &query.where_clauses).await;
-            for row in to_delete {
-                self.update_indexes(&query.table, &row, true).await; // Убираем метки — следов не будет!
-                table.remove(&row.id); // Выкидываем за борт — чистота!
+            let config = self.config.read().await; // Подзапросам в условиях нужна карта!
+            let to_delete = self.filter_rows(&config, LATEST, &query.table, &query.alias, &rows, &query.where_clauses, None)?;
+            drop(config);
+            single_row_cas(query.expected_version, to_delete.len())?;
+            let mut deleted = Vec::with_capacity(to_delete.len());
+            let write = self.mvcc.begin_write(); // Метка коммита — удаление станет видно разом!
+            // Сначала в историю, потом за борт — снимок не потеряет строку посередине!
+            let retire = |live: &Row| self.mvcc.record(&query.table, live.clone(), write.ts());
+            for row in &to_delete {
+                // Выкидываем за борт — с версией только если её никто не успел поменять!
+                let removed = match query.expected_version {
+                    Some(expected) => match table.remove_if(&row.id, |_, live| live.version == expected && { retire(live); true }) {
+                        Some(removed) => Some(removed),
+                        None => {
+                            let actual = table.get(&row.id).map_or("ничего — строка удалена".to_string(), |live| live.version.to_string());
+                            return Err(DbError::Conflict(query.table.clone(), format!("строка {}: ждали версию {}, а там {}", row.id, expected, actual)));
+                        }
+                    },
+                    None => table.remove_if(&row.id, |_, live| { retire(live); true }),
+                };
+                if let Some((_, removed)) = removed {
+                    self.dirty.mark(&query.table, removed.id);
+                    self.update_indexes(&query.table, &removed, true).await; // Убираем метки — следов не будет!
+                    deleted.push(removed);
+                }
             }
             // Не сохраняем сразу на диск — WAL уже зафиксировал изменения!
             self.join_cache.retain(|key, _| !key.contains(&query.table)); // Чистим кэш — без остатков!
+            return Ok(deleted); // Выкинутые — для RETURNING!
         }
-        Ok(()) // Чисто — полный вперёд!
+        Ok(Vec::new()) // Чисто — полный вперёд!
     }
 }
 
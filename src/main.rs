@@ -1,7 +1,6 @@
 use std::io::{self, Write}; // Ввод-вывод — как связь с мостика на астероид!
-use tokio; // Асинхронный движок — гиперпространство в деле!
 use tokio::time::Duration; // Добавляем Duration для задержек
-use yuaidb::{Database, Condition, Query}; // База данных — наш звёздный архив!
+use yuaidb::{Database, DbError, Condition, Query, SequenceOptions, Format, RecoveryTarget, Migration, IndexKind, FieldConfig}; // База данных — наш звёздный архив!
 use colored::*; // Цвета — голограммы для космической карты!
 
 // Парсим поля вида <field>:<value> — сканируем добычу с орбиты!
@@ -16,18 +15,22 @@ fn parse_fields(parts: &[&str]) -> Result<Vec<(String, String)>, String> {
 
             if start_with_quote { // Ловим длинный текст — как сообщение с далёкой планеты!
                 let mut in_quotes = true; // Лазерный щит активен — ждём закрытия!
-                let start = if value_start.len() > 1 { &value_start[1..] } else { "" }; // Отрезаем кавычку — чистим сигнал!
+                let mut start = if value_start.len() > 1 { &value_start[1..] } else { "" }; // Отрезаем кавычку — чистим сигнал!
+                if start.ends_with('"') && !start.ends_with("\\\"") { // Одно слово в кавычках — сигнал уже закрыт!
+                    start = &start[..start.len() - 1];
+                    in_quotes = false;
+                }
                 value.push_str(start); // Первый кусок в трюм!
 
                 i += 1; // Движемся по орбите — дальше в космос!
                 while i < parts.len() && in_quotes { // Сканируем до конца шифра!
                     let part = parts[i];
                     if part.ends_with('"') && !part.ends_with("\\\"") { // Конец сигнала — без подвоха!
-                        value.push_str(" "); // Пробел — как пустота между звёздами!
+                        value.push(' '); // Пробел — как пустота между звёздами!
                         value.push_str(&part[..part.len() - 1]); // Добавляем чистый кусок!
                         in_quotes = false; // Щит снят — сообщение получено!
                     } else {
-                        value.push_str(" "); // Пробел — соединяем обломки!
+                        value.push(' '); // Пробел — соединяем обломки!
                         value.push_str(part); // Кидаем в трюм!
                     }
                     i += 1; // Следующий сектор!
@@ -53,6 +56,263 @@ fn parse_fields(parts: &[&str]) -> Result<Vec<(String, String)>, String> {
     }
 }
 
+// Делим список по запятым верхнего уровня — запятые внутри скобок и кавычек не трогаем!
+fn split_top_level(input: &str) -> Vec<String> {
+    let mut items = Vec::new(); // Осколки списка — каждый в свой отсек!
+    let mut current = String::new(); // Текущий осколок!
+    let mut depth = 0; // Глубина скобок — как глубина чёрной дыры!
+    let mut quote: Option<char> = None; // Внутри кавычек? Какой?
+    for c in input.chars() {
+        match (quote, c) {
+            (Some(q), _) if c == q => { quote = None; current.push(c); } // Кавычка закрылась!
+            (Some(_), _) => current.push(c), // Внутри кавычек — всё подряд!
+            (None, '\'' | '"') => { quote = Some(c); current.push(c); } // Кавычка открылась!
+            (None, '(') => { depth += 1; current.push(c); }
+            (None, ')') => { depth -= 1; current.push(c); }
+            (None, ',') if depth == 0 => items.push(std::mem::take(&mut current).trim().to_string()), // Разделитель!
+            _ => current.push(c),
+        }
+    }
+    if !current.trim().is_empty() {
+        items.push(current.trim().to_string()); // Последний осколок!
+    }
+    items
+}
+
+// Разбираем хвост ON CONFLICT — `name do nothing` или `name do update ship_id:102`!
+fn parse_on_conflict(parts: &[&str], query: &mut Query) -> Result<(), String> {
+    let do_idx = parts.iter().position(|p| p.eq_ignore_ascii_case("do"))
+        .ok_or_else(|| "Ошибка: после 'on conflict' нужен 'do nothing' или 'do update' — курс не ясен!".to_string())?;
+    match parts.get(..do_idx) {
+        Some([field]) => { query.on_conflict(field.trim_matches(|c| c == '(' || c == ')')); } // Цель столкновения!
+        Some([]) => {} // Без цели — любое уникальное поле!
+        _ => return Err("Ошибка: в 'on conflict' можно указать только одно поле!".to_string()),
+    }
+    match parts.get(do_idx + 1).map(|p| p.to_lowercase()).as_deref() {
+        Some("nothing") => { query.do_nothing(); }
+        Some("update") => {
+            let rest = parts.get(do_idx + 2..).unwrap_or_default();
+            let rest = if rest.first().is_some_and(|p| p.eq_ignore_ascii_case("set")) { &rest[1..] } else { rest };
+            let fields = parse_fields(rest)?; // Пусто — parse_fields сам ругнётся!
+            query.do_update(fields.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect::<Vec<_>>());
+        }
+        _ => return Err("Ошибка: после 'do' ждём 'nothing' или 'update'!".to_string()),
+    }
+    Ok(())
+}
+
+// Разбираем настройки CREATE SEQUENCE — `start 100 increment 5 minvalue 1 maxvalue 999 cycle`!
+fn parse_sequence_options(parts: &[&str]) -> Result<SequenceOptions, String> {
+    let mut options = SequenceOptions::default();
+    let mut i = 0;
+    while i < parts.len() {
+        let key = parts[i].to_lowercase();
+        if key == "cycle" {
+            options.cycle = true; // По кругу — как стрелки компаса!
+            i += 1;
+            continue;
+        }
+        let number = parts.get(i + 1).and_then(|v| v.parse::<i64>().ok())
+            .ok_or_else(|| format!("Ошибка: после '{}' нужно целое число!", parts[i]))?;
+        match key.as_str() {
+            "start" => options.start = Some(number),
+            "increment" => options.increment = number,
+            "minvalue" => options.min_value = Some(number),
+            "maxvalue" => options.max_value = Some(number),
+            _ => return Err(format!("Ошибка: неизвестная настройка '{}' — ждём start, increment, minvalue, maxvalue, cycle!", parts[i])),
+        }
+        i += 2;
+    }
+    Ok(options)
+}
+
+// Разбираем миграцию — `add rank numeric default 1`, `drop rank`, `rename name nick`, `type score numeric using ...`, `index name unique`!
+fn parse_migration(table: &str, parts: &[&str]) -> Result<Migration, String> {
+    let table = table.to_string();
+    let arg = |i: usize, what: &str| parts.get(i).map(|p| p.to_string()).ok_or_else(|| format!("Ошибка: не хватает {}!", what));
+    let tail = |i: usize, word: &str| match parts.get(i) {
+        Some(w) if w.eq_ignore_ascii_case(word) && parts.len() > i + 1 => Ok(Some(parts[i + 1..].join(" ").trim_matches('"').to_string())),
+        None => Ok(None),
+        Some(w) => Err(format!("Ошибка: ждём '{} <значение>', а пришло '{}'!", word, w)),
+    };
+    match parts.first().map(|p| p.to_lowercase()).as_deref() {
+        Some("add") => Ok(Migration::AddColumn {
+            field: FieldConfig::new(&arg(1, "имени поля")?, &arg(2, "типа поля")?.to_lowercase()),
+            default: tail(3, "default")?,
+            table,
+        }),
+        Some("drop") => Ok(Migration::DropColumn { column: arg(1, "имени поля")?, table }),
+        Some("rename") => Ok(Migration::RenameColumn { from: arg(1, "старого имени")?, to: arg(2, "нового имени")?, table }),
+        Some("type") => Ok(Migration::ChangeType {
+            column: arg(1, "имени поля")?,
+            field_type: arg(2, "типа поля")?.to_lowercase(),
+            using: tail(3, "using")?,
+            table,
+        }),
+        Some("index") => {
+            let kind = match parts.get(2).map(|k| k.to_lowercase()).as_deref() {
+                None => IndexKind::Index,
+                Some("unique") => IndexKind::Unique,
+                Some("fulltext") => IndexKind::Fulltext,
+                Some(other) => return Err(format!("Ошибка: метка '{}' — бывают unique и fulltext!", other)),
+            };
+            Ok(Migration::AddIndex { column: arg(1, "имени поля")?, kind, table })
+        }
+        _ => Err("Ошибка: migrate <ангар> add|drop|rename|type|index ... (например, migrate pirates rename name nick)".to_string()),
+    }
+}
+
+// Формат файла — явный хвост `csv`/`jsonl` или по расширению!
+fn file_format(path: &str, explicit: Option<&&str>) -> Result<Format, String> {
+    match explicit.map(|f| f.to_lowercase()).as_deref() {
+        Some("csv") => Ok(Format::Csv),
+        Some("jsonl" | "ndjson" | "json") => Ok(Format::JsonLines),
+        #[cfg(feature = "columnar")]
+        Some("arrow" | "feather") => Ok(Format::ArrowIpc),
+        #[cfg(feature = "columnar")]
+        Some("parquet") => Ok(Format::Parquet),
+        Some(other) => Err(format!("Ошибка: формат '{}' не знаем — есть csv и jsonl!", other)),
+        None => Format::from_path(path)
+            .ok_or_else(|| format!("Ошибка: по имени '{}' формат не угадать — допишите csv или jsonl!", path)),
+    }
+}
+
+// Имя последовательности — `tickets` или `('tickets')`, как в SQL!
+fn sequence_name(part: &str) -> &str {
+    part.trim_matches(|c| c == '(' || c == ')' || c == '\'' || c == '"')
+}
+
+// Выражения SET — поле и текст выражения!
+type SetExprs = Vec<(String, String)>;
+
+// Выцепляем из SET выражения вида `score=(score + 10)` — остальное остаётся для parse_fields!
+fn take_set_exprs<'a>(parts: &[&'a str]) -> Result<(Vec<&'a str>, SetExprs), String> {
+    let mut rest = Vec::new(); // Обычные поле:значение!
+    let mut exprs = Vec::new(); // Поле и выражение!
+    let mut i = 0;
+    while i < parts.len() {
+        match parts[i].split_once("=(") {
+            Some((field, tail)) if !field.contains(':') => {
+                let head = format!("({}", tail); // Скобку возвращаем на место!
+                let mut pieces = vec![head.as_str()];
+                pieces.extend_from_slice(&parts[i + 1..]);
+                let mut j = 0;
+                exprs.push((field.to_string(), take_parenthesized(&pieces, &mut j)?));
+                i += j; // Перепрыгиваем выражение целиком!
+            }
+            _ => {
+                rest.push(parts[i]);
+                i += 1;
+            }
+        }
+    }
+    Ok((rest, exprs))
+}
+
+// Отрезаем хвост `returning id, name` — снаружи скобок, чтобы не задеть подзапросы!
+fn take_returning(parts: &mut Vec<&str>) -> Vec<String> {
+    let mut depth = 0i32;
+    let idx = parts.iter().position(|p| {
+        let top = depth == 0 && p.eq_ignore_ascii_case("returning");
+        depth += p.matches('(').count() as i32 - p.matches(')').count() as i32;
+        top
+    });
+    match idx {
+        Some(idx) if !parts[0].eq_ignore_ascii_case("select") => {
+            let fields = split_top_level(&parts[idx + 1..].join(" "));
+            parts.truncate(idx);
+            fields
+        }
+        _ => Vec::new(), // У SELECT и так всё возвращается!
+    }
+}
+
+// Запускаем запись — считаем тронутые строки и показываем RETURNING!
+async fn run_write(mut query: Query, returning: &[String], db: &Database) -> Result<(), DbError> {
+    if !returning.is_empty() {
+        query.returning(returning.iter().map(|f| f.as_str()).collect());
+    }
+    let (count, rows) = query.execute_affected(db).await?;
+    println!("{}", format!("Тронуто строк: {}", count).green()); // Счёт на мостик!
+    for row in rows.into_iter().flatten() {
+        println!("{}", format!("Вернулось с орбиты: {:?}", row).green()); // RETURNING!
+    }
+    Ok(())
+}
+
+// Ангары для записи — спрашиваем у служебного сундука, а не лезем в трюм базы!
+async fn table_names(db: &Database) -> Vec<String> {
+    let mut query = db.select("yuaidb_tables");
+    query.fields(vec!["name"]).where_eq("kind", "table");
+    match query.execute(db).await {
+        Ok(rows) => rows.unwrap_or_default().into_iter().filter_map(|mut row| row.remove("name")).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Ловим выражение в скобках — `(speed * 100 > 50)`, даже если оно разбито пробелами!
+fn take_parenthesized(parts: &[&str], i: &mut usize) -> Result<String, String> {
+    let mut depth = 0i32; // Глубина скобок!
+    let mut pieces = Vec::new(); // Куски выражения!
+    while *i < parts.len() {
+        let part = parts[*i];
+        depth += part.matches('(').count() as i32 - part.matches(')').count() as i32;
+        pieces.push(part);
+        *i += 1;
+        if depth <= 0 {
+            let joined = pieces.join(" ");
+            let joined = joined.trim_end_matches(','); // Запятая после скобки — это уже разделитель списка!
+            return Ok(joined[1..joined.len() - 1].trim().to_string()); // Снимаем внешние скобки!
+        }
+    }
+    Err("Ошибка: незакрытая скобка в выражении — штурман потерял курс!".to_string())
+}
+
+// Парсим ORDER BY — ключи через запятую: `speed desc nulls last, name asc`!
+fn parse_order_by(parts: &[&str], i: &mut usize, query: &mut Query) -> Result<(), String> {
+    loop {
+        if *i >= parts.len() { // Нет поля? Ошибка!
+            return Err("Ошибка: укажите поле после 'order by' — звёзды в хаосе!".to_string());
+        }
+        let field = if parts[*i].starts_with('(') { // Выражение в скобках — сортируем по расчёту!
+            take_parenthesized(parts, i)?
+        } else {
+            *i += 1;
+            parts[*i - 1].trim_end_matches(',').to_string() // Поле для порядка!
+        };
+        let mut more = parts[*i - 1].ends_with(','); // Запятая — будет ещё ключ!
+        let mut ascending = true; // По умолчанию вверх!
+        let mut nulls_first = None; // Пустоты — как решит направление!
+        if !more && *i < parts.len() {
+            match parts[*i].trim_end_matches(',').to_lowercase().as_str() {
+                "asc" => { more = parts[*i].ends_with(','); *i += 1; } // Вверх по орбите!
+                "desc" => { ascending = false; more = parts[*i].ends_with(','); *i += 1; } // Вниз к чёрной дыре!
+                _ => {}
+            }
+        }
+        if !more && *i + 1 < parts.len() && parts[*i].to_lowercase() == "nulls" { // Где пустоты?
+            nulls_first = match parts[*i + 1].trim_end_matches(',').to_lowercase().as_str() {
+                "first" => Some(true), // Пустоты вперёд!
+                "last" => Some(false), // Пустоты в хвост!
+                other => return Err(format!("Ошибка: после 'nulls' ждём first или last, а не '{}'!", other)),
+            };
+            more = parts[*i + 1].ends_with(',');
+            *i += 2;
+        }
+        if !more && *i < parts.len() && parts[*i] == "," { // Запятая отдельным словом!
+            more = true;
+            *i += 1;
+        }
+        match nulls_first {
+            Some(nf) => query.order_by_nulls(&field, ascending, nf), // Порядок с пустотами!
+            None => query.order_by(&field, ascending), // Настраиваем порядок!
+        };
+        if !more {
+            return Ok(()); // Все ключи на борту!
+        }
+    }
+}
+
 // Парсим условия WHERE — как радар для поиска в туманности!
 fn parse_where(parts: &[&str], i: &mut usize, query: &mut Query) -> Result<(), String> {
     let mut current_group = Vec::new(); // Группа условий — как эскадра дронов!
@@ -72,6 +332,12 @@ fn parse_where(parts: &[&str], i: &mut usize, query: &mut Query) -> Result<(), S
                 return Err(format!("Ошибка: укажите условие после '{}' — радар молчит!", part.to_lowercase()));
             }
 
+            if parts[*i].starts_with('(') { // Выражение в скобках — штурман считает сам!
+                let source = take_parenthesized(parts, i)?;
+                current_group.push(Condition::Expr(source)); // Расчёт на радаре!
+                continue;
+            }
+
             let field = parts[*i]; // Поле — как звезда на карте!
             *i += 1; // Следующий сигнал!
 
@@ -158,11 +424,11 @@ fn parse_value(parts: &[&str], i: &mut usize) -> Result<String, String> {
         while *i < parts.len() && in_quotes { // Сканируем до конца!
             let part = parts[*i];
             if part.ends_with('"') && !part.ends_with("\\\"") { // Конец сигнала — чистый выход!
-                value.push_str(" "); // Пробел — как вакуум между словами!
+                value.push(' '); // Пробел — как вакуум между словами!
                 value.push_str(&part[..part.len() - 1]); // Кидаем чистый кусок!
                 in_quotes = false; // Щит снят — сигнал получен!
             } else {
-                value.push_str(" "); // Соединяем обломки!
+                value.push(' '); // Соединяем обломки!
                 value.push_str(part); // Добавляем в буфер!
             }
             *i += 1; // Следующий сектор!
@@ -202,8 +468,43 @@ fn parse_in_values(parts: &[&str], i: &mut usize) -> Result<Vec<String>, String>
     Ok(values) // Координаты собраны — дроны готовы!
 }
 
+// `recover <копия> [archive <папка>] [lsn N | time T]` — откуда, где архив и до какого места!
+fn parse_recovery(args: &[String]) -> Result<(String, Option<String>, RecoveryTarget), String> {
+    let usage = "Ошибка: recover <копия> [archive <папка>] [lsn N | time T]";
+    let (backup, rest) = args.split_first().ok_or(usage)?;
+    let mut archive = None;
+    let mut target = RecoveryTarget::Latest;
+    for pair in rest.chunks(2) {
+        let [key, value] = pair else { return Err(usage.to_string()) };
+        let number = || value.parse::<i64>().map_err(|_| format!("Ошибка: '{}' — не число!", value));
+        match key.as_str() {
+            "archive" => archive = Some(value.clone()),
+            "lsn" => target = RecoveryTarget::Lsn(number()?.max(0) as u64),
+            "time" => target = RecoveryTarget::Time(number()?),
+            _ => return Err(usage.to_string()),
+        }
+    }
+    Ok((backup.clone(), archive, target))
+}
+
 #[tokio::main]
 async fn main() {
+    // `yuaidb recover` — копию раскладываем до старта, в пустую ./data; журнал повторит сам корабль!
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("recover") {
+        let prepared = match parse_recovery(&args[1..]) {
+            Ok((backup, archive, target)) => Database::restore_backup(&backup, "./data", archive.as_deref().map(std::path::Path::new), target).await,
+            Err(e) => { println!("{}", e.yellow()); std::process::exit(1); }
+        };
+        match prepared {
+            Ok(lsn) => println!("{}", format!("Копия разложена, журнал до LSN {} — поднимаем корабль!", lsn).green()),
+            Err(e) => {
+                println!("{}", format!("Ошибка: {}!", e).yellow());
+                std::process::exit(1);
+            }
+        }
+    }
+
     // Создаём базу — наш космический корабль с архивом!
     let db = match Database::new("./data", "./config.toml").await {
         Ok(db) => db, // Корабль на орбите — готов к рейду!
@@ -213,14 +514,59 @@ async fn main() {
         }
     };
 
+    // `YUAIDB_METRICS=127.0.0.1:9100` — метрики для Prometheus по HTTP на /metrics!
+    // Ручку держим до конца main — выброшенная погасила бы сервер!
+    let _metrics_server = match std::env::var("YUAIDB_METRICS") {
+        Ok(addr) => match db.serve_metrics(&addr).await {
+            Ok(server) => Some(server),
+            Err(e) => {
+                println!("{}", format!("Метрики не подняты на {}: {}!", addr, e).yellow());
+                None
+            }
+        },
+        Err(_) => None,
+    };
+
     // Даём шпиону время на первую инициализацию
     tokio::time::sleep(Duration::from_secs(5)).await;
 
+    // `yuaidb dump|restore <файл>`, `yuaidb backup <папка>`, `yuaidb recover ...` — без мостика, сделали дело и на берег!
+    if let [command, rest @ ..] = args.as_slice() {
+        let path = rest.first().cloned().unwrap_or_default();
+        let result = match command.as_str() {
+            "dump" => db.dump(&path).await.map(|n| format!("Дамп готов: {} строк в {}", n, path)),
+            "restore" => db.restore(&path).await.map(|n| format!("Восстановлено строк: {} из {}", n, path)),
+            "backup" => db.backup_to(&path).await.map(|lsn| format!("Копия готова в {}: снимок на LSN {}", path, lsn)),
+            "recover" => Ok("Корабль восстановлен — журнал повторён!".to_string()),
+            other => Err(DbError::Generic(format!("команда '{}' не знаем — есть dump, restore, backup и recover", other))),
+        };
+        match result {
+            Ok(message) => println!("{}", message.green()),
+            Err(e) => {
+                println!("{}", format!("Ошибка: {}!", e).yellow());
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     // Приветствие с мостика — голограмма для юного пирата!
     println!("{}", "Эй, звёздный корсар! Это твой пульт управления галактической базой!".purple().bold());
     println!("{}", "Вставка: insert pirates name:\"Капитан Джек Воробот Бла Бла Бла\" ship_id:101".purple()); // Грузим добычу в трюм!
+    println!("{}", "Вставка или правка: insert sessions sid:\"abc\" user:7 on conflict sid do update user:7".purple()); // UPSERT — без дублей!
     println!("{}", "Поиск: select name from pirates where name contains \"Иван\"".purple()); // Сканируем звёзды!
+    println!("{}", "Расчёты: select upper(name) as shout, speed * 100 as pct from ships where (speed > 0.5) order by (speed * 100) desc".purple()); // Штурман в деле!
+    println!("{}", "Подзапросы: select name from pirates where (ship_id in (select ship_id from ships where speed > 0.8))".purple()); // Разведка в разведке!
     println!("{}", "Обновка: update pirates set name:\"Капитан Джек Воробот Новый\" where ship_id = 101".purple()); // Чиним дроидов!
+    println!("{}", "Счётчики: update ships set speed=(speed * 1.1) where ship_id = 101".purple()); // Прибавка без гонок!
+    println!("{}", "Что вернуть: insert pirates name:\"Джек\" ship_id:101 returning id, name".purple()); // RETURNING — покажи, что вставил!
+    println!("{}", "Счётчики-последовательности: create sequence tickets start 100 increment 5 cycle / nextval tickets / setval tickets 500 / drop sequence tickets".purple()); // Номера без повторов!
+    println!("{}", "Погрузка пачкой: copy pirates from pirates.txt (строки как в insert: name:\"Джек\" ship_id:101)".purple()); // Миллион за раз!
+    println!("{}", "Файлы: import pirates from pirates.csv / export pirates to pirates.jsonl".purple()); // Чужие порты!
+    println!("{}", "Схема: create table ships (name text unique, speed numeric) / alter table ships add crew numeric default 0 / create index on ships (speed) / drop table ships".purple()); // Ангары на ходу!
+    println!("{}", "Миграции: migrate pirates add rank numeric default 1 / drop rank / rename name nick / type score numeric using ... / index name unique".purple()); // Перестройка на ходу!
+    println!("{}", "Карта: reload check (что поменяется) / reload (применить)".purple()); // Свежие координаты!
+    println!("{}", "Метрики: metrics (или YUAIDB_METRICS=127.0.0.1:9100 — HTTP на /metrics)".purple()); // Приборы мостика!
     println!("{}", "Чистка: delete from pirates where name = \"Капитан Джек Воробот Бла Бла Бла\"".purple()); // Выкидываем мусор в чёрную дыру!
     println!("{}", "- exit (сматываемся с орбиты)".purple()); // Пора в гиперпространство!
 
@@ -263,9 +609,10 @@ async fn main() {
             continue;
         }
 
-        let parts: Vec<&str> = input.split_whitespace().collect(); // Разбиваем приказ на куски — как метеоритный дождь!
+        let mut parts: Vec<&str> = input.split_whitespace().collect(); // Разбиваем приказ на куски — как метеоритный дождь!
+        let returning = take_returning(&mut parts); // RETURNING — хвост для записи!
 
-        match parts.get(0).map(|s| s.to_lowercase()).as_deref() { // Декодируем первую команду!
+        match parts.first().map(|s| s.to_lowercase()).as_deref() { // Декодируем первую команду!
             Some("insert") => { // Грузим добычу в трюм!
                 if parts.len() < 3 { // Сигнал короткий? Ошибка в протоколе!
                     println!("{}", "Ошибка: укажите ангар и хотя бы одно поле (например, insert pirates name:\"Джек\")".yellow());
@@ -273,17 +620,27 @@ async fn main() {
                 }
 
                 let table = parts[1]; // Ангар для добычи — где прячем?
-                match parse_fields(&parts[2..]) { // Сканируем груз!
+                // ON CONFLICT [поле] DO NOTHING | DO UPDATE поле:значение ... — хвост команды!
+                let conflict_idx = (2..parts.len().saturating_sub(1))
+                    .find(|&i| parts[i].eq_ignore_ascii_case("on") && parts[i + 1].eq_ignore_ascii_case("conflict"));
+                let fields_end = conflict_idx.unwrap_or(parts.len());
+                match parse_fields(&parts[2..fields_end]) { // Сканируем груз!
                     Ok(fields) => {
                         let fields_ref: Vec<(&str, &str)> = fields.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect(); // Преобразуем в сигнал для дроидов!
                         let mut query = db.insert(table); // Новый запрос — ангар готов!
                         query.values(fields_ref.clone()); // Грузим добычу!
+                        if let Some(idx) = conflict_idx {
+                            if let Err(e) = parse_on_conflict(&parts[idx + 2..], &mut query) {
+                                println!("{}", e.yellow());
+                                continue;
+                            }
+                        }
                         
                         // Новый комментарий: Проверяем наличие ангара в базе динамически
-                        let available_tables: Vec<String> = db.tables.iter().map(|t| t.key().clone()).collect();
-                        if db.tables.contains_key(table) { // Ангар на карте!
+                        let available_tables = table_names(&db).await;
+                        if available_tables.iter().any(|t| t == table) { // Ангар на карте!
                             println!("{}", format!("Грузим добычу в ангар '{}': {:?}", table, fields).green()); // Сигнал на мостик!
-                            if let Err(e) = query.execute(&db).await { // Пробуем спрятать груз!
+                            if let Err(e) = run_write(query, &returning, &db).await { // Пробуем спрятать груз!
                                 println!("{}", format!("Космический шторм помешал: {}!", e).yellow()); // Сбой в гиперпространстве!
                             } else {
                                 println!("{}", "Добыча в ангаре — полный порядок!".green()); // Успех — звёзды наши!
@@ -301,18 +658,47 @@ async fn main() {
                     continue;
                 }
 
-                let from_idx = parts.iter().position(|&p| p.to_lowercase() == "from"); // Ищем маяк FROM!
-                if from_idx.is_none() || from_idx.unwrap() < 2 { // Нет маяка? Сбой на радаре!
+                // DISTINCT или DISTINCT ON (...) — сразу после select!
+                let mut fields_start = 1; // Где начинаются поля!
+                let mut distinct = false; // Близнецов выкидываем?
+                let mut distinct_on = Vec::new(); // Ключи для кучек!
+                if parts[1].to_lowercase() == "distinct" {
+                    fields_start = 2;
+                    if parts[2].to_lowercase() == "on" { // DISTINCT ON (поля)!
+                        fields_start = 3;
+                        match take_parenthesized(&parts, &mut fields_start) {
+                            Ok(on) => distinct_on = split_top_level(&on),
+                            Err(e) => { println!("{}", e.yellow()); continue; }
+                        }
+                    } else {
+                        distinct = true;
+                    }
+                }
+
+                // Ищем маяк FROM — только снаружи скобок, у подзапросов свой FROM!
+                let mut depth = 0i32;
+                let from_idx = parts.iter().enumerate().skip(fields_start).find(|(_, p)| {
+                    let top = depth == 0 && p.to_lowercase() == "from";
+                    depth += p.matches('(').count() as i32 - p.matches(')').count() as i32;
+                    top
+                }).map(|(i, _)| i);
+                if from_idx.is_none() || from_idx.unwrap() <= fields_start || from_idx.unwrap() + 1 >= parts.len() { // Нет маяка? Сбой на радаре!
                     println!("{}", "Ошибка: укажите 'from' после полей — координаты потеряны!".yellow());
                     continue;
                 }
                 let from_idx = from_idx.unwrap(); // Маяк пойман!
 
-                let fields_str = parts[1..from_idx].join(" "); // Собираем поля — как звёзды в созвездии!
-                let fields = fields_str.split(',').map(|s| s.trim()).collect::<Vec<&str>>(); // Разделяем сигналы!
+                let fields_str = parts[fields_start..from_idx].join(" "); // Собираем поля — как звёзды в созвездии!
+                let fields = split_top_level(&fields_str); // Разделяем сигналы — функции не рвём!
                 let table = parts[from_idx + 1]; // Ангар для поиска!
                 let mut query = db.select(table); // Новый запрос — радар включён!
-                query.fields(fields); // Настраиваем сканер!
+                query.fields(fields.iter().map(|f| f.as_str()).collect()); // Настраиваем сканер!
+                if distinct {
+                    query.distinct(); // Близнецы — за борт!
+                }
+                if !distinct_on.is_empty() {
+                    query.distinct_on(distinct_on.iter().map(|f| f.as_str()).collect()); // По одному из кучки!
+                }
                 let mut i = from_idx + 2; // Курсор на орбите!
 
                 while i < parts.len() { // Сканируем дальше — что ещё на карте?
@@ -352,22 +738,11 @@ async fn main() {
                                 println!("{}", "Ошибка: укажите 'by' после 'order' — порядок потерян!".yellow());
                                 break;
                             }
-                            if i + 2 >= parts.len() { // Нет поля? Ошибка!
-                                println!("{}", "Ошибка: укажите поле после 'order by' — звёзды в хаосе!".yellow());
+                            i += 2; // Курсор на поле!
+                            if let Err(e) = parse_order_by(&parts, &mut i, &mut query) { // Сбой в порядке?
+                                println!("{}", e.yellow());
                                 break;
                             }
-                            let order_field = parts[i + 2]; // Поле для порядка!
-                            let ascending = if i + 3 < parts.len() { // Проверяем направление!
-                                match parts[i + 3].to_lowercase().as_str() {
-                                    "asc" => true, // Вверх по орбите!
-                                    "desc" => false, // Вниз к чёрной дыре!
-                                    _ => true, // По умолчанию вверх!
-                                }
-                            } else {
-                                true // Вверх, если не указано!
-                            };
-                            query.order_by(order_field, ascending); // Настраиваем порядок!
-                            i += if i + 3 < parts.len() && (parts[i + 3].to_lowercase() == "asc" || parts[i + 3].to_lowercase() == "desc") { 4 } else { 3 }; // Прыгаем дальше!
                         }
                         "limit" => { // Лимит — сколько звёзд утащить!
                             if i + 1 >= parts.len() { // Нет числа? Ошибка!
@@ -421,11 +796,20 @@ async fn main() {
                 let table = parts[1]; // Ангар для ремонта!
                 let mut i = 3; // Курсор на старте!
                 let fields_end = parts[i..].iter().position(|&p| p.to_lowercase() == "where").unwrap_or(parts.len() - i); // Ищем WHERE!
-                match parse_fields(&parts[i..i + fields_end]) { // Сканируем новый груз!
+                // Выражения `поле=(...)` — считаются от текущего значения строки!
+                let (plain, set_exprs) = match take_set_exprs(&parts[i..i + fields_end]) {
+                    Ok(split) => split,
+                    Err(e) => { println!("{}", e.yellow()); continue; }
+                };
+                let fields = if plain.is_empty() && !set_exprs.is_empty() { Ok(Vec::new()) } else { parse_fields(&plain) };
+                match fields { // Сканируем новый груз!
                     Ok(fields) => {
                         let fields_ref: Vec<(&str, &str)> = fields.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect(); // Готовим сигнал для дроидов!
                         let mut query = db.update(table); // Новый запрос — ремонтный ангар!
                         query.values(fields_ref.clone()); // Кидаем новый груз!
+                        for (field, e) in &set_exprs {
+                            query.set_expr(field, e); // Штурман посчитает под замком!
+                        }
                         i += fields_end; // Прыгаем дальше!
 
                         if i >= parts.len() { // Нет WHERE? Ошибка в протоколе!
@@ -444,10 +828,10 @@ async fn main() {
                         }
 
                         // Новый комментарий: Проверяем наличие ангара в базе динамически
-                        let available_tables: Vec<String> = db.tables.iter().map(|t| t.key().clone()).collect();
-                        if db.tables.contains_key(table) { // Ангар на карте!
+                        let available_tables = table_names(&db).await;
+                        if available_tables.iter().any(|t| t == table) { // Ангар на карте!
                             println!("{}", format!("Обновляем добычу в ангаре '{}': {:?}", table, fields).green()); // Сигнал на мостик!
-                            if let Err(e) = query.execute(&db).await { // Пробуем чинить!
+                            if let Err(e) = run_write(query, &returning, &db).await { // Пробуем чинить!
                                 println!("{}", format!("Ошибка при обновлении добычи: {}!", e).yellow()); // Сбой в ангаре!
                             } else {
                                 println!("{}", "Добыча обновлена — ангар в порядке!".green()); // Успех — звёзды сияют!
@@ -520,10 +904,10 @@ async fn main() {
                 }
 
                 // Проверяем наличие ангара в базе динамически
-                let available_tables: Vec<String> = db.tables.iter().map(|t| t.key().clone()).collect();
-                if db.tables.contains_key(table) { // Ангар на карте!
+                let available_tables = table_names(&db).await;
+                if available_tables.iter().any(|t| t == table) { // Ангар на карте!
                     println!("{}", format!("Выкидываем мусор из ангара '{}'", table).green()); // Сигнал на мостик!
-                    if let Err(e) = query.execute(&db).await { // Пробуем чистить!
+                    if let Err(e) = run_write(query, &returning, &db).await { // Пробуем чистить!
                         println!("{}", format!("Ошибка при выбросе в чёрную дыру: {}!", e).yellow()); // Сбой в ангаре!
                     } else {
                         println!("{}", "Мусор в космосе — ангар чист!".green()); // Успех — порядок на орбите!
@@ -532,11 +916,160 @@ async fn main() {
                     println!("{}", format!("Ошибка: неизвестный ангар '{}'. Доступны: {}", table, available_tables.join(", ")).yellow()); // Чужой сектор!
                 }
             }
+            Some("create") if parts.get(1).is_some_and(|p| p.eq_ignore_ascii_case("sequence")) => { // Новый счётчик в судовой журнал!
+                let Some(name) = parts.get(2) else {
+                    println!("{}", "Ошибка: укажите имя (например, create sequence tickets start 100)".yellow());
+                    continue;
+                };
+                match parse_sequence_options(&parts[3..]) {
+                    Ok(options) => match db.create_sequence(name, options).await {
+                        Ok(()) => println!("{}", format!("Последовательность '{}' заведена!", name).green()),
+                        Err(e) => println!("{}", format!("Ошибка при создании последовательности: {}!", e).yellow()),
+                    },
+                    Err(e) => println!("{}", e.yellow()),
+                }
+            }
+            Some("drop") if parts.get(1).is_some_and(|p| p.eq_ignore_ascii_case("sequence")) => { // Счётчик за борт!
+                let Some(name) = parts.get(2) else {
+                    println!("{}", "Ошибка: укажите имя (например, drop sequence tickets)".yellow());
+                    continue;
+                };
+                match db.drop_sequence(name).await {
+                    Ok(()) => println!("{}", format!("Последовательность '{}' выброшена за борт!", name).green()),
+                    Err(e) => println!("{}", format!("Ошибка при удалении последовательности: {}!", e).yellow()),
+                }
+            }
+            Some("create" | "drop" | "alter") => { // Перестраиваем ангары — CREATE/DROP/ALTER TABLE и метки!
+                match db.execute_ddl(input).await {
+                    Ok(Some(report)) => println!("{}", format!("Готово: миграция №{}, переписано строк: {}", report.id, report.rows).green()),
+                    Ok(None) => println!("{}", "Готово — карта обновлена!".green()),
+                    Err(e) => println!("{}", format!("Ошибка схемы: {}!", e).yellow()),
+                }
+            }
+            Some(cmd) if cmd.starts_with("nextval") => { // Следующий номер!
+                let name = match parts.get(1) {
+                    Some(name) => sequence_name(name),
+                    None => sequence_name(&parts[0]["nextval".len()..]), // nextval('tickets') — одним куском!
+                };
+                match db.nextval(name).await {
+                    Ok(value) => println!("{}", format!("Следующий номер: {}", value).green()), // Номер на мостик!
+                    Err(e) => println!("{}", format!("Ошибка при выдаче номера: {}!", e).yellow()),
+                }
+            }
+            Some("setval") => { // Переставляем счётчик!
+                let (Some(name), Some(value)) = (parts.get(1), parts.get(2).and_then(|v| v.parse::<i64>().ok())) else {
+                    println!("{}", "Ошибка: укажите имя и целое число (например, setval tickets 500)".yellow());
+                    continue;
+                };
+                match db.setval(sequence_name(name), value).await {
+                    Ok(()) => println!("{}", format!("Последовательность '{}' переставлена на {}!", sequence_name(name), value).green()),
+                    Err(e) => println!("{}", format!("Ошибка при установке номера: {}!", e).yellow()),
+                }
+            }
+            Some("copy") => { // Массовая погрузка — строки в формате insert, по одной на строку!
+                let (Some(table), Some(from), Some(source)) = (parts.get(1), parts.get(2), parts.get(3)) else {
+                    println!("{}", "Ошибка: copy <ангар> from <файл|stdin> (например, copy pirates from pirates.txt)".yellow());
+                    continue;
+                };
+                if !from.eq_ignore_ascii_case("from") {
+                    println!("{}", "Ошибка: после ангара ждём 'from'!".yellow());
+                    continue;
+                }
+                let source = source.trim_matches(|c| c == '\'' || c == '"');
+                let lines: Vec<String> = if source.eq_ignore_ascii_case("stdin") {
+                    println!("{}", "Шлите строки вида name:\"Джек\" ship_id:101, конец — строка \\.".purple());
+                    io::stdin().lines().map_while(Result::ok).take_while(|l| l.trim() != "\\.").collect()
+                } else {
+                    match std::fs::read_to_string(source) {
+                        Ok(text) => text.lines().map(str::to_string).collect(),
+                        Err(e) => {
+                            println!("{}", format!("Ошибка: файл '{}' не читается: {}!", source, e).yellow());
+                            continue;
+                        }
+                    }
+                };
+                // Разбираем строки — кривые сразу в отчёт, номер строки — как в файле!
+                let mut rows = Vec::new();
+                let mut numbers = Vec::new();
+                let mut bad = Vec::new();
+                for (i, line) in lines.iter().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+                    match parse_fields(&line.split_whitespace().collect::<Vec<_>>()) {
+                        Ok(fields) => { rows.push(fields.into_iter().collect()); numbers.push(i + 1); }
+                        Err(e) => bad.push((i + 1, e)),
+                    }
+                }
+                match db.bulk_load(table, rows).await {
+                    Ok(report) => {
+                        println!("{}", format!("Загружено строк: {}", report.loaded).green()); // Груз в трюме!
+                        let errors = report.errors.into_iter().map(|(row, e)| (numbers[row - 1], e.to_string()));
+                        let mut errors: Vec<(usize, String)> = errors.chain(bad).collect();
+                        errors.sort_by_key(|(line, _)| *line);
+                        for (line, e) in errors {
+                            println!("{}", format!("Строка {}: {}", line, e).yellow()); // Битая строка — в отчёт!
+                        }
+                    }
+                    Err(e) => println!("{}", format!("Ошибка при погрузке: {}!", e).yellow()),
+                }
+            }
+            Some("migrate") => { // Перестраиваем ангар на ходу — поля вместе с грузом!
+                let Some(table) = parts.get(1) else {
+                    println!("{}", "Ошибка: migrate <ангар> add|drop|rename|type|index ...".yellow());
+                    continue;
+                };
+                match parse_migration(table, &parts[2..]) {
+                    Ok(migration) => match db.migrate(migration).await {
+                        Ok(report) => println!("{}", format!("Миграция №{} готова, переписано строк: {}", report.id, report.rows).green()),
+                        Err(e) => println!("{}", format!("Ошибка миграции: {}!", e).yellow()),
+                    },
+                    Err(e) => println!("{}", e.yellow()),
+                }
+            }
+            Some(cmd @ ("import" | "export")) => { // Груз из чужого порта или в чужой порт!
+                let direction = if cmd == "import" { "from" } else { "to" };
+                let (Some(table), Some(word), Some(path)) = (parts.get(1), parts.get(2), parts.get(3)) else {
+                    println!("{}", format!("Ошибка: {} <ангар> {} <файл> [csv|jsonl]", cmd, direction).yellow());
+                    continue;
+                };
+                if !word.eq_ignore_ascii_case(direction) {
+                    println!("{}", format!("Ошибка: после ангара ждём '{}'!", direction).yellow());
+                    continue;
+                }
+                let path = path.trim_matches(|c| c == '\'' || c == '"');
+                let format = match file_format(path, parts.get(4)) {
+                    Ok(format) => format,
+                    Err(e) => { println!("{}", e.yellow()); continue; }
+                };
+                if cmd == "export" {
+                    match db.export(table, path, format).await {
+                        Ok(count) => println!("{}", format!("Выгружено строк: {} в {}", count, path).green()),
+                        Err(e) => println!("{}", format!("Ошибка при выгрузке: {}!", e).yellow()),
+                    }
+                    continue;
+                }
+                match db.import(table, path, format).await {
+                    Ok(report) => {
+                        println!("{}", format!("Загружено строк: {}", report.loaded).green()); // Груз в трюме!
+                        for (line, e) in report.errors {
+                            println!("{}", format!("Строка {}: {}", line, e).yellow()); // Битая строка — в отчёт!
+                        }
+                    }
+                    Err(e) => println!("{}", format!("Ошибка при погрузке: {}!", e).yellow()),
+                }
+            }
+            Some("reload") => { // Перечитываем карту — или только смотрим, что она поменяет!
+                let dry = parts.get(1).is_some_and(|p| p.eq_ignore_ascii_case("check"));
+                let result = if dry { db.preview_config().await } else { db.reload_config().await };
+                match result {
+                    Ok(report) => println!("{}", report.to_string().green()),
+                    Err(e) => println!("{}", format!("Ошибка: карта не перечитана: {}!", e).yellow()),
+                }
+            }
+            Some("metrics") => print!("{}", db.metrics_text()), // Приборы мостика — как их видит Prometheus!
             Some("exit") => { // Сматываемся с орбиты!
                 println!("{}", "До новых звёздных рейдов, корсар!".green()); // Прощальный сигнал!
                 break; // Прыжок в гиперпространство!
             }
-            Some(cmd) => println!("{}", format!("Неизвестный сигнал: {}. Доступны: insert, select, update, delete, exit", cmd).yellow()), // Чужая команда!
+            Some(cmd) => println!("{}", format!("Неизвестный сигнал: {}. Доступны: insert, select, update, delete, copy, import, export, migrate, reload, metrics, create/drop/alter table, create/drop index, create sequence, drop sequence, nextval, setval, exit", cmd).yellow()), // Чужая команда!
             None => continue, // Пустой эфир — ждём дальше!
         }
     }
@@ -0,0 +1,440 @@
+// Метрики — счётчики и гистограммы корабля: запросы, перебор и метки, журнал, точки сохранения, просрочка и память!
+// Забираются через `db.metrics()` или текстом Prometheus — из `db.metrics_text()` или по HTTP с `/metrics`.
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use dashmap::DashMap;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use crate::{system, Database, DbConfig, DbError, Hasher, QueryOp, Row, TableData, Value};
+
+// Вёдра задержки запроса (сек) — от десятой доли миллисекунды до секунды!
+const QUERY_BUCKETS: &[f64] = &[0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+// Вёдра точки сохранения (сек) — она дольше, сундуки пишутся на диск!
+const CHECKPOINT_BUCKETS: &[f64] = &[0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+// Запрос к `/metrics` — больше заголовков не ждём!
+const MAX_REQUEST: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// Шторм на accept (EMFILE и прочие) — пережидаем, удваивая паузу до потолка!
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+// Сундук не с карты — все такие запросы в одной серии, опечатки не плодят новых!
+const UNKNOWN_TABLE: &str = "unknown";
+
+// fsync на весь процесс — его делают файлы поколений и атомарные записи, у которых нет своей базы под рукой!
+pub(crate) static FSYNCS: AtomicU64 = AtomicU64::new(0);
+
+// Гистограмма — счёт по вёдрам, сумма в микросекундах и общий счёт!
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<AtomicU64>,   // По ведру на границу и ещё одно — для всего, что дольше!
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = self.bounds.iter().position(|b| seconds <= *b).unwrap_or(self.bounds.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let mut total = 0;
+        let buckets = self.bounds.iter().zip(&self.counts).map(|(bound, count)| {
+            total += count.load(Ordering::Relaxed);
+            (*bound, total)
+        }).collect();
+        HistogramSnapshot {
+            buckets,
+            count: self.count.load(Ordering::Relaxed),
+            sum: self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+        }
+    }
+}
+
+// Снимок гистограммы — вёдра накопительно, как у Prometheus: (граница в сек, сколько не дольше неё)!
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistogramSnapshot {
+    pub buckets: Vec<(f64, u64)>,
+    pub count: u64,   // Всего наблюдений!
+    pub sum: f64,     // Сумма, сек!
+}
+
+// Счётчики запросов одного вида к одному сундуку!
+struct QueryStats {
+    errors: AtomicU64,
+    latency: Histogram,
+}
+
+// Счётчики сундука — перебор, метки и просрочка!
+#[derive(Default)]
+struct TableStats {
+    rows_scanned: AtomicU64,
+    rows_returned: AtomicU64,
+    index_lookups: AtomicU64,
+    full_scans: AtomicU64,
+    ttl_evictions: AtomicU64,
+}
+
+// Все счётчики корабля — пишутся без замков, читаются снимком!
+pub(crate) struct Metrics {
+    queries: DashMap<(String, String), Arc<QueryStats>, Hasher>, // (вид, сундук) — счёт и задержка!
+    tables: DashMap<String, Arc<TableStats>, Hasher>,
+    wal_bytes: AtomicU64,
+    wal_records: AtomicU64,
+    wal_flushes: AtomicU64,
+    checkpoints: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            queries: DashMap::default(),
+            tables: DashMap::default(),
+            wal_bytes: AtomicU64::new(0),
+            wal_records: AtomicU64::new(0),
+            wal_flushes: AtomicU64::new(0),
+            checkpoints: Histogram::new(CHECKPOINT_BUCKETS),
+        }
+    }
+}
+
+fn op_name(op: &QueryOp) -> &'static str {
+    match op {
+        QueryOp::Select => "select",
+        QueryOp::Insert => "insert",
+        QueryOp::Update => "update",
+        QueryOp::Delete => "delete",
+    }
+}
+
+impl Metrics {
+    fn table(&self, table: &str) -> Arc<TableStats> {
+        if let Some(stats) = self.tables.get(table) {
+            return stats.clone(); // Частый путь — без новой строки под имя!
+        }
+        self.tables.entry(table.to_string()).or_default().clone()
+    }
+
+    // Запрос выполнен — вид, сундук, сколько шёл и удался ли!
+    pub(crate) fn query(&self, op: &QueryOp, table: &str, elapsed: Duration, ok: bool) {
+        let key = (op_name(op).to_string(), table.to_string());
+        let stats = self.queries.entry(key)
+            .or_insert_with(|| Arc::new(QueryStats { errors: AtomicU64::new(0), latency: Histogram::new(QUERY_BUCKETS) }))
+            .clone();
+        stats.latency.observe(elapsed);
+        if !ok {
+            stats.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn scanned(&self, table: &str, rows: usize) {
+        self.table(table).rows_scanned.fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn returned(&self, table: &str, rows: usize) {
+        self.table(table).rows_returned.fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    // Условие отвечено меткой — или пришлось перебрать строки!
+    pub(crate) fn lookup(&self, table: &str, indexed: bool) {
+        let stats = self.table(table);
+        let counter = if indexed { &stats.index_lookups } else { &stats.full_scans };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Сундук за бортом — его серии тоже!
+    pub(crate) fn drop_table(&self, table: &str) {
+        self.tables.remove(table);
+        self.queries.retain(|(_, name), _| name != table);
+    }
+
+    pub(crate) fn evicted(&self, table: &str, rows: usize) {
+        self.table(table).ttl_evictions.fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    // Запись легла в буфер журнала!
+    pub(crate) fn wal_write(&self, bytes: usize) {
+        self.wal_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.wal_records.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Буфер журнала ушёл в файл — считаем, только если в нём что-то было!
+    pub(crate) fn wal_flush(&self) {
+        self.wal_flushes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn checkpoint(&self, elapsed: Duration) {
+        self.checkpoints.observe(elapsed);
+    }
+}
+
+// Запросы одного вида к одному сундуку!
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryMetrics {
+    pub op: String,                   // "select", "insert", "update", "delete"!
+    pub table: String,
+    pub count: u64,
+    pub errors: u64,
+    pub latency: HistogramSnapshot,   // Задержка, сек!
+}
+
+// Сундук — перебор против меток, просрочка и память!
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TableMetrics {
+    pub table: String,
+    pub rows_scanned: u64,    // Строк снимка, которые запросы перебрали!
+    pub rows_returned: u64,   // Строк, что вернул SELECT или тронула запись!
+    pub index_lookups: u64,   // Условий, отвеченных меткой!
+    pub full_scans: u64,      // Условий и запросов без условий, прошедших перебором!
+    pub ttl_evictions: u64,   // Выметено по TTL!
+    pub memory_bytes: u64,    // Строки в памяти — оценка!
+    pub index_bytes: u64,     // Метки и полнотекст — оценка!
+}
+
+// Снимок всех метрик — `db.metrics()`!
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsReport {
+    pub queries: Vec<QueryMetrics>,
+    pub tables: Vec<TableMetrics>,
+    pub wal_bytes: u64,                 // Байт записано в журнал!
+    pub wal_records: u64,               // Записей журнала!
+    pub wal_flushes: u64,               // Сбросов буфера журнала в файл!
+    pub fsyncs: u64,                    // fsync файлов поколений и атомарных записей — на весь процесс!
+    pub checkpoints: HistogramSnapshot, // Длительность точек сохранения, сек!
+}
+
+// Память строки — оценка: сама строка, ключи полей и текст!
+fn row_bytes(row: &Row) -> usize {
+    std::mem::size_of::<Row>() + row.data.iter().map(|(name, value)| {
+        std::mem::size_of::<(String, Value)>() + name.capacity() + match value {
+            Value::Text(text) => text.capacity(),
+            _ => 0,
+        }
+    }).sum::<usize>()
+}
+
+fn table_bytes(table: &TableData) -> u64 {
+    table.iter().map(|row| row_bytes(&row) as u64).sum()
+}
+
+// Метка в Prometheus — кавычки, обратную косую и перевод строки экранируем!
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// Счётчик сундука для вывода — берём поле из его метрик!
+type TableValue = fn(&TableMetrics) -> u64;
+
+fn write_histogram(out: &mut String, name: &str, labels: &str, histogram: &HistogramSnapshot) {
+    let sep = if labels.is_empty() { "" } else { "," };
+    for (bound, count) in &histogram.buckets {
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, sep, bound, count);
+    }
+    let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, sep, histogram.count);
+    let braces = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+    let _ = writeln!(out, "{}_sum{} {}", name, braces, histogram.sum);
+    let _ = writeln!(out, "{}_count{} {}", name, braces, histogram.count);
+}
+
+impl MetricsReport {
+    // Текст для Prometheus — формат exposition 0.0.4!
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP yuaidb_queries_total Запросы по виду и сундуку.\n# TYPE yuaidb_queries_total counter");
+        for q in &self.queries {
+            let _ = writeln!(out, "yuaidb_queries_total{{op=\"{}\",table=\"{}\"}} {}", q.op, label(&q.table), q.count);
+        }
+        let _ = writeln!(out, "# HELP yuaidb_query_errors_total Запросы, вернувшие ошибку.\n# TYPE yuaidb_query_errors_total counter");
+        for q in &self.queries {
+            let _ = writeln!(out, "yuaidb_query_errors_total{{op=\"{}\",table=\"{}\"}} {}", q.op, label(&q.table), q.errors);
+        }
+        let _ = writeln!(out, "# HELP yuaidb_query_duration_seconds Задержка запроса.\n# TYPE yuaidb_query_duration_seconds histogram");
+        for q in &self.queries {
+            let labels = format!("op=\"{}\",table=\"{}\"", q.op, label(&q.table));
+            write_histogram(&mut out, "yuaidb_query_duration_seconds", &labels, &q.latency);
+        }
+        let per_table: [(&str, &str, &str, TableValue); 7] = [
+            ("yuaidb_rows_scanned_total", "counter", "Строки снимка, перебранные запросами.", |t| t.rows_scanned),
+            ("yuaidb_rows_returned_total", "counter", "Строки, возвращённые SELECT или тронутые записью.", |t| t.rows_returned),
+            ("yuaidb_index_lookups_total", "counter", "Условия, отвеченные меткой.", |t| t.index_lookups),
+            ("yuaidb_full_scans_total", "counter", "Условия и запросы, прошедшие перебором.", |t| t.full_scans),
+            ("yuaidb_ttl_evictions_total", "counter", "Строки, выметенные по TTL.", |t| t.ttl_evictions),
+            ("yuaidb_table_memory_bytes", "gauge", "Оценка памяти строк сундука.", |t| t.memory_bytes),
+            ("yuaidb_index_memory_bytes", "gauge", "Оценка памяти меток сундука.", |t| t.index_bytes),
+        ];
+        for (name, kind, help, value) in per_table {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+            for t in &self.tables {
+                let _ = writeln!(out, "{}{{table=\"{}\"}} {}", name, label(&t.table), value(t));
+            }
+        }
+        for (name, help, value) in [
+            ("yuaidb_wal_bytes_total", "Байты, записанные в журнал.", self.wal_bytes),
+            ("yuaidb_wal_records_total", "Записи журнала.", self.wal_records),
+            ("yuaidb_wal_flushes_total", "Сбросы буфера журнала в файл.", self.wal_flushes),
+            ("yuaidb_fsyncs_total", "fsync файлов поколений и атомарных записей на весь процесс.", self.fsyncs),
+        ] {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, value);
+        }
+        let _ = writeln!(out, "# HELP yuaidb_checkpoint_duration_seconds Длительность точки сохранения.\n# TYPE yuaidb_checkpoint_duration_seconds histogram");
+        write_histogram(&mut out, "yuaidb_checkpoint_duration_seconds", "", &self.checkpoints);
+        out
+    }
+}
+
+impl Database {
+    // Сундук для серии запроса — с карты или служебный, остальные идут под `unknown`!
+    pub(crate) fn metrics_table<'a>(&self, config: &DbConfig, table: &'a str) -> &'a str {
+        if config.tables.iter().any(|t| t.name == table) || system::is_read_only(table) { table } else { UNKNOWN_TABLE }
+    }
+
+    // Снимок метрик — счётчики с запуска, память сундуков считаем прямо сейчас!
+    pub fn metrics(&self) -> MetricsReport {
+        let metrics = &self.metrics;
+        let mut queries: Vec<QueryMetrics> = metrics.queries.iter().map(|entry| {
+            let ((op, table), stats) = (entry.key(), entry.value());
+            let latency = stats.latency.snapshot();
+            QueryMetrics { op: op.clone(), table: table.clone(), count: latency.count, errors: stats.errors.load(Ordering::Relaxed), latency }
+        }).collect();
+        queries.sort_by(|a, b| (&a.table, &a.op).cmp(&(&b.table, &b.op)));
+
+        let mut names: Vec<String> = metrics.tables.iter().map(|t| t.key().clone()).collect();
+        names.extend(self.tables.iter().map(|t| t.key().clone()));
+        names.sort();
+        names.dedup();
+        let tables = names.into_iter().map(|name| {
+            let stats = metrics.tables.get(&name).map(|s| s.clone()).unwrap_or_default();
+            let memory_bytes = self.tables.get(&name).map(|t| t.clone()).map_or(0, |t| table_bytes(&t));
+            let index_bytes = [&self.indexes, &self.fulltext_indexes].iter()
+                .filter_map(|indexes| indexes.get(&name).map(|m| m.clone()))
+                .flat_map(|fields| fields.iter().map(|f| f.value().clone()).collect::<Vec<_>>())
+                .map(|index| index.iter().map(|e| (e.key().capacity() + e.value().capacity() * 8) as u64).sum::<u64>())
+                .sum();
+            TableMetrics {
+                table: name,
+                rows_scanned: stats.rows_scanned.load(Ordering::Relaxed),
+                rows_returned: stats.rows_returned.load(Ordering::Relaxed),
+                index_lookups: stats.index_lookups.load(Ordering::Relaxed),
+                full_scans: stats.full_scans.load(Ordering::Relaxed),
+                ttl_evictions: stats.ttl_evictions.load(Ordering::Relaxed),
+                memory_bytes,
+                index_bytes,
+            }
+        }).collect();
+
+        MetricsReport {
+            queries,
+            tables,
+            wal_bytes: metrics.wal_bytes.load(Ordering::Relaxed),
+            wal_records: metrics.wal_records.load(Ordering::Relaxed),
+            wal_flushes: metrics.wal_flushes.load(Ordering::Relaxed),
+            fsyncs: FSYNCS.load(Ordering::Relaxed),
+            checkpoints: metrics.checkpoints.snapshot(),
+        }
+    }
+
+    // Метрики текстом Prometheus!
+    pub fn metrics_text(&self) -> String {
+        self.metrics().to_prometheus()
+    }
+
+    // HTTP с `/metrics` для Prometheus — `let server = db.serve_metrics("127.0.0.1:9100").await?`! Порт 0 — любой свободный,
+    // адрес — в `server.addr()`. Сервер живёт, пока жива ручка: выброшенная или `shutdown()` — и порт закрыт, база отпущена.
+    pub async fn serve_metrics(&self, addr: &str) -> Result<MetricsServer, DbError> {
+        let listener = TcpListener::bind(addr).await?;
+        let local = listener.local_addr()?;
+        let db = self.clone();
+        let (stop_tx, mut stop) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            let mut backoff = ACCEPT_BACKOFF_MIN;
+            loop {
+                let accepted = tokio::select! {
+                    _ = &mut stop => break, // Ручку выбросили или позвали shutdown — сворачиваемся!
+                    accepted = listener.accept() => accepted,
+                };
+                let stream = match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        println!("Шторм у /metrics: {} — ждём {:?}", e, backoff);
+                        tokio::select! {
+                            _ = &mut stop => break,
+                            _ = tokio::time::sleep(backoff) => {}
+                        }
+                        backoff = (backoff * 2).min(ACCEPT_BACKOFF_MAX);
+                        continue;
+                    }
+                };
+                backoff = ACCEPT_BACKOFF_MIN;
+                let db = db.clone();
+                tokio::spawn(async move {
+                    // Молчащий клиент не держит соединение вечно!
+                    let _ = tokio::time::timeout(REQUEST_TIMEOUT, db.answer_metrics(stream)).await;
+                });
+            }
+        });
+        println!("Метрики для Prometheus — на http://{}/metrics", local);
+        Ok(MetricsServer { addr: local, stop: stop_tx, task })
+    }
+
+    // Один запрос — GET /metrics отдаём, остальное — 404 или 405!
+    async fn answer_metrics(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+            let n = stream.read(&mut buffer).await?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..n]);
+        }
+        let line = String::from_utf8_lossy(&request);
+        let mut parts = line.split_whitespace();
+        let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+        let (status, content_type, body) = match (method, path.split('?').next().unwrap_or_default()) {
+            ("GET", "/metrics") => ("200 OK", "text/plain; version=0.0.4; charset=utf-8", self.metrics_text()),
+            (_, "/metrics") => ("405 Method Not Allowed", "text/plain; charset=utf-8", "только GET\n".to_string()),
+            _ => ("404 Not Found", "text/plain; charset=utf-8", "метрики — на /metrics\n".to_string()),
+        };
+        let head = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, content_type, body.len());
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
+
+// Ручка сервера `/metrics` — адрес и остановка! Упала ручка — сервер сворачивается следом.
+pub struct MetricsServer {
+    addr: SocketAddr,
+    stop: oneshot::Sender<()>, // Пока жив — цикл приёма крутится!
+    task: JoinHandle<()>,
+}
+
+impl MetricsServer {
+    // Где слушаем — с настоящим портом, даже если просили 0!
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Останавливаем и ждём: после возврата порт закрыт, а клон базы отпущен!
+    pub async fn shutdown(self) {
+        drop(self.stop);
+        let _ = self.task.await;
+    }
+}
@@ -0,0 +1,428 @@
+// Миграции схемы — поле добавить, выкинуть, переименовать или перетипизировать вместе с данными, не останавливая корабль!
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use crate::{ddl, expr, typed_value, Database, DbError, FieldConfig, Row, RowId, TableConfig, Value, WalOperation};
+
+// Сундук с историей миграций — номер (он же номер строки), сундук, вид, описание, время и сколько строк тронули!
+pub const MIGRATIONS_TABLE: &str = "yuaidb_migrations";
+
+const FIELD_TYPES: [&str; 4] = ["numeric", "text", "timestamp", "boolean"];
+const PROGRESS_EVERY: usize = 10_000; // Каждые столько строк — весточка на мостик!
+const PLAN_ATTEMPTS: usize = 3; // Столько раз сверяем при открытых воротах, потом — за закрытыми!
+
+// Какой индекс ставим!
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IndexKind {
+    Index,    // Обычная метка — `indexed = true`!
+    Unique,   // Уникальная — дубли проверяем до того, как поставить!
+    Fulltext, // Полнотекст — `fulltext = true`!
+}
+
+// Миграция — одна правка схемы вместе с данными!
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Migration {
+    AddColumn { table: String, field: FieldConfig, default: Option<String> }, // Новое поле; старым строкам — `default`, если задан!
+    DropColumn { table: String, column: String },                              // Поле и его значения за борт!
+    RenameColumn { table: String, from: String, to: String },                  // Новое имя — данные переезжают следом!
+    ChangeType { table: String, column: String, field_type: String, using: Option<String> }, // Новый тип; `using` — выражение пересчёта, иначе CAST!
+    AddIndex { table: String, column: String, kind: IndexKind },               // Метка на поле — уникальную сверяем с данными!
+    DropIndex { table: String, column: String, kind: IndexKind },              // Снимаем метку — данные не трогаем!
+}
+
+impl Migration {
+    // Сундук, который правим!
+    pub fn table(&self) -> &str {
+        match self {
+            Migration::AddColumn { table, .. } | Migration::DropColumn { table, .. } | Migration::RenameColumn { table, .. }
+            | Migration::ChangeType { table, .. } | Migration::AddIndex { table, .. } | Migration::DropIndex { table, .. } => table,
+        }
+    }
+
+    // Вид миграции — для сундука истории!
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Migration::AddColumn { .. } => "add_column",
+            Migration::DropColumn { .. } => "drop_column",
+            Migration::RenameColumn { .. } => "rename_column",
+            Migration::ChangeType { .. } => "change_type",
+            Migration::AddIndex { .. } => "add_index",
+            Migration::DropIndex { .. } => "drop_index",
+        }
+    }
+
+    // Что сделали — человеческими словами!
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Migration::AddColumn { field, default, .. } => match default {
+                Some(default) => format!("{} {} default {}", field.name, field.field_type, default),
+                None => format!("{} {}", field.name, field.field_type),
+            },
+            Migration::DropColumn { column, .. } => column.clone(),
+            Migration::RenameColumn { from, to, .. } => format!("{} -> {}", from, to),
+            Migration::ChangeType { column, field_type, using, .. } => match using {
+                Some(using) => format!("{} {} using {}", column, field_type, using),
+                None => format!("{} {}", column, field_type),
+            },
+            Migration::AddIndex { column, kind, .. } | Migration::DropIndex { column, kind, .. } => format!("{} {:?}", column, kind).to_lowercase(),
+        }
+    }
+}
+
+// Итог миграции — её номер в истории и сколько строк переписали!
+#[derive(Debug, Clone)]
+pub struct MigrationReport {
+    pub id: RowId,     // Номер в `yuaidb_migrations`!
+    pub rows: usize,   // Строк переписано!
+}
+
+// План — новая карта сундука и новые данные тронутых строк, всё посчитано до первой правки!
+struct Plan {
+    schema: TableConfig,
+    changed: Vec<(RowId, HashMap<String, Value>)>,
+}
+
+pub(crate) fn invalid(field: &str, reason: impl Into<String>) -> DbError {
+    DbError::InvalidValue(field.to_string(), reason.into())
+}
+
+pub(crate) fn check_type(field_type: &str) -> Result<(), DbError> {
+    if !FIELD_TYPES.contains(&field_type) {
+        return Err(invalid("field_type", format!("'{}' — бывают только {}", field_type, FIELD_TYPES.join(", "))));
+    }
+    Ok(())
+}
+
+// Поля, на которые опираются генерируемые — их не выкинуть и не переименовать из-под выражения!
+fn generated_refs(schema: &TableConfig, column: &str) -> Result<Option<String>, DbError> {
+    for (field, expr) in schema.generated_exprs()? {
+        let mut used = false;
+        expr.visit(&mut |e| used |= e.as_column().is_some_and(|(_, name)| name == column));
+        if used {
+            return Ok(Some(field.name));
+        }
+    }
+    Ok(None)
+}
+
+// Уникальность на готовых данных — первый дубль с номером строки!
+fn check_unique(column: &str, rows: &[Row], changed: &HashMap<RowId, &HashMap<String, Value>>) -> Result<(), DbError> {
+    let mut seen = HashSet::new();
+    for row in rows {
+        let data = changed.get(&row.id).copied().unwrap_or(&row.data);
+        if let Some(value) = data.get(column) {
+            if !seen.insert(value.to_string()) {
+                return Err(DbError::DuplicateValue(column.to_string(), format!("{} (строка {})", value, row.id)));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Считаем план! `strict` — живая миграция, всё сверяем; при повторе журнала уже сделанное просто пропускаем.
+fn plan(schema: &TableConfig, rows: &[Row], migration: &Migration, strict: bool) -> Result<Plan, DbError> {
+    let mut schema = schema.clone();
+    let mut changed = Vec::new();
+    match migration {
+        Migration::AddColumn { field, default, .. } => {
+            check_type(&field.field_type)?;
+            if field.autoincrement.is_some() || field.sequence.is_some() || field.identity.is_some() {
+                return Err(invalid(&field.name, "автоинкремент ставят при заведении сундука, а не миграцией"));
+            }
+            if field.generated.is_some() && default.is_some() {
+                return Err(invalid(&field.name, "у генерируемого поля не бывает значения по умолчанию"));
+            }
+            if let (Some(source), true) = (&field.generated, strict) { // Старый журнал повторяем как есть!
+                ddl::check_deterministic(&field.name, &expr::parse(source)?)?;
+            }
+            match schema.field(&field.name) {
+                Some(_) if strict => return Err(invalid(&field.name, "поле уже есть на карте")),
+                Some(_) => {}
+                None => schema.fields.push(field.clone()),
+            }
+            let value = default.as_deref().map(|d| typed_value(&field.field_type, &field.name, d)).transpose()?;
+            for row in rows {
+                if field.generated.is_some() {
+                    changed.push((row.id, row.data.clone())); // Генерируемое посчитаем ниже по новой карте!
+                } else if let (Some(value), false) = (&value, row.data.contains_key(&field.name)) {
+                    let mut data = row.data.clone();
+                    data.insert(field.name.clone(), value.clone());
+                    changed.push((row.id, data));
+                }
+            }
+            if field.unique == Some(true) {
+                check_unique(&field.name, rows, &changed.iter().map(|(id, data)| (*id, data)).collect())?;
+            }
+        }
+        Migration::DropColumn { column, .. } => {
+            if schema.field(column).is_none() && strict {
+                return Err(invalid(column, "поля нет на карте"));
+            }
+            if let Some(field) = generated_refs(&schema, column)?.filter(|f| f != column) {
+                return Err(invalid(column, format!("на поле опирается генерируемое '{}'", field)));
+            }
+            schema.fields.retain(|f| f.name != *column);
+            for row in rows.iter().filter(|r| r.data.contains_key(column)) {
+                let mut data = row.data.clone();
+                data.remove(column);
+                changed.push((row.id, data));
+            }
+        }
+        Migration::RenameColumn { from, to, .. } => {
+            if strict {
+                if schema.field(from).is_none() {
+                    return Err(invalid(from, "поля нет на карте"));
+                }
+                if schema.field(to).is_some() {
+                    return Err(invalid(to, "поле уже есть на карте"));
+                }
+                if let Some(row) = rows.iter().find(|r| r.data.contains_key(to)) {
+                    return Err(invalid(to, format!("имя уже занято данными в строке {}", row.id)));
+                }
+            }
+            if let Some(field) = generated_refs(&schema, from)?.filter(|f| f != from) {
+                return Err(invalid(from, format!("на поле опирается генерируемое '{}'", field)));
+            }
+            if let Some(field) = schema.fields.iter_mut().find(|f| f.name == *from) {
+                field.name = to.clone();
+            }
+            for row in rows.iter().filter(|r| r.data.contains_key(from)) {
+                let mut data = row.data.clone();
+                if let Some(value) = data.remove(from) {
+                    data.insert(to.clone(), value);
+                }
+                changed.push((row.id, data));
+            }
+        }
+        Migration::ChangeType { column, field_type, using, .. } => {
+            check_type(field_type)?;
+            let Some(field) = schema.fields.iter_mut().find(|f| f.name == *column) else {
+                return Err(invalid(column, "поля нет на карте"));
+            };
+            if field.generated.is_some() {
+                return Err(invalid(column, "тип генерируемого поля меняют вместе с выражением — выкиньте и добавьте заново"));
+            }
+            field.field_type = field_type.clone();
+            let unique = field.unique == Some(true);
+            let using = using.as_deref().map(expr::parse).transpose()?;
+            for row in rows {
+                let Some(value) = row.data.get(column) else { continue };
+                if crate::transfer::value_type(value) == field_type.as_str() {
+                    continue; // Уже нужного типа — в том числе после повтора журнала!
+                }
+                let converted = match &using {
+                    Some(using) => using.eval(&row.data)?,
+                    None => Some(value.clone()),
+                };
+                let mut data = row.data.clone();
+                match converted {
+                    Some(v) => {
+                        let v = expr::cast(v, field_type)
+                            .map_err(|_| invalid(column, format!("'{}' в строке {} не становится {}", value, row.id, field_type)))?;
+                        data.insert(column.clone(), v);
+                    }
+                    None => { data.remove(column); } // NULL — поле пустует!
+                }
+                changed.push((row.id, data));
+            }
+            if unique {
+                check_unique(column, rows, &changed.iter().map(|(id, data)| (*id, data)).collect())?;
+            }
+        }
+        Migration::AddIndex { column, kind, .. } => {
+            let Some(field) = schema.fields.iter_mut().find(|f| f.name == *column) else {
+                return Err(invalid(column, "поля нет на карте"));
+            };
+            match kind {
+                IndexKind::Index => field.indexed = Some(true),
+                IndexKind::Fulltext => field.fulltext = Some(true),
+                IndexKind::Unique => {
+                    field.unique = Some(true);
+                    field.indexed = Some(true); // Уникальное ищем по метке — иначе каждая вставка сканирует сундук!
+                    check_unique(column, rows, &HashMap::new())?;
+                }
+            }
+        }
+        Migration::DropIndex { column, kind, .. } => {
+            let field = schema.fields.iter_mut().find(|f| f.name == *column);
+            let flag = field.map(|f| match kind {
+                IndexKind::Index => &mut f.indexed,
+                IndexKind::Unique => &mut f.unique,
+                IndexKind::Fulltext => &mut f.fulltext,
+            });
+            match flag {
+                Some(flag) if *flag == Some(true) => *flag = None,
+                _ if strict => return Err(invalid(column, format!("метки {:?} на поле нет", kind).to_lowercase())),
+                _ => {}
+            }
+        }
+    }
+    // Генерируемые поля — пересчитываем по новой карте, одно могло опираться на перетипизированное!
+    let generated = schema.generated_exprs()?;
+    for (_, data) in &mut changed {
+        crate::apply_generated_exprs(&generated, data)?;
+    }
+    Ok(Plan { schema, changed })
+}
+
+// Отпечаток сундука — номер строки и метка её коммита: любая вставка, правка или удаление его меняет!
+fn stamp(rows: &[Row]) -> HashMap<RowId, u64> {
+    rows.iter().map(|r| (r.id, r.commit_ts)).collect()
+}
+
+// Сверяем пачку по порядку — следующую уже по данным и карте после предыдущей!
+fn plan_all(table: &str, mut schema: TableConfig, mut rows: Vec<Row>, migrations: &[Migration]) -> Result<Vec<(Migration, TableConfig, Plan)>, DbError> {
+    let positions: HashMap<RowId, usize> = rows.iter().enumerate().map(|(i, r)| (r.id, i)).collect();
+    let count = migrations.len();
+    let mut steps = Vec::with_capacity(count);
+    for (i, migration) in migrations.iter().enumerate() {
+        println!("Миграция сундука '{}': {} — сверяем {} строк...", table, migration.describe(), rows.len());
+        let plan = plan(&schema, &rows, migration, true)?;
+        if i + 1 < count { // Следующую сверяем уже по данным после этой!
+            for (id, data) in &plan.changed {
+                rows[positions[id]].data = data.clone();
+            }
+        }
+        let before = std::mem::replace(&mut schema, plan.schema.clone());
+        steps.push((migration.clone(), before, plan));
+    }
+    Ok(steps)
+}
+
+impl Database {
+    // Миграция схемы — `db.migrate(Migration::RenameColumn { .. }).await?`! Всё сверяем до первой правки: кривая строка — ошибка, а не полсундука.
+    pub async fn migrate(&self, migration: Migration) -> Result<MigrationReport, DbError> {
+        let table = migration.table().to_string();
+        Ok(self.migrate_all(&table, vec![migration]).await?.remove(0))
+    }
+
+    // Пачка миграций одного сундука — сверяем все разом, потом применяем по порядку: кривая последняя не оставит первые сделанными!
+    // Сверка и пересчёт строк идут при открытых воротах — писатели не ждут; ворота закрываем только на подмену, журнал и каталог.
+    // Если за время сверки сундук или карту тронули — сверяем заново, а после нескольких промахов уже за закрытыми воротами.
+    pub(crate) async fn migrate_all(&self, table: &str, migrations: Vec<Migration>) -> Result<Vec<MigrationReport>, DbError> {
+        if let Some(migration) = migrations.iter().find(|m| m.table() != table) {
+            return Err(invalid(migration.table(), format!("в пачке миграций сундука '{}' чужой сундук", table)));
+        }
+        let mut attempt = 0;
+        let (_gate, steps) = loop {
+            attempt += 1;
+            if attempt > PLAN_ATTEMPTS {
+                // Сундук правят без передышки — сверяем за закрытыми воротами, как в старые времена!
+                let gate = self.write_gate.write().await;
+                let (schema, rows) = self.migration_input(table).await?;
+                break (gate, plan_all(table, schema, rows, &migrations)?);
+            }
+            let (schema, rows) = {
+                let _gate = self.write_gate.read().await; // Точка сохранения подождёт, писатели — нет!
+                self.migration_input(table).await?
+            };
+            let planned = stamp(&rows);
+            let steps = plan_all(table, schema.clone(), rows, &migrations)?;
+            let gate = self.write_gate.write().await;
+            let (current, rows) = self.migration_input(table).await?;
+            if current == schema && planned == stamp(&rows) {
+                break (gate, steps); // Пока сверяли, никто не тронул — подменяем!
+            }
+            println!("Миграция сундука '{}': пока сверяли, сундук поправили — сверяем заново!", table);
+        };
+
+        let mut reports = Vec::with_capacity(steps.len());
+        for (migration, before, plan) in steps {
+            let id = self.next_row_id(MIGRATIONS_TABLE);
+            let applied_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+            self.log_to_wal(&WalOperation::Migrate { id, applied_at, migration: migration.clone(), before }).await?; // Сначала журнал — потом трюм!
+            let rows = self.apply_migration(id, applied_at, &migration, plan).await?;
+            println!("Миграция №{} готова: переписано строк {}", id, rows);
+            reports.push(MigrationReport { id, rows });
+        }
+        Ok(reports)
+    }
+
+    // Карта сундука и его строки — то, по чему сверяем миграцию!
+    async fn migration_input(&self, table: &str) -> Result<(TableConfig, Vec<Row>), DbError> {
+        let schema = self.config.read().await.tables.iter().find(|t| t.name == table).cloned()
+            .ok_or_else(|| DbError::TableNotFound(table.to_string()))?;
+        let rows: Vec<Row> = self.tables.get(table).map(|t| t.iter().map(|r| r.clone()).collect()).unwrap_or_default();
+        Ok((schema, rows))
+    }
+
+    // Повтор миграции из журнала — карта могла уже успеть её отразить, так что сделанное не повторяем!
+    pub(crate) async fn replay_migration(&self, id: RowId, applied_at: i64, migration: &Migration) -> Result<usize, DbError> {
+        let table = migration.table();
+        let Some(schema) = self.config.read().await.tables.iter().find(|t| t.name == table).cloned() else {
+            return Err(DbError::TableNotFound(table.to_string()));
+        };
+        let rows: Vec<Row> = self.tables.get(table).map(|t| t.iter().map(|r| r.clone()).collect()).unwrap_or_default();
+        let plan = plan(&schema, &rows, migration, false)?;
+        self.apply_migration(id, applied_at, migration, plan).await
+    }
+
+    // Кладём план в трюм — новые версии строк, новая карта, метки и запись в истории!
+    async fn apply_migration(&self, id: RowId, applied_at: i64, migration: &Migration, plan: Plan) -> Result<usize, DbError> {
+        let table = migration.table();
+        let total = plan.changed.len();
+        if let Some(table_data) = self.tables.get(table).map(|t| t.clone()) {
+            let write = self.mvcc.begin_write(); // Одна метка — снимки увидят сундук либо старым, либо новым целиком!
+            for (i, (row_id, data)) in plan.changed.into_iter().enumerate() {
+                if let Some(mut live) = table_data.get_mut(&row_id) {
+                    self.mvcc.record(table, live.clone(), write.ts());
+                    live.data = data;
+                    live.version += 1; // Данные сменились — compare-and-set это заметит!
+                    live.commit_ts = write.ts();
+                }
+                self.dirty.mark(table, row_id);
+                if (i + 1) % PROGRESS_EVERY == 0 {
+                    println!("Миграция №{}: {} из {} строк", id, i + 1, total);
+                }
+            }
+        }
+
+        // Новая карта — в память и на диск; метки со старых или пропавших полей снимаем, потом строим заново!
+        let schema = plan.schema;
+        let changed = {
+            let mut config = self.config.write().await;
+            match config.tables.iter_mut().find(|t| t.name == table) {
+                Some(current) if *current == schema => false,
+                Some(current) => { *current = schema.clone(); true }
+                None => { config.tables.push(schema.clone()); true }
+            }
+        };
+        for (indexes, flag) in [(&self.indexes, false), (&self.fulltext_indexes, true)] {
+            if let Some(fields) = indexes.get(table).map(|m| m.clone()) {
+                fields.retain(|name, _| schema.field(name).is_some_and(|f| if flag { f.fulltext } else { f.indexed } == Some(true)));
+            }
+        }
+        self.rebuild_indexes(table).await;
+        self.join_cache.retain(|key, _| !key.contains(table)); // Чистим кэш — старое долой!
+        self.record_migration(id, applied_at, migration, total);
+        if changed {
+            self.save_catalog().await?;
+        }
+        Ok(total)
+    }
+
+    // Строка в сундук истории — при повторе журнала второй раз не кладём!
+    fn record_migration(&self, id: RowId, applied_at: i64, migration: &Migration, rows: usize) {
+        let history = self.tables.entry(MIGRATIONS_TABLE.to_string())
+            .or_insert_with(|| Arc::new(DashMap::with_hasher(Default::default())))
+            .clone();
+        self.row_id_counter(MIGRATIONS_TABLE).fetch_max(id + 1, Ordering::SeqCst);
+        if history.contains_key(&id) {
+            return;
+        }
+        let data = HashMap::from([
+            ("id".to_string(), Value::Numeric(id as f64)),
+            ("table".to_string(), Value::Text(migration.table().to_string())),
+            ("kind".to_string(), Value::Text(migration.kind().to_string())),
+            ("description".to_string(), Value::Text(migration.describe())),
+            ("applied_at".to_string(), Value::Timestamp(applied_at)),
+            ("rows".to_string(), Value::Numeric(rows as f64)),
+        ]);
+        let write = self.mvcc.begin_write();
+        history.insert(id, Row { id, data, expires_at: None, version: 1, commit_ts: write.ts() });
+        self.dirty.mark(MIGRATIONS_TABLE, id);
+    }
+}
@@ -0,0 +1,155 @@
+// MVCC — многоверсионные строки: читатель видит трюм таким, каким он был в момент снимка!
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use dashmap::DashMap;
+use crate::{Hasher, Row, RowId, TableData};
+
+// Старые версии одного сундука — ID строки в список (версия, момент, когда её сменили)!
+type TableHistory = Arc<DashMap<RowId, Vec<(Row, u64)>, Hasher>>;
+
+// Часы коммитов, идущие записи, открытые снимки и старые версии строк!
+#[derive(Default)]
+pub(crate) struct Mvcc {
+    clock: AtomicU64,                       // Последняя выданная метка коммита!
+    writers: Mutex<BTreeSet<u64>>,          // Записи в пути — их метки ещё не видны читателям!
+    readers: Mutex<BTreeMap<u64, usize>>,   // Открытые снимки — метка и сколько читателей на ней!
+    history: DashMap<String, TableHistory, Hasher>, // Сменённые и удалённые версии по сундукам!
+}
+
+impl Mvcc {
+    // Начинаем запись — берём метку коммита; видна она станет, когда запись закончится!
+    pub(crate) fn begin_write(self: &Arc<Self>) -> WriteTs {
+        let mut writers = self.writers.lock().unwrap();
+        let ts = self.clock.fetch_add(1, Ordering::SeqCst) + 1;
+        writers.insert(ts);
+        WriteTs { ts, mvcc: self.clone() }
+    }
+
+    // Метка для нового снимка — всё закоммиченное до самой ранней недописанной записи!
+    fn read_ts(&self) -> u64 {
+        let writers = self.writers.lock().unwrap();
+        match writers.first() {
+            Some(oldest) => oldest - 1, // Кто-то ещё пишет — его и всех после не видим!
+            None => self.clock.load(Ordering::SeqCst),
+        }
+    }
+
+    // Открываем снимок — пока он жив, сборщик не тронет нужные ему версии!
+    pub(crate) fn pin(self: &Arc<Self>) -> ReadPin {
+        let mut readers = self.readers.lock().unwrap(); // Под тем же замком, что и горизонт сборщика!
+        let ts = self.read_ts();
+        *readers.entry(ts).or_insert(0) += 1;
+        ReadPin { ts, mvcc: self.clone() }
+    }
+
+    // Запоминаем сменённую версию — до того, как новая займёт её место в трюме!
+    pub(crate) fn record(&self, table: &str, old: Row, superseded_at: u64) {
+        let history = self.history.entry(table.to_string()).or_default().clone();
+        history.entry(old.id).or_default().push((old, superseded_at));
+    }
+
+    // Строки сундука, какими они были на метке `ts`!
+    pub(crate) fn visible(&self, table: &str, data: &TableData, ts: u64) -> Vec<Row> {
+        let history = self.history.get(table).map(|h| h.clone());
+        let mut seen = HashSet::new();
+        let mut rows = Vec::new();
+        for live in data.iter() {
+            seen.insert(live.id);
+            if live.commit_ts <= ts {
+                rows.push(live.clone()); // Свежая версия уже была на снимке!
+            } else if let Some(row) = version_at(history.as_ref(), live.id, ts) {
+                rows.push(row); // Строку поменяли после снимка — берём прежнюю!
+            }
+        }
+        // Удалённые после снимка — живут только в истории! В историю пишем до удаления, так что не потеряем.
+        if let Some(history) = &history {
+            let deleted: Vec<RowId> = history.iter().map(|e| *e.key()).filter(|id| !seen.contains(id)).collect();
+            rows.extend(deleted.into_iter().filter_map(|id| version_at(Some(history), id, ts)));
+        }
+        rows
+    }
+
+    // Меняли ли сундук после метки `ts` — есть ли в истории версии, сменённые позже неё!
+    pub(crate) fn changed_since(&self, table: &str, ts: u64) -> bool {
+        let Some(history) = self.history.get(table).map(|h| h.clone()) else { return false };
+        let changed = history.iter().any(|versions| versions.iter().any(|(_, superseded_at)| *superseded_at > ts));
+        changed
+    }
+
+    // То же, но только для строк с номерами из `ids` — дельте не нужен весь трюм!
+    pub(crate) fn visible_ids(&self, table: &str, data: &TableData, ts: u64, ids: impl Iterator<Item = RowId>) -> Vec<Row> {
+        let history = self.history.get(table).map(|h| h.clone());
+        ids.filter_map(|id| match data.get(&id).map(|live| live.clone()) {
+            Some(live) if live.commit_ts <= ts => Some(live),
+            _ => version_at(history.as_ref(), id, ts), // Сменили или выкинули после снимка — берём прежнюю!
+        }).collect()
+    }
+
+    // Сборщик — выкидываем версии, которые уже не увидит ни один снимок!
+    pub(crate) fn gc(&self) -> usize {
+        let horizon = {
+            let readers = self.readers.lock().unwrap();
+            let current = self.read_ts();
+            readers.keys().next().map_or(current, |oldest| (*oldest).min(current))
+        };
+        let mut removed = 0;
+        for table in self.history.iter() {
+            table.retain(|_, versions| {
+                let before = versions.len();
+                versions.retain(|(_, superseded_at)| *superseded_at > horizon); // Сменили до горизонта — никому не нужна!
+                removed += before - versions.len();
+                !versions.is_empty()
+            });
+        }
+        removed
+    }
+}
+
+// Версия строки на метке `ts` из истории — если её там нет, на снимке строки не было!
+fn version_at(history: Option<&TableHistory>, id: RowId, ts: u64) -> Option<Row> {
+    let versions = history?.get(&id)?.clone();
+    versions.into_iter().find(|(row, superseded_at)| row.commit_ts <= ts && ts < *superseded_at).map(|(row, _)| row)
+}
+
+// Метка идущей записи — снимаем с учёта при выходе, даже если запись упала!
+pub(crate) struct WriteTs {
+    ts: u64,
+    mvcc: Arc<Mvcc>,
+}
+
+impl WriteTs {
+    pub(crate) fn ts(&self) -> u64 {
+        self.ts
+    }
+}
+
+impl Drop for WriteTs {
+    fn drop(&mut self) {
+        self.mvcc.writers.lock().unwrap().remove(&self.ts); // Коммит — теперь читатели нас видят!
+    }
+}
+
+// Удерживаемый снимок — сборщик не тронет версии, нужные этой метке!
+pub(crate) struct ReadPin {
+    ts: u64,
+    mvcc: Arc<Mvcc>,
+}
+
+impl ReadPin {
+    pub(crate) fn ts(&self) -> u64 {
+        self.ts
+    }
+}
+
+impl Drop for ReadPin {
+    fn drop(&mut self) {
+        let mut readers = self.mvcc.readers.lock().unwrap();
+        if let Some(count) = readers.get_mut(&self.ts) {
+            *count -= 1;
+            if *count == 0 {
+                readers.remove(&self.ts); // Последний читатель ушёл — метка свободна!
+            }
+        }
+    }
+}
@@ -0,0 +1,203 @@
+// Перечитка карты — шпион ловит правки файла сразу, а `db.reload_config()` покажет и применит разницу с каталогом!
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use notify::{EventKind, PollWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Duration};
+use crate::crypto::Keyring;
+use crate::{ddl, snapshot, Database, DbConfig, DbError, Migration, MigrationReport, TableConfig};
+
+const SETTLE: Duration = Duration::from_millis(200); // Редактор пишет файл в несколько заходов — ждём, пока уляжется!
+const POLL_INTERVAL: Duration = Duration::from_secs(5); // Без событий файловой системы — старый добрый опрос!
+
+// Разница карты с каталогом — что перечитка поменяет (или уже поменяла)!
+#[derive(Debug, Default)]
+pub struct ReloadReport {
+    pub settings: Vec<String>,              // Изменённые разделы настроек — "wal_archive", "encryption"...
+    pub created: Vec<TableConfig>,          // Новые сундуки с карты — заводятся в каталоге!
+    pub migrations: Vec<Migration>,         // Безопасные правки — новые поля, метки поставить и снять!
+    pub skipped: Vec<Migration>,            // Выброс, переименование и смена типа — карта их не делает, только ALTER TABLE!
+    pub conflicts: Vec<(String, String)>,   // Сундук и почему его не свести с каталогом!
+    pub undeclared: Vec<String>,            // В каталоге есть, на карте нет — остаются до DROP TABLE!
+    pub applied: Vec<MigrationReport>,      // Применённые миграции — у пробного прогона пусто!
+}
+
+impl ReloadReport {
+    // Перечитка ничего не меняет — карта уже сведена с каталогом!
+    pub fn is_empty(&self) -> bool {
+        self.settings.is_empty() && self.created.is_empty() && self.migrations.is_empty()
+            && self.skipped.is_empty() && self.conflicts.is_empty()
+    }
+}
+
+impl fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "Карта совпадает с каталогом — править нечего!");
+        }
+        let mut lines = Vec::new();
+        if !self.settings.is_empty() {
+            lines.push(format!("Настройки: {}", self.settings.join(", ")));
+        }
+        lines.extend(self.created.iter().map(|t| format!("Новый сундук: {}", t.name)));
+        lines.extend(self.migrations.iter().map(|m| format!("Правка: {} {} '{}'", m.table(), m.kind(), m.describe())));
+        lines.extend(self.skipped.iter().map(|m| format!("Только через ALTER TABLE: {} {} '{}'", m.table(), m.kind(), m.describe())));
+        lines.extend(self.conflicts.iter().map(|(table, reason)| format!("Не сведено: {} — {}", table, reason)));
+        lines.extend(self.undeclared.iter().map(|t| format!("Нет на карте, остаётся в каталоге: {}", t)));
+        if !self.applied.is_empty() {
+            lines.push(format!("Применено миграций: {}", self.applied.len()));
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+// Правка с карты, которая теряет или перетипизирует данные — её делаем только явно!
+fn destructive(migration: &Migration) -> bool {
+    matches!(migration, Migration::DropColumn { .. } | Migration::ChangeType { .. } | Migration::RenameColumn { .. })
+}
+
+impl Database {
+    // Пробный прогон — что поменяет перечитка карты, ничего не трогая!
+    pub async fn preview_config(&self) -> Result<ReloadReport, DbError> {
+        let config = self.read_config().await?;
+        self.plan_config(&config).await
+    }
+
+    // Перечитываем карту — `db.reload_config().await?`! Трогаем только настройки и сундуки, чья карта изменилась;
+    // журнал не повторяем, так что вторая перечитка той же карты ничего не сделает.
+    pub async fn reload_config(&self) -> Result<ReloadReport, DbError> {
+        let config = self.read_config().await?;
+        self.apply_config(&config).await
+    }
+
+    async fn read_config(&self) -> Result<DbConfig, DbError> {
+        let text = tokio::fs::read_to_string(&self.config_file).await?;
+        Ok(toml::from_str(&text)?)
+    }
+
+    // Разница карты с тем, что на борту: настройки — с памятью, сундуки — с каталогом!
+    async fn plan_config(&self, new_config: &DbConfig) -> Result<ReloadReport, DbError> {
+        let mut report = ReloadReport::default();
+        let current = self.config.read().await.clone();
+        // Кривые настройки ловим до первой правки — перечитка либо целиком годится, либо не трогает ничего!
+        snapshot::Compression::from_config(new_config.snapshot_compression.as_deref())?;
+        if new_config.encryption != current.encryption {
+            Keyring::from_config(new_config.encryption.as_ref())?;
+        }
+        for (name, changed) in [
+            ("wal_archive", new_config.wal_archive != current.wal_archive),
+            ("snapshot_compression", new_config.snapshot_compression != current.snapshot_compression),
+            ("snapshot_compact_after", new_config.snapshot_compact_after != current.snapshot_compact_after),
+            ("encryption", new_config.encryption != current.encryption),
+        ] {
+            if changed {
+                report.settings.push(name.to_string());
+            }
+        }
+        if new_config.tables.is_empty() {
+            return Ok(report); // Сундуков на карте нет — схема целиком в каталоге!
+        }
+
+        for table in &new_config.tables {
+            let Some(existing) = current.tables.iter().find(|t| t.name == table.name) else {
+                match ddl::validate_table(table) {
+                    Ok(()) => report.created.push(table.clone()),
+                    Err(e) => report.conflicts.push((table.name.clone(), e.to_string())),
+                }
+                continue;
+            };
+            match ddl::validate_table(table).and_then(|_| ddl::diff(existing, table)) {
+                Ok(migrations) => {
+                    let (skipped, safe): (Vec<Migration>, Vec<Migration>) = migrations.into_iter().partition(destructive);
+                    report.skipped.extend(skipped);
+                    report.migrations.extend(safe);
+                }
+                Err(e) => report.conflicts.push((table.name.clone(), e.to_string())),
+            }
+        }
+        report.undeclared = current.tables.iter()
+            .filter(|t| !new_config.tables.iter().any(|d| d.name == t.name))
+            .map(|t| t.name.clone())
+            .collect();
+        Ok(report)
+    }
+
+    // Применяем карту — настройки, новые сундуки и безопасные правки; остальные сундуки не трогаем вовсе!
+    pub(crate) async fn apply_config(&self, new_config: &DbConfig) -> Result<ReloadReport, DbError> {
+        let _reload = self.reload_lock.lock().await; // Шпион и явная перечитка — по одной!
+        let mut report = self.plan_config(new_config).await?;
+
+        // Ключи сменились — новые записи под новым ключом, а старое перешифрует следующая точка сохранения!
+        if report.settings.iter().any(|s| s == "encryption") {
+            let keys = Keyring::from_config(new_config.encryption.as_ref())?;
+            *self.keys.write().unwrap() = Arc::new(keys);
+            for table in self.tables.iter() {
+                self.dirty.mark_full(table.key());
+            }
+            self.reseal_catalog().await?;
+        }
+        if !report.settings.is_empty() {
+            let mut config = self.config.write().await;
+            config.wal_archive = new_config.wal_archive.clone();
+            config.snapshot_compression = new_config.snapshot_compression.clone();
+            config.snapshot_compact_after = new_config.snapshot_compact_after;
+            config.encryption = new_config.encryption.clone();
+        }
+
+        for table in &report.created {
+            if let Err(e) = self.create_table(table.clone()).await {
+                report.conflicts.push((table.name.clone(), e.to_string()));
+            }
+        }
+        // Правки одного сундука идут подряд — каждая пачка целиком сверяется до первой правки!
+        for batch in report.migrations.chunk_by(|a, b| a.table() == b.table()) {
+            let table = batch[0].table().to_string();
+            match self.migrate_all(&table, batch.to_vec()).await {
+                Ok(applied) => report.applied.extend(applied),
+                Err(e) => report.conflicts.push((table, e.to_string())),
+            }
+        }
+        Ok(report)
+    }
+
+    // Шпион следит за папкой карты — редактор часто пишет новый файл и переименовывает его поверх старого!
+    pub(crate) async fn watch_config(&self) {
+        let path = Path::new(&self.config_file);
+        let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let name = path.file_name().map(|n| n.to_os_string());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let handler = move |event: notify::Result<notify::Event>| { let _ = tx.send(event); };
+        let mut watcher: Box<dyn Watcher + Send> = match notify::recommended_watcher(handler.clone()) {
+            Ok(watcher) => Box::new(watcher),
+            Err(e) => {
+                println!("События файловой системы недоступны: {}, опрашиваем карту каждые {} сек!", e, POLL_INTERVAL.as_secs());
+                match PollWatcher::new(handler, notify::Config::default().with_poll_interval(POLL_INTERVAL)) {
+                    Ok(watcher) => Box::new(watcher),
+                    Err(e) => return println!("Шпион за картой не заступил: {} — перечитывайте её через reload_config!", e),
+                }
+            }
+        };
+        if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+            return println!("Шпион за картой не заступил: {} — перечитывайте её через reload_config!", e);
+        }
+
+        while let Some(event) = rx.recv().await {
+            let ours = match &event {
+                Ok(event) => matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+                    && event.paths.iter().any(|p| p.file_name() == name.as_deref()),
+                Err(e) => { println!("Шторм у шпиона за картой: {}", e); false }
+            };
+            if !ours {
+                continue;
+            }
+            sleep(SETTLE).await;
+            while rx.try_recv().is_ok() {} // Пачка событий от одной записи — одна перечитка!
+            match self.reload_config().await {
+                Ok(report) if report.is_empty() => {}
+                Ok(report) => println!("Йо-хо-хо! Карта {} перечитана:\n{}", self.config_file, report),
+                Err(e) => println!("Карта {} не перечитана: {} — плывём по каталогу!", self.config_file, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,204 @@
+// Последовательности — именованные счётчики: номера идут по порядку и не повторяются после перезапуска!
+use serde::{Deserialize, Serialize};
+use crate::DbError;
+
+// Сколько номеров бронируем одной записью в WAL — реже пишем, а после шторма будет лишь дырка в номерах!
+const CACHE: u64 = 32;
+
+// Настройки последовательности — как в CREATE SEQUENCE!
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceOptions {
+    pub start: Option<i64>,     // Первый номер — по умолчанию нижняя граница (верхняя, если шагаем вниз)!
+    pub increment: i64,         // Шаг — бывает и отрицательным, но не нулём!
+    pub min_value: Option<i64>, // Нижняя граница — по умолчанию 1 (или i64::MIN при шаге вниз)!
+    pub max_value: Option<i64>, // Верхняя граница — по умолчанию i64::MAX (или -1 при шаге вниз)!
+    pub cycle: bool,            // Дошли до края — по кругу или ошибка?
+}
+
+impl Default for SequenceOptions {
+    fn default() -> Self {
+        SequenceOptions { start: None, increment: 1, min_value: None, max_value: None, cycle: false }
+    }
+}
+
+// Сама последовательность — настройки и последний выданный номер, так и лежит на диске и в WAL!
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) struct Sequence {
+    pub(crate) name: String,
+    increment: i64,
+    min_value: i64,
+    max_value: i64,
+    cycle: bool,
+    value: i64,   // Последний выданный номер — или первый, если ещё не выдавали!
+    called: bool, // Выдавали ли `value` — после setval следующий пойдёт за ним!
+}
+
+impl Sequence {
+    // Собираем последовательность из настроек — границы и старт проверяем сразу!
+    pub(crate) fn new(name: &str, options: &SequenceOptions) -> Result<Self, DbError> {
+        let increment = options.increment;
+        if increment == 0 {
+            return Err(DbError::InvalidValue("increment".to_string(), "0".to_string()));
+        }
+        let min_value = options.min_value.unwrap_or(if increment > 0 { 1 } else { i64::MIN });
+        let max_value = options.max_value.unwrap_or(if increment > 0 { i64::MAX } else { -1 });
+        if min_value > max_value {
+            return Err(DbError::InvalidValue("min_value".to_string(), format!("{} больше max_value {}", min_value, max_value)));
+        }
+        let start = options.start.unwrap_or(if increment > 0 { min_value } else { max_value });
+        if !(min_value..=max_value).contains(&start) {
+            return Err(DbError::InvalidValue("start".to_string(), start.to_string()));
+        }
+        Ok(Sequence {
+            name: name.to_string(),
+            increment, min_value, max_value,
+            cycle: options.cycle,
+            value: start,
+            called: false,
+        })
+    }
+
+    // Следующий номер — шагаем, а на краю идём по кругу или сдаёмся!
+    fn advance(&mut self) -> Result<i64, DbError> {
+        if !self.called {
+            self.called = true;
+            return Ok(self.value); // Первый раз — отдаём стартовый!
+        }
+        let next = self.value.checked_add(self.increment)
+            .filter(|v| (self.min_value..=self.max_value).contains(v));
+        self.value = match next {
+            Some(v) => v,
+            None if self.cycle => if self.increment > 0 { self.min_value } else { self.max_value },
+            None => return Err(DbError::SequenceExhausted(self.name.clone())),
+        };
+        Ok(self.value)
+    }
+
+    // Ставим номер вручную — следующий nextval пойдёт за ним!
+    fn set(&mut self, value: i64) -> Result<(), DbError> {
+        if !(self.min_value..=self.max_value).contains(&value) {
+            return Err(DbError::InvalidValue(self.name.clone(), value.to_string()));
+        }
+        self.value = value;
+        self.called = true;
+        Ok(())
+    }
+
+    // Выдаст ли счётчик `value` в будущем? Тогда его уже заняли руками — надо перешагнуть!
+    fn is_ahead(&self, value: i64) -> bool {
+        if !(self.min_value..=self.max_value).contains(&value) {
+            return false; // За границами — счётчик туда не доберётся!
+        }
+        match (self.increment > 0, self.called) {
+            (true, true) => value > self.value,
+            (true, false) => value >= self.value,
+            (false, true) => value < self.value,
+            (false, false) => value <= self.value,
+        }
+    }
+}
+
+// Последовательность в памяти — текущий номер и забронированный в WAL, до которого можно не писать!
+#[derive(Debug, Clone)]
+pub(crate) struct SequenceState {
+    current: Sequence, // Что уже выдали!
+    logged: Sequence,  // Что записано в WAL и на диск — после шторма продолжим отсюда!
+    left: u64,         // Сколько номеров ещё покрыто бронью!
+}
+
+impl SequenceState {
+    // Свежая или загруженная последовательность — брони нет, всё записанное уже выдано!
+    pub(crate) fn new(sequence: Sequence) -> Self {
+        SequenceState { current: sequence.clone(), logged: sequence, left: 0 }
+    }
+
+    // Что кладём на диск — бронь, а не текущий номер, чтобы выданное не повторилось!
+    pub(crate) fn durable(&self) -> &Sequence {
+        &self.logged
+    }
+
+    // Берём `n` номеров! Если брони мало — отдаём новую бронь, её надо записать в WAL до выдачи номеров.
+    pub(crate) fn take(&mut self, n: u64) -> Result<(Vec<i64>, Option<Sequence>), DbError> {
+        let mut record = None;
+        if self.left < n {
+            let mut ahead = self.current.clone();
+            let mut steps = 0;
+            while steps < n.max(CACHE) && ahead.advance().is_ok() {
+                steps += 1; // У края без цикла бронируем сколько есть!
+            }
+            if steps < n {
+                // Всех `n` не набрать — отказ до первого шага: выданные наполовину номера сгорели бы зря!
+                return Err(DbError::SequenceExhausted(self.current.name.clone()));
+            }
+            self.logged = ahead.clone();
+            self.left = steps;
+            record = Some(ahead);
+        }
+        let mut values = Vec::with_capacity(n as usize);
+        for _ in 0..n {
+            values.push(self.current.advance()?); // Бронь покрывает все `n` — край тут не встретится!
+        }
+        self.left -= n;
+        Ok((values, record))
+    }
+
+    // setval — новая точка отсчёта, сразу в WAL!
+    pub(crate) fn set(&mut self, value: i64) -> Result<Sequence, DbError> {
+        self.current.set(value)?;
+        self.logged = self.current.clone();
+        self.left = 0;
+        Ok(self.logged.clone())
+    }
+
+    // Номер вставили руками? Сдвигаем счётчик за него, чтобы не выдать его же! Запись для WAL — если сдвинули.
+    pub(crate) fn skip_past(&mut self, value: i64) -> Result<Option<Sequence>, DbError> {
+        if self.current.is_ahead(value) {
+            return self.set(value).map(Some);
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(options: SequenceOptions) -> SequenceState {
+        SequenceState::new(Sequence::new("tickets", &options).unwrap())
+    }
+
+    #[test]
+    fn take_reserves_ahead_and_steps_by_increment() {
+        let mut s = state(SequenceOptions { start: Some(100), increment: 5, ..Default::default() });
+        let (values, record) = s.take(3).unwrap();
+        assert_eq!(values, vec![100, 105, 110]);
+        assert_eq!(record.unwrap().value, 100 + 5 * (CACHE as i64 - 1)); // Бронь — на CACHE номеров вперёд!
+        let (values, record) = s.take(1).unwrap();
+        assert_eq!((values, record), (vec![115], None)); // Внутри брони WAL не нужен!
+    }
+
+    #[test]
+    fn exhausted_take_changes_nothing() {
+        let mut s = state(SequenceOptions { max_value: Some(3), ..Default::default() });
+        assert_eq!(s.take(2).unwrap().0, vec![1, 2]);
+        // Осталась одна тройка — просим две: отказ, и тройка по-прежнему ждёт своего часа!
+        assert!(matches!(s.take(2), Err(DbError::SequenceExhausted(_))));
+        assert_eq!(s.take(1).unwrap().0, vec![3]);
+        assert!(matches!(s.take(1), Err(DbError::SequenceExhausted(_))));
+
+        let mut s = state(SequenceOptions { max_value: Some(2), cycle: true, ..Default::default() });
+        assert_eq!(s.take(5).unwrap().0, vec![1, 2, 1, 2, 1]); // По кругу — края нет!
+    }
+
+    #[test]
+    fn set_and_skip_past_move_the_counter() {
+        let mut s = state(SequenceOptions::default());
+        s.take(1).unwrap();
+        assert_eq!(s.set(10).unwrap().value, 10);
+        assert_eq!(s.take(1).unwrap().0, vec![11]);
+        assert!(s.skip_past(5).unwrap().is_none()); // Уже позади — не трогаем!
+        assert!(s.skip_past(20).unwrap().is_some());
+        assert_eq!(s.take(1).unwrap().0, vec![21]);
+        assert!(matches!(s.set(0), Err(DbError::InvalidValue(..)))); // Ниже min_value!
+    }
+}
@@ -0,0 +1,476 @@
+// Сундук на диске — заголовок с версией формата, имена полей один раз на сундук, по желанию сжатие LZ4 или zstd!
+// Свои типы для диска: поменяем `Row` или `Value` — старые сундуки читаются как раньше, через миграции ниже.
+use std::collections::{BTreeSet, HashMap};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use crate::crypto::Keyring;
+use crate::{DbError, Row, RowId, Value};
+
+// Метка формата — первые байты `.bin`!
+const MAGIC: &[u8; 4] = b"YUAI";
+const DELTA_MAGIC: &[u8; 4] = b"YUAD"; // А это — первые байты дельты!
+
+// Версии формата сундука:
+// 0 — `HashMap<i32, Row>` без заголовка (до версии строк — ещё и без `version`);
+// 1 — `TableFile` без заголовка: строки с 64-битными номерами и счётчик;
+// 2 — заголовок, словарь полей, строки с номерами полей вместо имён;
+// 3 — в заголовке ещё байт шифрования, сжатые байты по желанию зашифрованы, заголовок — в проверке подлинности.
+pub(crate) const SNAPSHOT_VERSION: u16 = 3;
+
+// Сжатие сундука — задаётся на карте `snapshot_compression = "lz4"`!
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Compression {
+    #[default]
+    None,
+    Lz4,  // Быстро, жмёт умеренно!
+    Zstd, // Жмёт сильнее — нужна фича `zstd`!
+}
+
+impl Compression {
+    // Сжатие с карты — `none`, `lz4` или `zstd`; пусто — без сжатия!
+    pub(crate) fn from_config(name: Option<&str>) -> Result<Self, DbError> {
+        match name.map(str::to_lowercase).as_deref() {
+            None | Some("none") => Ok(Compression::None),
+            Some("lz4") => Ok(Compression::Lz4),
+            Some("zstd") => Ok(Compression::Zstd),
+            Some(other) => Err(DbError::InvalidValue("snapshot_compression".to_string(), other.to_string())),
+        }
+    }
+
+    fn tag(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    fn compress(self, payload: Vec<u8>) -> Result<Vec<u8>, DbError> {
+        match self {
+            Compression::None => Ok(payload),
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(&payload)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(zstd::bulk::compress(&payload, 3)?),
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => Err(DbError::FormatError("zstd без фичи `zstd` не собран".to_string())),
+        }
+    }
+
+    fn decompress(tag: u8, payload: &[u8]) -> Result<Vec<u8>, DbError> {
+        match tag {
+            0 => Ok(payload.to_vec()),
+            1 => lz4_flex::decompress_size_prepended(payload).map_err(|e| DbError::FormatError(e.to_string())),
+            #[cfg(feature = "zstd")]
+            2 => Ok(zstd::stream::decode_all(payload)?),
+            #[cfg(not(feature = "zstd"))]
+            2 => Err(DbError::FormatError("сундук сжат zstd, а фича `zstd` не собрана".to_string())),
+            other => Err(DbError::FormatError(format!("неизвестное сжатие {}", other))),
+        }
+    }
+}
+
+// Значение на диске — порядок вариантов менять нельзя, только дописывать в конец!
+#[derive(Serialize, Deserialize)]
+enum DiskValue {
+    Numeric(f64),
+    Text(String),
+    Timestamp(i64),
+    Boolean(bool),
+}
+
+impl From<&Value> for DiskValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Numeric(n) => DiskValue::Numeric(*n),
+            Value::Text(s) => DiskValue::Text(s.clone()),
+            Value::Timestamp(t) => DiskValue::Timestamp(*t),
+            Value::Boolean(b) => DiskValue::Boolean(*b),
+        }
+    }
+}
+
+impl From<DiskValue> for Value {
+    fn from(value: DiskValue) -> Self {
+        match value {
+            DiskValue::Numeric(n) => Value::Numeric(n),
+            DiskValue::Text(s) => Value::Text(s),
+            DiskValue::Timestamp(t) => Value::Timestamp(t),
+            DiskValue::Boolean(b) => Value::Boolean(b),
+        }
+    }
+}
+
+fn row_data(data: HashMap<String, DiskValue>) -> HashMap<String, Value> {
+    data.into_iter().map(|(k, v)| (k, v.into())).collect()
+}
+
+// Строка версии 2 — поля по номеру в словаре сундука!
+#[derive(Serialize, Deserialize)]
+struct DiskRow {
+    id: RowId,
+    version: u64,
+    expires_at: Option<i64>,
+    fields: Vec<(u32, DiskValue)>,
+}
+
+// Сундук версии 2 — счётчик, словарь имён полей и строки!
+#[derive(Serialize, Deserialize)]
+struct DiskTable {
+    next_id: RowId,
+    columns: Vec<String>,
+    rows: Vec<DiskRow>,
+}
+
+// Дельта — тронутые страницы сундука после базового `.bin`; страница в дельте заменяет прежнюю целиком!
+#[derive(Serialize, Deserialize)]
+struct DiskDelta {
+    next_id: RowId,
+    page_rows: u64, // Размер страницы при записи — сменим его, старые дельты всё равно лягут верно!
+    pages: Vec<u64>,
+    columns: Vec<String>,
+    rows: Vec<DiskRow>,
+}
+
+// Сундук в памяти после чтения — строки и следующий свободный номер, чтобы удалённые номера не вернулись!
+pub(crate) struct TableFile {
+    pub(crate) next_id: RowId,
+    pub(crate) rows: HashMap<RowId, Row>,
+    pub(crate) migrated: bool, // Старая версия или старый ключ — при следующей точке сохранения перепишем целиком!
+}
+
+// Дельта в памяти — какие строки заменить и на что!
+pub(crate) struct TableDelta {
+    pub(crate) next_id: RowId,
+    pub(crate) page_rows: u64,
+    pub(crate) pages: Vec<u64>,
+    pub(crate) rows: Vec<Row>,
+    pub(crate) migrated: bool, // Как у сундука — дельту старой версии или под старым ключом уберёт полная перезапись!
+}
+
+// Словарь полей и строки с номерами вместо имён — строки по номеру, чтобы одинаковые сундуки давали одинаковые байты!
+fn to_disk(mut rows: Vec<Row>) -> (Vec<String>, Vec<DiskRow>) {
+    rows.sort_by_key(|r| r.id);
+    let columns: Vec<String> = rows.iter().flat_map(|r| r.data.keys()).collect::<BTreeSet<_>>().into_iter().cloned().collect();
+    let index: HashMap<&str, u32> = columns.iter().enumerate().map(|(i, c)| (c.as_str(), i as u32)).collect();
+    let disk_rows = rows.iter().map(|r| {
+        let mut fields: Vec<(u32, DiskValue)> = r.data.iter().map(|(k, v)| (index[k.as_str()], v.into())).collect();
+        fields.sort_by_key(|(i, _)| *i);
+        DiskRow { id: r.id, version: r.version, expires_at: r.expires_at, fields }
+    }).collect();
+    (columns, disk_rows)
+}
+
+// Обратно — имена из словаря!
+fn from_disk(columns: &[String], disk_rows: Vec<DiskRow>) -> Result<Vec<Row>, DbError> {
+    disk_rows.into_iter().map(|row| {
+        let mut data = HashMap::with_capacity(row.fields.len());
+        for (i, value) in row.fields {
+            let name = columns.get(i as usize)
+                .ok_or_else(|| DbError::FormatError(format!("поле №{} вне словаря", i)))?;
+            data.insert(name.clone(), value.into());
+        }
+        Ok(Row { id: row.id, data, expires_at: row.expires_at, version: row.version, commit_ts: 0 })
+    }).collect()
+}
+
+// Заголовок, сжатие и шифр — одинаковые у сундука и дельты!
+fn frame<T: Serialize>(magic: &[u8; 4], value: &T, compression: Compression, keys: &Keyring) -> Result<Vec<u8>, DbError> {
+    let payload = compression.compress(bincode::DefaultOptions::new().serialize(value)?)?;
+    let mut out = Vec::with_capacity(payload.len() + 8);
+    out.extend_from_slice(magic);
+    out.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    out.push(compression.tag());
+    out.push(keys.sealing() as u8);
+    if keys.sealing() {
+        let sealed = keys.seal(&out, &payload)?; // Заголовок — в проверку: подменят сжатие или версию, ключ не откроет!
+        out.extend(sealed);
+    } else {
+        out.extend(payload);
+    }
+    Ok(out)
+}
+
+// Снимаем заголовок — распакованные байты и признак «лежит в текущей версии и под текущим ключом»!
+fn unframe(buffer: &[u8], magic: &[u8; 4], keys: &Keyring, what: &str) -> Result<(Vec<u8>, bool), DbError> {
+    let rest = &buffer[magic.len()..];
+    if rest.len() < 3 {
+        return Err(DbError::FormatError(format!("{}: заголовок оборван", what)));
+    }
+    let version = u16::from_le_bytes([rest[0], rest[1]]);
+    let compression = rest[2];
+    match version {
+        2 => Ok((Compression::decompress(compression, &rest[3..])?, false)), // Без шифра — перепишем в текущую!
+        3 => {
+            let sealed = *rest.get(3).ok_or_else(|| DbError::FormatError(format!("{}: заголовок оборван", what)))?;
+            let header = &buffer[..magic.len() + 4];
+            let body = &rest[4..];
+            let (payload, fresh) = match sealed {
+                0 => (body.to_vec(), !keys.sealing()), // Открытый, а ключ уже есть — пора зашифровать!
+                _ => keys.open(header, body, what)?,
+            };
+            Ok((Compression::decompress(compression, &payload)?, fresh))
+        }
+        other => Err(DbError::FormatError(format!("{}: версия {} новее этой сборки (знаем до {})", what, other, SNAPSHOT_VERSION))),
+    }
+}
+
+// Кодируем сундук в текущую версию!
+pub(crate) fn encode(next_id: RowId, rows: Vec<Row>, compression: Compression, keys: &Keyring) -> Result<Vec<u8>, DbError> {
+    let (columns, rows) = to_disk(rows);
+    frame(MAGIC, &DiskTable { next_id, columns, rows }, compression, keys)
+}
+
+// Читаем сундук любой версии — старые мигрируем в текущую, на диск новая ляжет при следующей точке сохранения!
+pub(crate) fn decode(buffer: &[u8], keys: &Keyring, name: &str) -> Result<TableFile, DbError> {
+    if !buffer.starts_with(MAGIC) {
+        return migrate_unversioned(buffer); // Заголовка нет — версия 0 или 1!
+    }
+    let (payload, fresh) = unframe(buffer, MAGIC, keys, name)?;
+    let table: DiskTable = bincode::DefaultOptions::new().reject_trailing_bytes().deserialize(&payload)?;
+    let rows = from_disk(&table.columns, table.rows)?.into_iter().map(|r| (r.id, r)).collect();
+    Ok(TableFile { next_id: table.next_id, rows, migrated: !fresh })
+}
+
+// Кодируем дельту — строки снимка на тронутых страницах!
+pub(crate) fn encode_delta(next_id: RowId, page_rows: u64, pages: Vec<u64>, rows: Vec<Row>, compression: Compression, keys: &Keyring) -> Result<Vec<u8>, DbError> {
+    let (columns, rows) = to_disk(rows);
+    frame(DELTA_MAGIC, &DiskDelta { next_id, page_rows, pages, columns, rows }, compression, keys)
+}
+
+pub(crate) fn decode_delta(buffer: &[u8], keys: &Keyring, name: &str) -> Result<TableDelta, DbError> {
+    if !buffer.starts_with(DELTA_MAGIC) {
+        return Err(DbError::FormatError(format!("{}: не дельта сундука", name)));
+    }
+    let (payload, fresh) = unframe(buffer, DELTA_MAGIC, keys, name)?;
+    let delta: DiskDelta = bincode::DefaultOptions::new().reject_trailing_bytes().deserialize(&payload)?;
+    let rows = from_disk(&delta.columns, delta.rows)?;
+    Ok(TableDelta { next_id: delta.next_id, page_rows: delta.page_rows, pages: delta.pages, rows, migrated: !fresh })
+}
+
+// Имя дельты — `{сундук}.{номер}.delta`, по номеру они и накладываются!
+pub(crate) fn delta_name(table: &str, number: u64) -> String {
+    format!("{}.{:06}.delta", table, number)
+}
+
+// Дельты папки по сундукам — номер и имя файла, по порядку!
+pub(crate) async fn deltas(dir: &str) -> Result<HashMap<String, Vec<(u64, String)>>, DbError> {
+    let mut found: HashMap<String, Vec<(u64, String)>> = HashMap::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else { continue };
+        let Some((table, number)) = name.strip_suffix(".delta").and_then(|stem| stem.rsplit_once('.')) else { continue };
+        if let Ok(number) = number.parse() {
+            found.entry(table.to_string()).or_default().push((number, name.clone()));
+        }
+    }
+    for list in found.values_mut() {
+        list.sort();
+    }
+    Ok(found)
+}
+
+// Строка версии 1 — 64-битный номер, имена полей в каждой строке!
+#[derive(Deserialize)]
+struct RowV1 {
+    id: RowId,
+    data: HashMap<String, DiskValue>,
+    expires_at: Option<i64>,
+    version: u64,
+}
+
+// Сундук версии 1 — счётчик и строки по номеру!
+#[derive(Deserialize)]
+struct TableV1 {
+    next_id: RowId,
+    rows: HashMap<RowId, RowV1>,
+}
+
+// Строка версии 0 с версией строки, но ещё с 32-битным номером!
+#[derive(Deserialize)]
+struct VersionedRowV0 {
+    id: i32,
+    data: HashMap<String, DiskValue>,
+    expires_at: Option<i64>,
+    version: u64,
+}
+
+// Строка версии 0 — самая старая: без версии строки!
+#[derive(Deserialize)]
+struct RowV0 {
+    id: i32,
+    data: HashMap<String, DiskValue>,
+    expires_at: Option<i64>,
+}
+
+// Миграция версий 0 и 1 — пробуем от новой к старой!
+fn migrate_unversioned(buffer: &[u8]) -> Result<TableFile, DbError> {
+    // Строго до последнего байта — иначе старый формат мог бы притвориться новым!
+    let strict = || bincode::DefaultOptions::new().with_fixint_encoding().reject_trailing_bytes();
+    if let Ok(table) = strict().deserialize::<TableV1>(buffer) {
+        let rows = table.rows.into_iter()
+            .map(|(id, r)| (id, Row { id: r.id, data: row_data(r.data), expires_at: r.expires_at, version: r.version, commit_ts: 0 }))
+            .collect();
+        return Ok(TableFile { next_id: table.next_id, rows, migrated: true });
+    }
+    // Старые сундуки — счётчика нет, поднимем его по самому большому номеру!
+    let rows: HashMap<RowId, Row> = match strict().deserialize::<HashMap<i32, VersionedRowV0>>(buffer) {
+        Ok(legacy) => legacy.into_values()
+            .map(|r| Row { id: r.id as RowId, data: row_data(r.data), expires_at: r.expires_at, version: r.version, commit_ts: 0 })
+            .map(|r| (r.id, r))
+            .collect(),
+        Err(_) => strict().deserialize::<HashMap<i32, RowV0>>(buffer)?.into_values()
+            .map(|r| Row { id: r.id as RowId, data: row_data(r.data), expires_at: r.expires_at, version: 1, commit_ts: 0 }) // Старым — первая версия!
+            .map(|r| (r.id, r))
+            .collect(),
+    };
+    let next_id = rows.keys().max().map_or(1, |max| max + 1);
+    Ok(TableFile { next_id, rows, migrated: true })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Старые форматы — писать их сборка давно не умеет, собираем байты как их писали тогда!
+    #[derive(Serialize)]
+    struct RowV0Out {
+        id: i32,
+        data: HashMap<String, DiskValue>,
+        expires_at: Option<i64>,
+    }
+
+    #[derive(Serialize)]
+    struct VersionedRowV0Out {
+        id: i32,
+        data: HashMap<String, DiskValue>,
+        expires_at: Option<i64>,
+        version: u64,
+    }
+
+    #[derive(Serialize)]
+    struct RowV1Out {
+        id: RowId,
+        data: HashMap<String, DiskValue>,
+        expires_at: Option<i64>,
+        version: u64,
+    }
+
+    #[derive(Serialize)]
+    struct TableV1Out {
+        next_id: RowId,
+        rows: HashMap<RowId, RowV1Out>,
+    }
+
+    fn disk_data(name: &str) -> HashMap<String, DiskValue> {
+        HashMap::from([("name".to_string(), DiskValue::Text(name.to_string())), ("speed".to_string(), DiskValue::Numeric(9.5))])
+    }
+
+    fn row(id: RowId, name: &str, version: u64) -> Row {
+        let data = row_data(disk_data(name));
+        Row { id, data, expires_at: None, version, commit_ts: 0 }
+    }
+
+    // Строки по номеру — имя, версия и срок, без метки коммита!
+    fn summary(file: &TableFile) -> Vec<(RowId, String, u64, Option<i64>)> {
+        let mut rows: Vec<_> = file.rows.values()
+            .map(|r| (r.id, r.data["name"].to_string(), r.version, r.expires_at))
+            .collect();
+        rows.sort();
+        rows
+    }
+
+    #[test]
+    fn version_0_without_row_versions_gets_version_1() {
+        let legacy = HashMap::from([
+            (3, RowV0Out { id: 3, data: disk_data("Жемчужина"), expires_at: Some(100) }),
+            (7, RowV0Out { id: 7, data: disk_data("Голландец"), expires_at: None }),
+        ]);
+        let file = decode(&bincode::serialize(&legacy).unwrap(), &Keyring::default(), "ships").unwrap();
+        assert!(file.migrated);
+        assert_eq!(file.next_id, 8);
+        assert_eq!(summary(&file), vec![(3, "Жемчужина".to_string(), 1, Some(100)), (7, "Голландец".to_string(), 1, None)]);
+        assert_eq!(file.rows[&3].data["speed"], Value::Numeric(9.5));
+    }
+
+    #[test]
+    fn version_0_with_row_versions_keeps_them() {
+        let legacy = HashMap::from([(2, VersionedRowV0Out { id: 2, data: disk_data("Месть"), expires_at: None, version: 5 })]);
+        let file = decode(&bincode::serialize(&legacy).unwrap(), &Keyring::default(), "ships").unwrap();
+        assert!(file.migrated);
+        assert_eq!(file.next_id, 3);
+        assert_eq!(summary(&file), vec![(2, "Месть".to_string(), 5, None)]);
+    }
+
+    #[test]
+    fn version_1_keeps_its_counter() {
+        let table = TableV1Out {
+            next_id: 40,
+            rows: HashMap::from([(4, RowV1Out { id: 4, data: disk_data("Перехватчик"), expires_at: None, version: 2 })]),
+        };
+        let file = decode(&bincode::serialize(&table).unwrap(), &Keyring::default(), "ships").unwrap();
+        assert!(file.migrated);
+        assert_eq!(file.next_id, 40); // Удалённые номера не вернутся!
+        assert_eq!(summary(&file), vec![(4, "Перехватчик".to_string(), 2, None)]);
+    }
+
+    #[test]
+    fn version_2_is_read_and_marked_for_rewrite() {
+        let (columns, rows) = to_disk(vec![row(1, "Жемчужина", 3)]);
+        let payload = Compression::Lz4.compress(bincode::DefaultOptions::new().serialize(&DiskTable { next_id: 2, columns, rows }).unwrap()).unwrap();
+        let mut buffer = MAGIC.to_vec();
+        buffer.extend_from_slice(&2u16.to_le_bytes());
+        buffer.push(Compression::Lz4.tag());
+        buffer.extend(payload);
+        let file = decode(&buffer, &Keyring::default(), "ships").unwrap();
+        assert!(file.migrated);
+        assert_eq!(summary(&file), vec![(1, "Жемчужина".to_string(), 3, None)]);
+    }
+
+    #[test]
+    fn current_version_round_trips_with_compression() {
+        for compression in [Compression::None, Compression::Lz4] {
+            let rows = vec![row(2, "Голландец", 1), row(1, "Жемчужина", 4)];
+            let buffer = encode(9, rows.clone(), compression, &Keyring::default()).unwrap();
+            assert_eq!(buffer, encode(9, rows.into_iter().rev().collect(), compression, &Keyring::default()).unwrap()); // Порядок не важен!
+            let file = decode(&buffer, &Keyring::default(), "ships").unwrap();
+            assert!(!file.migrated);
+            assert_eq!(file.next_id, 9);
+            assert_eq!(summary(&file), vec![(1, "Жемчужина".to_string(), 4, None), (2, "Голландец".to_string(), 1, None)]);
+        }
+    }
+
+    #[test]
+    fn delta_round_trips_its_pages() {
+        let buffer = encode_delta(2049, 1024, vec![0, 2], vec![row(2048, "Месть", 2), row(5, "Жемчужина", 7)], Compression::Lz4, &Keyring::default()).unwrap();
+        let delta = decode_delta(&buffer, &Keyring::default(), "ships.000001.delta").unwrap();
+        assert!(!delta.migrated);
+        assert_eq!((delta.next_id, delta.page_rows, delta.pages), (2049, 1024, vec![0, 2]));
+        let rows: Vec<(RowId, u64)> = delta.rows.iter().map(|r| (r.id, r.version)).collect();
+        assert_eq!(rows, vec![(5, 7), (2048, 2)]);
+
+        // Сундук — не дельта, и наоборот!
+        let table = encode(1, Vec::new(), Compression::None, &Keyring::default()).unwrap();
+        assert!(matches!(decode_delta(&table, &Keyring::default(), "ships"), Err(DbError::FormatError(_))));
+    }
+
+    #[tokio::test]
+    async fn deltas_are_listed_per_table_in_order() {
+        let dir = std::env::temp_dir().join(format!("yuaidb-deltas-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in [delta_name("ships", 10), delta_name("ships", 2), delta_name("crew.old", 1), "ships.bin".to_string()] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let found = deltas(dir.to_str().unwrap()).await.unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found["ships"], vec![(2, delta_name("ships", 2)), (10, delta_name("ships", 10))]);
+        assert_eq!(found["crew.old"], vec![(1, delta_name("crew.old", 1))]);
+    }
+
+    #[test]
+    fn newer_version_is_refused() {
+        let mut buffer = encode(1, Vec::new(), Compression::None, &Keyring::default()).unwrap();
+        buffer[4..6].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        assert!(matches!(decode(&buffer, &Keyring::default(), "ships"), Err(DbError::FormatError(_))));
+    }
+}
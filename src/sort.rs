@@ -0,0 +1,199 @@
+// Сортировка — порядок в трюме по нескольким ключам, с NULL-ами и правильным алфавитом!
+use std::cmp::Ordering; // Кто больше — тот выше!
+use std::collections::BinaryHeap; // Куча — держим только лучших!
+use serde::{Serialize, Deserialize}; // Ключи сортировки плавают вместе с запросом!
+use crate::{DbError, Value};
+
+// Ключ сортировки — поле или выражение, направление и место для пустот!
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SortKey {
+    pub expr: String,              // Поле, кличка проекции или выражение!
+    pub ascending: bool,           // ASC=true, DESC=false!
+    pub nulls_first: Option<bool>, // NULLS FIRST/LAST; None — как в Postgres: NULL больше всех!
+}
+
+impl SortKey {
+    // Где пустоты — сверху или снизу?
+    pub fn nulls_first(&self) -> bool {
+        self.nulls_first.unwrap_or(!self.ascending) // ASC — NULL в конце, DESC — в начале!
+    }
+}
+
+// Правила сравнения текста — побайтно, без регистра или по-русски!
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Collation {
+    #[default]
+    Binary, // Байт за байтом — как раньше!
+    NoCase, // Регистр не важен — "иван" == "Иван"!
+    Ru,     // Русский алфавит — ё сразу после е, строчные перед заглавными!
+}
+
+impl Collation {
+    // Разбираем имя из конфига — неизвестное не прощаем!
+    pub fn parse(name: Option<&str>) -> Result<Self, DbError> {
+        match name.map(|n| n.to_lowercase()).as_deref() {
+            None | Some("binary") => Ok(Collation::Binary),
+            Some("nocase") => Ok(Collation::NoCase),
+            Some("ru") | Some("ru_ru") | Some("locale") => Ok(Collation::Ru),
+            Some(other) => Err(DbError::InvalidValue("collation".to_string(), other.to_string())),
+        }
+    }
+
+    // Сравниваем два значения с учётом правил — текст по коллации, остальное как есть!
+    // Порядок полный: NaN — после всех чисел, разные типы — по рангу, иначе сортировка может и упасть.
+    pub fn compare(&self, a: &Value, b: &Value) -> Ordering {
+        match (self, a, b) {
+            (Collation::NoCase, Value::Text(x), Value::Text(y)) => {
+                x.to_lowercase().cmp(&y.to_lowercase()).then_with(|| x.cmp(y))
+            }
+            (Collation::Ru, Value::Text(x), Value::Text(y)) => {
+                let primary = x.chars().map(ru_weight).cmp(y.chars().map(ru_weight));
+                // Равны по буквам? Тогда строчные раньше заглавных, а дальше — побайтно!
+                primary.then_with(|| x.chars().map(|c| c.is_uppercase()).cmp(y.chars().map(|c| c.is_uppercase())))
+                    .then_with(|| x.cmp(y))
+            }
+            _ => total_compare(a, b),
+        }
+    }
+}
+
+// Ранг типа — логические, потом числа и время, потом текст!
+fn rank(v: &Value) -> u8 {
+    match v {
+        Value::Boolean(_) => 0,
+        Value::Numeric(_) | Value::Timestamp(_) => 1,
+        Value::Text(_) => 2,
+    }
+}
+
+// Полный порядок без коллации — числа через `total_cmp`, время с числами на одной оси!
+fn total_compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Numeric(x), Value::Numeric(y)) => x.total_cmp(y),
+        (Value::Timestamp(x), Value::Timestamp(y)) => x.cmp(y),
+        (Value::Boolean(x), Value::Boolean(y)) => x.cmp(y),
+        (Value::Text(x), Value::Text(y)) => x.cmp(y),
+        (Value::Numeric(x), Value::Timestamp(y)) => x.total_cmp(&(*y as f64)).then(Ordering::Less),
+        (Value::Timestamp(x), Value::Numeric(y)) => (*x as f64).total_cmp(y).then(Ordering::Greater),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+// Вес буквы для русского алфавита — ё встаёт между е и ж, регистр забываем!
+fn ru_weight(c: char) -> u32 {
+    let lower = c.to_lowercase().next().unwrap_or(c);
+    match lower {
+        'ё' => ('е' as u32) * 2 + 1, // Ровно между е и ж!
+        _ => (lower as u32) * 2,
+    }
+}
+
+// Готовый к сравнению ключ — значения уже посчитаны, правила известны!
+pub struct SortSpec {
+    pub ascending: bool,      // Направление!
+    pub nulls_first: bool,    // Место для пустот!
+    pub collation: Collation, // Алфавит!
+}
+
+// Сравниваем два набора ключей — по очереди, пока не найдём разницу!
+pub fn compare_keys(specs: &[SortSpec], a: &[Option<Value>], b: &[Option<Value>]) -> Ordering {
+    for (i, spec) in specs.iter().enumerate() {
+        let ord = match (&a[i], &b[i]) {
+            (None, None) => Ordering::Equal,
+            // Пустоты ставим на своё место — направление их не переворачивает!
+            (None, Some(_)) => if spec.nulls_first { Ordering::Less } else { Ordering::Greater },
+            (Some(_), None) => if spec.nulls_first { Ordering::Greater } else { Ordering::Less },
+            (Some(x), Some(y)) => {
+                let ord = spec.collation.compare(x, y);
+                if spec.ascending { ord } else { ord.reverse() }
+            }
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+// Кандидат в топ — ключи плюс исходная позиция, чтобы порядок был стабильным!
+struct Ranked<'a> {
+    keys: Vec<Option<Value>>, // Посчитанные ключи!
+    pos: usize,               // Исходное место в трюме!
+    specs: &'a [SortSpec],    // Правила сравнения!
+}
+
+impl Ord for Ranked<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_keys(self.specs, &self.keys, &other.keys).then(self.pos.cmp(&other.pos))
+    }
+}
+
+impl PartialOrd for Ranked<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl PartialEq for Ranked<'_> {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+
+impl Eq for Ranked<'_> {}
+
+// Сортируем добычу по ключам — полностью или только первые `top` штук через кучу!
+// Возвращаем позиции строк в нужном порядке — сами строки двигает вызывающий.
+pub fn order(specs: &[SortSpec], keys: Vec<Vec<Option<Value>>>, top: Option<usize>) -> Vec<usize> {
+    match top {
+        Some(k) if k < keys.len() => {
+            // Куча на k мест — самый "худший" сверху, выкидываем его, если пришёл кто-то лучше!
+            let mut heap: BinaryHeap<Ranked> = BinaryHeap::with_capacity(k + 1);
+            for (pos, row_keys) in keys.into_iter().enumerate() {
+                let candidate = Ranked { keys: row_keys, pos, specs };
+                if heap.len() < k {
+                    heap.push(candidate);
+                } else if heap.peek().is_some_and(|worst| candidate < *worst) {
+                    heap.pop();
+                    heap.push(candidate);
+                }
+            }
+            heap.into_sorted_vec().into_iter().map(|r| r.pos).collect()
+        }
+        _ => {
+            let mut ranked: Vec<(usize, Vec<Option<Value>>)> = keys.into_iter().enumerate().collect();
+            ranked.sort_by(|(_, a), (_, b)| compare_keys(specs, a, b)); // Стабильная сортировка — равные не прыгают!
+            ranked.into_iter().map(|(pos, _)| pos).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ключи вперемешку: NaN, числа, текст, время — сортировка не падает и не ходит по кругу!
+    #[test]
+    fn mixed_and_nan_keys_sort_in_a_total_order() {
+        let values = vec![
+            Value::Numeric(10.0), Value::Text("1a".to_string()), Value::Numeric(2.0), Value::Numeric(f64::NAN),
+            Value::Timestamp(5), Value::Boolean(true), Value::Numeric(-1.0), Value::Text("10".to_string()),
+        ];
+        let specs = [SortSpec { ascending: true, nulls_first: false, collation: Collation::Binary }];
+        let keys: Vec<Vec<Option<Value>>> = values.iter().map(|v| vec![Some(v.clone())]).collect();
+        let sorted: Vec<String> = order(&specs, keys.clone(), None).into_iter().map(|i| values[i].to_string()).collect();
+        assert_eq!(sorted, vec!["true", "-1", "2", "5", "10", "NaN", "10", "1a"]);
+        let top: Vec<String> = order(&specs, keys, Some(3)).into_iter().map(|i| values[i].to_string()).collect();
+        assert_eq!(top, vec!["true", "-1", "2"]);
+
+        // Транзитивность на всех тройках — и для коллаций тоже!
+        for collation in [Collation::Binary, Collation::NoCase, Collation::Ru] {
+            for a in &values {
+                for b in &values {
+                    assert_eq!(collation.compare(a, b), collation.compare(b, a).reverse());
+                    for c in &values {
+                        if collation.compare(a, b) != Ordering::Greater && collation.compare(b, c) != Ordering::Greater {
+                            assert_ne!(collation.compare(a, c), Ordering::Greater);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
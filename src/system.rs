@@ -0,0 +1,207 @@
+// Служебные сундуки — схема, метки и счётчики базы, собранные на лету! Только для чтения, обычным `select`.
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::Ordering;
+use crate::{wal, Database, DbConfig, Row, RowId, Value, MIGRATIONS_TABLE};
+
+const TABLES: &str = "yuaidb_tables";     // Сундуки — строки, просрочка, следующий номер!
+const COLUMNS: &str = "yuaidb_columns";   // Поля — типы, метки, счётчики и выражения!
+const INDEXES: &str = "yuaidb_indexes";   // Метки — вид и размер!
+const STATS: &str = "yuaidb_stats";       // Корабль целиком — журнал и точка сохранения!
+
+// Имена служебных сундуков — их не завести, не поправить и не выкинуть!
+pub const SYSTEM_TABLES: [&str; 4] = [TABLES, COLUMNS, INDEXES, STATS];
+
+// Поля служебных сундуков по порядку — для `select *`!
+const TABLES_FIELDS: &[&str] = &["name", "kind", "fields", "rows", "expired", "next_id"];
+const COLUMNS_FIELDS: &[&str] = &["table", "name", "position", "type", "indexed", "unique", "fulltext", "autoincrement", "sequence", "identity", "generated", "collation"];
+const INDEXES_FIELDS: &[&str] = &["table", "column", "kind", "keys", "entries"];
+const STATS_FIELDS: &[&str] = &["tables", "rows", "expired", "sequences", "wal_bytes", "wal_segments", "wal_lsn", "checkpoint_lsn", "last_checkpoint"];
+
+pub(crate) fn fields(name: &str) -> Option<&'static [&'static str]> {
+    match name {
+        TABLES => Some(TABLES_FIELDS),
+        COLUMNS => Some(COLUMNS_FIELDS),
+        INDEXES => Some(INDEXES_FIELDS),
+        STATS => Some(STATS_FIELDS),
+        _ => None,
+    }
+}
+
+// Служебный ли сундук — собранный на лету, а не лежащий в трюме?
+pub(crate) fn is_virtual(name: &str) -> bool {
+    SYSTEM_TABLES.contains(&name)
+}
+
+// Только для чтения — служебные собраны на лету, а историю миграций пишет лишь сама миграция!
+pub(crate) fn is_read_only(name: &str) -> bool {
+    is_virtual(name) || name == MIGRATIONS_TABLE
+}
+
+// Строка служебного сундука — пустые значения просто не кладём, как NULL в обычных!
+fn row(id: usize, fields: Vec<(&str, Option<Value>)>) -> Row {
+    Row {
+        id: id as RowId,
+        data: fields.into_iter().filter_map(|(name, value)| value.map(|v| (name.to_string(), v))).collect(),
+        expires_at: None,
+        version: 1,
+        commit_ts: 0, // Видна с любого снимка!
+    }
+}
+
+fn number(n: f64) -> Option<Value> {
+    Some(Value::Numeric(n))
+}
+
+fn text(s: impl Into<String>) -> Option<Value> {
+    Some(Value::Text(s.into()))
+}
+
+fn flag(f: Option<bool>) -> Option<Value> {
+    Some(Value::Boolean(f == Some(true)))
+}
+
+impl Database {
+    // Строки служебного сундука на снимке — `None`, если сундук не служебный!
+    pub(crate) fn system_rows(&self, config: &DbConfig, name: &str, snapshot: u64) -> Option<Vec<Row>> {
+        let rows = match name {
+            TABLES => self.system_tables(config, snapshot),
+            COLUMNS => self.system_columns(config),
+            INDEXES => self.system_indexes(config),
+            STATS => vec![self.system_stats(snapshot)],
+            _ => return None,
+        };
+        Some(rows)
+    }
+
+    // Имена сундуков — из трюма и с карты, по алфавиту!
+    fn table_names(&self, config: &DbConfig) -> Vec<String> {
+        let mut names: Vec<String> = self.tables.iter().map(|t| t.key().clone()).collect();
+        names.extend(config.tables.iter().map(|t| t.name.clone()));
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // Живые и просроченные строки сундука на снимке — просрочку ещё не вымели, но и не видно!
+    fn row_counts(&self, name: &str, snapshot: u64, now: i64) -> (usize, usize) {
+        let Some(table) = self.tables.get(name).map(|t| t.clone()) else { return (0, 0) };
+        let rows = self.mvcc.visible(name, &table, snapshot);
+        let expired = rows.iter().filter(|r| r.expires_at.is_some_and(|at| at <= now)).count();
+        (rows.len() - expired, expired)
+    }
+
+    fn system_tables(&self, config: &DbConfig, snapshot: u64) -> Vec<Row> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let mut rows = Vec::new();
+        for name in self.table_names(config) {
+            let (live, expired) = self.row_counts(&name, snapshot, now);
+            let kind = if name == MIGRATIONS_TABLE { "system" } else { "table" };
+            rows.push(row(rows.len(), vec![
+                ("name", text(name.as_str())),
+                ("kind", text(kind)),
+                ("fields", config.tables.iter().find(|t| t.name == name).and_then(|t| number(t.fields.len() as f64))),
+                ("rows", number(live as f64)),
+                ("expired", number(expired as f64)),
+                ("next_id", number(self.peek_row_id(&name) as f64)), // Подсмотр — чтение счётчиков не заводит!
+            ]));
+        }
+        for name in SYSTEM_TABLES {
+            rows.push(row(rows.len(), vec![("name", text(name)), ("kind", text("virtual"))]));
+        }
+        rows
+    }
+
+    fn system_columns(&self, config: &DbConfig) -> Vec<Row> {
+        let mut rows = Vec::new();
+        for table in &config.tables {
+            for (position, field) in table.fields.iter().enumerate() {
+                rows.push(row(rows.len(), vec![
+                    ("table", text(table.name.as_str())),
+                    ("name", text(field.name.as_str())),
+                    ("position", number((position + 1) as f64)),
+                    ("type", text(field.field_type.as_str())),
+                    ("indexed", flag(field.indexed)),
+                    ("unique", flag(field.unique)),
+                    ("fulltext", flag(field.fulltext)),
+                    ("autoincrement", flag(field.autoincrement)),
+                    ("sequence", field.sequence.clone().and_then(text)),
+                    ("identity", field.identity.clone().and_then(text)),
+                    ("generated", field.generated.clone().and_then(text)),
+                    ("collation", field.collation.clone().and_then(text)),
+                ]));
+            }
+        }
+        rows
+    }
+
+    fn system_indexes(&self, config: &DbConfig) -> Vec<Row> {
+        let mut rows = Vec::new();
+        for table in &config.tables {
+            for field in &table.fields {
+                let unique = field.unique == Some(true);
+                let kinds = [
+                    (unique || field.indexed == Some(true), if unique { "unique" } else { "index" }, &self.indexes),
+                    (field.fulltext == Some(true), "fulltext", &self.fulltext_indexes),
+                ];
+                for (declared, kind, indexes) in kinds {
+                    if !declared {
+                        continue;
+                    }
+                    // Размер — сколько разных значений (или слов) и сколько ссылок на строки в метке!
+                    let index = indexes.get(&table.name).and_then(|m| m.get(&field.name).map(|i| i.clone()));
+                    let entries = index.as_ref().map(|i| i.iter().map(|ids| ids.len()).sum::<usize>());
+                    rows.push(row(rows.len(), vec![
+                        ("table", text(table.name.as_str())),
+                        ("column", text(field.name.as_str())),
+                        ("kind", text(kind)),
+                        ("keys", index.as_ref().and_then(|i| number(i.iter().filter(|ids| !ids.is_empty()).count() as f64))), // Выкинутые значения оставляют пустой ярлык — не считаем!
+                        ("entries", entries.and_then(|n| number(n as f64))),
+                    ]));
+                }
+            }
+        }
+        rows
+    }
+
+    fn system_stats(&self, snapshot: u64) -> Row {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let names: Vec<String> = self.tables.iter().map(|t| t.key().clone()).collect();
+        let (live, expired) = names.iter()
+            .map(|name| self.row_counts(name, snapshot, now))
+            .fold((0, 0), |(l, e), (live, expired)| (l + live, e + expired));
+        let (wal_bytes, wal_segments) = wal::size(&self.data_dir);
+        let checkpoint = wal::checkpoint_stat(&self.data_dir);
+        row(0, vec![
+            ("tables", number(names.len() as f64)),
+            ("rows", number(live as f64)),
+            ("expired", number(expired as f64)),
+            ("sequences", number(self.sequences.len() as f64)),
+            ("wal_bytes", number(wal_bytes as f64)),
+            ("wal_segments", number(wal_segments as f64)),
+            ("wal_lsn", number(self.wal_lsn.load(Ordering::SeqCst) as f64)),
+            ("checkpoint_lsn", checkpoint.and_then(|(lsn, _)| number(lsn as f64))),
+            ("last_checkpoint", checkpoint.map(|(_, at)| Value::Timestamp(at))),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Database;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reading_tables_does_not_create_row_counters() {
+        let dir = std::env::temp_dir().join(format!("yuaidb-system-peek-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let dir = dir.to_string_lossy().into_owned();
+        let db = Database::new(&format!("{}/data", dir), &format!("{}/config.toml", dir)).await.unwrap();
+        db.execute_ddl("CREATE TABLE ships (name text)").await.unwrap();
+        assert!(!db.row_ids.contains_key("ships"));
+
+        let mut q = db.select("yuaidb_tables");
+        q.where_eq("name", "ships");
+        let row = q.execute(&db).await.unwrap().unwrap().remove(0);
+        assert_eq!(row["next_id"], "1");
+        assert!(db.row_ids.is_empty()); // Подсмотрели — и ни одного счётчика, даже для истории миграций!
+    }
+}
@@ -0,0 +1,275 @@
+// Погрузка и выгрузка — CSV, JSON Lines, а с фичей `columnar` ещё Arrow и Parquet, чтобы добыча ходила и в чужие порты!
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+use crate::{BulkLoadReport, Database, DbError, Query, Row, Value};
+
+// Формат файла — таблица с запятыми или по JSON-объекту на строку!
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,       // Первая строка — имена полей, дальше значения!
+    JsonLines, // Одна строка — один объект `{"name": "Джек", "ship_id": 101}`!
+    #[cfg(feature = "columnar")]
+    ArrowIpc,  // Arrow IPC — колонки с типами, для аналитиков!
+    #[cfg(feature = "columnar")]
+    Parquet,   // Parquet — колонки со сжатием!
+}
+
+impl Format {
+    // Угадываем формат по расширению — `.csv`, `.jsonl`, `.ndjson`, а с фичей `columnar` ещё `.arrow` и `.parquet`!
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Format> {
+        match path.as_ref().extension()?.to_str()?.to_lowercase().as_str() {
+            "csv" => Some(Format::Csv),
+            "jsonl" | "ndjson" => Some(Format::JsonLines),
+            #[cfg(feature = "columnar")]
+            "arrow" | "feather" => Some(Format::ArrowIpc),
+            #[cfg(feature = "columnar")]
+            "parquet" => Some(Format::Parquet),
+            _ => None,
+        }
+    }
+}
+
+// Строки из файла — номер строки в файле и поля; кривые сразу с ошибкой!
+pub(crate) type ParsedLines = Vec<(usize, Result<HashMap<String, String>, DbError>)>;
+
+// Какие ячейки записи в кавычках — читалка CSV этого не говорит, а `""` и пустота для нас разные вещи!
+// `raw` — байты файла с начала записи, смотрим до конца строки вне кавычек.
+fn quoted_cells(raw: &[u8]) -> Vec<bool> {
+    let mut quoted = Vec::new();
+    let (mut at_start, mut in_quotes) = (true, false);
+    let mut i = 0;
+    while i < raw.len() {
+        let b = raw[i];
+        if at_start {
+            quoted.push(b == b'"');
+            at_start = false;
+            if b == b'"' {
+                in_quotes = true;
+                i += 1;
+                continue;
+            }
+        }
+        if in_quotes {
+            if b == b'"' {
+                if raw.get(i + 1) == Some(&b'"') { i += 1; } else { in_quotes = false; } // `""` внутри — просто кавычка!
+            }
+        } else if b == b',' {
+            at_start = true;
+        } else if b == b'\n' || b == b'\r' {
+            break;
+        }
+        i += 1;
+    }
+    if at_start {
+        quoted.push(false); // Запятая в конце — последняя ячейка пустая и без кавычек!
+    }
+    quoted
+}
+
+// Разбираем CSV — пустая ячейка без кавычек значит, что поля нет, а `""` — пустая строка!
+fn parse_csv(buffer: &[u8]) -> Result<ParsedLines, DbError> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(buffer);
+    let headers = reader.headers().map_err(|e| DbError::FormatError(format!("строка 1: {}", e)))?.clone();
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, |p| p.line() as usize);
+                let row = if record.len() != headers.len() {
+                    Err(DbError::FormatError(format!("ячеек {}, а в заголовке {}", record.len(), headers.len())))
+                } else {
+                    let quoted = record.position().map_or_else(Vec::new, |p| quoted_cells(&buffer[p.byte() as usize..]));
+                    Ok(headers.iter().zip(record.iter()).enumerate()
+                        .filter(|(i, (_, value))| !value.is_empty() || quoted.get(*i) == Some(&true))
+                        .map(|(_, (name, value))| (name.to_string(), value.to_string()))
+                        .collect())
+                };
+                rows.push((line, row));
+            }
+            Err(e) => {
+                let line = e.position().map_or(0, |p| p.line() as usize);
+                rows.push((line, Err(DbError::FormatError(e.to_string()))));
+            }
+        }
+    }
+    Ok(rows)
+}
+
+// Разбираем JSON Lines — null значит, что поля нет, вложенные объекты не берём!
+fn parse_json_lines(buffer: &[u8]) -> Result<ParsedLines, DbError> {
+    let text = std::str::from_utf8(buffer).map_err(|e| DbError::FormatError(e.to_string()))?;
+    let mut rows = Vec::new();
+    for (i, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let row = match serde_json::from_str::<serde_json::Value>(line) {
+            Ok(serde_json::Value::Object(object)) => object.into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(name, value)| match value {
+                    serde_json::Value::String(s) => Ok((name, s)),
+                    serde_json::Value::Number(n) => Ok((name, n.to_string())),
+                    serde_json::Value::Bool(b) => Ok((name, b.to_string())),
+                    _ => Err(DbError::InvalidValue(name, "вложенные массивы и объекты не грузим".to_string())),
+                })
+                .collect(),
+            Ok(_) => Err(DbError::FormatError("ждём объект `{...}`".to_string())),
+            Err(e) => Err(DbError::FormatError(e.to_string())),
+        };
+        rows.push((i + 1, row));
+    }
+    Ok(rows)
+}
+
+// Значение для JSON — числа числами, флаги флагами!
+fn json_value(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Numeric(n) if n.fract() == 0.0 && n.abs() < 9e15 => serde_json::Value::from(*n as i64), // Целые — без `.0`!
+        Value::Numeric(n) => serde_json::Number::from_f64(*n).map_or(serde_json::Value::Null, serde_json::Value::Number),
+        Value::Text(s) => serde_json::Value::String(s.clone()),
+        Value::Timestamp(t) => serde_json::Value::from(*t),
+        Value::Boolean(b) => serde_json::Value::Bool(*b),
+    }
+}
+
+// Колонка выгрузки — имя и тип по карте: "numeric", "text", "timestamp" или "boolean"!
+pub(crate) type Column = (String, String);
+
+// Тип колонки без карты — по первому значению!
+pub(crate) fn value_type(value: &Value) -> &'static str {
+    match value {
+        Value::Numeric(_) => "numeric",
+        Value::Text(_) => "text",
+        Value::Timestamp(_) => "timestamp",
+        Value::Boolean(_) => "boolean",
+    }
+}
+
+// Ячейка CSV — текст всегда в кавычках, даже пустой, пропуск — пустота без кавычек: погрузка их не спутает!
+// Писатель CSV кавычки по ячейке не ставит, поэтому строки данных собираем сами.
+fn csv_cell(value: Option<&Value>) -> String {
+    match value.map(json_value) {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(serde_json::Value::String(s)) => format!("\"{}\"", s.replace('"', "\"\"")),
+        Some(other) => other.to_string(),
+    }
+}
+
+// Кодируем строки в файл — колонки по порядку, пропуски пустые!
+fn encode(columns: &[Column], rows: &[HashMap<String, Value>], format: Format) -> Result<Vec<u8>, DbError> {
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.write_record(columns.iter().map(|(name, _)| name)).map_err(|e| DbError::FormatError(e.to_string()))?;
+            let mut out = writer.into_inner().map_err(|e| DbError::FormatError(e.to_string()))?;
+            for row in rows {
+                let cells: Vec<String> = columns.iter().map(|(name, _)| csv_cell(row.get(name))).collect();
+                out.extend_from_slice(cells.join(",").as_bytes());
+                out.push(b'\n');
+            }
+            Ok(out)
+        }
+        Format::JsonLines => {
+            let mut out = Vec::new();
+            for row in rows {
+                let object: serde_json::Map<String, serde_json::Value> = columns.iter()
+                    .filter_map(|(name, _)| row.get(name).map(|v| (name.clone(), json_value(v))))
+                    .collect();
+                serde_json::to_writer(&mut out, &object).map_err(|e| DbError::FormatError(e.to_string()))?;
+                out.push(b'\n');
+            }
+            Ok(out)
+        }
+        #[cfg(feature = "columnar")]
+        Format::ArrowIpc | Format::Parquet => crate::columnar::encode(columns, rows, format == Format::Parquet),
+    }
+}
+
+impl Database {
+    // Погрузка из файла — `db.import("pirates", "pirates.csv", Format::Csv)`! Поля типизируются по карте сундука.
+    // Кривые строки не останавливают погрузку — в отчёте номер строки в файле и причина.
+    pub async fn import(&self, table: &str, path: impl AsRef<Path>, format: Format) -> Result<BulkLoadReport, DbError> {
+        let mut buffer = Vec::new();
+        File::open(path.as_ref()).await?.read_to_end(&mut buffer).await?;
+        let parsed = match format {
+            Format::Csv => parse_csv(&buffer)?,
+            Format::JsonLines => parse_json_lines(&buffer)?,
+            #[cfg(feature = "columnar")]
+            Format::ArrowIpc | Format::Parquet => crate::columnar::decode(buffer, format == Format::Parquet)?, // Номер строки — номер записи!
+        };
+        let mut lines = Vec::new();
+        let mut rows = Vec::new();
+        let mut errors = Vec::new();
+        for (line, row) in parsed {
+            match row {
+                Ok(row) => { lines.push(line); rows.push(row); }
+                Err(e) => errors.push((line, e)),
+            }
+        }
+        let mut report = self.bulk_load(table, rows).await?;
+        // Номера во входе пачки — в номера строк файла!
+        report.errors = report.errors.into_iter().map(|(row, e)| (lines[row - 1], e)).chain(errors).collect();
+        report.errors.sort_by_key(|(line, _)| *line);
+        Ok(report)
+    }
+
+    // Выгрузка сундука в файл — согласованный снимок, поля в порядке карты, типы сохраняем!
+    // Строкам со сроком жизни добавляем колонку `ttl` — остаток в секундах, погрузка поймёт её обратно.
+    pub async fn export(&self, table: &str, path: impl AsRef<Path>, format: Format) -> Result<usize, DbError> {
+        let table_data = self.tables.get(table).map(|t| t.clone())
+            .ok_or_else(|| DbError::TableNotFound(table.to_string()))?;
+        let pin = self.mvcc.pin(); // Снимок — пишущие рядом не порвут выгрузку!
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let mut rows: Vec<Row> = self.mvcc.visible(table, &table_data, pin.ts()).into_iter()
+            .filter(|r| r.expires_at.is_none_or(|at| at > now)) // Просрочку не везём!
+            .collect();
+        rows.sort_by_key(|r| r.id);
+
+        // Колонки — сперва по карте, потом всё, чего на карте нет, с типом по первому значению!
+        let mut columns: Vec<Column> = self.config.read().await.tables.iter()
+            .find(|t| t.name == table)
+            .map(|t| t.fields.iter().map(|f| (f.name.clone(), f.field_type.clone())).collect())
+            .unwrap_or_default();
+        let mut extra: BTreeMap<&String, &Value> = BTreeMap::new();
+        for (name, value) in rows.iter().flat_map(|r| r.data.iter()) {
+            if !columns.iter().any(|(c, _)| c == name) {
+                extra.entry(name).or_insert(value);
+            }
+        }
+        columns.extend(extra.into_iter().map(|(name, value)| (name.clone(), value_type(value).to_string())));
+        if rows.iter().any(|r| r.expires_at.is_some()) {
+            columns.push(("ttl".to_string(), "numeric".to_string()));
+        }
+
+        let values: Vec<HashMap<String, Value>> = rows.into_iter().map(|r| {
+            let mut row = r.data;
+            if let Some(at) = r.expires_at {
+                row.insert("ttl".to_string(), Value::Numeric((at - now) as f64));
+            }
+            row
+        }).collect();
+        tokio::fs::write(path.as_ref(), encode(&columns, &values, format)?).await?; // Целиком и с досылкой — недописанный `File` при сбросе теряет хвост!
+        Ok(values.len())
+    }
+
+    // Выгрузка результата SELECT — колонки в порядке проекции, тип по первому значению: числа остаются числами!
+    pub async fn export_query(&self, query: Query, path: impl AsRef<Path>, format: Format) -> Result<usize, DbError> {
+        let pin = self.mvcc.pin();
+        let rows = {
+            let config = self.config.read().await;
+            self.select_rows(&config, pin.ts(), &query, None)?
+        };
+        let mut columns: Vec<Column> = Vec::new();
+        for (name, _) in rows.iter().flatten() {
+            if !columns.iter().any(|(c, _)| c == name) {
+                let first = rows.iter().flatten().find_map(|(c, v)| v.as_ref().filter(|_| c == name));
+                columns.push((name.clone(), first.map_or("text", value_type).to_string())); // Одни NULL — пусть будет текст!
+            }
+        }
+        let values: Vec<HashMap<String, Value>> = rows.into_iter()
+            .map(|r| r.into_iter().filter_map(|(k, v)| v.map(|v| (k, v))).collect()) // NULL — пропуск, как и в SELECT!
+            .collect();
+        tokio::fs::write(path.as_ref(), encode(&columns, &values, format)?).await?;
+        Ok(values.len())
+    }
+}
@@ -0,0 +1,336 @@
+// Судовой журнал на диске — записи с номером (LSN) и временем, сегменты и точка сохранения!
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::crypto::Keyring;
+use crate::{metrics, DbError, WalOperation};
+
+// Старший бит длины — запись нового образца, с номером и временем! Старые записи без него — просто операция.
+const RECORD_FLAG: u64 = 1 << 63;
+// Следующий бит — запись зашифрована, длина с флагами идёт в проверку подлинности!
+const SEALED_FLAG: u64 = 1 << 62;
+
+// Запись журнала — номер по порядку, время (мс с эпохи UNIX) и сама операция!
+#[derive(Debug, Deserialize)]
+pub(crate) struct WalRecord {
+    pub(crate) lsn: u64, // 0 — запись старого образца, её повторяем всегда!
+    pub(crate) at: i64,
+    pub(crate) operation: WalOperation,
+}
+
+// То же для записи — операцию не клонируем!
+#[derive(Serialize)]
+struct WalRecordRef<'a> {
+    lsn: u64,
+    at: i64,
+    operation: &'a WalOperation,
+}
+
+// Кодируем запись — длина с флагом и байты, с ключом на карте — зашифрованные!
+pub(crate) fn encode(lsn: u64, at: i64, operation: &WalOperation, keys: &Keyring) -> Result<Vec<u8>, DbError> {
+    let mut payload = bincode::serialize(&WalRecordRef { lsn, at, operation })?;
+    let mut header = payload.len() as u64 | RECORD_FLAG;
+    if keys.sealing() {
+        header = (payload.len() + keys.overhead()) as u64 | RECORD_FLAG | SEALED_FLAG;
+        payload = keys.seal(&header.to_le_bytes(), &payload)?;
+    }
+    let mut out = Vec::with_capacity(payload.len() + 8);
+    out.extend_from_slice(&header.to_le_bytes());
+    out.extend_from_slice(&payload);
+    Ok(out)
+}
+
+// Время записи — миллисекунды, чтобы восстановление до момента было точнее секунды!
+pub(crate) fn now_millis() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64
+}
+
+// Разбираем сегмент — целые записи и признак оборванного хвоста (шторм посреди записи)!
+pub(crate) fn decode(buffer: &[u8], keys: &Keyring) -> Result<(Vec<WalRecord>, bool), DbError> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < buffer.len() {
+        if offset + 8 > buffer.len() {
+            return Ok((records, true)); // Длина оборвана!
+        }
+        let header = u64::from_le_bytes(buffer[offset..offset + 8].try_into().unwrap());
+        let len = (header & !(RECORD_FLAG | SEALED_FLAG)) as usize;
+        offset += 8;
+        if offset + len > buffer.len() {
+            return Ok((records, true)); // Запись оборвана!
+        }
+        let payload = &buffer[offset..offset + len];
+        records.push(if header & SEALED_FLAG != 0 {
+            let (plain, _) = keys.open(&header.to_le_bytes(), payload, "журнал")?;
+            bincode::deserialize(&plain)?
+        } else if header & RECORD_FLAG != 0 {
+            bincode::deserialize(payload)?
+        } else {
+            WalRecord { lsn: 0, at: 0, operation: bincode::deserialize(payload)? }
+        });
+        offset += len;
+    }
+    Ok((records, false))
+}
+
+// Читаем сегмент с диска!
+pub(crate) async fn read_segment(path: &Path, keys: &Keyring) -> Result<(Vec<WalRecord>, bool), DbError> {
+    let mut buffer = Vec::new();
+    File::open(path).await?.read_to_end(&mut buffer).await?;
+    decode(&buffer, keys)
+}
+
+// Имя отложенного сегмента — `wal.<последний LSN>.log`, по имени они и сортируются!
+pub(crate) fn segment_name(lsn: u64) -> String {
+    format!("wal.{:020}.log", lsn)
+}
+
+// Последний LSN сегмента по имени — `wal.00000000000000000042.log` даёт 42!
+pub(crate) fn segment_lsn(path: &Path) -> Option<u64> {
+    path.file_name()?.to_str()?.strip_prefix("wal.")?.strip_suffix(".log")?.parse().ok()
+}
+
+// Сегменты папки — отложенные по порядку, живой `wal.log` последним!
+pub(crate) async fn segments(dir: &str) -> Result<Vec<PathBuf>, DbError> {
+    let mut pending = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if segment_lsn(&entry.path()).is_some() {
+            pending.push(entry.path());
+        }
+    }
+    pending.sort();
+    let live = Path::new(dir).join("wal.log");
+    if live.exists() {
+        pending.push(live);
+    }
+    Ok(pending)
+}
+
+// Точка сохранения — до какого LSN всё уже лежит в .bin!
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    lsn: u64,
+}
+
+pub(crate) async fn read_checkpoint(dir: &str) -> Result<u64, DbError> {
+    let path = Path::new(dir).join("checkpoint");
+    if !path.exists() {
+        return Ok(0); // Ещё ни одной — повторяем весь журнал!
+    }
+    let mut buffer = Vec::new();
+    File::open(&path).await?.read_to_end(&mut buffer).await?;
+    Ok(bincode::deserialize::<Checkpoint>(&buffer)?.lsn)
+}
+
+// Журнал на диске — байты и число сегментов, записи не читаем! Синхронно: служебным сундукам ждать нельзя.
+pub(crate) fn size(dir: &str) -> (u64, usize) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return (0, 0) };
+    entries.flatten()
+        .filter(|e| segment_lsn(&e.path()).is_some() || e.file_name() == "wal.log")
+        .fold((0, 0), |(bytes, count), e| (bytes + e.metadata().map_or(0, |m| m.len()), count + 1))
+}
+
+// Последняя точка сохранения — её LSN и когда её поставили (сек с эпохи UNIX)!
+pub(crate) fn checkpoint_stat(dir: &str) -> Option<(u64, i64)> {
+    let path = Path::new(dir).join("checkpoint");
+    let lsn = bincode::deserialize::<Checkpoint>(&std::fs::read(&path).ok()?).ok()?.lsn;
+    let at = std::fs::metadata(&path).ok()?.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+    Some((lsn, at))
+}
+
+// Пишем файл через временный — чтобы после шторма лежал либо старый, либо новый, но не обрывок!
+// Временный — `.part`, а не `.tmp`: каталог пишется и посреди точки сохранения, и `settle` не должен утащить его `.tmp` в поколение.
+pub(crate) async fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), DbError> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".part");
+    let tmp = PathBuf::from(tmp);
+    let mut file = File::create(&tmp).await?;
+    file.write_all(bytes).await?;
+    file.sync_all().await?;
+    metrics::FSYNCS.fetch_add(1, Ordering::Relaxed);
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+// Список файлов, которые поколение выкидывает — старые дельты после сжатия сундука!
+const OBSOLETE: &str = "obsolete";
+
+// Поколение сундуков — сперва все `.tmp`, потом `checkpoint.next` как знак, что поколение целое,
+// и только потом переименования и выброс старого. Шторм посреди — `settle` при старте доведёт дело до конца!
+pub(crate) struct Generation {
+    dir: PathBuf,
+    files: Vec<PathBuf>,
+    obsolete: Vec<String>,
+}
+
+impl Generation {
+    pub(crate) fn new(dir: &str) -> Self {
+        Generation { dir: PathBuf::from(dir), files: Vec::new(), obsolete: Vec::new() }
+    }
+
+    // Файл больше не нужен — выкинем, когда поколение встанет на место, не раньше!
+    pub(crate) fn discard(&mut self, name: &str) {
+        self.obsolete.push(name.to_string());
+    }
+
+    // Кладём файл поколения во временный!
+    pub(crate) async fn stage(&mut self, name: &str, bytes: &[u8]) -> Result<(), DbError> {
+        let path = self.dir.join(name);
+        let mut file = File::create(tmp_path(&path)).await?;
+        file.write_all(bytes).await?;
+        file.sync_all().await?;
+        metrics::FSYNCS.fetch_add(1, Ordering::Relaxed);
+        self.files.push(path);
+        Ok(())
+    }
+
+    // Поколение целое — отмечаем LSN и ставим файлы на место!
+    pub(crate) async fn commit(mut self, lsn: u64) -> Result<(), DbError> {
+        if !self.obsolete.is_empty() {
+            let list = self.obsolete.join("\n");
+            self.stage(OBSOLETE, list.as_bytes()).await?;
+        }
+        write_atomic(&self.dir.join("checkpoint.next"), &bincode::serialize(&Checkpoint { lsn })?).await?;
+        settle(self.dir.to_str().unwrap_or(".")).await
+    }
+}
+
+// Доводим поколение после шторма: есть `checkpoint.next` — ставим `.tmp` на место, нет — выкидываем недописанное!
+pub(crate) async fn settle(dir: &str) -> Result<(), DbError> {
+    let next = Path::new(dir).join("checkpoint.next");
+    let complete = next.exists();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension() != Some("tmp".as_ref()) {
+            continue;
+        }
+        if complete {
+            tokio::fs::rename(&path, path.with_extension("")).await?;
+        } else {
+            tokio::fs::remove_file(&path).await?;
+        }
+    }
+    // Поколение на месте — выкидываем то, что оно заменило! Шторм посреди — список остался, повторим.
+    let obsolete = Path::new(dir).join(OBSOLETE);
+    if complete && obsolete.exists() {
+        for name in tokio::fs::read_to_string(&obsolete).await?.lines() {
+            match tokio::fs::remove_file(Path::new(dir).join(name)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        tokio::fs::remove_file(&obsolete).await?;
+    }
+    if complete {
+        tokio::fs::rename(&next, Path::new(dir).join("checkpoint")).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(name: &str) -> WalOperation {
+        WalOperation::DropSequence { name: name.to_string() }
+    }
+
+    fn names(records: &[WalRecord]) -> Vec<(u64, i64, String)> {
+        records.iter().map(|r| match &r.operation {
+            WalOperation::DropSequence { name } => (r.lsn, r.at, name.clone()),
+            other => panic!("чужая операция {:?}", other),
+        }).collect()
+    }
+
+    // Пустая папка под тест — своя на каждый!
+    fn fresh_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("yuaidb-wal-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn records_round_trip_and_torn_tail_is_dropped() {
+        let keys = Keyring::default();
+        let mut buffer = encode(1, 10, &op("a"), &keys).unwrap();
+        buffer.extend(encode(2, 20, &op("b"), &keys).unwrap());
+        let whole = buffer.len();
+        let (records, torn) = decode(&buffer, &keys).unwrap();
+        assert!(!torn);
+        assert_eq!(names(&records), vec![(1, 10, "a".to_string()), (2, 20, "b".to_string())]);
+
+        // Шторм посреди третьей записи — первые две целы, хвост помечен оборванным!
+        buffer.extend(encode(3, 30, &op("c"), &keys).unwrap());
+        for cut in [whole + 4, buffer.len() - 1] {
+            let (records, torn) = decode(&buffer[..cut], &keys).unwrap();
+            assert!(torn);
+            assert_eq!(records.len(), 2);
+        }
+    }
+
+    #[test]
+    fn legacy_records_replay_without_lsn() {
+        let payload = bincode::serialize(&op("old")).unwrap();
+        let mut buffer = (payload.len() as u64).to_le_bytes().to_vec();
+        buffer.extend(payload);
+        let (records, torn) = decode(&buffer, &Keyring::default()).unwrap();
+        assert!(!torn);
+        assert_eq!(names(&records), vec![(0, 0, "old".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn segments_sort_by_lsn_with_live_log_last() {
+        let dir = fresh_dir("segments");
+        for name in ["wal.log".to_string(), segment_name(10), segment_name(9), "wal.log.tmp".to_string()] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        assert_eq!(segment_lsn(&dir.join(segment_name(42))), Some(42));
+        let found: Vec<String> = segments(dir.to_str().unwrap()).await.unwrap().iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(found, vec![segment_name(9), segment_name(10), "wal.log".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn committed_generation_replaces_files_and_drops_obsolete() {
+        let dir = fresh_dir("commit");
+        std::fs::write(dir.join("ships.bin"), b"old").unwrap();
+        std::fs::write(dir.join("ships.1.delta"), b"delta").unwrap();
+        let mut generation = Generation::new(dir.to_str().unwrap());
+        generation.stage("ships.bin", b"new").await.unwrap();
+        generation.discard("ships.1.delta");
+        generation.commit(7).await.unwrap();
+
+        assert_eq!(std::fs::read(dir.join("ships.bin")).unwrap(), b"new");
+        assert!(!dir.join("ships.1.delta").exists());
+        assert!(!dir.join("checkpoint.next").exists());
+        assert!(!dir.join(OBSOLETE).exists());
+        assert_eq!(read_checkpoint(dir.to_str().unwrap()).await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn settle_discards_a_generation_without_checkpoint_next() {
+        let dir = fresh_dir("torn");
+        std::fs::write(dir.join("ships.bin"), b"old").unwrap();
+        let mut generation = Generation::new(dir.to_str().unwrap());
+        generation.stage("ships.bin", b"new").await.unwrap();
+        drop(generation); // Шторм до отметки — поколение не целое!
+
+        settle(dir.to_str().unwrap()).await.unwrap();
+        assert_eq!(std::fs::read(dir.join("ships.bin")).unwrap(), b"old");
+        assert!(!dir.join("ships.bin.tmp").exists());
+        assert_eq!(read_checkpoint(dir.to_str().unwrap()).await.unwrap(), 0);
+    }
+}
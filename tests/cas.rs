@@ -0,0 +1,63 @@
+// Compare-and-set по версии строки — устаревшая версия даёт конфликт, частичной правки не бывает!
+mod common;
+
+use common::{column, fresh_dir, insert, open};
+use yuaidb::DbError;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stale_version_is_a_conflict_and_changes_nothing() {
+    let dir = fresh_dir("cas-stale");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE pirates (id numeric, name text)").await.unwrap();
+    insert(&db, "pirates", vec![("id", "1"), ("name", "Джек")]).await;
+
+    let mut q = db.select("pirates");
+    q.fields(vec!["_version"]).where_eq("id", "1");
+    assert_eq!(column(q.clone().execute(&db).await.unwrap(), "_version"), vec!["1"]);
+
+    // Версия совпала — правка прошла и подняла её!
+    let mut update = db.update_if_version("pirates", 1);
+    update.where_eq("id", "1").values(vec![("name", "Капитан Джек")]);
+    update.clone().execute(&db).await.unwrap();
+    assert_eq!(column(q.clone().execute(&db).await.unwrap(), "_version"), vec!["2"]);
+
+    // Та же версия второй раз — уже устарела!
+    assert!(matches!(update.execute(&db).await, Err(DbError::Conflict(..))));
+    let mut delete = db.delete("pirates");
+    delete.where_eq("id", "1").where_version(1);
+    assert!(matches!(delete.execute(&db).await, Err(DbError::Conflict(..))));
+    assert_eq!(column(db.select("pirates").execute(&db).await.unwrap(), "name"), vec!["Капитан Джек"]);
+
+    let mut delete = db.delete("pirates");
+    delete.where_eq("id", "1").where_version(2);
+    delete.execute(&db).await.unwrap();
+    assert!(db.select("pirates").execute(&db).await.unwrap().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_check_over_several_rows_touches_none() {
+    let dir = fresh_dir("cas-multi");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE pirates (id numeric, gold numeric)").await.unwrap();
+    for id in ["1", "2", "3"] {
+        insert(&db, "pirates", vec![("id", id), ("gold", "0")]).await;
+    }
+    // У третьей строки версия другая — раньше первые две успели бы поменяться!
+    let mut q = db.update("pirates");
+    q.where_eq("id", "3").increment("gold", 1.0);
+    q.execute(&db).await.unwrap();
+
+    let mut q = db.update_if_version("pirates", 1);
+    q.where_lt("gold", "100").increment("gold", 10.0);
+    assert!(matches!(q.execute(&db).await, Err(DbError::InvalidValue(..))));
+    let mut q = db.delete("pirates");
+    q.where_version(1);
+    assert!(matches!(q.execute(&db).await, Err(DbError::InvalidValue(..))));
+
+    let mut q = db.select("pirates");
+    q.fields(vec!["gold", "_version"]);
+    let rows = q.execute(&db).await.unwrap().unwrap();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(column(Some(rows.clone()), "gold"), vec!["0", "0", "1"]);
+    assert_eq!(column(Some(rows), "_version"), vec!["1", "1", "2"]);
+}
@@ -0,0 +1,50 @@
+// Точки сохранения — тронутые страницы ложатся дельтой, и сундук после перезапуска собирается из базы и дельт!
+mod common;
+
+use std::collections::HashMap;
+use common::{fresh_dir, open, reopen, select};
+
+fn delta_files(dir: &str) -> Vec<String> {
+    std::fs::read_dir(format!("{}/data", dir)).unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".delta"))
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn small_changes_are_written_as_deltas_and_reloaded() {
+    let dir = fresh_dir("checkpoint-delta");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE crew (name text, rank numeric)").await.unwrap();
+    let rows = (0..5000).map(|i| HashMap::from([
+        ("name".to_string(), format!("пират {}", i)),
+        ("rank".to_string(), "1".to_string()),
+    ]));
+    assert_eq!(db.bulk_load("crew", rows).await.unwrap().loaded, 5000);
+
+    // Первая точка сохранения при старте — базы ещё нет, сундук ложится целиком!
+    let db = reopen(&dir).await;
+    assert!(delta_files(&dir).is_empty());
+    let base = std::fs::read(format!("{}/data/crew.bin", dir)).unwrap();
+    let mut q = db.update("crew");
+    q.values(vec![("rank", "10")]).where_eq("name", "пират 42");
+    q.execute(&db).await.unwrap();
+    let mut q = db.delete("crew");
+    q.where_eq("name", "пират 4321");
+    q.execute(&db).await.unwrap();
+
+    // Тронуты две страницы из пяти — дельты, а база лежит как лежала!
+    // Дельт может быть и две: таймер второго корабля мог поставить точку между правкой и удалением.
+    reopen(&dir).await;
+    assert!(!delta_files(&dir).is_empty());
+    assert_eq!(std::fs::read(format!("{}/data/crew.bin", dir)).unwrap(), base);
+
+    let db = reopen(&dir).await;
+    let mut q = db.select("crew");
+    q.where_gt("rank", "1");
+    assert_eq!(select(&db, &q, "name").await, vec!["пират 42"]);
+    let mut q = db.select("crew");
+    q.where_eq("name", "пират 4321");
+    assert!(select(&db, &q, "name").await.is_empty());
+    assert_eq!(select(&db, &db.select("crew"), "name").await.len(), 4999);
+}
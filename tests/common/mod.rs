@@ -0,0 +1,41 @@
+// Общий такелаж для проверок — свежий тайник на каждый тест!
+#![allow(dead_code)]
+use std::collections::HashMap;
+use yuaidb::{Database, Query};
+
+// Пустая папка под тест — старый тайник с прошлого прогона выкидываем!
+pub fn fresh_dir(name: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("yuaidb-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.to_string_lossy().into_owned()
+}
+
+// Корабль в папке — карта в ней же, её может и не быть!
+pub async fn open(dir: &str) -> Database {
+    Database::new(&format!("{}/data", dir), &format!("{}/config.toml", dir)).await.unwrap()
+}
+
+pub async fn insert(db: &Database, table: &str, values: Vec<(&str, &str)>) {
+    let mut q = db.insert(table);
+    q.values(values);
+    q.execute(db).await.unwrap();
+}
+
+// Значения поля у найденных строк — по порядку, чтобы сравнивать без оглядки на порядок в трюме!
+pub fn column(rows: Option<Vec<HashMap<String, String>>>, field: &str) -> Vec<String> {
+    let mut values: Vec<String> = rows.unwrap_or_default().into_iter().filter_map(|mut r| r.remove(field)).collect();
+    values.sort();
+    values
+}
+
+pub async fn select(db: &Database, query: &Query, field: &str) -> Vec<String> {
+    column(query.clone().execute(db).await.unwrap(), field)
+}
+
+// Второй корабль на ту же папку — сперва даём первому доделать стартовую точку сохранения:
+// закрыть базу нечем, а две точки сохранения в одной папке друг другу мешают.
+pub async fn reopen(dir: &str) -> Database {
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    open(dir).await
+}
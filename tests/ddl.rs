@@ -0,0 +1,198 @@
+// DDL — сундуки и метки через API и SQL: данные идут следом за схемой, а журнал повторяет всё после перезапуска!
+mod common;
+
+use std::time::Duration;
+use common::{fresh_dir, insert, open, reopen, select};
+use yuaidb::{Database, DbError, FieldConfig, IndexKind, TableConfig};
+
+async fn columns(db: &Database, table: &str) -> Vec<(String, String)> {
+    let mut q = db.select("yuaidb_columns");
+    q.where_eq("table", table).order_by("position", true);
+    q.execute(db).await.unwrap().unwrap_or_default().into_iter()
+        .map(|mut c| (c.remove("name").unwrap(), c.remove("type").unwrap()))
+        .collect()
+}
+
+async fn indexes(db: &Database, table: &str) -> Vec<String> {
+    let mut q = db.select("yuaidb_indexes");
+    q.where_eq("table", table);
+    q.execute(db).await.unwrap().unwrap_or_default().into_iter()
+        .map(|i| format!("{} {}", i["column"], i["kind"]))
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn create_and_drop_table_through_api_and_sql() {
+    let dir = fresh_dir("ddl-create-drop");
+    let db = open(&dir).await;
+    db.create_table(TableConfig { name: "ships".into(), fields: vec![FieldConfig::new("name", "text")] }).await.unwrap();
+    db.execute_ddl("CREATE TABLE crew (name text, age numeric)").await.unwrap();
+    assert!(matches!(db.execute_ddl("CREATE TABLE crew (name text)").await, Err(DbError::TableExists(t)) if t == "crew"));
+    insert(&db, "ships", vec![("name", "Жемчужина")]).await;
+    insert(&db, "crew", vec![("name", "Джек"), ("age", "40")]).await;
+    assert_eq!(columns(&db, "crew").await, vec![("name".into(), "text".into()), ("age".into(), "numeric".into())]);
+
+    db.drop_table("ships").await.unwrap();
+    db.execute_ddl("DROP TABLE crew").await.unwrap();
+    for table in ["ships", "crew"] {
+        assert!(matches!(db.select(table).execute(&db).await, Err(DbError::TableNotFound(_))));
+        let mut q = db.insert(table);
+        q.values(vec![("name", "Гиббс")]);
+        assert!(matches!(q.execute(&db).await, Err(DbError::TableNotFound(_))));
+        assert!(matches!(db.drop_table(table).await, Err(DbError::TableNotFound(_))));
+    }
+    assert!(columns(&db, "crew").await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn alter_table_moves_the_data_with_the_schema() {
+    let dir = fresh_dir("ddl-alter");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (name text, speed text)").await.unwrap();
+    insert(&db, "ships", vec![("name", "Жемчужина"), ("speed", "9")]).await;
+    insert(&db, "ships", vec![("name", "Голландец"), ("speed", "12")]).await;
+
+    let report = db.execute_ddl("ALTER TABLE ships ADD COLUMN crew numeric DEFAULT 0").await.unwrap().unwrap();
+    assert_eq!(report.rows, 2);
+    assert_eq!(select(&db, &db.select("ships"), "crew").await, vec!["0", "0"]);
+
+    db.execute_ddl("ALTER TABLE ships RENAME COLUMN crew TO sailors").await.unwrap();
+    assert_eq!(select(&db, &db.select("ships"), "sailors").await, vec!["0", "0"]);
+    assert!(select(&db, &db.select("ships"), "crew").await.is_empty());
+
+    // Текст "12" меньше "9", число — больше: после смены типа сравнение честное!
+    db.execute_ddl("ALTER TABLE ships ALTER COLUMN speed TYPE numeric").await.unwrap();
+    let mut q = db.select("ships");
+    q.where_gt("speed", "10");
+    assert_eq!(select(&db, &q, "name").await, vec!["Голландец"]);
+
+    db.execute_ddl("ALTER TABLE ships DROP COLUMN sailors").await.unwrap();
+    assert!(select(&db, &db.select("ships"), "sailors").await.is_empty());
+    assert_eq!(columns(&db, "ships").await, vec![("name".into(), "text".into()), ("speed".into(), "numeric".into())]);
+
+    // То же через API — желаемая карта целиком!
+    let wanted = TableConfig { name: "ships".into(), fields: vec![FieldConfig::new("name", "text"), FieldConfig::new("speed", "text")] };
+    assert_eq!(db.alter_table(wanted).await.unwrap().len(), 1);
+    assert_eq!(columns(&db, "ships").await[1], ("speed".into(), "text".into()));
+    assert_eq!(select(&db, &db.select("ships"), "speed").await, vec!["12", "9"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn create_and_drop_index() {
+    let dir = fresh_dir("ddl-index");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (name text, log text)").await.unwrap();
+    insert(&db, "ships", vec![("name", "Жемчужина"), ("log", "ром и песни")]).await;
+    insert(&db, "ships", vec![("name", "Жемчужина")]).await;
+
+    db.execute_ddl("CREATE INDEX ships_name ON ships (name)").await.unwrap();
+    db.create_index("ships", "log", IndexKind::Fulltext).await.unwrap();
+    assert_eq!(indexes(&db, "ships").await, vec!["name index", "log fulltext"]);
+    let mut q = db.select("ships");
+    q.where_eq("name", "Жемчужина");
+    assert_eq!(select(&db, &q, "name").await.len(), 2); // Метка нашла обе строки!
+
+    // Уникальная метка поверх дублей — отказ, схема прежняя!
+    assert!(matches!(db.execute_ddl("CREATE UNIQUE INDEX ON ships (name)").await, Err(DbError::DuplicateValue(..))));
+    assert_eq!(indexes(&db, "ships").await, vec!["name index", "log fulltext"]);
+
+    db.execute_ddl("DROP INDEX ON ships (name)").await.unwrap();
+    db.drop_index("ships", "log", IndexKind::Fulltext).await.unwrap();
+    assert!(indexes(&db, "ships").await.is_empty());
+    assert_eq!(select(&db, &db.select("ships"), "name").await.len(), 2); // Данные на месте!
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn malformed_ddl_is_a_parse_error() {
+    let dir = fresh_dir("ddl-parse");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (name text)").await.unwrap();
+    for sql in [
+        "ALTER TABLE ships",
+        "ALTER TABLE ships FROB name",
+        "ALTER TABLE ships RENAME COLUMN name",
+        "ALTER TABLE ships ALTER COLUMN name numeric",
+        "CREATE INDEX ON ships",
+        "CREATE INDEX ON ships (name",
+        "DROP INDEX ships (name)",
+        "CREATE TABLE crew (name text DEFAULT 'Джек')",
+    ] {
+        assert!(matches!(db.execute_ddl(sql).await, Err(DbError::InvalidExpression(..))), "{}", sql);
+    }
+    assert_eq!(columns(&db, "ships").await, vec![("name".into(), "text".into())]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn create_and_drop_table_replay_after_reopen() {
+    let dir = fresh_dir("ddl-replay");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (name text)").await.unwrap();
+    insert(&db, "ships", vec![("name", "Жемчужина")]).await;
+    db.execute_ddl("DROP TABLE ships").await.unwrap();
+    // То же имя, другая карта — старые строки не воскресают!
+    db.execute_ddl("CREATE TABLE ships (title text, speed numeric)").await.unwrap();
+    insert(&db, "ships", vec![("title", "Голландец"), ("speed", "12")]).await;
+    db.execute_ddl("CREATE TABLE crew (name text)").await.unwrap();
+    insert(&db, "crew", vec![("name", "Джек")]).await;
+    db.execute_ddl("DROP TABLE crew").await.unwrap();
+
+    let db = reopen(&dir).await;
+    assert_eq!(columns(&db, "ships").await, vec![("title".into(), "text".into()), ("speed".into(), "numeric".into())]);
+    assert_eq!(select(&db, &db.select("ships"), "title").await, vec!["Голландец"]);
+    assert!(select(&db, &db.select("ships"), "name").await.is_empty());
+    let mut q = db.select("ships");
+    q.where_gt("speed", "9");
+    assert_eq!(select(&db, &q, "title").await, vec!["Голландец"]); // Число, а не текст!
+    assert!(matches!(db.select("crew").execute(&db).await, Err(DbError::TableNotFound(_))));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn drop_table_is_not_blocked_by_running_updates() {
+    let dir = fresh_dir("ddl-drop-busy");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE counters (name text UNIQUE, n numeric)").await.unwrap();
+    insert(&db, "counters", vec![("name", "ром"), ("n", "0")]).await;
+
+    let mut writers = Vec::new();
+    for _ in 0..4 {
+        let db = db.clone();
+        writers.push(tokio::spawn(async move {
+            loop {
+                let mut q = db.update("counters");
+                q.where_eq("name", "ром").increment("n", 1.0);
+                match q.execute_affected(&db).await {
+                    Ok((0, _)) | Err(DbError::TableNotFound(_)) => break, // Сундук за бортом — править нечего!
+                    Ok(_) => {}
+                    Err(e) => panic!("{}", e),
+                }
+                let mut q = db.delete("counters");
+                q.where_eq("name", "нет такого");
+                let _ = q.execute(&db).await;
+            }
+        }));
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    tokio::time::timeout(Duration::from_secs(10), db.drop_table("counters")).await.expect("DROP TABLE завис").unwrap();
+    for writer in writers {
+        tokio::time::timeout(Duration::from_secs(10), writer).await.expect("правка зависла").unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generated_column_must_not_read_the_clock() {
+    let dir = fresh_dir("ddl-generated-now");
+    let db = open(&dir).await;
+    // now() журнал при повторе посчитал бы заново — после перезапуска значение уплыло бы!
+    let sql = "CREATE TABLE log (msg text, at timestamp GENERATED ALWAYS AS (now()) STORED)";
+    assert!(matches!(db.execute_ddl(sql).await, Err(DbError::InvalidValue(f, _)) if f == "at"));
+    db.execute_ddl("CREATE TABLE log (msg text)").await.unwrap();
+    let sql = "ALTER TABLE log ADD COLUMN at text GENERATED ALWAYS AS (coalesce(msg, now())) STORED";
+    assert!(matches!(db.execute_ddl(sql).await, Err(DbError::InvalidValue(f, _)) if f == "at"));
+    assert_eq!(columns(&db, "log").await, vec![("msg".into(), "text".into())]);
+
+    // Чистое выражение — то же значение и после повтора журнала!
+    db.execute_ddl("ALTER TABLE log ADD COLUMN shout text GENERATED ALWAYS AS (upper(msg)) STORED").await.unwrap();
+    insert(&db, "log", vec![("msg", "йо-хо")]).await;
+    let db = reopen(&dir).await;
+    assert_eq!(select(&db, &db.select("log"), "shout").await, vec!["ЙО-ХО"]);
+}
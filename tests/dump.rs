@@ -0,0 +1,94 @@
+// Дамп и восстановление — сундуки заводятся через каталог, а без карты не заводятся вовсе!
+mod common;
+
+use common::{fresh_dir, insert, open, reopen, select};
+use yuaidb::{DbError, SequenceOptions};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restore_creates_tables_through_the_catalog() {
+    let dir = fresh_dir("dump-source");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (name text INDEX, speed numeric)").await.unwrap();
+    insert(&db, "ships", vec![("name", "Чёрная жемчужина"), ("speed", "9")]).await;
+    insert(&db, "ships", vec![("name", "Летучий голландец"), ("speed", "11")]).await;
+    let file = format!("{}/dump.jsonl", dir);
+    assert_eq!(db.dump(&file).await.unwrap(), 2);
+
+    let target = fresh_dir("dump-target");
+    let db = open(&target).await;
+    assert_eq!(db.restore(&file).await.unwrap(), 2);
+    let mut q = db.select("ships");
+    q.where_eq("name", "Летучий голландец");
+    assert_eq!(select(&db, &q, "speed").await, vec!["11"]);
+
+    // Сундук записан в каталог — после перезапуска он на месте вместе с картой!
+    let db = reopen(&target).await;
+    let mut q = db.select("ships");
+    q.where_gt("speed", "10");
+    assert_eq!(select(&db, &q, "name").await, vec!["Летучий голландец"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restore_rejects_a_table_without_schema() {
+    let dir = fresh_dir("dump-schemaless");
+    let file = format!("{}/dump.jsonl", dir);
+    std::fs::write(&file, concat!(
+        r#"{"format":"yuaidb-dump","version":1,"created_at":0,"tables":[{"name":"ghost","next_id":1,"schema":null}],"sequences":[]}"#,
+        "\n",
+    )).unwrap();
+
+    let db = open(&dir).await;
+    match db.restore(&file).await {
+        Err(DbError::TableNotFound(table)) => assert_eq!(table, "ghost"),
+        other => panic!("ждали TableNotFound, получили {:?}", other),
+    }
+    assert!(matches!(db.select("ghost").execute(&db).await, Err(DbError::TableNotFound(_))));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restore_refuses_a_table_with_rows() {
+    let dir = fresh_dir("dump-busy-source");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (name text)").await.unwrap();
+    insert(&db, "ships", vec![("name", "Жемчужина")]).await;
+    let file = format!("{}/dump.jsonl", dir);
+    db.dump(&file).await.unwrap();
+
+    let target = fresh_dir("dump-busy-target");
+    let db = open(&target).await;
+    db.execute_ddl("CREATE TABLE ships (name text)").await.unwrap();
+    insert(&db, "ships", vec![("name", "Голландец")]).await;
+    assert!(matches!(db.restore(&file).await, Err(DbError::Generic(_))));
+    assert_eq!(select(&db, &db.select("ships"), "name").await, vec!["Голландец"]); // Чужую добычу не тронули!
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restore_brings_back_sequences_and_row_counters() {
+    let dir = fresh_dir("dump-counters-source");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (num numeric AUTOINCREMENT, name text)").await.unwrap();
+    db.create_sequence("tickets", SequenceOptions { start: Some(100), ..Default::default() }).await.unwrap();
+    for name in ["Жемчужина", "Голландец", "Месть"] {
+        insert(&db, "ships", vec![("name", name)]).await;
+    }
+    assert_eq!(db.nextval("tickets").await.unwrap(), 100);
+    // Последняя строка удалена — её номер строки не должен достаться новой после восстановления!
+    let mut q = db.delete("ships");
+    q.where_eq("name", "Месть");
+    q.execute(&db).await.unwrap();
+    let file = format!("{}/dump.jsonl", dir);
+    assert_eq!(db.dump(&file).await.unwrap(), 2);
+
+    let target = fresh_dir("dump-counters-target");
+    let db = open(&target).await;
+    assert_eq!(db.restore(&file).await.unwrap(), 2);
+    assert!(db.nextval("tickets").await.unwrap() > 100); // Выданный номер не повторится!
+    let mut q = db.select("yuaidb_tables");
+    q.where_eq("name", "ships");
+    assert_eq!(select(&db, &q, "next_id").await, vec!["4"]); // Третий номер удалён до дампа, но уже выдан!
+    insert(&db, "ships", vec![("name", "Разящий")]).await;
+    assert_eq!(select(&db, &q, "next_id").await, vec!["5"]);
+    let mut q = db.select("ships");
+    q.where_eq("name", "Разящий");
+    assert!(select(&db, &q, "num").await[0].parse::<i64>().unwrap() > 3); // Автоинкремент идёт за бронью из дампа, а не с единицы!
+}
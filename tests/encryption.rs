@@ -0,0 +1,54 @@
+// Шифрование на диске — смена ключа без потери сундуков, чужой ключ не открывает ничего!
+mod common;
+
+use common::{fresh_dir, insert, select};
+use yuaidb::{Database, DbError};
+
+// Карта со своим ключом — у каждого шага своя, чтобы прежние корабли не перечитали чужую!
+fn config(dir: &str, name: &str, key: u8, old_keys: &[u8]) -> String {
+    let source = |byte: u8| {
+        let path = format!("{}/key-{}", dir, byte);
+        std::fs::write(&path, format!("{:02x}", byte).repeat(32)).unwrap();
+        format!("\"file:{}\"", path)
+    };
+    let old: Vec<String> = old_keys.iter().map(|b| source(*b)).collect();
+    let path = format!("{}/{}.toml", dir, name);
+    std::fs::write(&path, format!("[encryption]\nkey = {}\nold_keys = [{}]\n", source(key), old.join(", "))).unwrap();
+    path
+}
+
+async fn open_with(dir: &str, config: &str) -> Result<Database, DbError> {
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await; // Прежний корабль доделывает стартовую точку!
+    Database::new(&format!("{}/data", dir), config).await
+}
+
+fn on_disk(dir: &str, needle: &str) -> bool {
+    std::fs::read_dir(format!("{}/data", dir)).unwrap()
+        .map(|e| std::fs::read(e.unwrap().path()).unwrap_or_default())
+        .any(|bytes| bytes.windows(needle.len()).any(|w| w == needle.as_bytes()))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn key_rotation_rewrites_tables_under_the_new_key() {
+    let dir = fresh_dir("encryption-rotate");
+    let db = open_with(&dir, &config(&dir, "first", 1, &[])).await.unwrap();
+    db.execute_ddl("CREATE TABLE ships (name text)").await.unwrap();
+    insert(&db, "ships", vec![("name", "Жемчужина")]).await;
+    assert!(!on_disk(&dir, "Жемчужина")); // Журнал уже под замком!
+
+    // Первый ключ в старых — сундуки читаются и переписываются новым!
+    let db = open_with(&dir, &config(&dir, "rotated", 2, &[1])).await.unwrap();
+    assert_eq!(select(&db, &db.select("ships"), "name").await, vec!["Жемчужина"]);
+    insert(&db, "ships", vec![("name", "Голландец")]).await;
+
+    // Точка сохранения прошла — старый ключ больше не нужен!
+    let db = open_with(&dir, &config(&dir, "second", 2, &[])).await.unwrap();
+    assert_eq!(select(&db, &db.select("ships"), "name").await, vec!["Голландец", "Жемчужина"]);
+    assert!(!on_disk(&dir, "Жемчужина"));
+
+    // Чужой ключ — ошибка при старте, файлы не тронуты!
+    let files: Vec<_> = std::fs::read_dir(format!("{}/data", dir)).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert!(matches!(open_with(&dir, &config(&dir, "wrong", 3, &[])).await, Err(DbError::WrongKey(_))));
+    let after: Vec<_> = std::fs::read_dir(format!("{}/data", dir)).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(files, after);
+}
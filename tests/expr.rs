@@ -0,0 +1,27 @@
+// Расчёты со временем — переполнение даёт ошибку, а не валит корабль!
+mod common;
+
+use common::{fresh_dir, insert, open, select};
+use yuaidb::DbError;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn timestamp_overflow_is_an_error_not_a_panic() {
+    let dir = fresh_dir("expr-overflow");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (name text)").await.unwrap();
+    insert(&db, "ships", vec![("name", "Чёрная жемчужина")]).await;
+
+    for expr in ["now() + 10000000000000000000 > now()", "now() - 10000000000000000000 < now()", "(now() + 9000000000000000000) - (now() - 9000000000000000000) > 0"] {
+        let mut q = db.select("ships");
+        q.where_expr(expr);
+        match q.execute(&db).await {
+            Err(DbError::InvalidValue(..)) => {}
+            other => panic!("{}: ждали InvalidValue, получили {:?}", expr, other),
+        }
+    }
+
+    // В пределах i64 арифметика по-прежнему работает.
+    let mut q = db.select("ships");
+    q.where_expr("now() + 60 > now() and now() - (now() - 60) = 60");
+    assert_eq!(select(&db, &q, "name").await, vec!["Чёрная жемчужина"]);
+}
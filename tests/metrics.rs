@@ -0,0 +1,84 @@
+// Метрики — серии только по сундукам с карты, и уходят вместе с сундуком; /metrics гаснет вместе с ручкой!
+mod common;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use common::{fresh_dir, insert, open};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+async fn get_metrics(addr: SocketAddr) -> std::io::Result<String> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: yuaidb\r\n\r\n").await?;
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).await?;
+    Ok(answer)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unknown_and_dropped_tables_leave_no_series() {
+    let dir = fresh_dir("metrics-series");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (name text)").await.unwrap();
+    insert(&db, "ships", vec![("name", "Чёрная жемчужина")]).await;
+    db.select("ships").execute(&db).await.unwrap();
+
+    // Опечатки — каждая своя, а серия у всех одна!
+    for table in ["shps", "sihps", "ship"] {
+        assert!(db.select(table).execute(&db).await.is_err());
+        let mut q = db.insert(table);
+        q.values(vec![("name", "Летучий голландец")]);
+        assert!(q.execute(&db).await.is_err());
+    }
+    let report = db.metrics();
+    let mut tables: Vec<&str> = report.queries.iter().map(|q| q.table.as_str()).collect();
+    tables.dedup();
+    assert_eq!(tables, vec!["ships", "unknown"]);
+    let unknown: u64 = report.queries.iter().filter(|q| q.table == "unknown").map(|q| q.errors).sum();
+    assert_eq!(unknown, 6);
+
+    db.execute_ddl("DROP TABLE ships").await.unwrap();
+    let report = db.metrics();
+    assert!(report.queries.iter().all(|q| q.table != "ships"));
+    assert!(report.tables.iter().all(|t| t.table != "ships"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn metrics_server_stops_on_shutdown_and_on_drop() {
+    let dir = fresh_dir("metrics-server");
+    let db = open(&dir).await;
+    let server = db.serve_metrics("127.0.0.1:0").await.unwrap();
+    let addr = server.addr();
+    let answer = get_metrics(addr).await.unwrap();
+    assert!(answer.starts_with("HTTP/1.1 200 OK") && answer.contains("yuaidb_wal_records_total"), "{}", answer);
+    server.shutdown().await;
+    assert!(TcpStream::connect(addr).await.is_err()); // Порт закрыт сразу после shutdown!
+
+    // Выброшенная ручка — сервер сворачивается сам, без ожидания!
+    let addr = db.serve_metrics("127.0.0.1:0").await.unwrap().addr();
+    let mut closed = false;
+    for _ in 0..100 {
+        if TcpStream::connect(addr).await.is_err() {
+            closed = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(closed, "сервер пережил свою ручку");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn wal_flushes_count_only_flushed_buffers() {
+    let dir = fresh_dir("metrics-flushes");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE log (msg text)").await.unwrap();
+    let before = db.metrics();
+    for msg in ["йо", "хо", "хо"] {
+        insert(&db, "log", vec![("msg", msg)]).await;
+    }
+    // Запись крупнее буфера идёт в файл мимо него — записи прибавка, сбросу нет!
+    insert(&db, "log", vec![("msg", &"ром".repeat(10_000))]).await;
+    let after = db.metrics();
+    assert_eq!(after.wal_records - before.wal_records, 4);
+    assert_eq!(after.wal_flushes - before.wal_flushes, 3);
+}
@@ -0,0 +1,83 @@
+// Миграции — правка схемы вместе с данными, история и повтор журнала!
+mod common;
+
+use common::{column, fresh_dir, insert, open, reopen};
+use yuaidb::{DbError, FieldConfig, Migration, MIGRATIONS_TABLE};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn migrations_history_is_read_only() {
+    let dir = fresh_dir("migrate-history");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (name text)").await.unwrap();
+    db.execute_ddl("ALTER TABLE ships ADD COLUMN crew numeric DEFAULT 0").await.unwrap();
+    assert_eq!(column(db.select(MIGRATIONS_TABLE).execute(&db).await.unwrap(), "kind"), vec!["add_column"]);
+
+    let mut q = db.insert(MIGRATIONS_TABLE);
+    q.values(vec![("table", "ships"), ("kind", "forged")]);
+    assert!(matches!(q.execute(&db).await, Err(DbError::ReadOnlyTable(_))));
+    let mut q = db.update(MIGRATIONS_TABLE);
+    q.values(vec![("kind", "forged")]);
+    assert!(matches!(q.execute(&db).await, Err(DbError::ReadOnlyTable(_))));
+    assert!(matches!(db.delete(MIGRATIONS_TABLE).execute(&db).await, Err(DbError::ReadOnlyTable(_))));
+    let rows = vec![[("kind".to_string(), "forged".to_string())].into_iter().collect()];
+    assert!(matches!(db.bulk_load(MIGRATIONS_TABLE, rows).await, Err(DbError::ReadOnlyTable(_))));
+
+    assert_eq!(column(db.select(MIGRATIONS_TABLE).execute(&db).await.unwrap(), "kind"), vec!["add_column"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn migrations_rewrite_rows_and_survive_replay() {
+    let dir = fresh_dir("migrate-replay");
+    {
+        let db = open(&dir).await;
+        db.execute_ddl("CREATE TABLE ships (name text, speed text)").await.unwrap();
+        insert(&db, "ships", vec![("name", "pearl"), ("speed", "7")]).await;
+        db.migrate(Migration::ChangeType { table: "ships".into(), column: "speed".into(), field_type: "numeric".into(), using: Some("speed * 10".into()) }).await.unwrap();
+        db.migrate(Migration::RenameColumn { table: "ships".into(), from: "name".into(), to: "title".into() }).await.unwrap();
+        db.migrate(Migration::AddColumn { table: "ships".into(), field: FieldConfig::new("crew", "numeric"), default: Some("12".into()) }).await.unwrap();
+
+        // Битая миграция не трогает ничего!
+        insert(&db, "ships", vec![("title", "ghost"), ("speed", "1"), ("crew", "12")]).await;
+        let bad = db.migrate(Migration::AddIndex { table: "ships".into(), column: "crew".into(), kind: yuaidb::IndexKind::Unique }).await;
+        assert!(bad.is_err());
+    }
+    // Повтор журнала на новом корабле — то же самое!
+    let db = reopen(&dir).await;
+    let mut q = db.select("ships");
+    q.where_eq("title", "pearl");
+    let rows = q.execute(&db).await.unwrap().unwrap();
+    assert_eq!(rows[0].get("speed").map(String::as_str), Some("70"));
+    assert_eq!(rows[0].get("crew").map(String::as_str), Some("12"));
+    assert!(!rows[0].contains_key("name"));
+    assert_eq!(column(db.select(MIGRATIONS_TABLE).execute(&db).await.unwrap(), "kind"), vec!["add_column", "change_type", "rename_column"]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn migration_does_not_stall_writers() {
+    let dir = fresh_dir("migrate-online");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (speed text)").await.unwrap();
+    db.execute_ddl("CREATE TABLE log (note text)").await.unwrap();
+    let rows: Vec<_> = (0..100_000).map(|i| [("speed".to_string(), (i % 50 + 1).to_string())].into_iter().collect()).collect();
+    db.bulk_load("ships", rows).await.unwrap();
+
+    let migration = {
+        let db = db.clone();
+        tokio::spawn(async move {
+            db.migrate(Migration::ChangeType { table: "ships".into(), column: "speed".into(), field_type: "numeric".into(), using: Some("speed * 2".into()) }).await
+        })
+    };
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    // Пока сверяют чужой сундук, запись в соседний проходит сразу!
+    for i in 0..20 {
+        insert(&db, "log", vec![("note", i.to_string().as_str())]).await;
+    }
+    assert!(!migration.is_finished(), "миграция кончилась раньше записей — сундук слишком мал для проверки");
+    // И в тот же сундук — его сверят заново, новая строка тоже перетипизируется!
+    insert(&db, "ships", vec![("speed", "7")]).await;
+    migration.await.unwrap().unwrap();
+
+    let mut q = db.select("ships");
+    q.where_gt("speed", "0"); // Только числа!
+    assert_eq!(q.execute(&db).await.unwrap().unwrap().len(), 100_001);
+}
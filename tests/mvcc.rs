@@ -0,0 +1,60 @@
+// Снимки — повторяемое чтение, и с метками тоже!
+mod common;
+
+use common::{column, fresh_dir, insert, open};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn snapshot_ignores_writes_after_it_with_indexed_where_eq() {
+    let dir = fresh_dir("mvcc-index");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (name text INDEX, speed numeric, note text FULLTEXT)").await.unwrap();
+    insert(&db, "ships", vec![("name", "black pearl"), ("speed", "1"), ("note", "fast pirate ship")]).await;
+    insert(&db, "ships", vec![("name", "dutchman"), ("speed", "2"), ("note", "ghost ship")]).await;
+
+    let snapshot = db.snapshot();
+
+    // После снимка: новая строка с тем же значением, правка и удаление!
+    insert(&db, "ships", vec![("name", "black pearl"), ("speed", "3"), ("note", "fast copy")]).await;
+    let mut q = db.update("ships");
+    q.values(vec![("name", "flying dutchman")]).where_eq("name", "dutchman");
+    q.execute(&db).await.unwrap();
+    let mut q = db.delete("ships");
+    q.where_eq("speed", "1");
+    q.execute(&db).await.unwrap();
+
+    let mut q = db.select("ships");
+    q.where_eq("name", "black pearl");
+    assert_eq!(column(snapshot.execute(q.clone()).await.unwrap(), "speed"), vec!["1"]);
+    assert_eq!(column(q.execute(&db).await.unwrap(), "speed"), vec!["3"]);
+
+    let mut q = db.select("ships");
+    q.where_eq("name", "dutchman");
+    assert_eq!(column(snapshot.execute(q.clone()).await.unwrap(), "speed"), vec!["2"]);
+    assert!(q.execute(&db).await.unwrap().is_none());
+
+    let mut q = db.select("ships");
+    q.where_in("name", vec!["black pearl", "dutchman"]);
+    assert_eq!(column(snapshot.execute(q).await.unwrap(), "speed"), vec!["1", "2"]);
+
+    let mut q = db.select("ships");
+    q.where_contains("note", "fast");
+    assert_eq!(column(snapshot.execute(q.clone()).await.unwrap(), "speed"), vec!["1"]);
+    assert_eq!(column(q.execute(&db).await.unwrap(), "speed"), vec!["3"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn indexed_where_eq_skips_expired_rows() {
+    let dir = fresh_dir("mvcc-ttl");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE sessions (sid text INDEX)").await.unwrap();
+    insert(&db, "sessions", vec![("sid", "a"), ("ttl", "1")]).await;
+    insert(&db, "sessions", vec![("sid", "b")]).await;
+    tokio::time::sleep(std::time::Duration::from_millis(2100)).await;
+
+    let mut q = db.select("sessions");
+    q.where_eq("sid", "a");
+    assert!(q.execute(&db).await.unwrap().is_none());
+    let mut q = db.select("sessions");
+    q.where_eq("sid", "b");
+    assert_eq!(column(q.execute(&db).await.unwrap(), "sid"), vec!["b"]);
+}
@@ -0,0 +1,53 @@
+// Перечитка карты — пробный прогон ничего не трогает, а повторная перечитка той же карты ничего не делает!
+mod common;
+
+use common::{fresh_dir, open, select};
+use yuaidb::{Database, Migration};
+
+const CONFIG: &str = "[[tables]]\nname = \"ships\"\n[[tables.fields]]\nname = \"name\"\nfield_type = \"text\"\n\
+    [[tables.fields]]\nname = \"speed\"\nfield_type = \"numeric\"\nindexed = true\n\
+    [[tables]]\nname = \"crew\"\n[[tables.fields]]\nname = \"name\"\nfield_type = \"text\"\n";
+
+async fn ship_columns(db: &Database) -> Vec<String> {
+    let mut q = db.select("yuaidb_columns");
+    q.where_eq("table", "ships");
+    select(db, &q, "name").await
+}
+
+async fn tables(db: &Database) -> Vec<String> {
+    let mut q = db.select("yuaidb_tables");
+    q.where_eq("kind", "table");
+    select(db, &q, "name").await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn preview_changes_nothing_and_second_reload_is_a_no_op() {
+    let dir = fresh_dir("reload-idempotent");
+    std::fs::write(format!("{}/config.toml", dir), CONFIG).unwrap();
+    let db = open(&dir).await;
+    assert!(db.preview_config().await.unwrap().is_empty()); // Старт уже свёл карту с каталогом!
+
+    // Каталог уходит от карты через DDL — файл не трогаем, шпион за картой молчит!
+    db.execute_ddl("ALTER TABLE ships DROP COLUMN speed").await.unwrap();
+    db.execute_ddl("DROP TABLE crew").await.unwrap();
+
+    let preview = db.preview_config().await.unwrap();
+    let created: Vec<&str> = preview.created.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(created, vec!["crew"]);
+    assert!(matches!(preview.migrations.as_slice(), [Migration::AddColumn { table, field, .. }] if table == "ships" && field.name == "speed"));
+    assert!(preview.applied.is_empty());
+    assert_eq!(ship_columns(&db).await, vec!["name"]); // Пробный прогон — только отчёт!
+    assert_eq!(tables(&db).await, vec!["ships"]);
+
+    let report = db.reload_config().await.unwrap();
+    assert_eq!(report.applied.len(), 1);
+    assert_eq!(ship_columns(&db).await, vec!["name", "speed"]);
+    assert_eq!(tables(&db).await, vec!["crew", "ships"]);
+
+    // Та же карта ещё раз — ни правок, ни миграций, каталог прежний!
+    let again = db.reload_config().await.unwrap();
+    assert!(again.is_empty());
+    assert!(again.applied.is_empty());
+    assert!(db.preview_config().await.unwrap().is_empty());
+    assert_eq!(ship_columns(&db).await, vec!["name", "speed"]);
+}
@@ -0,0 +1,42 @@
+// RETURNING и счёт тронутых строк — для вставки, правки и удаления!
+mod common;
+
+use common::{column, fresh_dir, insert, open};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn returning_rows_and_affected_counts() {
+    let dir = fresh_dir("returning");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE pirates (id numeric AUTOINCREMENT, name text, gold numeric)").await.unwrap();
+
+    // Вставка — номер, выданный счётчиком, и выражение с кличкой!
+    let mut q = db.insert("pirates");
+    q.values(vec![("name", "Джек"), ("gold", "10")]).returning(vec!["id", "upper(name) AS shout"]);
+    let (affected, rows) = q.execute_affected(&db).await.unwrap();
+    let rows = rows.unwrap();
+    assert_eq!(affected, 1);
+    assert_eq!((rows[0]["id"].as_str(), rows[0]["shout"].as_str()), ("1", "ДЖЕК"));
+    insert(&db, "pirates", vec![("name", "Анна"), ("gold", "5")]).await;
+    insert(&db, "pirates", vec![("name", "Билл"), ("gold", "1")]).await;
+
+    // Правка — новые значения тронутых строк!
+    let mut q = db.update("pirates");
+    q.where_gt("gold", "2").increment("gold", 100.0).returning(vec!["name", "gold"]);
+    let (affected, rows) = q.execute_affected(&db).await.unwrap();
+    assert_eq!(affected, 2);
+    assert_eq!(column(rows, "gold"), vec!["105", "110"]);
+
+    // Без RETURNING — только счёт, строк нет; промах — ноль!
+    let mut q = db.update("pirates");
+    q.where_eq("name", "Никто").values(vec![("gold", "0")]);
+    assert_eq!(q.execute_affected(&db).await.unwrap(), (0, None));
+
+    // Удаление — выкинутые строки целиком!
+    let mut q = db.delete("pirates");
+    q.where_lt("gold", "100").returning(vec!["*"]);
+    let (affected, rows) = q.execute_affected(&db).await.unwrap();
+    let rows = rows.unwrap();
+    assert_eq!(affected, 1);
+    assert_eq!((rows[0]["name"].as_str(), rows[0]["gold"].as_str(), rows[0]["id"].as_str()), ("Билл", "1", "3"));
+    assert_eq!(column(db.select("pirates").execute(&db).await.unwrap(), "name"), vec!["Анна", "Джек"]);
+}
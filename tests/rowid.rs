@@ -0,0 +1,83 @@
+// Номера строк — 64-битный счётчик переживает перезапуск, удалённые номера не возвращаются, старые сундуки читаются!
+mod common;
+
+use std::collections::HashMap;
+use serde::Serialize;
+use common::{fresh_dir, insert, open, reopen};
+use yuaidb::Database;
+
+async fn next_id(db: &Database, table: &str) -> String {
+    let mut q = db.select("yuaidb_tables");
+    q.where_eq("name", table);
+    q.execute(db).await.unwrap().unwrap().remove(0).remove("next_id").unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn counter_survives_restart_and_skips_deleted_ids() {
+    let dir = fresh_dir("rowid-restart");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (name text)").await.unwrap();
+    for name in ["Жемчужина", "Голландец", "Месть"] {
+        insert(&db, "ships", vec![("name", name)]).await;
+    }
+    let mut q = db.delete("ships");
+    q.where_eq("name", "Месть");
+    q.execute(&db).await.unwrap();
+    assert_eq!(next_id(&db, "ships").await, "4");
+
+    // Первый перезапуск — из журнала, второй — из сундука с точки сохранения: номер 3 так и не вернётся!
+    let db = reopen(&dir).await;
+    assert_eq!(next_id(&db, "ships").await, "4");
+    let db = reopen(&dir).await;
+    assert_eq!(next_id(&db, "ships").await, "4");
+    insert(&db, "ships", vec![("name", "Перехватчик")]).await;
+    assert_eq!(next_id(&db, "ships").await, "5");
+}
+
+// Сундук версии 1 — `TableFile` без заголовка, как его писали до версионного формата!
+#[derive(Serialize)]
+enum DiskValue {
+    #[allow(dead_code)]
+    Numeric(f64),
+    Text(String),
+}
+
+#[derive(Serialize)]
+struct RowV1 {
+    id: u64,
+    data: HashMap<String, DiskValue>,
+    expires_at: Option<i64>,
+    version: u64,
+}
+
+#[derive(Serialize)]
+struct TableFileV1 {
+    next_id: u64,
+    rows: HashMap<u64, RowV1>,
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn legacy_table_file_keeps_its_counter() {
+    let dir = fresh_dir("rowid-legacy");
+    std::fs::create_dir_all(format!("{}/data", dir)).unwrap();
+    std::fs::write(format!("{}/config.toml", dir), "[[tables]]\nname = \"ships\"\n[[tables.fields]]\nname = \"name\"\nfield_type = \"text\"\n").unwrap();
+    // Номер больше u32 — 64 бита в деле, и счётчик ушёл вперёд: строки после 5_000_000_000 когда-то удалили!
+    let id = 5_000_000_000;
+    let legacy = TableFileV1 {
+        next_id: id + 10,
+        rows: HashMap::from([(id, RowV1 { id, data: HashMap::from([("name".to_string(), DiskValue::Text("Жемчужина".to_string()))]), expires_at: None, version: 3 })]),
+    };
+    std::fs::write(format!("{}/data/ships.bin", dir), bincode::serialize(&legacy).unwrap()).unwrap();
+
+    let db = open(&dir).await;
+    let mut q = db.select("ships");
+    q.fields(vec!["name", "_version"]);
+    let row = q.execute(&db).await.unwrap().unwrap().remove(0);
+    assert_eq!((row["name"].as_str(), row["_version"].as_str()), ("Жемчужина", "3"));
+    assert_eq!(next_id(&db, "ships").await, (id + 10).to_string());
+
+    // Старт переписал сундук новым форматом — счётчик на месте!
+    insert(&db, "ships", vec![("name", "Голландец")]).await;
+    let db = reopen(&dir).await;
+    assert_eq!(next_id(&db, "ships").await, (id + 11).to_string());
+}
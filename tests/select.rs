@@ -0,0 +1,46 @@
+// SELECT без схлопывания — DISTINCT, DISTINCT ON и честные страницы LIMIT/OFFSET!
+mod common;
+
+use common::{fresh_dir, insert, open};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn distinct_is_explicit_and_pages_are_full() {
+    let dir = fresh_dir("select-distinct");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE pirates (id numeric, name text, ship numeric)").await.unwrap();
+    for (id, name, ship) in [("1", Some("Джек"), "1"), ("2", Some("Джек"), "1"), ("3", None, "2"), ("4", None, "2"), ("5", Some("Анна"), "3")] {
+        let mut values = vec![("id", id), ("ship", ship)];
+        values.extend(name.map(|n| ("name", n)));
+        insert(&db, "pirates", values).await;
+    }
+    let names = |rows: Option<Vec<std::collections::HashMap<String, String>>>| -> Vec<Option<String>> {
+        rows.unwrap_or_default().into_iter().map(|mut r| r.remove("name")).collect()
+    };
+
+    // Без DISTINCT — все пять строк, безымянные тоже!
+    let mut q = db.select("pirates");
+    q.fields(vec!["name"]).order_by("id", true);
+    assert_eq!(names(q.clone().execute(&db).await.unwrap()).len(), 5);
+
+    // Страницы по две — полные, пустые имена в счёт!
+    for (offset, expected) in [(0, 2), (2, 2), (4, 1)] {
+        let mut page = q.clone();
+        page.offset(offset).limit(2);
+        assert_eq!(page.execute(&db).await.unwrap().unwrap().len(), expected, "offset {}", offset);
+    }
+
+    // DISTINCT — по одной на значение, NULL тоже значение; страница режется после схлопывания!
+    let mut q = db.select("pirates");
+    q.fields(vec!["name"]).distinct().order_by("name", true);
+    assert_eq!(names(q.clone().execute(&db).await.unwrap()), vec![Some("Анна".to_string()), Some("Джек".to_string()), None]);
+    q.offset(1).limit(1);
+    assert_eq!(names(q.execute(&db).await.unwrap()), vec![Some("Джек".to_string())]);
+
+    // DISTINCT ON — первый по порядку с каждого корабля!
+    let mut q = db.select("pirates");
+    q.fields(vec!["id"]).distinct_on(vec!["ship"]).order_by("ship", true).order_by("id", false);
+    let ids: Vec<String> = q.clone().execute(&db).await.unwrap().unwrap().into_iter().map(|mut r| r.remove("id").unwrap()).collect();
+    assert_eq!(ids, vec!["2", "4", "5"]);
+    q.limit(2);
+    assert_eq!(q.execute(&db).await.unwrap().unwrap().len(), 2);
+}
@@ -0,0 +1,48 @@
+// Последовательности — nextval, setval, край без цикла и перезапуск без повторов!
+mod common;
+
+use common::{fresh_dir, open, reopen};
+use yuaidb::{DbError, SequenceOptions};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn nextval_and_setval_survive_restart() {
+    let dir = fresh_dir("sequence-restart");
+    let db = open(&dir).await;
+    db.create_sequence("tickets", SequenceOptions { start: Some(100), increment: 5, ..Default::default() }).await.unwrap();
+    assert_eq!(db.nextval("tickets").await.unwrap(), 100);
+    assert_eq!(db.nextval("tickets").await.unwrap(), 105);
+
+    // После перезапуска — дальше брони: дырка бывает, повтор — никогда!
+    let db = reopen(&dir).await;
+    let after = db.nextval("tickets").await.unwrap();
+    assert!(after > 105 && (after - 100) % 5 == 0, "{}", after);
+
+    // setval — следующий пойдёт за ним, и после перезапуска тоже!
+    db.setval("tickets", 500).await.unwrap();
+    assert_eq!(db.nextval("tickets").await.unwrap(), 505);
+    db.setval("tickets", 1000).await.unwrap();
+    let db = reopen(&dir).await;
+    assert_eq!(db.nextval("tickets").await.unwrap(), 1005);
+
+    db.drop_sequence("tickets").await.unwrap();
+    assert!(matches!(db.nextval("tickets").await, Err(DbError::SequenceNotFound(_))));
+    let db = reopen(&dir).await;
+    assert!(matches!(db.nextval("tickets").await, Err(DbError::SequenceNotFound(_))));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bounded_sequence_stops_or_cycles() {
+    let dir = fresh_dir("sequence-bounds");
+    let db = open(&dir).await;
+    db.create_sequence("short", SequenceOptions { max_value: Some(2), ..Default::default() }).await.unwrap();
+    db.create_sequence("round", SequenceOptions { max_value: Some(2), cycle: true, ..Default::default() }).await.unwrap();
+    assert!(matches!(db.create_sequence("zero", SequenceOptions { increment: 0, ..Default::default() }).await, Err(DbError::InvalidValue(..))));
+
+    assert_eq!(db.nextval("short").await.unwrap(), 1);
+    assert_eq!(db.nextval("short").await.unwrap(), 2);
+    assert!(matches!(db.nextval("short").await, Err(DbError::SequenceExhausted(_))));
+    assert!(matches!(db.setval("short", 3).await, Err(DbError::InvalidValue(..))));
+
+    let values = [db.nextval("round").await.unwrap(), db.nextval("round").await.unwrap(), db.nextval("round").await.unwrap()];
+    assert_eq!(values, [1, 2, 1]);
+}
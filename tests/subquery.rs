@@ -0,0 +1,51 @@
+// Подзапросы — IN, EXISTS и скалярные, в том числе коррелированные!
+mod common;
+
+use common::{fresh_dir, insert, open, select};
+use yuaidb::{parse_select, DbError};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn in_exists_and_scalar_subqueries() {
+    let dir = fresh_dir("subquery");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (ship_id numeric, name text, speed numeric)").await.unwrap();
+    db.execute_ddl("CREATE TABLE pirates (name text, ship_id numeric)").await.unwrap();
+    insert(&db, "ships", vec![("ship_id", "1"), ("name", "Жемчужина"), ("speed", "0.9")]).await;
+    insert(&db, "ships", vec![("ship_id", "2"), ("name", "Месть"), ("speed", "0.5")]).await;
+    insert(&db, "pirates", vec![("name", "Джек"), ("ship_id", "1")]).await;
+    insert(&db, "pirates", vec![("name", "Барбосса"), ("ship_id", "2")]).await;
+    insert(&db, "pirates", vec![("name", "Тёрнер"), ("ship_id", "3")]).await;
+
+    // IN (SELECT ...) — некоррелированный, считается один раз!
+    let fast = db.select("ships").fields(vec!["ship_id"]).where_gt("speed", "0.8").clone();
+    let mut q = db.select("pirates");
+    q.where_in_subquery("ship_id", fast);
+    assert_eq!(select(&db, &q, "name").await, vec!["Джек"]);
+
+    // EXISTS и NOT EXISTS — коррелированные по внешней кличке!
+    let mut has_ship = db.select("ships");
+    has_ship.where_expr("ship_id = p.ship_id");
+    let mut q = db.select("pirates");
+    q.alias("p").where_exists(has_ship.clone());
+    assert_eq!(select(&db, &q, "name").await, vec!["Барбосса", "Джек"]);
+    let mut q = db.select("pirates");
+    q.alias("p").where_not_exists(has_ship);
+    assert_eq!(select(&db, &q, "name").await, vec!["Тёрнер"]);
+
+    // Скалярный коррелированный в проекции — одно значение на строку, нет строки — NULL!
+    let q = parse_select("SELECT name, (SELECT name FROM ships AS s WHERE s.ship_id = p.ship_id) AS ship FROM pirates AS p").unwrap();
+    let mut rows = q.execute(&db).await.unwrap().unwrap();
+    rows.sort_by(|a, b| a["name"].cmp(&b["name"]));
+    let ships: Vec<Option<&str>> = rows.iter().map(|r| r.get("ship").map(String::as_str)).collect();
+    assert_eq!(ships, vec![Some("Месть"), Some("Жемчужина"), None]);
+
+    // То же через SQL в WHERE — IN и скалярное сравнение!
+    let q = parse_select("SELECT name FROM pirates WHERE ship_id IN (SELECT ship_id FROM ships WHERE speed < 0.8)").unwrap();
+    assert_eq!(select(&db, &q, "name").await, vec!["Барбосса"]);
+    let q = parse_select("SELECT name FROM pirates WHERE ship_id = (SELECT ship_id FROM ships WHERE name = 'Жемчужина')").unwrap();
+    assert_eq!(select(&db, &q, "name").await, vec!["Джек"]);
+
+    // Скалярный подзапрос с двумя строками — ошибка, а не первая попавшаяся!
+    let q = parse_select("SELECT name FROM pirates WHERE ship_id = (SELECT ship_id FROM ships)").unwrap();
+    assert!(matches!(q.execute(&db).await, Err(DbError::InvalidExpression(..))));
+}
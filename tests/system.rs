@@ -0,0 +1,81 @@
+// Служебные сундуки — схема, метки и счётчики видны обычным select, а писать в них нельзя!
+mod common;
+
+use std::collections::HashMap;
+use common::{fresh_dir, insert, open, select};
+use yuaidb::{Database, DbError, MIGRATIONS_TABLE, SYSTEM_TABLES};
+
+async fn one(db: &Database, table: &str, field: &str, value: &str) -> HashMap<String, String> {
+    let mut q = db.select(table);
+    q.where_eq(field, value);
+    q.execute(db).await.unwrap().unwrap().remove(0)
+}
+
+async fn ships(db: &Database) {
+    db.execute_ddl("CREATE TABLE ships (name text UNIQUE INDEX, speed numeric INDEX, log text FULLTEXT)").await.unwrap();
+    insert(db, "ships", vec![("name", "Жемчужина"), ("speed", "0.75"), ("log", "ром")]).await;
+    insert(db, "ships", vec![("name", "Голландец"), ("speed", "0.5")]).await;
+    insert(db, "ships", vec![("name", "Месть"), ("speed", "0.5")]).await;
+    let mut q = db.delete("ships");
+    q.where_eq("name", "Месть");
+    q.execute(db).await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn system_tables_describe_tables_columns_indexes_and_stats() {
+    let dir = fresh_dir("system-tables");
+    let db = open(&dir).await;
+    ships(&db).await;
+
+    let table = one(&db, "yuaidb_tables", "name", "ships").await;
+    let counts: Vec<&str> = ["kind", "fields", "rows", "expired", "next_id"].iter().map(|f| table[*f].as_str()).collect();
+    assert_eq!(counts, vec!["table", "3", "2", "0", "4"]); // Удалённый номер не вернётся!
+    let mut q = db.select("yuaidb_tables");
+    q.where_eq("kind", "virtual");
+    let mut system: Vec<&str> = SYSTEM_TABLES.to_vec();
+    system.sort();
+    assert_eq!(select(&db, &q, "name").await, system);
+
+    let mut q = db.select("yuaidb_columns");
+    q.where_eq("table", "ships").order_by("position", true);
+    let columns = q.execute(&db).await.unwrap().unwrap();
+    let described: Vec<(&str, &str, &str, &str)> = columns.iter()
+        .map(|c| (c["name"].as_str(), c["type"].as_str(), c["unique"].as_str(), c["fulltext"].as_str()))
+        .collect();
+    assert_eq!(described, vec![("name", "text", "true", "false"), ("speed", "numeric", "false", "false"), ("log", "text", "false", "true")]);
+
+    let mut q = db.select("yuaidb_indexes");
+    q.where_eq("table", "ships");
+    let indexes: HashMap<String, HashMap<String, String>> = q.execute(&db).await.unwrap().unwrap().into_iter()
+        .map(|mut i| (i.remove("column").unwrap(), i))
+        .collect();
+    assert_eq!((indexes["name"]["kind"].as_str(), indexes["name"]["keys"].as_str(), indexes["name"]["entries"].as_str()), ("unique", "2", "2"));
+    assert_eq!((indexes["speed"]["kind"].as_str(), indexes["speed"]["keys"].as_str(), indexes["speed"]["entries"].as_str()), ("index", "2", "2"));
+    assert_eq!(indexes["log"]["kind"], "fulltext");
+
+    let stats = db.select("yuaidb_stats").execute(&db).await.unwrap().unwrap().remove(0);
+    assert_eq!(stats["rows"], "2");
+    assert!(stats["wal_lsn"].parse::<u64>().unwrap() > 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn system_tables_and_migration_history_are_read_only() {
+    let dir = fresh_dir("system-read-only");
+    let db = open(&dir).await;
+    ships(&db).await;
+
+    for table in SYSTEM_TABLES.into_iter().chain([MIGRATIONS_TABLE]) {
+        let mut q = db.insert(table);
+        q.values(vec![("name", "Кракен")]);
+        assert!(matches!(q.execute(&db).await, Err(DbError::ReadOnlyTable(t)) if t == table));
+        let mut q = db.update(table);
+        q.values(vec![("name", "Кракен")]);
+        assert!(matches!(q.execute(&db).await, Err(DbError::ReadOnlyTable(_))));
+        assert!(matches!(db.delete(table).execute(&db).await, Err(DbError::ReadOnlyTable(_))));
+        let rows = vec![HashMap::from([("name".to_string(), "Кракен".to_string())])];
+        assert!(matches!(db.bulk_load(table, rows).await, Err(DbError::ReadOnlyTable(_))));
+    }
+    // Завести сундук со служебным именем тоже нельзя!
+    assert!(db.execute_ddl("CREATE TABLE yuaidb_tables (name text)").await.is_err());
+    assert_eq!(one(&db, "yuaidb_tables", "name", "ships").await["rows"], "2");
+}
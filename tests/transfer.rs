@@ -0,0 +1,126 @@
+// Погрузка и выгрузка — пустая строка не теряется, кривые строки в отчёте с номером строки файла!
+mod common;
+
+use std::collections::HashMap;
+use common::{fresh_dir, insert, open};
+use yuaidb::{Database, DbError, Format};
+
+async fn rows(db: &Database, table: &str) -> Vec<HashMap<String, String>> {
+    let mut rows = db.select(table).execute(db).await.unwrap().unwrap_or_default();
+    rows.sort_by(|a, b| a["num"].cmp(&b["num"]));
+    rows
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn export_and_import_round_trip_keeps_empty_text_and_absent_fields() {
+    let dir = fresh_dir("transfer-round-trip");
+    let db = open(&dir).await;
+    let schema = "(num numeric, name text, note text, alive boolean, joined timestamp)";
+    db.execute_ddl(&format!("CREATE TABLE crew {}", schema)).await.unwrap();
+    insert(&db, "crew", vec![("num", "1"), ("name", "Джек"), ("note", ""), ("alive", "true"), ("joined", "1700000000")]).await;
+    insert(&db, "crew", vec![("num", "2"), ("name", "Уилл, \"Прихлоп\"\nТёрнер"), ("alive", "false")]).await;
+    insert(&db, "crew", vec![("num", "3.5"), ("note", "  ")]).await;
+    let expected = rows(&db, "crew").await;
+    assert_eq!(expected[0]["note"], ""); // Пустая строка — значение, а не пропуск!
+    assert!(!expected[1].contains_key("note"));
+
+    for (table, format) in [("crew_csv", Format::Csv), ("crew_jsonl", Format::JsonLines)] {
+        let path = format!("{}/{}", dir, table);
+        assert_eq!(db.export("crew", &path, format).await.unwrap(), 3);
+        db.execute_ddl(&format!("CREATE TABLE {} {}", table, schema)).await.unwrap();
+        let report = db.import(table, &path, format).await.unwrap();
+        assert_eq!((report.loaded, report.errors.len()), (3, 0), "{}", table);
+        assert_eq!(rows(&db, table).await, expected, "{}", table);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn csv_import_reports_type_errors_by_file_line() {
+    let dir = fresh_dir("transfer-errors");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE crew (num numeric, name text, alive boolean)").await.unwrap();
+    let path = format!("{}/crew.csv", dir);
+    // Строка 1 — заголовок, вторая запись тянется на две строки файла!
+    let csv = "num,name,alive\n1,Джек,true\n2,\"Уилл\nТёрнер\",false\nтри,Гиббс,true\n4,,нет\n5,\"\",true\n";
+    std::fs::write(&path, csv).unwrap();
+
+    let report = db.import("crew", &path, Format::Csv).await.unwrap();
+    assert_eq!(report.loaded, 3);
+    let lines: Vec<usize> = report.errors.iter().map(|(line, _)| *line).collect();
+    assert_eq!(lines, vec![5, 6]);
+    assert!(report.errors.iter().all(|(_, e)| matches!(e, DbError::InvalidValue(..))));
+
+    let rows = rows(&db, "crew").await;
+    assert_eq!(rows[1]["name"], "Уилл\nТёрнер");
+    assert_eq!(rows[2]["name"], ""); // `""` — пустая строка, а не пропуск!
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn export_query_keeps_projection_order_and_types() {
+    let dir = fresh_dir("transfer-query");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (name text, speed numeric, flagship boolean)").await.unwrap();
+    insert(&db, "ships", vec![("name", "Жемчужина"), ("speed", "0.75"), ("flagship", "true")]).await;
+    insert(&db, "ships", vec![("name", "42"), ("speed", "0.5")]).await;
+
+    let mut q = db.select("ships");
+    q.fields(vec!["name", "speed * 100 AS pct", "flagship"]).order_by("name", false);
+    let path = format!("{}/ships.jsonl", dir);
+    assert_eq!(db.export_query(q.clone(), &path, Format::JsonLines).await.unwrap(), 2);
+    let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path).unwrap().lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines[0], serde_json::json!({"name": "Жемчужина", "pct": 75, "flagship": true}));
+    assert_eq!(lines[1], serde_json::json!({"name": "42", "pct": 50})); // Текст остаётся текстом, NULL — пропуском!
+
+    let path = format!("{}/ships.csv", dir);
+    db.export_query(q, &path, Format::Csv).await.unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "name,pct,flagship\n\"Жемчужина\",75,true\n\"42\",50,\n");
+}
+
+#[cfg(feature = "columnar")]
+#[tokio::test(flavor = "multi_thread")]
+async fn columnar_round_trip_keeps_types_and_absent_fields() {
+    let dir = fresh_dir("transfer-columnar");
+    let db = open(&dir).await;
+    let schema = "(num numeric, name text, note text, alive boolean, joined timestamp)";
+    db.execute_ddl(&format!("CREATE TABLE crew {}", schema)).await.unwrap();
+    insert(&db, "crew", vec![("num", "1"), ("name", "Джек"), ("note", ""), ("alive", "true"), ("joined", "1700000000")]).await;
+    insert(&db, "crew", vec![("num", "2"), ("name", "Уилл"), ("alive", "false")]).await;
+    insert(&db, "crew", vec![("num", "3.5"), ("joined", "-5")]).await;
+    let expected = rows(&db, "crew").await;
+
+    for (table, format) in [("crew_arrow", Format::ArrowIpc), ("crew_parquet", Format::Parquet)] {
+        let path = format!("{}/{}", dir, table);
+        assert_eq!(db.export("crew", &path, format).await.unwrap(), 3);
+        db.execute_ddl(&format!("CREATE TABLE {} {}", table, schema)).await.unwrap();
+        let report = db.import(table, &path, format).await.unwrap();
+        assert_eq!((report.loaded, report.errors.len()), (3, 0), "{}", table);
+        assert_eq!(rows(&db, table).await, expected, "{}", table);
+    }
+}
+
+#[cfg(feature = "columnar")]
+#[tokio::test(flavor = "multi_thread")]
+async fn columnar_export_query_keeps_projection_order() {
+    use arrow_schema::DataType;
+    let dir = fresh_dir("transfer-columnar-query");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE ships (name text, speed numeric, flagship boolean)").await.unwrap();
+    insert(&db, "ships", vec![("name", "Жемчужина"), ("speed", "0.75"), ("flagship", "true")]).await;
+    insert(&db, "ships", vec![("name", "Голландец"), ("speed", "0.5")]).await;
+    let mut q = db.select("ships");
+    q.fields(vec!["speed * 100 AS pct", "name", "flagship"]);
+
+    let arrow = format!("{}/ships.arrow", dir);
+    db.export_query(q.clone(), &arrow, Format::ArrowIpc).await.unwrap();
+    let reader = arrow_ipc::reader::FileReader::try_new(std::fs::File::open(&arrow).unwrap(), None).unwrap();
+    let parquet = format!("{}/ships.parquet", dir);
+    db.export_query(q, &parquet, Format::Parquet).await.unwrap();
+    let builder = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&parquet).unwrap()).unwrap();
+
+    for schema in [reader.schema(), builder.schema().clone()] {
+        let columns: Vec<(&str, &DataType)> = schema.fields().iter().map(|f| (f.name().as_str(), f.data_type())).collect();
+        assert_eq!(columns, vec![("pct", &DataType::Float64), ("name", &DataType::Utf8), ("flagship", &DataType::Boolean)]);
+    }
+}
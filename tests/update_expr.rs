@@ -0,0 +1,91 @@
+// Правки выражениями — параллельные прибавки не теряются, повтор журнала даёт то же самое!
+mod common;
+
+use common::{fresh_dir, insert, open, reopen};
+use yuaidb::{Database, DbError, FieldConfig, TableConfig};
+
+async fn value(db: &Database, table: &str, field: &str) -> String {
+    db.select(table).execute(db).await.unwrap().unwrap().remove(0).remove(field).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn concurrent_increments_add_up() {
+    let dir = fresh_dir("expr-increment");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE counters (key text, hits numeric)").await.unwrap();
+    insert(&db, "counters", vec![("key", "a"), ("hits", "0")]).await;
+
+    let tasks: Vec<_> = (0..100).map(|_| {
+        let db = db.clone();
+        tokio::spawn(async move {
+            let mut q = db.update("counters");
+            q.where_eq("key", "a").increment("hits", 1.0);
+            q.execute(&db).await
+        })
+    }).collect();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+    assert_eq!(value(&db, "counters", "hits").await, "100");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn replay_repeats_non_commutative_updates_and_now() {
+    let dir = fresh_dir("expr-replay");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE counters (key text, score numeric, seen timestamp)").await.unwrap();
+    insert(&db, "counters", vec![("key", "a"), ("score", "1")]).await;
+
+    // `* 2` и `+ 1` вперемешку — итог зависит от порядка, журнал обязан его помнить!
+    let tasks: Vec<_> = (0..40).map(|i| {
+        let db = db.clone();
+        tokio::spawn(async move {
+            let mut q = db.update("counters");
+            q.where_eq("key", "a");
+            if i % 2 == 0 { q.multiply("score", 2.0); } else { q.increment("score", 1.0); }
+            q.execute(&db).await
+        })
+    }).collect();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+    let mut q = db.update("counters");
+    q.where_eq("key", "a").set_now("seen");
+    q.execute(&db).await.unwrap();
+    let (score, seen) = (value(&db, "counters", "score").await, value(&db, "counters", "seen").await);
+
+    // Часы ушли вперёд — повтор журнала ставит то же время, что и вживую!
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    let db = reopen(&dir).await;
+    assert_eq!(value(&db, "counters", "score").await, score);
+    assert_eq!(value(&db, "counters", "seen").await, seen);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn helpers_quote_the_field_and_refuse_non_finite_numbers() {
+    let dir = fresh_dir("expr-helpers");
+    let db = open(&dir).await;
+    let fields = vec![FieldConfig::new("key", "text"), FieldConfig::new("hit count", "numeric"), FieldConfig::new("ship.log", "text")];
+    db.create_table(TableConfig { name: "counters".into(), fields }).await.unwrap();
+    insert(&db, "counters", vec![("key", "a"), ("hit count", "10"), ("ship.log", "йо")]).await;
+
+    // Пробел и точка в имени — не помеха, отрицательный шаг — тоже!
+    let mut q = db.update("counters");
+    q.where_eq("key", "a").increment("hit count", -2.5).append("ship.log", "-хо");
+    q.execute(&db).await.unwrap();
+    assert_eq!(value(&db, "counters", "hit count").await, "7.5");
+    assert_eq!(value(&db, "counters", "ship.log").await, "йо-хо");
+    let mut q = db.update("counters");
+    q.where_eq("key", "a").multiply("hit count", 2.0);
+    q.execute(&db).await.unwrap();
+
+    // NaN и бесконечность — отказ до журнала, строка прежняя!
+    for by in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+        let mut q = db.update("counters");
+        q.where_eq("key", "a").decrement("hit count", by);
+        assert!(matches!(q.execute(&db).await, Err(DbError::InvalidValue(f, _)) if f == "hit count"));
+    }
+    let db = reopen(&dir).await;
+    assert_eq!(value(&db, "counters", "hit count").await, "15");
+    assert_eq!(value(&db, "counters", "ship.log").await, "йо-хо");
+}
@@ -0,0 +1,97 @@
+// INSERT ... ON CONFLICT — столкновения по уникальному полю, и в гонке тоже!
+mod common;
+
+use common::{column, fresh_dir, insert, open};
+use yuaidb::DbError;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn on_conflict_updates_or_skips_existing_row() {
+    let dir = fresh_dir("upsert-basic");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE sessions (sid text UNIQUE, user numeric)").await.unwrap();
+    insert(&db, "sessions", vec![("sid", "a"), ("user", "1")]).await;
+
+    let mut q = db.insert("sessions");
+    q.values(vec![("sid", "a"), ("user", "2")]).on_conflict("sid").do_update(vec![("user", "2")]);
+    assert_eq!(q.execute_affected(&db).await.unwrap().0, 1);
+    let mut q = db.insert("sessions");
+    q.values(vec![("sid", "a"), ("user", "3")]).on_conflict("sid").do_nothing();
+    assert_eq!(q.execute_affected(&db).await.unwrap().0, 0);
+
+    let mut q = db.insert("sessions");
+    q.values(vec![("sid", "a"), ("user", "4")]);
+    assert!(matches!(q.execute(&db).await, Err(DbError::DuplicateValue(..))));
+    assert_eq!(column(db.select("sessions").execute(&db).await.unwrap(), "user"), vec!["2"]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn concurrent_upserts_on_one_key_leave_one_row() {
+    let dir = fresh_dir("upsert-race");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE sessions (sid text UNIQUE, user numeric)").await.unwrap();
+
+    let tasks: Vec<_> = (0..64).map(|i| {
+        let db = db.clone();
+        tokio::spawn(async move {
+            let user = i.to_string();
+            let mut q = db.insert("sessions");
+            q.values(vec![("sid", "shared"), ("user", user.as_str())]).on_conflict("sid").do_update(vec![("user", user.as_str())]);
+            q.execute(&db).await
+        })
+    }).collect();
+    for task in tasks {
+        task.await.unwrap().unwrap(); // Столкновение — не ошибка!
+    }
+    assert_eq!(column(db.select("sessions").execute(&db).await.unwrap(), "sid"), vec!["shared"]);
+
+    // Простые вставки одного ключа — одна проходит, остальные ловят дубль!
+    let tasks: Vec<_> = (0..64).map(|_| {
+        let db = db.clone();
+        tokio::spawn(async move {
+            let mut q = db.insert("sessions");
+            q.values(vec![("sid", "plain"), ("user", "1")]);
+            q.execute(&db).await
+        })
+    }).collect();
+    let mut inserted = 0;
+    for task in tasks {
+        match task.await.unwrap() {
+            Ok(_) => inserted += 1,
+            Err(DbError::DuplicateValue(..)) => {}
+            Err(e) => panic!("{}", e),
+        }
+    }
+    assert_eq!(inserted, 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn upsert_does_not_lose_a_concurrent_increment() {
+    let dir = fresh_dir("upsert-increment");
+    let db = open(&dir).await;
+    db.execute_ddl("CREATE TABLE counters (key text UNIQUE, hits numeric, label text)").await.unwrap();
+    insert(&db, "counters", vec![("key", "a"), ("hits", "0"), ("label", "-")]).await;
+
+    // Прибавки идут мимо замка уникальности, UPSERT правит ту же строку — ни одна прибавка не теряется!
+    let tasks: Vec<_> = (0..64).map(|i| {
+        let db = db.clone();
+        tokio::spawn(async move {
+            if i % 2 == 0 {
+                let mut q = db.update("counters");
+                q.where_eq("key", "a").increment("hits", 1.0);
+                q.execute(&db).await
+            } else {
+                let label = i.to_string();
+                let mut q = db.insert("counters");
+                q.values(vec![("key", "a"), ("label", label.as_str())]).on_conflict("key").do_update(vec![("label", label.as_str())]);
+                q.execute(&db).await
+            }
+        })
+    }).collect();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+    let rows = db.select("counters").execute(&db).await.unwrap().unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["hits"], "32");
+    assert_eq!(rows[0]["key"], "a");
+}